@@ -62,28 +62,79 @@
 #![allow(unused_imports)]
 #[macro_use]
 extern crate askama_derive;
+#[cfg(feature = "i18n")]
+pub use askama_i18n as i18n;
+#[cfg(feature = "hot-reload")]
+pub use askama_runtime as runtime;
 pub use askama_shared as shared;
 
+use std::collections::HashMap;
 use std::fs::{self, DirEntry};
 use std::io;
 use std::path::Path;
 
-pub use askama_escape::{Html, Text};
+pub use askama_escape::{Css, Csv, Html, Js, Latex, Markdown, Shell, Text, Url};
 
 /// Main `Template` trait; implementations are generally derived
 pub trait Template {
     /// Helper method which allocates a new `String` and renders into it
     fn render(&self) -> Result<String> {
-        let mut buf = String::with_capacity(self.size_hint());
-        self.render_into(&mut buf)?;
+        let mut buf = String::new();
+        self.render_into_buf(&mut buf)?;
         Ok(buf)
     }
+    /// Clears `buf` and renders the template into it, so that a buffer can be
+    /// reused across renders instead of allocating a new `String` each time
+    fn render_into_buf(&self, buf: &mut String) -> Result<()> {
+        buf.clear();
+        buf.reserve(self.size_hint());
+        self.render_into(buf)
+    }
     /// Renders the template to the given `writer` buffer
     fn render_into(&self, writer: &mut dyn std::fmt::Write) -> Result<()>;
+    /// Renders the template as UTF-8 bytes to the given `writer`, like a
+    /// file or socket, without building a `String` first
+    fn write_into_io<W: io::Write + ?Sized>(&self, writer: &mut W) -> Result<()>
+    where
+        Self: Sized,
+    {
+        let mut writer = helpers::IoWriter::new(writer);
+        let res = self.render_into(&mut writer);
+        writer.finish(res)
+    }
+    /// Renders the template in chunks of at least `chunk_size` bytes (except
+    /// for the last one), passing each one to `f` as soon as it is complete,
+    /// so that large output can be streamed instead of buffered as a whole
+    fn render_chunks(
+        &self,
+        chunk_size: usize,
+        f: &mut dyn FnMut(&str) -> Result<()>,
+    ) -> Result<()> {
+        let mut writer = helpers::ChunkWriter::new(chunk_size, f);
+        let res = self.render_into(&mut writer);
+        writer.finish(res)
+    }
     /// Helper function to inspect the template's extension
     fn extension(&self) -> Option<&'static str>;
     /// Provides an conservative estimate of the expanded length of the rendered template
     fn size_hint(&self) -> usize;
+    /// Helper method which renders a single block into a new `String`
+    fn render_block(&self, block: &str) -> Result<String> {
+        let mut buf = String::new();
+        self.render_block_into(block, &mut buf)?;
+        Ok(buf)
+    }
+    /// Renders the block named `block` to the given `writer` buffer, using
+    /// the same context as the whole template
+    fn render_block_into(&self, block: &str, _writer: &mut dyn std::fmt::Write) -> Result<()> {
+        Err(Error::Custom(format!("no block named '{}'", block).into()))
+    }
+    /// The content type declared by the block named `block`, as in
+    /// `{% block icon type "image/svg+xml" %}`, for responses rendering it
+    /// on its own
+    fn block_content_type(&self, _block: &str) -> Option<&'static str> {
+        None
+    }
 }
 
 pub trait SizedTemplate {
@@ -93,6 +144,92 @@ pub trait SizedTemplate {
     fn size_hint() -> usize;
 }
 
+/// A template that can be looked up by name in a `Registry`
+///
+/// Implemented by deriving `Template` with the `register` attribute:
+/// `#[template(path = "welcome.html", register = "email/welcome")]`.
+pub trait RegisteredTemplate: Template {
+    /// The name given in the `register` attribute
+    const NAME: &'static str;
+}
+
+/// Builds templates chosen by name at runtime
+///
+/// Every template in a registry is built from the same argument type `A`,
+/// through its `From<A>` implementation, so that an application can pick a
+/// template from a name stored in a database or configuration file:
+///
+/// ```
+/// use askama::{Registry, Template};
+///
+/// #[derive(Template)]
+/// #[template(source = "Welcome, {{ name }}!", ext = "txt", register = "welcome")]
+/// struct Welcome {
+///     name: String,
+/// }
+///
+/// impl From<&str> for Welcome {
+///     fn from(name: &str) -> Self {
+///         Welcome { name: name.into() }
+///     }
+/// }
+///
+/// let mut registry = Registry::new();
+/// registry.register::<Welcome>();
+/// let template = registry.build("welcome", "Ann").unwrap();
+/// assert_eq!(template.render().unwrap(), "Welcome, Ann!");
+/// ```
+pub struct Registry<A> {
+    constructors: HashMap<&'static str, Constructor<A>>,
+}
+
+/// Builds a registered template from the registry's arguments
+pub type Constructor<A> = fn(A) -> Box<dyn Template>;
+
+impl<A> Registry<A> {
+    /// Creates an empty registry
+    pub fn new() -> Self {
+        Registry {
+            constructors: HashMap::new(),
+        }
+    }
+
+    /// Adds a template under its registered name
+    ///
+    /// Panics if another template was added with the same name.
+    pub fn register<T>(&mut self) -> &mut Self
+    where
+        T: RegisteredTemplate + From<A> + 'static,
+    {
+        let constructor: Constructor<A> = |args| Box::new(T::from(args));
+        if self.constructors.insert(T::NAME, constructor).is_some() {
+            panic!("template '{}' registered twice", T::NAME);
+        }
+        self
+    }
+
+    /// Returns the constructor for the template named `name`
+    pub fn get(&self, name: &str) -> Option<Constructor<A>> {
+        self.constructors.get(name).copied()
+    }
+
+    /// Builds the template named `name` from `args`
+    pub fn build(&self, name: &str, args: A) -> Option<Box<dyn Template>> {
+        self.get(name).map(|constructor| constructor(args))
+    }
+
+    /// Returns the names of all registered templates, in no particular order
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.constructors.keys().copied()
+    }
+}
+
+impl<A> Default for Registry<A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub use crate::shared::filters;
 pub use crate::shared::helpers;
 pub use crate::shared::{read_config_file, Error, MarkupDisplay, Result};
@@ -110,6 +247,16 @@ pub mod mime {
         basic_type
     }
 
+    /// The content type of a response rendering a single block: the type the
+    /// block declares, or else the one for the template's extension
+    #[cfg(all(feature = "mime_guess", feature = "mime"))]
+    pub fn block_content_type<T: crate::Template + ?Sized>(t: &T, block: &str) -> String {
+        match t.block_content_type(block) {
+            Some(content_type) => content_type.to_string(),
+            None => extension_to_mime_type(t.extension().unwrap_or("txt")).to_string(),
+        }
+    }
+
     #[cfg(all(feature = "mime_guess", feature = "mime"))]
     const TEXT_TYPES: [(mime_guess::Mime, mime_guess::Mime); 6] = [
         (mime::TEXT_PLAIN, mime::TEXT_PLAIN_UTF_8),
@@ -1,10 +1,15 @@
 pub use askama::*;
-use bytes::BytesMut;
+use bytes::{Bytes, BytesMut};
 
-use actix_web::{error::ErrorInternalServerError, Error, HttpResponse};
+use ::futures::channel::mpsc;
+use ::futures::executor::block_on;
+use ::futures::{SinkExt, StreamExt};
+use actix_web::{error::ErrorInternalServerError, rt, web, Error, HttpResponse};
 
 pub trait TemplateIntoResponse {
     fn into_response(&self) -> ::std::result::Result<HttpResponse, Error>;
+    /// Responds with the block named `block`, using its declared content type
+    fn into_block_response(&self, block: &str) -> ::std::result::Result<HttpResponse, Error>;
 }
 
 impl<T: askama::Template> TemplateIntoResponse for T {
@@ -19,6 +24,51 @@ impl<T: askama::Template> TemplateIntoResponse for T {
             .content_type(ctype.as_str())
             .body(buffer.freeze()))
     }
+
+    fn into_block_response(&self, block: &str) -> ::std::result::Result<HttpResponse, Error> {
+        let mut buffer = BytesMut::new();
+        self.render_block_into(block, &mut buffer)
+            .map_err(|_| ErrorInternalServerError("Template parsing error"))?;
+
+        let ctype = askama::mime::block_content_type(self, block);
+        Ok(HttpResponse::Ok()
+            .content_type(ctype.as_str())
+            .body(buffer.freeze()))
+    }
+}
+
+/// Streams the rendered template to the client in chunks of at least
+/// `chunk_size` bytes, instead of sending it once it is complete
+///
+/// The template is rendered on actix-web's blocking thread pool, where it waits
+/// for the client to take each chunk before rendering the next one. Since the
+/// response status is sent with the first chunk, a render error can only abort
+/// the response.
+pub fn stream<T>(t: T, chunk_size: usize) -> HttpResponse
+where
+    T: askama::Template + Send + 'static,
+{
+    let ctype = askama::mime::extension_to_mime_type(t.extension().unwrap_or("txt")).to_string();
+
+    let (mut tx, rx) = mpsc::channel(1);
+    let render = web::block(move || {
+        let res = t.render_chunks(chunk_size, &mut |chunk| {
+            // The receiver is gone once the client disconnects
+            let chunk = Bytes::copy_from_slice(chunk.as_bytes());
+            block_on(tx.send(Ok(chunk))).map_err(|err| askama::Error::Custom(Box::new(err)))
+        });
+        if let Err(err) = res {
+            let _ = block_on(tx.send(Err(err)));
+        }
+        Ok::<_, ()>(())
+    });
+    rt::spawn(async move {
+        let _ = render.await;
+    });
+
+    HttpResponse::Ok()
+        .content_type(ctype.as_str())
+        .streaming(rx.map(|res| res.map_err(ErrorInternalServerError)))
 }
 
 // Re-exported for use by generated code
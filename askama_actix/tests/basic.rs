@@ -49,3 +49,57 @@ async fn test_actix_web_responder() {
     let bytes = response.body().await.unwrap();
     assert_eq!(bytes, Bytes::from_static("Hello, world!".as_ref()));
 }
+
+#[derive(Template)]
+#[template(source = "{% for i in 0..count %}{{ i }},{% endfor %}", ext = "txt")]
+struct CountTemplate {
+    count: usize,
+}
+
+#[actix_rt::test]
+async fn test_actix_web_stream() {
+    let srv = test::start(|| {
+        actix_web::App::new().service(
+            web::resource("/")
+                .to(|| async { askama_actix::stream(CountTemplate { count: 1000 }, 64) }),
+        )
+    });
+
+    let request = srv.get("/");
+    let mut response = request.send().await.unwrap();
+    assert!(response.status().is_success());
+    assert_eq!(
+        response.headers().get(CONTENT_TYPE).unwrap(),
+        "text/plain; charset=utf-8"
+    );
+
+    let bytes = response.body().await.unwrap();
+    let expected = (0..1000).map(|i| format!("{},", i)).collect::<String>();
+    assert_eq!(bytes, Bytes::from(expected));
+}
+
+#[derive(Template)]
+#[template(
+    source = "<p>{% block icon type \"image/svg+xml\" %}<svg></svg>{% endblock %}</p>",
+    ext = "html"
+)]
+struct IconTemplate;
+
+#[actix_rt::test]
+async fn test_actix_web_block_response() {
+    let srv = test::start(|| {
+        actix_web::App::new()
+            .service(web::resource("/").to(|| async { IconTemplate.into_block_response("icon") }))
+    });
+
+    let request = srv.get("/");
+    let mut response = request.send().await.unwrap();
+    assert!(response.status().is_success());
+    assert_eq!(
+        response.headers().get(CONTENT_TYPE).unwrap(),
+        "image/svg+xml"
+    );
+
+    let bytes = response.body().await.unwrap();
+    assert_eq!(bytes, Bytes::from_static("<svg></svg>".as_ref()));
+}
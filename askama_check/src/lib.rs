@@ -0,0 +1,315 @@
+//! Checks Askama templates without compiling the crate that uses them
+//!
+//! Every file in the template directories from the configuration is parsed
+//! with the default syntax, and the templates that `extends`, `include` and
+//! `import` tags refer to, as well as the macros that `call` tags use, are
+//! looked up. The `cargo askama check` command prints the problems found:
+//!
+//! ```no_run
+//! use askama_shared::{read_config_file, Config};
+//!
+//! let config_toml = read_config_file();
+//! let config = Config::new(&config_toml);
+//! for diagnostic in askama_check::check(&config) {
+//!     eprintln!("{}", diagnostic);
+//! }
+//! ```
+//!
+//! Templates are only checked on their own: errors in Rust expressions, and
+//! problems that depend on the struct deriving `Template` (such as a template
+//! syntax chosen with the `syntax` attribute), are left to the compiler.
+//!
+//! `cargo askama fmt` normalizes the spacing inside tags and expressions of
+//! the same templates, with `format()`.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use askama_shared::parser::{parse_recover, Expr, Node, ParseError, WithSpan};
+use askama_shared::printer::print;
+use askama_shared::visitor::{walk_node, walk_nodes, Visitor};
+use askama_shared::Config;
+
+/// A problem found in a template
+#[derive(Debug, PartialEq)]
+pub struct Diagnostic {
+    /// Template path, relative to the template directory it was found in
+    pub path: PathBuf,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.message)
+    }
+}
+
+/// A template whose formatted source differs from the file
+#[derive(Debug, PartialEq)]
+pub struct Formatted {
+    pub path: PathBuf,
+    pub source: String,
+}
+
+/// Checks all templates in the configured directories
+pub fn check(config: &Config) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let sources = read_templates(config, &mut diagnostics);
+
+    let syntax = &config.syntaxes[config.default_syntax];
+    let mut parsed = HashMap::new();
+    for (path, source) in &sources {
+        // Like the derive macro, which drops the final newline
+        let source = source.strip_suffix('\n').unwrap_or(source);
+        let (nodes, errors) = parse_recover(source, syntax);
+        if errors.is_empty() {
+            parsed.insert(path.as_path(), nodes);
+        } else {
+            for err in errors {
+                diagnostics.push(diagnostic(config, path, parse_error(&err)));
+            }
+        }
+    }
+
+    for (path, _) in &sources {
+        if let Some(nodes) = parsed.get(path.as_path()) {
+            let mut messages = Vec::new();
+            check_template(config, path, nodes, &parsed, &mut messages);
+            for message in messages {
+                diagnostics.push(diagnostic(config, path, message));
+            }
+        }
+    }
+    diagnostics
+}
+
+/// Formats all templates in the configured directories, returning those that
+/// changed
+///
+/// Only the spacing inside tags and expressions is normalized: text between
+/// tags is part of the output, and is kept as written. Templates that fail to
+/// parse are reported instead, as are templates using `+` after a block
+/// start, which the parse tree doesn't keep.
+pub fn format(config: &Config) -> (Vec<Formatted>, Vec<Diagnostic>) {
+    let mut diagnostics = Vec::new();
+    let sources = read_templates(config, &mut diagnostics);
+
+    // Trimming settings remove text from the parse tree
+    let mut syntax = config.syntaxes[config.default_syntax].clone();
+    syntax.trim_blocks = false;
+    syntax.lstrip_blocks = false;
+    let lstrip_marker = format!("{}+", syntax.block_start);
+
+    let mut formatted = Vec::new();
+    for (path, source) in &sources {
+        let src = source.strip_suffix('\n').unwrap_or(source);
+        if src.contains(&lstrip_marker) {
+            let message = format!("not formatted: uses {:?}", lstrip_marker);
+            diagnostics.push(diagnostic(config, path, message));
+            continue;
+        }
+        let (nodes, errors) = parse_recover(src, &syntax);
+        if !errors.is_empty() {
+            for err in errors {
+                diagnostics.push(diagnostic(config, path, parse_error(&err)));
+            }
+            continue;
+        }
+
+        let mut out = print(&nodes, &syntax);
+        // Guard against printing something that parses differently
+        let (reparsed, errors) = parse_recover(&out, &syntax);
+        if !errors.is_empty() || reparsed != nodes {
+            let message = "not formatted: formatting would change the template".to_string();
+            diagnostics.push(diagnostic(config, path, message));
+            continue;
+        }
+        if src.len() < source.len() {
+            out.push('\n');
+        }
+        if out != *source {
+            formatted.push(Formatted {
+                path: path.clone(),
+                source: out,
+            });
+        }
+    }
+    (formatted, diagnostics)
+}
+
+// Reads every template file in the configured directories
+fn read_templates(config: &Config, diagnostics: &mut Vec<Diagnostic>) -> Vec<(PathBuf, String)> {
+    let mut paths = Vec::new();
+    for dir in &config.dirs {
+        find_files(dir, &mut paths);
+    }
+    paths.sort();
+    paths.dedup();
+
+    let mut sources = Vec::new();
+    for path in paths {
+        match fs::read_to_string(&path) {
+            Ok(source) => sources.push((path, source)),
+            Err(err) => diagnostics.push(diagnostic(config, &path, format!("{}", err))),
+        }
+    }
+    sources
+}
+
+fn check_template(
+    config: &Config,
+    path: &Path,
+    nodes: &[WithSpan<Node<'_>>],
+    parsed: &HashMap<&Path, Vec<WithSpan<Node<'_>>>>,
+    messages: &mut Vec<String>,
+) {
+    // Macro calls are resolved like the code generator does: macros defined
+    // in the same template first, then those imported from other templates
+    let mut imports = HashMap::new();
+    let mut from_imports = HashMap::new();
+    for n in nodes {
+        match &**n {
+            Node::Extends(WithSpan {
+                inner: Expr::StrLit(name),
+                ..
+            }) => {
+                find(config, path, "extends", name, messages);
+            }
+            Node::Import(_, name, scope) => {
+                if let Some(found) = find(config, path, "import", name, messages) {
+                    imports.insert(*scope, found);
+                }
+            }
+            Node::FromImport(_, name, names) => {
+                if let Some(found) = find(config, path, "import", name, messages) {
+                    for (name, alias) in names {
+                        from_imports.insert(*alias, (found.clone(), *name));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut calls = Vec::new();
+    walk(nodes, true, &mut |n, top| match n {
+        Node::Include(_, name) => {
+            find(config, path, "include", name, messages);
+        }
+        Node::Extends(_)
+        | Node::Macro(_, _)
+        | Node::Import(_, _, _)
+        | Node::FromImport(_, _, _)
+            if !top =>
+        {
+            messages.push("extends, macro or import blocks not allowed below top level".into());
+        }
+        Node::Call(_, scope, name, _) | Node::CallBlock(_, scope, name, _, _, _) => {
+            calls.push((*scope, *name));
+        }
+        _ => {}
+    });
+
+    let defines = |path: &Path, name: &str| match parsed.get(path) {
+        Some(nodes) => nodes
+            .iter()
+            .any(|n| matches!(&**n, Node::Macro(def, _) if *def == name)),
+        // Parse errors in the other template are reported on their own
+        None => true,
+    };
+    for (import, name) in from_imports.values() {
+        if !defines(import, name) {
+            messages.push(format!("imported macro '{}' not found", name));
+        }
+    }
+    for (scope, name) in calls {
+        match scope {
+            Some(scope) => match imports.get(scope) {
+                Some(import) if !defines(import, name) => {
+                    messages.push(format!("macro '{}' not found in scope '{}'", name, scope));
+                }
+                Some(_) => {}
+                None => messages.push(format!("no import found for scope '{}'", scope)),
+            },
+            None if name == "super" || from_imports.contains_key(name) => {}
+            None => {
+                if !defines(path, name) {
+                    messages.push(format!("macro '{}' not found", name));
+                }
+            }
+        }
+    }
+}
+
+fn find(
+    config: &Config,
+    path: &Path,
+    tag: &str,
+    name: &str,
+    messages: &mut Vec<String>,
+) -> Option<PathBuf> {
+    let found = config.try_find_template(name, Some(path));
+    if found.is_none() {
+        messages.push(format!("template {:?} in {} tag not found", name, tag));
+    }
+    found
+}
+
+// Calls `f` with every node and whether it is at the top level of the template
+fn walk<'a>(nodes: &'a [WithSpan<Node<'a>>], top: bool, f: &mut dyn FnMut(&'a Node<'a>, bool)) {
+    struct Walker<'f, 'a> {
+        top: bool,
+        f: &'f mut dyn FnMut(&'a Node<'a>, bool),
+    }
+
+    impl<'f, 'a> Visitor<'a> for Walker<'f, 'a> {
+        fn visit_node(&mut self, node: &'a Node<'a>) {
+            (self.f)(node, self.top);
+            let top = std::mem::replace(&mut self.top, false);
+            walk_node(self, node);
+            self.top = top;
+        }
+    }
+
+    walk_nodes(&mut Walker { top, f }, nodes);
+}
+
+// Collects the files below `dir`, skipping hidden files and directories
+fn find_files(dir: &Path, paths: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        if path.is_dir() {
+            find_files(&path, paths);
+        } else {
+            paths.push(path);
+        }
+    }
+}
+
+fn parse_error(err: &ParseError) -> String {
+    format!(
+        "{} at line {}, column {}",
+        err.message, err.line, err.column
+    )
+}
+
+fn diagnostic(config: &Config, path: &Path, message: String) -> Diagnostic {
+    let path = config
+        .dirs
+        .iter()
+        .find_map(|dir| path.strip_prefix(dir).ok())
+        .unwrap_or(path);
+    Diagnostic {
+        path: path.to_path_buf(),
+        message,
+    }
+}
@@ -0,0 +1,70 @@
+use std::env;
+use std::fs;
+use std::process;
+
+use askama_shared::{read_config_file, Config};
+
+const USAGE: &str = "usage: cargo askama check [CRATE_DIR]
+       cargo askama fmt [--check] [CRATE_DIR]";
+
+fn main() {
+    // Cargo passes the subcommand name as the first argument
+    let mut args = env::args().skip(1).peekable();
+    if args.peek().map(String::as_str) == Some("askama") {
+        args.next();
+    }
+    let command = match args.next() {
+        Some(command) if command == "check" || command == "fmt" => command,
+        Some(ref arg) if arg == "-h" || arg == "--help" => {
+            println!("{}", USAGE);
+            return;
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    let check_only = command == "fmt" && args.peek().map(String::as_str) == Some("--check");
+    if check_only {
+        args.next();
+    }
+
+    // The configuration is found like the derive macro finds it, relative to
+    // the crate that uses the templates
+    let dir = match args.next() {
+        Some(dir) => dir.into(),
+        None => env::current_dir().unwrap_or_else(|err| {
+            eprintln!("unable to find the current directory: {}", err);
+            process::exit(2);
+        }),
+    };
+    env::set_var("CARGO_MANIFEST_DIR", &dir);
+
+    let config_toml = read_config_file();
+    let config = Config::new(&config_toml);
+    let diagnostics = if command == "check" {
+        askama_check::check(&config)
+    } else {
+        let (formatted, diagnostics) = askama_check::format(&config);
+        for template in &formatted {
+            if check_only {
+                println!("would reformat {}", template.path.display());
+            } else if let Err(err) = fs::write(&template.path, &template.source) {
+                eprintln!("unable to write {}: {}", template.path.display(), err);
+                process::exit(1);
+            }
+        }
+        if check_only && !formatted.is_empty() {
+            process::exit(1);
+        }
+        diagnostics
+    };
+
+    for diagnostic in &diagnostics {
+        eprintln!("error: {}", diagnostic);
+    }
+    if !diagnostics.is_empty() {
+        eprintln!("{} template errors found", diagnostics.len());
+        process::exit(1);
+    }
+}
@@ -0,0 +1,51 @@
+use askama_check::{check, format, Formatted};
+use askama_shared::Config;
+
+fn messages(dir: &str) -> Vec<String> {
+    let config_toml = format!("[general]\ndirs = [\"tests/templates/{}\"]", dir);
+    let config = Config::new(&config_toml);
+    check(&config).iter().map(|d| d.to_string()).collect()
+}
+
+fn formatted(dir: &str) -> (Vec<Formatted>, Vec<String>) {
+    let config_toml = format!("[general]\ndirs = [\"tests/templates/{}\"]", dir);
+    let config = Config::new(&config_toml);
+    let (formatted, diagnostics) = format(&config);
+    (
+        formatted,
+        diagnostics.iter().map(|d| d.to_string()).collect(),
+    )
+}
+
+#[test]
+fn test_good_templates() {
+    assert_eq!(messages("good"), Vec::<String>::new());
+}
+
+#[test]
+fn test_bad_templates() {
+    assert_eq!(
+        messages("bad"),
+        vec![
+            "broken.html: unable to parse template at line 2, column 1",
+            "missing.html: template \"nope.html\" in extends tag not found",
+            "missing.html: template \"gone.html\" in include tag not found",
+            "missing.html: imported macro 'gone' not found",
+            "missing.html: macro 'nothing' not found in scope 'm'",
+            "missing.html: no import found for scope 'x'",
+            "missing.html: macro 'local' not found",
+        ]
+    );
+}
+
+#[test]
+fn test_format_templates() {
+    let (formatted, messages) = formatted("fmt");
+    assert_eq!(formatted.len(), 1);
+    assert!(formatted[0].path.ends_with("messy.html"));
+    assert_eq!(
+        formatted[0].source,
+        "{% if user.admin -%}\n  <b>{{ name|upper }}</b>\n{%- endif %}\n{#  kept as written #}\n"
+    );
+    assert_eq!(messages, vec!["plus.html: not formatted: uses \"{%+\""]);
+}
@@ -0,0 +1,190 @@
+//! Parsed template files, shared by every derive in the compiler process
+//!
+//! Structs deriving `Template` often extend the same layout or include the same
+//! partials, which would otherwise be parsed again for each of them. Files are
+//! still read every time, and parsed again when their contents change, which
+//! matters in long-running processes like IDEs.
+
+use askama_shared::parser::{Node, WithSpan};
+use askama_shared::{get_template_source, Syntax};
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// A template's source and the parse tree borrowing from it
+pub(crate) struct Parsed {
+    // Declared first, so that it is dropped before the source
+    nodes: Vec<WithSpan<Node<'static>>>,
+    source: Arc<str>,
+}
+
+impl Parsed {
+    /// Parses `source` with `parse`, keeping both together
+    pub(crate) fn new<F>(source: Arc<str>, parse: F) -> Self
+    where
+        F: for<'s> FnOnce(&'s str) -> Vec<WithSpan<Node<'s>>>,
+    {
+        // SAFETY: the nodes only borrow from the source, which stays in place
+        // behind the `Arc` for as long as they live, and they are only handed
+        // out with the lifetime of `&self`
+        let src: &'static str = unsafe { &*(&*source as *const str) };
+        let nodes = parse(src);
+        Parsed { nodes, source }
+    }
+
+    pub(crate) fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub(crate) fn nodes(&self) -> &[WithSpan<Node<'_>>] {
+        &self.nodes
+    }
+}
+
+// Each file keeps one entry per syntax, which is replaced when its contents
+// change, so the cache doesn't grow past the templates in use
+#[derive(Default)]
+struct Cache {
+    files: HashMap<(PathBuf, SyntaxKey), Entry>,
+}
+
+struct Entry {
+    hash: u64,
+    parsed: Arc<Parsed>,
+}
+
+// The settings of a syntax, owned so that entries outlive the derive that
+// parsed them
+#[derive(Hash, PartialEq, Eq)]
+struct SyntaxKey {
+    delimiters: [String; 6],
+    lenient: bool,
+    trim_blocks: bool,
+    lstrip_blocks: bool,
+    max_depth: usize,
+}
+
+impl SyntaxKey {
+    fn new(syntax: &Syntax<'_>) -> Self {
+        // Destructured, so that settings added later can't be left out
+        let Syntax {
+            block_start,
+            block_end,
+            expr_start,
+            expr_end,
+            comment_start,
+            comment_end,
+            lenient,
+            trim_blocks,
+            lstrip_blocks,
+            max_depth,
+        } = *syntax;
+        SyntaxKey {
+            delimiters: [
+                block_start.to_owned(),
+                block_end.to_owned(),
+                expr_start.to_owned(),
+                expr_end.to_owned(),
+                comment_start.to_owned(),
+                comment_end.to_owned(),
+            ],
+            lenient,
+            trim_blocks,
+            lstrip_blocks,
+            max_depth,
+        }
+    }
+}
+
+static CACHE: Mutex<Option<Cache>> = Mutex::new(None);
+
+/// Returns the parsed template file at `path`, calling `parse` only if the
+/// file wasn't parsed with the same syntax before or its contents changed
+///
+/// The lock isn't held while parsing, since `parse` panics on syntax errors,
+/// and derives in other threads may parse the same file at the same time.
+pub(crate) fn load<F>(path: &Path, syntax: &Syntax<'_>, parse: F) -> Arc<Parsed>
+where
+    F: for<'s> FnOnce(&'s str) -> Vec<WithSpan<Node<'s>>>,
+{
+    let source = get_template_source(path);
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    let hash = hasher.finish();
+
+    let key = (path.to_path_buf(), SyntaxKey::new(syntax));
+    {
+        let mut cache = CACHE.lock().unwrap_or_else(PoisonError::into_inner);
+        let cache = cache.get_or_insert_with(Cache::default);
+        match cache.files.get(&key) {
+            Some(entry) if entry.hash == hash => return Arc::clone(&entry.parsed),
+            _ => {}
+        }
+    }
+
+    let parsed = Arc::new(Parsed::new(source.into(), parse));
+    let mut cache = CACHE.lock().unwrap_or_else(PoisonError::into_inner);
+    let cache = cache.get_or_insert_with(Cache::default);
+    let entry = Entry {
+        hash,
+        parsed: Arc::clone(&parsed),
+    };
+    cache.files.insert(key, entry);
+    parsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::load;
+    use askama_shared::parser::parse;
+    use askama_shared::Syntax;
+
+    use std::cell::Cell;
+    use std::sync::Arc;
+    use std::{env, fs, process};
+
+    #[test]
+    fn test_load() {
+        let path = env::temp_dir().join(format!("askama-cache-{}.html", process::id()));
+        fs::write(&path, "{{ a }}\n").unwrap();
+
+        let parses = Cell::new(0);
+        let load = |syntax: &Syntax<'_>| {
+            load(&path, syntax, |src| {
+                parses.set(parses.get() + 1);
+                parse(src, syntax)
+            })
+        };
+
+        let syntax = Syntax::default();
+        let parsed = load(&syntax);
+        assert_eq!(parsed.source(), "{{ a }}");
+        assert!(Arc::ptr_eq(&load(&syntax), &parsed));
+        assert_eq!(parses.get(), 1);
+
+        let other = Syntax {
+            expr_start: "[[",
+            expr_end: "]]",
+            ..Syntax::default()
+        };
+        assert_eq!(load(&other).nodes().len(), 1);
+        assert_eq!(parses.get(), 2);
+
+        fs::write(&path, "{{ a }}{{ b }}\n").unwrap();
+        assert_eq!(load(&syntax).nodes().len(), 2);
+        assert_eq!(parses.get(), 3);
+
+        // Edits that keep the length of the file are picked up too
+        fs::write(&path, "{{ a }}{{ c }}\n").unwrap();
+        assert_eq!(load(&syntax).source(), "{{ a }}{{ c }}");
+        assert_eq!(parses.get(), 4);
+
+        // Parse trees in use outlive the entries they were replaced in
+        assert_eq!(parsed.nodes().len(), 1);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,18 +1,74 @@
 extern crate proc_macro;
 
+mod cache;
+
+use askama_shared::deps::{dependencies, DependencyKind};
 use askama_shared::heritage::{Context, Heritage};
 use askama_shared::input::{Print, Source, TemplateInput};
-use askama_shared::parser::{parse, Expr, Node};
-use askama_shared::{generator, get_template_source, read_config_file, Config, Integrations};
+use askama_shared::parser::{parse_recover, Expr, Node, WithSpan};
+use askama_shared::{generator, read_config_file, Config, Integrations, Syntax};
+use cache::Parsed;
 use proc_macro::TokenStream;
+use quote::ToTokens;
 
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 #[proc_macro_derive(Template, attributes(template))]
 pub fn derive_template(input: TokenStream) -> TokenStream {
     let ast: syn::DeriveInput = syn::parse(input).unwrap();
-    build_template(&ast).parse().unwrap()
+    let source_file = source_file();
+    match panic::catch_unwind(AssertUnwindSafe(|| {
+        build_template(&ast, source_file.as_deref())
+    })) {
+        Ok(code) => code.parse().unwrap(),
+        Err(err) => template_error(&ast, &*err).into(),
+    }
+}
+
+/// Returns the path of the Rust file containing the derive, if it is a file on
+/// disk, for templates with `relative = true`
+fn source_file() -> Option<PathBuf> {
+    let file = proc_macro::Span::call_site().local_file()?;
+    // The path is relative to the directory the compiler runs in
+    match env::current_dir() {
+        Ok(dir) => Some(dir.join(file)),
+        Err(_) => Some(file),
+    }
+}
+
+/// Turns a panic from parsing or generating the template into a
+/// `compile_error!()`, so that it is reported like any other compiler error
+///
+/// The error points at the template's `source` or `path` value, which is as
+/// close to the template as a span can get.
+fn template_error(ast: &syn::DeriveInput, err: &(dyn Any + Send)) -> proc_macro2::TokenStream {
+    let msg = match err.downcast_ref::<String>() {
+        Some(msg) => msg.as_str(),
+        None => err
+            .downcast_ref::<&str>()
+            .copied()
+            .unwrap_or("unknown error"),
+    };
+
+    let mut tokens = ast.ident.to_token_stream();
+    for attr in ast.attrs.iter().filter(|a| a.path.is_ident("template")) {
+        tokens = attr.to_token_stream();
+        if let Ok(syn::Meta::List(list)) = attr.parse_meta() {
+            for nested in list.nested {
+                if let syn::NestedMeta::Meta(syn::Meta::NameValue(pair)) = nested {
+                    if pair.path.is_ident("source") || pair.path.is_ident("path") {
+                        tokens = pair.lit.to_token_stream();
+                    }
+                }
+            }
+        }
+    }
+    syn::Error::new_spanned(tokens, msg).to_compile_error()
 }
 
 /// Takes a `syn::DeriveInput` and generates source code for it
@@ -22,37 +78,42 @@ pub fn derive_template(input: TokenStream) -> TokenStream {
 /// parsed, and the parse tree is fed to the code generator. Will print
 /// the parse tree and/or generated source according to the `print` key's
 /// value as passed to the `template()` attribute.
-fn build_template(ast: &syn::DeriveInput) -> String {
+fn build_template(ast: &syn::DeriveInput, source_file: Option<&Path>) -> String {
     let config_toml = read_config_file();
     let config = Config::new(&config_toml);
-    let input = TemplateInput::new(ast, &config);
-    let source: String = match input.source {
-        Source::Source(ref s) => s.clone(),
-        Source::Path(_) => get_template_source(&input.path),
+    let input = TemplateInput::new(ast, &config, source_file);
+    let root = match input.source {
+        Source::Source(ref s) => Arc::new(Parsed::new(s.as_str().into(), |src| {
+            parse_template(&input, &input.path, src, &input.syntax)
+        })),
+        Source::Path(_) => load_template(&input, &input.path),
     };
 
-    let mut sources = HashMap::new();
-    find_used_templates(&input, &mut sources, source);
-
     let mut parsed = HashMap::new();
-    for (path, src) in &sources {
-        parsed.insert(path, parse(src, input.syntax));
-    }
+    find_used_templates(&input, &mut parsed, root);
 
     let mut contexts = HashMap::new();
-    for (path, nodes) in &parsed {
-        contexts.insert(*path, Context::new(&input.config, path, nodes));
+    for (path, parsed) in &parsed {
+        let ctx = Context::new(input.config, path, parsed.source(), parsed.nodes());
+        contexts.insert(path, ctx);
     }
+    check_cycles(
+        &input,
+        &contexts,
+        &input.path,
+        &mut vec![],
+        &mut HashSet::new(),
+    );
 
     let ctx = &contexts[&input.path];
-    let heritage = if !ctx.blocks.is_empty() || ctx.extends.is_some() {
+    let heritage = if !ctx.blocks.is_empty() || ctx.extends.is_some() || !ctx.includes.is_empty() {
         Some(Heritage::new(ctx, &contexts))
     } else {
         None
     };
 
     if input.print == Print::Ast || input.print == Print::All {
-        eprintln!("{:?}", parsed[&input.path]);
+        eprintln!("{:?}", parsed[&input.path].nodes());
     }
 
     let code = generator::generate(&input, &contexts, &heritage, INTEGRATIONS);
@@ -62,26 +123,149 @@ fn build_template(ast: &syn::DeriveInput) -> String {
     code
 }
 
-fn find_used_templates(input: &TemplateInput, map: &mut HashMap<PathBuf, String>, source: String) {
-    let mut check = vec![(input.path.clone(), source)];
-    while let Some((path, source)) = check.pop() {
-        for n in parse(&source, input.syntax) {
-            match n {
-                Node::Extends(Expr::StrLit(extends)) => {
+fn find_used_templates(
+    input: &TemplateInput,
+    map: &mut HashMap<PathBuf, Arc<Parsed>>,
+    root: Arc<Parsed>,
+) {
+    let mut check = vec![(input.path.clone(), root)];
+    while let Some((path, parsed)) = check.pop() {
+        for n in parsed.nodes() {
+            match &**n {
+                Node::Extends(WithSpan {
+                    inner: Expr::StrLit(extends),
+                    ..
+                }) => {
                     let extends = input.config.find_template(extends, Some(&path));
-                    let source = get_template_source(&extends);
-                    check.push((extends, source));
+                    if !map.contains_key(&extends) && !check.iter().any(|(p, _)| *p == extends) {
+                        let parsed = load_template(input, &extends);
+                        check.push((extends, parsed));
+                    }
                 }
-                Node::Import(_, import, _) => {
+                // Templates may import each other, so each one is only loaded once
+                Node::Import(_, import, _) | Node::FromImport(_, import, _) => {
                     let import = input.config.find_template(import, Some(&path));
-                    let source = get_template_source(&import);
-                    check.push((import, source));
+                    if import != path
+                        && !map.contains_key(&import)
+                        && !check.iter().any(|(p, _)| *p == import)
+                    {
+                        let parsed = load_template(input, &import);
+                        check.push((import, parsed));
+                    }
                 }
                 _ => {}
             }
         }
-        map.insert(path, source);
+
+        // Included templates are parsed once here, no matter how many times
+        // they are included. Like the code generator, resolve them relative
+        // to the template being derived.
+        for include in find_includes(parsed.nodes()) {
+            let include = input.config.find_template(include, Some(&input.path));
+            if !map.contains_key(&include) && !check.iter().any(|(p, _)| *p == include) {
+                let parsed = load_template(input, &include);
+                check.push((include, parsed));
+            }
+        }
+
+        map.insert(path, parsed);
+    }
+}
+
+// Reads and parses a template file, or takes it from the cache
+fn load_template(input: &TemplateInput, path: &Path) -> Arc<Parsed> {
+    cache::load(path, &input.syntax, |src| {
+        parse_template(input, path, src, &input.syntax)
+    })
+}
+
+// Parses a template, naming the file (or the struct, for inline sources) in errors
+fn parse_template<'a>(
+    input: &TemplateInput,
+    path: &Path,
+    src: &'a str,
+    syntax: &Syntax<'_>,
+) -> Vec<WithSpan<Node<'a>>> {
+    #[cfg(test)]
+    tests::PARSES.with(|parses| parses.set(parses.get() + 1));
+
+    let (nodes, errors) = parse_recover(src, syntax);
+    if !errors.is_empty() {
+        let inline = matches!(input.source, Source::Source(_)) && path == input.path;
+        let name = if inline {
+            format!("template source of '{}'", input.ast.ident)
+        } else {
+            format!("template '{}'", path.display())
+        };
+        let errors: Vec<_> = errors
+            .into_iter()
+            .map(|mut err| {
+                if !inline {
+                    err.file = Some(path.to_path_buf());
+                }
+                err.to_string()
+            })
+            .collect();
+        panic!("failed to parse {}:\n\n{}", name, errors.join("\n\n"));
+    }
+    nodes
+}
+
+// Follows the `extends` and `include` tags from `path`, which the code generator
+// would follow forever if they led back to a template already on the `chain`.
+// Imports are left out: templates may import each other's macros.
+fn check_cycles(
+    input: &TemplateInput,
+    contexts: &HashMap<&PathBuf, Context>,
+    path: &Path,
+    chain: &mut Vec<PathBuf>,
+    done: &mut HashSet<PathBuf>,
+) {
+    if let Some(start) = chain.iter().position(|p| p == path) {
+        let names = chain[start..]
+            .iter()
+            .map(|p| p.as_path())
+            .chain(Some(path))
+            .map(|p| {
+                let name = input
+                    .config
+                    .dirs
+                    .iter()
+                    .find_map(|dir| p.strip_prefix(dir).ok())
+                    .unwrap_or(p);
+                name.display().to_string()
+            })
+            .collect::<Vec<_>>();
+        panic!("cyclic template dependency: {}", names.join(" -> "));
     }
+    let ctx = match contexts.get(&path.to_path_buf()) {
+        Some(ctx) if !done.contains(path) => ctx,
+        _ => return,
+    };
+
+    // Like the code generator, includes are resolved relative to the
+    // template being derived
+    let next = ctx.extends.iter().cloned().chain(
+        find_includes(ctx.nodes)
+            .into_iter()
+            .map(|include| input.config.find_template(include, Some(&input.path))),
+    );
+
+    chain.push(path.to_path_buf());
+    for next in next.collect::<Vec<_>>() {
+        check_cycles(input, contexts, &next, chain, done);
+    }
+    chain.pop();
+    done.insert(path.to_path_buf());
+}
+
+// Lists the templates included by `nodes`, at any depth
+fn find_includes<'a>(nodes: &'a [WithSpan<Node<'a>>]) -> Vec<&'a str> {
+    dependencies(nodes)
+        .into_iter()
+        .filter(|dep| dep.kind == DependencyKind::Include)
+        .map(|dep| dep.path)
+        .collect()
 }
 
 const INTEGRATIONS: Integrations = Integrations {
@@ -89,7 +273,127 @@ const INTEGRATIONS: Integrations = Integrations {
     gotham: cfg!(feature = "gotham"),
     iron: cfg!(feature = "iron"),
     mendes: cfg!(feature = "mendes"),
+    poem: cfg!(feature = "poem"),
     rocket: cfg!(feature = "rocket"),
+    salvo: cfg!(feature = "salvo"),
     tide: cfg!(feature = "tide"),
     warp: cfg!(feature = "warp"),
+    hot_reload: cfg!(feature = "hot-reload"),
+    rayon: cfg!(feature = "rayon"),
+    fragments: cfg!(feature = "fragments"),
 };
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::Cell;
+
+    thread_local! {
+        /// How many times `parse_template()` was called on this thread
+        pub(super) static PARSES: Cell<usize> = const { Cell::new(0) };
+    }
+
+    #[test]
+    fn test_include_parsed_once() {
+        let dir = env::temp_dir();
+        let name = format!("askama-include-{}.html", std::process::id());
+        std::fs::write(dir.join(&name), "included").unwrap();
+        let mut config = Config::new("");
+        config.dirs = vec![dir];
+
+        let find = |source: &str| {
+            let ast: syn::DeriveInput = syn::parse_str(&format!(
+                "#[template(source = {:?}, ext = \"txt\")] struct Twice;",
+                source
+            ))
+            .unwrap();
+            let input = TemplateInput::new(&ast, &config, None);
+            let root = Arc::new(Parsed::new(source.into(), |src| {
+                parse_template(&input, &input.path, src, &input.syntax)
+            }));
+            let mut parsed = HashMap::new();
+            find_used_templates(&input, &mut parsed, root);
+            parsed.keys().cloned().collect::<HashSet<_>>()
+        };
+
+        // The inline source and the included file are each parsed once
+        let source = format!("{{% include {:?} %}}{{% include {:?} %}}", name, name);
+        let included = config.find_template(&name, None);
+        let parsed = find(&source);
+        assert_eq!(parsed.len(), 2);
+        assert!(parsed.contains(&included));
+        assert_eq!(PARSES.with(Cell::get), 2);
+
+        // Other templates including it take it from the cache
+        assert!(find(&format!("{{% include {:?} %}}", name)).contains(&included));
+        assert_eq!(PARSES.with(Cell::get), 3);
+
+        std::fs::remove_file(&included).unwrap();
+    }
+
+    #[test]
+    fn test_cyclic_templates() {
+        let ast: syn::DeriveInput =
+            syn::parse_str(r#"#[template(path = "cycle-a.html")] struct Cycle;"#).unwrap();
+        let mut config = Config::new("");
+        config.dirs = vec![PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../testing/templates")];
+        let input = TemplateInput::new(&ast, &config, None);
+
+        let mut parsed = HashMap::new();
+        find_used_templates(&input, &mut parsed, load_template(&input, &input.path));
+        let contexts: HashMap<_, _> = parsed
+            .iter()
+            .map(|(path, parsed)| {
+                let ctx = Context::new(&config, path, parsed.source(), parsed.nodes());
+                (path, ctx)
+            })
+            .collect();
+
+        let err = panic::catch_unwind(AssertUnwindSafe(|| {
+            check_cycles(
+                &input,
+                &contexts,
+                &input.path,
+                &mut vec![],
+                &mut HashSet::new(),
+            )
+        }))
+        .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<String>().unwrap(),
+            "cyclic template dependency: cycle-a.html -> cycle-b.html -> cycle-a.html"
+        );
+    }
+
+    #[test]
+    fn test_template_error() {
+        let ast: syn::DeriveInput = syn::parse_str(
+            r#"#[template(source = "{{ a }}\n  {% if %}", ext = "txt")]
+            struct Broken;"#,
+        )
+        .unwrap();
+        let err = panic::catch_unwind(|| build_template(&ast, None)).unwrap_err();
+        let code = template_error(&ast, &*err).to_string();
+        assert!(code.starts_with("compile_error !"));
+        assert!(code.contains("failed to parse template source of 'Broken'"));
+        assert!(code.contains("--> 2:3"));
+    }
+
+    #[test]
+    fn test_relative_path() {
+        let ast: syn::DeriveInput =
+            syn::parse_str(r#"#[template(path = "hello.html", relative = true)] struct Rel;"#)
+                .unwrap();
+        let config = Config::new("");
+        let file = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../testing/templates/x.rs");
+        let input = TemplateInput::new(&ast, &config, Some(&file));
+        assert_eq!(input.path, file.with_file_name("hello.html"));
+
+        let err = panic::catch_unwind(|| TemplateInput::new(&ast, &config, None).path).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<String>().unwrap(),
+            "relative template path 'hello.html' used, but the location of the source file is unknown"
+        );
+    }
+}
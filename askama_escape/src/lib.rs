@@ -1,5 +1,7 @@
-use std::fmt::{self, Display, Formatter, Write};
-use std::str;
+#![no_std]
+
+use core::fmt::{self, Display, Formatter, Write};
+use core::str;
 
 pub struct MarkupDisplay<E, T>
 where
@@ -88,7 +90,7 @@ where
     escaper: E,
 }
 
-impl<'a, E> ::std::fmt::Display for Escaped<'a, E>
+impl<'a, E> ::core::fmt::Display for Escaped<'a, E>
 where
     E: Escaper,
 {
@@ -148,6 +150,171 @@ impl Escaper for Text {
     }
 }
 
+/// Escapes values for JavaScript string literals
+///
+/// Everything but ASCII letters, digits, `,`, `.` and `_` is written as a
+/// `\xHH` escape, as are the line and paragraph separators (as `\uHHHH`), so
+/// the output is also safe in HTML attributes such as `onclick`.
+pub struct Js;
+
+impl Escaper for Js {
+    fn write_escaped<W>(&self, mut fmt: W, string: &str) -> fmt::Result
+    where
+        W: Write,
+    {
+        for c in string.chars() {
+            match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | ',' | '.' | '_' => fmt.write_char(c)?,
+                c if c.is_ascii() => write!(fmt, "\\x{:02X}", c as u32)?,
+                '\u{2028}' | '\u{2029}' => write!(fmt, "\\u{:04X}", c as u32)?,
+                c => fmt.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Escapes values for CSS strings and property values
+///
+/// Everything but ASCII letters and digits is written as a `\HH ` escape.
+pub struct Css;
+
+impl Escaper for Css {
+    fn write_escaped<W>(&self, mut fmt: W, string: &str) -> fmt::Result
+    where
+        W: Write,
+    {
+        for c in string.chars() {
+            match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' => fmt.write_char(c)?,
+                c if c.is_ascii() => write!(fmt, "\\{:X} ", c as u32)?,
+                c => fmt.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Escapes values for URL path segments and query parameters
+///
+/// Every byte but ASCII letters, digits, `-`, `.`, `_` and `~` is
+/// percent-encoded.
+pub struct Url;
+
+impl Escaper for Url {
+    fn write_escaped<W>(&self, mut fmt: W, string: &str) -> fmt::Result
+    where
+        W: Write,
+    {
+        for b in string.bytes() {
+            match b {
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                    fmt.write_char(b as char)?
+                }
+                b => write!(fmt, "%{:02X}", b)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Escapes LaTeX special characters
+pub struct Latex;
+
+impl Escaper for Latex {
+    fn write_escaped<W>(&self, mut fmt: W, string: &str) -> fmt::Result
+    where
+        W: Write,
+    {
+        for c in string.chars() {
+            match c {
+                '\\' => fmt.write_str("\\textbackslash{}")?,
+                '^' => fmt.write_str("\\textasciicircum{}")?,
+                '~' => fmt.write_str("\\textasciitilde{}")?,
+                '{' | '}' | '$' | '&' | '#' | '_' | '%' => {
+                    fmt.write_char('\\')?;
+                    fmt.write_char(c)?;
+                }
+                c => fmt.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Escapes Markdown punctuation with backslashes, so values are shown as
+/// plain text
+pub struct Markdown;
+
+impl Escaper for Markdown {
+    fn write_escaped<W>(&self, mut fmt: W, string: &str) -> fmt::Result
+    where
+        W: Write,
+    {
+        for c in string.chars() {
+            if "\\`*_{}[]<>()#+-.!|~&".contains(c) {
+                fmt.write_char('\\')?;
+            }
+            fmt.write_char(c)?;
+        }
+        Ok(())
+    }
+}
+
+/// Escapes values for double-quoted CSV fields by doubling quotes
+///
+/// The quotes around the field are part of the template, as in
+/// `"{{ name }}",{{ count }}`.
+pub struct Csv;
+
+impl Escaper for Csv {
+    fn write_escaped<W>(&self, mut fmt: W, string: &str) -> fmt::Result
+    where
+        W: Write,
+    {
+        let bytes = string.as_bytes();
+        let mut start = 0;
+        for (i, b) in bytes.iter().enumerate() {
+            if *b == b'"' {
+                escaping_body!(start, i, fmt, bytes, "\"\"");
+            }
+        }
+        if start < bytes.len() {
+            fmt.write_str(unsafe { str::from_utf8_unchecked(&bytes[start..]) })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Escapes values for POSIX shell scripts, so each value is read as (part
+/// of) a single unquoted word
+///
+/// Characters other than ASCII letters, digits and `,./:=@_-+%` are escaped
+/// with a backslash, and newlines are quoted.
+pub struct Shell;
+
+impl Escaper for Shell {
+    fn write_escaped<W>(&self, mut fmt: W, string: &str) -> fmt::Result
+    where
+        W: Write,
+    {
+        for c in string.chars() {
+            match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' => {}
+                ',' | '.' | '/' | ':' | '=' | '@' | '_' | '-' | '+' | '%' => {}
+                '\n' => {
+                    fmt.write_str("'\n'")?;
+                    continue;
+                }
+                _ => fmt.write_char('\\')?,
+            }
+            fmt.write_char(c)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, PartialEq)]
 enum DisplayValue<T>
 where
@@ -167,7 +334,10 @@ const FLAG: u8 = b'>' - b'"';
 
 #[cfg(test)]
 mod tests {
+    extern crate std;
+
     use super::*;
+    use std::string::ToString;
     #[test]
     fn test_escape() {
         assert_eq!(escape("", Html).to_string(), "");
@@ -176,4 +346,40 @@ mod tests {
         assert_eq!(escape("<foo", Html).to_string(), "&lt;foo");
         assert_eq!(escape("bla&h", Html).to_string(), "bla&amp;h");
     }
+
+    #[test]
+    fn test_escape_contexts() {
+        assert_eq!(
+            escape("it's </script>\u{2028}é", Js).to_string(),
+            "it\\x27s\\x20\\x3C\\x2Fscript\\x3E\\u2028é"
+        );
+        assert_eq!(escape("a.b_c,1", Js).to_string(), "a.b_c,1");
+        assert_eq!(
+            escape("red;} body{", Css).to_string(),
+            "red\\3B \\7D \\20 body\\7B "
+        );
+        assert_eq!(escape("a b/é?&", Url).to_string(), "a%20b%2F%C3%A9%3F%26");
+        assert_eq!(escape("A-z.0_~", Url).to_string(), "A-z.0_~");
+    }
+
+    #[test]
+    fn test_escape_formats() {
+        assert_eq!(
+            escape("50% of $x_1 & {y} \\ ~^#", Latex).to_string(),
+            "50\\% of \\$x\\_1 \\& \\{y\\} \\textbackslash{} \\textasciitilde{}\\textasciicircum{}\\#"
+        );
+        assert_eq!(
+            escape("*not* [a](b) #1", Markdown).to_string(),
+            "\\*not\\* \\[a\\]\\(b\\) \\#1"
+        );
+        assert_eq!(
+            escape("say \"hi\", ok", Csv).to_string(),
+            "say \"\"hi\"\", ok"
+        );
+        assert_eq!(
+            escape("it's $HOME; a\nb", Shell).to_string(),
+            "it\\'s\\ \\$HOME\\;\\ a'\n'b"
+        );
+        assert_eq!(escape("a/b.c-1", Shell).to_string(), "a/b.c-1");
+    }
 }
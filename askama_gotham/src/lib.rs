@@ -20,3 +20,18 @@ pub fn respond<T: Template>(t: &T, ext: &str) -> Response<Body> {
             .unwrap(),
     }
 }
+
+/// Responds with the block named `block`, using its declared content type
+pub fn respond_block<T: Template>(t: &T, block: &str) -> Response<Body> {
+    match t.render_block(block) {
+        Ok(body) => Response::builder()
+            .status(StatusCode::OK)
+            .header("content-type", mime::block_content_type(t, block))
+            .body(body.into())
+            .unwrap(),
+        Err(_) => Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(vec![].into())
+            .unwrap(),
+    }
+}
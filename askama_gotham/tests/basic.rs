@@ -38,3 +38,32 @@ fn test_gotham() {
     let body = res.read_utf8_body().expect("failed to read response body");
     assert_eq!(&body, "Hello, world!");
 }
+
+#[derive(Template)]
+#[template(
+    source = "<p>{% block icon type \"image/svg+xml\" %}<svg></svg>{% endblock %}</p>",
+    ext = "html"
+)]
+struct IconTemplate;
+
+fn icon(state: State) -> (State, hyper::Response<hyper::Body>) {
+    (state, askama_gotham::respond_block(&IconTemplate, "icon"))
+}
+
+#[test]
+fn test_gotham_block() {
+    let test_server = TestServer::new(|| Ok(icon)).expect("Failed to mount test router");
+
+    let res = test_server
+        .client()
+        .get("http://localhost/")
+        .perform()
+        .expect("Failed to send request to gotham");
+
+    assert_eq!(res.status(), StatusCode::OK);
+    let content_type = res.headers().get("content-type").unwrap();
+    assert_eq!(content_type.to_str().unwrap(), "image/svg+xml");
+
+    let body = res.read_utf8_body().expect("failed to read response body");
+    assert_eq!(&body, "<svg></svg>");
+}
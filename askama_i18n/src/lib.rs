@@ -0,0 +1,464 @@
+//! Message translation for the `{% trans %}` tag in Askama templates
+//!
+//! Messages are written in the [Fluent](https://projectfluent.org/) syntax,
+//! with one resource per locale:
+//!
+//! ```text
+//! # Comments start with a hash
+//! -brand = Askama
+//! greeting = Hello, { $name }! Welcome to { -brand }.
+//! unread = { $count ->
+//!     [0] No new messages
+//!     [one] One new message
+//!    *[other] { $count } new messages
+//! }
+//! ```
+//!
+//! Messages are formatted with the `fluent-bundle` crate, so select
+//! expressions match numbers against the CLDR plural rules of the locale.
+//!
+//! The code generated for a template embeds the resources of all locales in a
+//! [`Bundles`] value, which parses them the first time a message is formatted.
+//! Message ids and arguments are checked against the resources when the
+//! template is compiled.
+
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+use std::sync::OnceLock;
+
+use fluent_bundle::concurrent::FluentBundle;
+use fluent_bundle::{FluentArgs, FluentResource, FluentValue};
+use fluent_syntax::ast::{Entry, Expression, InlineExpression, Pattern, PatternElement};
+use unic_langid::LanguageIdentifier;
+
+// Guards against cycles between messages that reference each other
+const MAX_DEPTH: usize = 16;
+
+/// The value of a message argument
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Str(String),
+    Num(f64),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Value::Str(ref s) => f.write_str(s),
+            Value::Num(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", n as i64),
+            Value::Num(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+/// Converts template expressions to message arguments
+///
+/// Numbers become [`Value::Num`], so that select expressions can match them
+/// against plural categories; strings become [`Value::Str`].
+pub trait ToValue {
+    fn to_value(&self) -> Value;
+}
+
+impl ToValue for str {
+    fn to_value(&self) -> Value {
+        Value::Str(self.to_owned())
+    }
+}
+
+impl ToValue for String {
+    fn to_value(&self) -> Value {
+        Value::Str(self.clone())
+    }
+}
+
+impl ToValue for Value {
+    fn to_value(&self) -> Value {
+        self.clone()
+    }
+}
+
+impl<T: ToValue + ?Sized> ToValue for &T {
+    fn to_value(&self) -> Value {
+        (**self).to_value()
+    }
+}
+
+macro_rules! impl_to_value_num {
+    ($($ty:ty),*) => {
+        $(
+            impl ToValue for $ty {
+                fn to_value(&self) -> Value {
+                    Value::Num(*self as f64)
+                }
+            }
+        )*
+    };
+}
+
+impl_to_value_num!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize, f32, f64);
+
+/// The messages and terms for a single locale
+pub struct Resource(FluentResource);
+
+impl fmt::Debug for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Resource").field(&self.0.source()).finish()
+    }
+}
+
+impl Resource {
+    /// Parses a resource, returning a description of the first error if any
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let line = |pos: usize| src[..pos.min(src.len())].matches('\n').count() + 1;
+        let res = FluentResource::try_new(src.to_owned()).map_err(|(_, errors)| {
+            let err = &errors[0];
+            format!("line {}: {}", line(err.pos.start), err.kind)
+        })?;
+
+        // A bundle would reject these when the template is rendered
+        let mut ids = HashSet::new();
+        for entry in res.entries() {
+            let id = match entry {
+                Entry::Message(msg) => msg.id.name.to_owned(),
+                Entry::Term(term) => format!("-{}", term.id.name),
+                _ => continue,
+            };
+            if !ids.insert(id.clone()) {
+                return Err(format!("`{}` is defined twice", id));
+            }
+        }
+        Ok(Resource(res))
+    }
+
+    fn message(&self, id: &str) -> Option<&Pattern<&str>> {
+        self.0.entries().find_map(|entry| match entry {
+            Entry::Message(msg) if msg.id.name == id => msg.value.as_ref(),
+            _ => None,
+        })
+    }
+
+    /// Whether the resource defines a message with the given id and a value
+    pub fn has_message(&self, id: &str) -> bool {
+        self.message(id).is_some()
+    }
+
+    /// The names of the variables a message uses, including those used by the
+    /// messages it references
+    pub fn message_args(&self, id: &str) -> Option<BTreeSet<String>> {
+        let pattern = self.message(id)?;
+        let mut args = BTreeSet::new();
+        self.collect_args(pattern, &mut args, 0);
+        Some(args)
+    }
+
+    fn collect_args(&self, pattern: &Pattern<&str>, args: &mut BTreeSet<String>, depth: usize) {
+        if depth > MAX_DEPTH {
+            return;
+        }
+        for elem in &pattern.elements {
+            if let PatternElement::Placeable { expression } = elem {
+                self.collect_expr_args(expression, args, depth);
+            }
+        }
+    }
+
+    fn collect_expr_args(
+        &self,
+        expr: &Expression<&str>,
+        args: &mut BTreeSet<String>,
+        depth: usize,
+    ) {
+        match expr {
+            Expression::Inline(inline) => self.collect_inline_args(inline, args, depth),
+            Expression::Select { selector, variants } => {
+                self.collect_inline_args(selector, args, depth);
+                for variant in variants {
+                    self.collect_args(&variant.value, args, depth + 1);
+                }
+            }
+        }
+    }
+
+    // The variables in terms are their arguments, not those of the message
+    fn collect_inline_args(
+        &self,
+        expr: &InlineExpression<&str>,
+        args: &mut BTreeSet<String>,
+        depth: usize,
+    ) {
+        match expr {
+            InlineExpression::VariableReference { id } => {
+                args.insert(id.name.to_owned());
+            }
+            InlineExpression::MessageReference { id, attribute } => {
+                let pattern = self.0.entries().find_map(|entry| match entry {
+                    Entry::Message(msg) if msg.id.name == id.name => match attribute {
+                        Some(attr) => msg
+                            .attributes
+                            .iter()
+                            .find(|a| a.id.name == attr.name)
+                            .map(|a| &a.value),
+                        None => msg.value.as_ref(),
+                    },
+                    _ => None,
+                });
+                if let Some(pattern) = pattern {
+                    self.collect_args(pattern, args, depth + 1);
+                }
+            }
+            InlineExpression::FunctionReference { arguments, .. }
+            | InlineExpression::TermReference {
+                arguments: Some(arguments),
+                ..
+            } => {
+                let named = arguments.named.iter().map(|arg| &arg.value);
+                for arg in arguments.positional.iter().chain(named) {
+                    self.collect_inline_args(arg, args, depth);
+                }
+            }
+            InlineExpression::Placeable { expression } => {
+                self.collect_expr_args(expression, args, depth + 1);
+            }
+            InlineExpression::StringLiteral { .. }
+            | InlineExpression::NumberLiteral { .. }
+            | InlineExpression::TermReference { .. } => {}
+        }
+    }
+}
+
+/// The resources of all locales, as embedded in the generated code
+pub struct Bundles {
+    fallback: &'static str,
+    sources: &'static [(&'static str, &'static str)],
+    parsed: OnceLock<Vec<(&'static str, FluentBundle<FluentResource>)>>,
+}
+
+impl Bundles {
+    /// Creates bundles for `(locale, source)` pairs, using the `fallback`
+    /// locale for messages that a locale doesn't define
+    pub const fn new(
+        fallback: &'static str,
+        sources: &'static [(&'static str, &'static str)],
+    ) -> Self {
+        Self {
+            fallback,
+            sources,
+            parsed: OnceLock::new(),
+        }
+    }
+
+    /// Formats the message `id` in the best match for `locale`
+    ///
+    /// Locales match exactly or by language, so that `de-AT` can use the
+    /// resource for `de`. If no matching resource defines the message, the
+    /// fallback locale is used, and if that doesn't define it either, the
+    /// message id is returned. Variables missing from `args` are rendered as
+    /// `{$name}`.
+    pub fn format(&self, locale: &str, id: &str, args: &[(&str, Value)]) -> String {
+        let parsed = self.parsed.get_or_init(|| {
+            self.sources
+                .iter()
+                .map(|&(locale, src)| (locale, bundle(locale, src)))
+                .collect()
+        });
+
+        let mut fluent_args = FluentArgs::with_capacity(args.len());
+        for (name, val) in args {
+            let val = match val {
+                Value::Str(s) => FluentValue::from(s.as_str()),
+                Value::Num(n) => FluentValue::from(*n),
+            };
+            fluent_args.set(*name, val);
+        }
+
+        let lang = locale.split(&['-', '_'][..]).next().unwrap_or("");
+        let candidates = [locale, lang, self.fallback];
+        for candidate in candidates.iter() {
+            let found = parsed
+                .iter()
+                .find(|(l, _)| l.eq_ignore_ascii_case(candidate));
+            if let Some((_, bundle)) = found {
+                if let Some(pattern) = bundle.get_message(id).and_then(|msg| msg.value()) {
+                    let mut errors = Vec::new();
+                    return bundle
+                        .format_pattern(pattern, Some(&fluent_args), &mut errors)
+                        .into_owned();
+                }
+            }
+        }
+        id.to_owned()
+    }
+}
+
+fn bundle(locale: &str, src: &str) -> FluentBundle<FluentResource> {
+    let langid = locale
+        .parse::<LanguageIdentifier>()
+        .unwrap_or_else(|err| panic!("invalid locale '{}': {}", locale, err));
+    let Resource(res) = Resource::parse(src)
+        .unwrap_or_else(|err| panic!("invalid resource for locale '{}': {}", locale, err));
+    let mut bundle = FluentBundle::new_concurrent(vec![langid]);
+    // Templates escape the output, so Unicode isolation marks aren't needed
+    bundle.set_use_isolating(false);
+    bundle
+        .add_resource(res)
+        .unwrap_or_else(|errs| panic!("invalid resource for locale '{}': {:?}", locale, errs));
+    bundle
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EN: &str = "# comment
+-brand = Askama
+greeting = Hello, { $name }! Welcome to { -brand }.
+unread = { $count ->
+    [0] No new messages
+    [one] One new message
+   *[other] { $count } new messages
+}
+nested = { greeting } { \"literal\" } { 42 }
+multi =
+    First line
+    second line
+";
+
+    static BUNDLES: Bundles = Bundles::new(
+        "en",
+        &[
+            ("en", EN),
+            ("de", "a = DE { $x }"),
+            (
+                "ar",
+                "n = { $n ->
+    [zero] zero
+    [one] one
+    [two] two
+    [few] few
+    [many] many
+   *[other] other
+}",
+            ),
+            (
+                "ru",
+                "n = { $n ->
+    [one] one
+    [few] few
+    [many] many
+   *[other] other
+}",
+            ),
+            (
+                "fr",
+                "n = { $n ->
+    [one] one
+   *[other] other
+}",
+            ),
+        ],
+    );
+
+    #[test]
+    fn test_format() {
+        let name = [("name", "Ada".to_value())];
+        assert_eq!(
+            BUNDLES.format("en", "greeting", &name),
+            "Hello, Ada! Welcome to Askama."
+        );
+        for (n, expected) in &[
+            (0, "No new messages"),
+            (1, "One new message"),
+            (7, "7 new messages"),
+        ] {
+            let args = [("count", n.to_value())];
+            assert_eq!(BUNDLES.format("en", "unread", &args), *expected);
+        }
+        assert_eq!(
+            BUNDLES.format("en", "nested", &name),
+            "Hello, Ada! Welcome to Askama. literal 42"
+        );
+        assert_eq!(
+            BUNDLES.format("en", "multi", &[]),
+            "First line\nsecond line"
+        );
+        assert_eq!(
+            BUNDLES.format("en", "greeting", &[]),
+            "Hello, {$name}! Welcome to Askama."
+        );
+    }
+
+    #[test]
+    fn test_message_args() {
+        let res = Resource::parse(EN).unwrap();
+        let args = |id| {
+            res.message_args(id)
+                .unwrap()
+                .into_iter()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(args("greeting"), vec!["name"]);
+        assert_eq!(args("unread"), vec!["count"]);
+        assert_eq!(args("nested"), vec!["name"]);
+        assert!(args("multi").is_empty());
+        assert!(res.has_message("greeting"));
+        assert!(!res.has_message("brand"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Resource::parse("a = { $x").is_err());
+        assert!(Resource::parse("a = { $x ->\n [one] x\n}").is_err());
+        assert!(Resource::parse("a = x\na = y").is_err());
+        assert!(Resource::parse("1a = x").is_err());
+        assert_eq!(
+            Resource::parse("a = x\nb = { $x").unwrap_err(),
+            "line 2: Expected a token starting with \"}\""
+        );
+    }
+
+    #[test]
+    fn test_plural_rules() {
+        let format = |locale, n: f64| BUNDLES.format(locale, "n", &[("n", n.to_value())]);
+        let cases: &[(&str, &[(f64, &str)])] = &[
+            (
+                "ar",
+                &[
+                    (0.0, "zero"),
+                    (1.0, "one"),
+                    (2.0, "two"),
+                    (3.0, "few"),
+                    (11.0, "many"),
+                    (100.0, "other"),
+                ],
+            ),
+            (
+                "ru",
+                &[
+                    (1.0, "one"),
+                    (21.0, "one"),
+                    (22.0, "few"),
+                    (12.0, "many"),
+                    (1.5, "other"),
+                ],
+            ),
+            ("fr", &[(0.0, "one"), (1.5, "one"), (2.0, "other")]),
+        ];
+        for (locale, cases) in cases {
+            for (n, expected) in cases.iter() {
+                assert_eq!(format(locale, *n), *expected, "{} {}", locale, n);
+            }
+        }
+    }
+
+    #[test]
+    fn test_bundles() {
+        let args = [("x", 1.to_value())];
+        assert_eq!(BUNDLES.format("de-AT", "a", &args), "DE 1");
+        assert_eq!(
+            BUNDLES.format("de", "greeting", &[]),
+            "Hello, {$name}! Welcome to Askama."
+        );
+        assert_eq!(BUNDLES.format("ja", "unread", &[]), "{$count} new messages");
+        assert_eq!(BUNDLES.format("fr", "c", &args), "c");
+    }
+}
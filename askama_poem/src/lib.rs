@@ -0,0 +1,30 @@
+pub use askama::*;
+pub use poem;
+
+use poem::http::StatusCode;
+use poem::Response;
+
+pub fn into_response<T: askama::Template>(t: &T, ext: &str) -> Response {
+    match t.render() {
+        Ok(body) => Response::builder()
+            .status(StatusCode::OK)
+            .content_type(mime::extension_to_mime_type(ext))
+            .body(body),
+        Err(_) => Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .finish(),
+    }
+}
+
+/// Responds with the block named `block`, using its declared content type
+pub fn into_block_response<T: askama::Template>(t: &T, block: &str) -> Response {
+    match t.render_block(block) {
+        Ok(body) => Response::builder()
+            .status(StatusCode::OK)
+            .content_type(mime::block_content_type(t, block))
+            .body(body),
+        Err(_) => Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .finish(),
+    }
+}
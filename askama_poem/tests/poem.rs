@@ -0,0 +1,37 @@
+use askama::Template;
+use poem::http::StatusCode;
+use poem::IntoResponse;
+
+#[derive(Template)]
+#[template(path = "hello.html")]
+struct HelloTemplate<'a> {
+    name: &'a str,
+}
+
+#[tokio::test]
+async fn test_poem() {
+    let res = HelloTemplate { name: "world" }.into_response();
+
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.content_type(), Some("text/html; charset=utf-8"));
+    assert_eq!(
+        res.into_body().into_string().await.unwrap(),
+        "Hello, world!"
+    );
+}
+
+#[derive(Template)]
+#[template(
+    source = "<p>{% block icon type \"image/svg+xml\" %}<svg></svg>{% endblock %}</p>",
+    ext = "html"
+)]
+struct IconTemplate;
+
+#[tokio::test]
+async fn test_poem_block() {
+    let res = askama_poem::into_block_response(&IconTemplate, "icon");
+
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.content_type(), Some("image/svg+xml"));
+    assert_eq!(res.into_body().into_string().await.unwrap(), "<svg></svg>");
+}
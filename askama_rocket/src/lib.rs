@@ -14,3 +14,16 @@ pub fn respond<T: Template>(t: &T, ext: &str) -> Result<'static> {
         .sized_body(Cursor::new(rsp))
         .ok()
 }
+
+/// Responds with the block named `block`, using its declared content type
+pub fn respond_block<T: Template>(t: &T, block: &str) -> Result<'static> {
+    let rsp = t
+        .render_block(block)
+        .map_err(|_| Status::InternalServerError)?;
+    let ctype = ContentType::parse_flexible(&mime::block_content_type(t, block))
+        .ok_or(Status::InternalServerError)?;
+    Response::build()
+        .header(ctype)
+        .sized_body(Cursor::new(rsp))
+        .ok()
+}
@@ -19,6 +19,18 @@ fn hello() -> HelloTemplate<'static> {
     HelloTemplate { name: "world" }
 }
 
+#[derive(Template)]
+#[template(
+    source = "<p>{% block icon type \"image/svg+xml\" %}<svg></svg>{% endblock %}</p>",
+    ext = "html"
+)]
+struct IconTemplate;
+
+#[get("/icon")]
+fn icon() -> rocket::response::Result<'static> {
+    askama_rocket::respond_block(&IconTemplate, "icon")
+}
+
 #[test]
 fn test_rocket() {
     let rocket = rocket::ignite().mount("/", routes![hello]);
@@ -28,3 +40,13 @@ fn test_rocket() {
     assert_eq!(rsp.content_type(), Some(ContentType::HTML));
     assert_eq!(rsp.body_string().unwrap(), "Hello, world!");
 }
+
+#[test]
+fn test_rocket_block() {
+    let rocket = rocket::ignite().mount("/", routes![icon]);
+    let client = Client::new(rocket).unwrap();
+    let mut rsp = client.get("/icon").dispatch();
+    assert_eq!(rsp.status(), Status::Ok);
+    assert_eq!(rsp.content_type(), Some(ContentType::SVG));
+    assert_eq!(rsp.body_string().unwrap(), "<svg></svg>");
+}
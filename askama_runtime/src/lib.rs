@@ -0,0 +1,936 @@
+//! Renders templates written in the Askama syntax at runtime
+//!
+//! The compile-time templates generated by `askama_derive` are the right
+//! choice for templates that ship with an application. Templates that are
+//! only known at runtime, like user-editable email templates, can instead be
+//! rendered with this crate, which evaluates the parsed template against any
+//! context that implements `serde::Serialize`:
+//!
+//! ```
+//! let ctx = serde_json::json!({ "name": "<world>", "items": [1, 2, 3] });
+//! let out = askama_runtime::render(
+//!     "Hello, {{ name }}!{% for i in items %} {{ i * 2 }}{% endfor %}",
+//!     &ctx,
+//! )
+//! .unwrap();
+//! assert_eq!(out, "Hello, &lt;world&gt;! 2 4 6");
+//! ```
+//!
+//! Values are represented as `serde_json::Value`s, so only the parts of the
+//! template language that make sense without Rust types are supported:
+//! literals, variables, field and index access, operators, tests, most of the
+//! built-in string filters and the `if`, `for`, `let`, `with`, `filter`,
+//! `block` and `raw` tags. Includes, inheritance, macros and `match` need the
+//! compile-time engine and are reported as errors. Integer overflow and
+//! ranges longer than [`MAX_RANGE_LEN`] are reported as errors as well.
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use askama_escape::{escape, Css, Html, Js, Text, Url};
+use askama_shared::filters;
+use askama_shared::parser::{self, Expr, Loop, Node, Target, WithSpan, WS};
+use askama_shared::Syntax;
+use serde::Serialize;
+use serde_json::{Map, Number, Value};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The most items a range such as `0..n` may have, so that a template can't
+/// make the renderer allocate without bounds
+pub const MAX_RANGE_LEN: usize = 1 << 20;
+
+/// Error returned when a template can't be rendered
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum Error {
+    /// the template source contains syntax errors
+    Parse(String),
+    /// the template could not be evaluated against the given context
+    Render(String),
+    /// the template uses a feature that needs the compile-time engine
+    Unsupported(String),
+    /// the context could not be converted to a JSON value
+    Json(serde_json::Error),
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            Error::Json(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Error::Parse(ref err) => write!(f, "template parse error: {}", err),
+            Error::Render(ref err) => write!(f, "{}", err),
+            Error::Unsupported(ref err) => write!(f, "{}", err),
+            Error::Json(ref err) => write!(f, "json conversion error: {}", err),
+        }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Json(err)
+    }
+}
+
+impl From<askama_shared::Error> for Error {
+    fn from(err: askama_shared::Error) -> Self {
+        Error::Render(err.to_string())
+    }
+}
+
+fn error<T>(msg: String) -> Result<T> {
+    Err(Error::Render(msg))
+}
+
+fn unsupported<T>(msg: &str) -> Result<T> {
+    Err(Error::Unsupported(msg.into()))
+}
+
+/// Support for the `hot-reload` feature of askama
+///
+/// Templates that implement `Serialize` are rendered through [`Engine`], other
+/// templates are left to the compiled code. Both traits are in scope in the
+/// generated code; method resolution picks `Serialized` when its bound holds
+/// and falls back to the impl on the reference otherwise.
+#[doc(hidden)]
+pub mod reload {
+    use super::{Engine, Result};
+    use serde::Serialize;
+
+    pub struct Reload<'a, T: ?Sized>(pub &'a T);
+
+    pub trait Serialized {
+        fn reload(&self, engine: &Engine<'_>, source: &str) -> Option<Result<String>>;
+    }
+
+    impl<T: Serialize + ?Sized> Serialized for Reload<'_, T> {
+        fn reload(&self, engine: &Engine<'_>, source: &str) -> Option<Result<String>> {
+            Some(engine.render(source, self.0))
+        }
+    }
+
+    pub trait Fallback {
+        fn reload(&self, _: &Engine<'_>, _: &str) -> Option<Result<String>> {
+            None
+        }
+    }
+
+    impl<T: ?Sized> Fallback for &Reload<'_, T> {}
+}
+
+/// Renders `source` with the default syntax, escaping expressions as HTML
+pub fn render<T: Serialize + ?Sized>(source: &str, context: &T) -> Result<String> {
+    Engine::new().render(source, context)
+}
+
+/// Renders templates with a configurable syntax and escaping mode
+#[derive(Debug, Default)]
+pub struct Engine<'a> {
+    syntax: Syntax<'a>,
+    no_escape: bool,
+}
+
+impl<'a> Engine<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Uses the given delimiters instead of the default ones
+    pub fn syntax(mut self, syntax: Syntax<'a>) -> Self {
+        self.syntax = syntax;
+        self
+    }
+
+    /// Sets whether expressions are HTML-escaped (the default) or not
+    pub fn escape(mut self, escape: bool) -> Self {
+        self.no_escape = !escape;
+        self
+    }
+
+    /// Renders `source` against `context`, which must serialize to a map
+    pub fn render<T: Serialize + ?Sized>(&self, source: &str, context: &T) -> Result<String> {
+        let (nodes, errors) = parser::parse_recover(source, &self.syntax);
+        if !errors.is_empty() {
+            let errors: Vec<_> = errors.iter().map(ToString::to_string).collect();
+            return Err(Error::Parse(errors.join("\n\n")));
+        }
+
+        let context = match serde_json::to_value(context)? {
+            Value::Object(map) => map,
+            Value::Null => Map::new(),
+            _ => return error("the template context must serialize to a map".into()),
+        };
+
+        let mut renderer = Renderer {
+            escape: !self.no_escape,
+            scopes: vec![context],
+            buf: String::new(),
+            next_ws: None,
+            skip_ws: false,
+        };
+        if let Flow::Break(_) | Flow::Continue(_) = renderer.handle(&nodes)? {
+            return error("`break` or `continue` used outside of a loop".into());
+        }
+        renderer.flush_ws(WS(false, false));
+        Ok(renderer.buf)
+    }
+}
+
+// How control leaves a list of nodes
+enum Flow<'a> {
+    Normal,
+    Break(Option<&'a str>),
+    Continue(Option<&'a str>),
+}
+
+struct Renderer<'a> {
+    // Whether expressions are HTML-escaped
+    escape: bool,
+    // Variable scopes, the outermost of which is the template context
+    scopes: Vec<Map<String, Value>>,
+    // The rendered output
+    buf: String,
+    // Whitespace from a literal, written out once the next node's prefix
+    // whitespace suppressor is known (see the generator)
+    next_ws: Option<&'a str>,
+    // Whether to skip the leading whitespace of the next literal
+    skip_ws: bool,
+}
+
+impl<'a> Renderer<'a> {
+    fn handle(&mut self, nodes: &'a [WithSpan<Node<'a>>]) -> Result<Flow<'a>> {
+        for node in nodes {
+            match **node {
+                Node::Lit(lws, val, rws) => self.visit_lit(lws, val, rws),
+                Node::Comment(ws, _) => self.handle_ws(ws),
+                Node::Expr(ws, ref expr) => {
+                    self.handle_ws(ws);
+                    self.write_expr(expr)?;
+                }
+                Node::LetDecl(ws, ref target) => {
+                    self.handle_ws(ws);
+                    let mut names = Vec::new();
+                    target.names(&mut names);
+                    for name in names {
+                        self.insert(name, Value::Null);
+                    }
+                }
+                Node::Let(ws, ref target, ref expr) => {
+                    self.handle_ws(ws);
+                    let val = self.eval(expr)?;
+                    self.bind(target, val)?;
+                }
+                Node::Do(ws, ref expr) => {
+                    self.handle_ws(ws);
+                    self.eval(expr)?;
+                }
+                Node::Cond(ref conds, ws) => {
+                    let flow = self.write_cond(conds, ws)?;
+                    if !matches!(flow, Flow::Normal) {
+                        return Ok(flow);
+                    }
+                }
+                Node::Loop(ref loop_block) => self.write_loop(loop_block)?,
+                Node::BlockDef(ref def) => {
+                    self.handle_ws(def.ws1);
+                    let flow = self.scoped(&def.nodes)?;
+                    self.handle_ws(def.ws2);
+                    if !matches!(flow, Flow::Normal) {
+                        return Ok(flow);
+                    }
+                }
+                Node::With(ws1, ref vars, ref nodes, ws2) => {
+                    self.handle_ws(ws1);
+                    self.scopes.push(Map::new());
+                    for (name, expr) in vars {
+                        let val = self.eval(expr)?;
+                        self.insert(name, val);
+                    }
+                    let flow = self.handle(nodes)?;
+                    self.handle_ws(ws2);
+                    self.scopes.pop();
+                    if !matches!(flow, Flow::Normal) {
+                        return Ok(flow);
+                    }
+                }
+                Node::FilterBlock(ws1, ref filters, ref nodes, ws2) => {
+                    self.handle_ws(ws1);
+                    let body = self.capture(nodes, ws2)?;
+                    let mut val = Value::String(body);
+                    for (name, args) in filters {
+                        val = self.filter(name, val, args)?;
+                    }
+                    self.buf.push_str(&display(&val));
+                }
+                Node::LetBlock(ws1, name, ref nodes, ws2) => {
+                    self.handle_ws(ws1);
+                    let body = self.capture(nodes, ws2)?;
+                    self.insert(name, Value::String(body));
+                }
+                Node::Raw(ws1, contents, ws2) => {
+                    self.handle_ws(ws1);
+                    self.buf.push_str(contents);
+                    self.handle_ws(ws2);
+                }
+                Node::Break(ws, label) => {
+                    self.handle_ws(ws);
+                    return Ok(Flow::Break(label));
+                }
+                Node::Continue(ws, label) => {
+                    self.handle_ws(ws);
+                    return Ok(Flow::Continue(label));
+                }
+                Node::Extends(_) => return unsupported("`extends` is not supported at runtime"),
+                Node::Include(..) => return unsupported("`include` is not supported at runtime"),
+                Node::Import(..) | Node::FromImport(..) => {
+                    return unsupported("`import` is not supported at runtime");
+                }
+                Node::Macro(..) | Node::Call(..) | Node::CallBlock(..) => {
+                    return unsupported("macros are not supported at runtime");
+                }
+                Node::Match(..) => return unsupported("`match` is not supported at runtime"),
+                Node::Trans(..) => return unsupported("`trans` is not supported at runtime"),
+                Node::AutoEscape(..) => {
+                    return unsupported("`autoescape` is not supported at runtime");
+                }
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    // Runs `nodes` in a new scope
+    fn scoped(&mut self, nodes: &'a [WithSpan<Node<'a>>]) -> Result<Flow<'a>> {
+        self.scopes.push(Map::new());
+        let flow = self.handle(nodes);
+        self.scopes.pop();
+        flow
+    }
+
+    // Renders `nodes` into a separate string, as for `filter` and `let` blocks
+    fn capture(&mut self, nodes: &'a [WithSpan<Node<'a>>], ws: WS) -> Result<String> {
+        let outer = std::mem::take(&mut self.buf);
+        let flow = self.scoped(nodes);
+        self.handle_ws(ws);
+        let body = std::mem::replace(&mut self.buf, outer);
+        match flow? {
+            Flow::Normal => Ok(body),
+            _ => error("`break` or `continue` can't leave a block that is captured".into()),
+        }
+    }
+
+    fn write_cond(&mut self, conds: &'a [parser::Cond<'a>], ws: WS) -> Result<Flow<'a>> {
+        if let Some((first, _, _)) = conds.first() {
+            self.flush_ws(*first);
+        }
+        for (i, (cws, cond, nodes)) in conds.iter().enumerate() {
+            let taken = match cond {
+                Some(expr) => truthy(&self.eval(expr)?),
+                None => true,
+            };
+            if !taken {
+                continue;
+            }
+
+            self.prepare_ws(*cws);
+            let flow = self.scoped(nodes)?;
+            let end = conds.get(i + 1).map_or(ws, |(next, _, _)| *next);
+            self.flush_ws(end);
+            self.prepare_ws(ws);
+            return Ok(flow);
+        }
+        self.prepare_ws(ws);
+        Ok(Flow::Normal)
+    }
+
+    fn write_loop(&mut self, loop_block: &'a Loop<'a>) -> Result<()> {
+        self.flush_ws(loop_block.ws1);
+        let items = match self.eval(&loop_block.iter)? {
+            Value::Array(items) => items,
+            Value::Object(map) => map
+                .into_iter()
+                .map(|(k, v)| Value::Array(vec![Value::String(k), v]))
+                .collect(),
+            Value::String(s) => s.chars().map(|c| Value::String(c.into())).collect(),
+            Value::Null => Vec::new(),
+            val => return error(format!("can't iterate over {}", val)),
+        };
+
+        let len = items.len();
+        for (i, item) in items.into_iter().enumerate() {
+            self.scopes.push(Map::new());
+            let mut info = Map::new();
+            info.insert("index".into(), (i + 1).into());
+            info.insert("index0".into(), i.into());
+            info.insert("first".into(), (i == 0).into());
+            info.insert("last".into(), (i + 1 == len).into());
+            self.insert("loop", Value::Object(info));
+            if let Some(index) = loop_block.index {
+                self.insert(index, i.into());
+            }
+            self.bind(&loop_block.var, item)?;
+
+            self.prepare_ws(loop_block.ws1);
+            let flow = self.handle(&loop_block.body);
+            self.scopes.pop();
+            let flow = flow?;
+            self.flush_ws(loop_block.ws2);
+
+            let (brk, label) = match flow {
+                Flow::Normal => continue,
+                Flow::Break(label) => (true, label),
+                Flow::Continue(label) => (false, label),
+            };
+            match label {
+                Some(label) if Some(label) != loop_block.label => {
+                    return error(format!("no enclosing loop labeled '{}'", label));
+                }
+                _ if brk => break,
+                _ => continue,
+            }
+        }
+        self.flush_ws(loop_block.ws2);
+        self.prepare_ws(loop_block.ws2);
+        Ok(())
+    }
+
+    fn write_expr(&mut self, expr: &Expr<'_>) -> Result<()> {
+        let val = self.eval(expr)?;
+        let safe = match *expr {
+            Expr::Filter(name, _) => {
+                name == "safe" || name == "escape" || name == "e" || name == "json"
+            }
+            _ => false,
+        };
+        let s = display(&val);
+        if self.escape && !safe {
+            self.buf.push_str(&escape(&s, Html).to_string());
+        } else {
+            self.buf.push_str(&s);
+        }
+        Ok(())
+    }
+
+    fn bind(&mut self, target: &Target<'a>, val: Value) -> Result<()> {
+        match *target {
+            Target::Name(name) => self.insert(name, val),
+            Target::Tuple(_, ref targets) => {
+                let mut items = match val {
+                    Value::Array(items) => items,
+                    val => return error(format!("can't destructure {} as a tuple", val)),
+                };
+                let rest = targets.iter().position(|t| *t == Target::Rest);
+                let (front, back) = match rest {
+                    Some(pos) => (&targets[..pos], &targets[pos + 1..]),
+                    None if targets.len() == items.len() => (&targets[..], &targets[..0]),
+                    None => {
+                        return error(format!(
+                            "expected a tuple of {} elements, found {}",
+                            targets.len(),
+                            items.len()
+                        ));
+                    }
+                };
+                if front.len() + back.len() > items.len() {
+                    return error("not enough elements to destructure".into());
+                }
+                let tail = items.split_off(items.len() - back.len());
+                for (target, item) in front.iter().zip(items) {
+                    self.bind(target, item)?;
+                }
+                for (target, item) in back.iter().zip(tail) {
+                    self.bind(target, item)?;
+                }
+            }
+            Target::Struct(_, ref fields) => {
+                let mut map = match val {
+                    Value::Object(map) => map,
+                    val => return error(format!("can't destructure {} as a struct", val)),
+                };
+                for (field, target) in fields {
+                    match map.remove(*field) {
+                        Some(item) => self.bind(target, item)?,
+                        None => return error(format!("no field `{}` to destructure", field)),
+                    }
+                }
+            }
+            Target::Rest => {}
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, name: &str, val: Value) {
+        self.scopes.last_mut().unwrap().insert(name.into(), val);
+    }
+
+    fn lookup(&self, name: &str) -> Result<Value> {
+        match self.scopes.iter().rev().find_map(|scope| scope.get(name)) {
+            Some(val) => Ok(val.clone()),
+            None => error(format!("unknown variable `{}`", name)),
+        }
+    }
+
+    /* Expressions */
+
+    fn eval(&mut self, expr: &Expr<'_>) -> Result<Value> {
+        Ok(match *expr {
+            Expr::BoolLit(s) => Value::Bool(s == "true"),
+            Expr::NumLit(s) => num_lit(s)?,
+            Expr::StrLit(s) | Expr::CharLit(s) => Value::String(unescape(s)?),
+            Expr::RawStrLit(s) => {
+                let s = s.trim_start_matches('r').trim_matches('#');
+                Value::String(s[1..s.len() - 1].into())
+            }
+            Expr::Var(name) => self.lookup(name)?,
+            Expr::Array(ref elems) => {
+                Value::Array(elems.iter().map(|e| self.eval(e)).collect::<Result<_>>()?)
+            }
+            Expr::Attr(ref obj, attr) => {
+                let obj = self.eval(obj)?;
+                attr_of(&obj, attr)?
+            }
+            Expr::Index(ref obj, ref key) => {
+                let obj = self.eval(obj)?;
+                let key = self.eval(key)?;
+                match key {
+                    Value::String(ref key) => attr_of(&obj, key)?,
+                    ref key => attr_of(&obj, &display(key))?,
+                }
+            }
+            Expr::Filter(name, ref args) => match args.split_first() {
+                Some((first, rest)) => {
+                    let val = self.eval(first)?;
+                    self.filter(name, val, rest)?
+                }
+                None => return error(format!("the '{}' filter needs an argument", name)),
+            },
+            Expr::Test(name, ref args) => Value::Bool(self.test(name, args)?),
+            Expr::Unary(op, ref inner) => {
+                let val = self.eval(inner)?;
+                match (op, val) {
+                    ("!", val) => Value::Bool(!truthy(&val)),
+                    ("-", Value::Number(n)) => match n.as_i64() {
+                        Some(i) => match i.checked_neg() {
+                            Some(i) => i.into(),
+                            None => return error("arithmetic overflow in `-`".into()),
+                        },
+                        None => number(-n.as_f64().unwrap_or_default()),
+                    },
+                    (op, val) => return error(format!("can't apply `{}` to {}", op, val)),
+                }
+            }
+            Expr::As(ref inner, ty) => {
+                let val = self.eval(inner)?;
+                let f = match val {
+                    Value::Number(ref n) => n.as_f64().unwrap_or_default(),
+                    Value::Bool(b) => f64::from(u8::from(b)),
+                    ref val => return error(format!("can't cast {} as {}", val, ty)),
+                };
+                match ty {
+                    "f32" | "f64" => number(f),
+                    _ if val.is_i64() || val.is_u64() => val,
+                    _ => (f as i64).into(),
+                }
+            }
+            Expr::BinOp(op, ref left, ref right) => self.binop(op, left, right)?,
+            Expr::Range(op, Some(ref start), Some(ref end)) => {
+                let start = self.eval_int(start)?;
+                let end = self.eval_int(end)?;
+                let len = i128::from(end) - i128::from(start) + i128::from(op == "..=");
+                if len > MAX_RANGE_LEN as i128 {
+                    return error(format!(
+                        "range {}{}{} is too long; the limit is {} items",
+                        start, op, end, MAX_RANGE_LEN
+                    ));
+                }
+                let items = (0..len.max(0) as i64).map(|n| Value::from(start + n));
+                Value::Array(items.collect())
+            }
+            Expr::Group(ref inner) | Expr::Try(ref inner) => self.eval(inner)?,
+            Expr::MethodCall(ref obj, method, ref args) => self.method(obj, method, args)?,
+            Expr::IfElse(ref cond, ref then, ref otherwise) => {
+                if truthy(&self.eval(cond)?) {
+                    self.eval(then)?
+                } else {
+                    self.eval(otherwise)?
+                }
+            }
+            Expr::Range(..) => return error("only bounded ranges are supported".into()),
+            Expr::Path(_) | Expr::VarCall(..) | Expr::PathCall(..) | Expr::RustMacro(..) => {
+                return unsupported("Rust paths and calls are not supported at runtime");
+            }
+            Expr::NamedArgument(name, _) => {
+                return error(format!("unexpected named argument `{}`", name));
+            }
+        })
+    }
+
+    fn eval_int(&mut self, expr: &Expr<'_>) -> Result<i64> {
+        match self.eval(expr)? {
+            Value::Number(ref n) if n.is_i64() => Ok(n.as_i64().unwrap()),
+            val => error(format!("expected an integer, found {}", val)),
+        }
+    }
+
+    fn binop(&mut self, op: &str, left: &Expr<'_>, right: &Expr<'_>) -> Result<Value> {
+        let left = self.eval(left)?;
+        match op {
+            "&&" if !truthy(&left) => return Ok(Value::Bool(false)),
+            "||" if truthy(&left) => return Ok(Value::Bool(true)),
+            "??" if !left.is_null() => return Ok(left),
+            _ => {}
+        }
+        let right = self.eval(right)?;
+
+        Ok(match op {
+            "&&" | "||" => Value::Bool(truthy(&right)),
+            "??" => right,
+            "~" => Value::String(display(&left) + &display(&right)),
+            "==" => Value::Bool(equals(&left, &right)),
+            "!=" => Value::Bool(!equals(&left, &right)),
+            "<" | "<=" | ">" | ">=" => {
+                let ord = match (&left, &right) {
+                    (Value::Number(a), Value::Number(b)) => a
+                        .as_f64()
+                        .unwrap_or_default()
+                        .partial_cmp(&b.as_f64().unwrap_or_default()),
+                    (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+                    _ => None,
+                };
+                let ord = match ord {
+                    Some(ord) => ord,
+                    None => return error(format!("can't compare {} and {}", left, right)),
+                };
+                Value::Bool(match op {
+                    "<" => ord.is_lt(),
+                    "<=" => ord.is_le(),
+                    ">" => ord.is_gt(),
+                    _ => ord.is_ge(),
+                })
+            }
+            _ => match (left.as_i64(), right.as_i64()) {
+                (Some(a), Some(b)) => {
+                    let res = match op {
+                        "+" => a.checked_add(b),
+                        "-" => a.checked_sub(b),
+                        "*" => a.checked_mul(b),
+                        "/" => a.checked_div(b),
+                        "%" => a.checked_rem(b),
+                        "&" => Some(a & b),
+                        "|" => Some(a | b),
+                        "^" => Some(a ^ b),
+                        "<<" => u32::try_from(b).ok().and_then(|b| a.checked_shl(b)),
+                        ">>" => u32::try_from(b).ok().and_then(|b| a.checked_shr(b)),
+                        _ => return error(format!("unsupported operator `{}`", op)),
+                    };
+                    match res {
+                        Some(res) => res.into(),
+                        None => return error(format!("arithmetic overflow in `{}`", op)),
+                    }
+                }
+                _ => match (left.as_f64(), right.as_f64()) {
+                    (Some(a), Some(b)) => number(match op {
+                        "+" => a + b,
+                        "-" => a - b,
+                        "*" => a * b,
+                        "/" => a / b,
+                        "%" => a % b,
+                        _ => return error(format!("`{}` needs integer operands", op)),
+                    }),
+                    _ => return error(format!("can't apply `{}` to {} and {}", op, left, right)),
+                },
+            },
+        })
+    }
+
+    fn method(
+        &mut self,
+        obj: &Expr<'_>,
+        method: &str,
+        args: &[WithSpan<Expr<'_>>],
+    ) -> Result<Value> {
+        if let (Expr::Var("loop"), "cycle") = (obj, method) {
+            let index = attr_of(&self.lookup("loop")?, "index0")?;
+            return match (args, index.as_u64()) {
+                ([arg], Some(index)) => match self.eval(arg)? {
+                    Value::Array(ref items) if !items.is_empty() => {
+                        Ok(items[index as usize % items.len()].clone())
+                    }
+                    _ => error("loop.cycle() expects a non-empty array".into()),
+                },
+                _ => error("loop.cycle() expects exactly one argument".into()),
+            };
+        }
+
+        let val = self.eval(obj)?;
+        let args = args
+            .iter()
+            .map(|arg| self.eval(arg))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(match (method, &args[..]) {
+            ("len", []) => length(&val)?.into(),
+            ("is_empty", []) => Value::Bool(length(&val)? == 0),
+            ("contains", [needle]) => Value::Bool(match val {
+                Value::String(ref s) => s.contains(&display(needle)),
+                Value::Array(ref items) => items.iter().any(|item| equals(item, needle)),
+                Value::Object(ref map) => map.contains_key(&display(needle)),
+                _ => return error(format!("can't call contains() on {}", val)),
+            }),
+            ("to_string", []) => Value::String(display(&val)),
+            _ => {
+                return Err(Error::Unsupported(format!(
+                    "unsupported method `{}`",
+                    method
+                )))
+            }
+        })
+    }
+
+    fn test(&mut self, name: &str, args: &[WithSpan<Expr<'_>>]) -> Result<bool> {
+        let mut vals = Vec::with_capacity(args.len());
+        for arg in args {
+            vals.push(self.eval(arg)?);
+        }
+        Ok(match (name, &vals[..]) {
+            ("some", [val]) => !val.is_null(),
+            ("none", [val]) => val.is_null(),
+            ("empty", [val]) => length(val)? == 0,
+            ("odd", [val]) => self.int_arg(val)? % 2 != 0,
+            ("even", [val]) => self.int_arg(val)? % 2 == 0,
+            ("divisibleby", [val, divisor]) => match self.int_arg(divisor)? {
+                0 => false,
+                divisor => self.int_arg(val)?.wrapping_rem(divisor) == 0,
+            },
+            _ => return Err(Error::Unsupported(format!("unsupported test `{}`", name))),
+        })
+    }
+
+    fn int_arg(&self, val: &Value) -> Result<i64> {
+        match val.as_i64() {
+            Some(i) => Ok(i),
+            None => error(format!("expected an integer, found {}", val)),
+        }
+    }
+
+    /* Filters */
+
+    fn filter(&mut self, name: &str, val: Value, args: &[WithSpan<Expr<'_>>]) -> Result<Value> {
+        let mut vals = Vec::with_capacity(args.len());
+        for arg in args {
+            vals.push(self.eval(arg)?);
+        }
+        let s = || display(&val);
+        Ok(match (name, &vals[..]) {
+            ("safe", []) => val,
+            ("escape", []) | ("e", []) => Value::String(escape(&s(), Html).to_string()),
+            ("escape", [Value::String(context)]) | ("e", [Value::String(context)]) => {
+                Value::String(match context.as_str() {
+                    "html" => escape(&s(), Html).to_string(),
+                    "js" => escape(&s(), Js).to_string(),
+                    "css" => escape(&s(), Css).to_string(),
+                    "url" => escape(&s(), Url).to_string(),
+                    _ => return error(format!("unknown escaping context '{}'", context)),
+                })
+            }
+            ("lower", []) | ("lowercase", []) => filters::lower(&s())?.into(),
+            ("upper", []) | ("uppercase", []) => filters::upper(&s())?.into(),
+            ("trim", []) => filters::trim(&s())?.into(),
+            ("capitalize", []) => filters::capitalize(&s())?.into(),
+            ("linebreaks", []) => filters::linebreaks(&s())?.into(),
+            ("linebreaksbr", []) => filters::linebreaksbr(&s())?.into(),
+            ("wordcount", []) => filters::wordcount(&s())?.into(),
+            ("truncate", [len]) => {
+                filters::truncate(&s(), &self.usize_arg(len)?, &"...", &false)?.into()
+            }
+            ("indent", [width]) => filters::indent(&s(), &self.usize_arg(width)?, &false)?.into(),
+            ("wordwrap", [width]) => filters::wordwrap(&s(), &self.usize_arg(width)?)?.into(),
+            ("center", [width]) => filters::center(&s(), &self.usize_arg(width)?)?.into(),
+            ("repeat", [n]) => filters::repeat(&s(), &self.usize_arg(n)?)?.into(),
+            ("length", []) => length(&val)?.into(),
+            ("abs", []) => match val.as_i64() {
+                Some(i) => i.unsigned_abs().into(),
+                None => number(val.as_f64().unwrap_or_default().abs()),
+            },
+            // Like the compiled filter, but JSON has no options holding values
+            ("default", [fallback]) => match val {
+                Value::Null | Value::Bool(_) | Value::Number(_) | Value::String(_)
+                    if !truthy(&val) =>
+                {
+                    fallback.clone()
+                }
+                val => val,
+            },
+            ("join", [sep]) => match val {
+                Value::Array(ref items) => {
+                    let items = items.iter().map(display).collect::<Vec<_>>();
+                    Value::String(items.join(&display(sep)))
+                }
+                _ => return error(format!("can't join {}", val)),
+            },
+            // Script-safe like the compiled filter, since the output isn't escaped
+            ("json", []) => Value::String(filters::json(Text, &val, &2)?.to_string()),
+            ("json", [indent]) => {
+                Value::String(filters::json(Text, &val, &self.usize_arg(indent)?)?.to_string())
+            }
+            _ => return Err(Error::Unsupported(format!("unsupported filter `{}`", name))),
+        })
+    }
+
+    fn usize_arg(&self, val: &Value) -> Result<usize> {
+        match val.as_u64() {
+            Some(n) => Ok(n as usize),
+            None => error(format!("expected a non-negative integer, found {}", val)),
+        }
+    }
+
+    /* Whitespace handling, mirroring the generator */
+
+    fn visit_lit(&mut self, lws: &'a str, val: &'a str, rws: &'a str) {
+        if !lws.is_empty() {
+            if self.skip_ws {
+                self.skip_ws = false;
+            } else if val.is_empty() {
+                self.next_ws = Some(lws);
+            } else {
+                self.buf.push_str(lws);
+            }
+        }
+        self.buf.push_str(val);
+        if !rws.is_empty() {
+            self.next_ws = Some(rws);
+        }
+    }
+
+    fn handle_ws(&mut self, ws: WS) {
+        self.flush_ws(ws);
+        self.prepare_ws(ws);
+    }
+
+    fn flush_ws(&mut self, ws: WS) {
+        if let Some(val) = self.next_ws.take() {
+            if !ws.0 {
+                self.buf.push_str(val);
+            }
+        }
+    }
+
+    fn prepare_ws(&mut self, ws: WS) {
+        self.skip_ws = ws.1;
+    }
+}
+
+/* Values */
+
+fn display(val: &Value) -> String {
+    match *val {
+        Value::Null => String::new(),
+        Value::String(ref s) => s.clone(),
+        ref val => val.to_string(),
+    }
+}
+
+fn truthy(val: &Value) -> bool {
+    match *val {
+        Value::Null => false,
+        Value::Bool(b) => b,
+        Value::Number(ref n) => n.as_f64() != Some(0.0),
+        Value::String(ref s) => !s.is_empty(),
+        Value::Array(ref items) => !items.is_empty(),
+        Value::Object(ref map) => !map.is_empty(),
+    }
+}
+
+fn equals(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64() == b.as_f64(),
+        _ => left == right,
+    }
+}
+
+fn length(val: &Value) -> Result<usize> {
+    match *val {
+        Value::String(ref s) => Ok(s.chars().count()),
+        Value::Array(ref items) => Ok(items.len()),
+        Value::Object(ref map) => Ok(map.len()),
+        Value::Null => Ok(0),
+        ref val => error(format!("{} has no length", val)),
+    }
+}
+
+fn number(f: f64) -> Value {
+    Number::from_f64(f).map_or(Value::Null, Value::Number)
+}
+
+fn attr_of(obj: &Value, attr: &str) -> Result<Value> {
+    let found = match *obj {
+        Value::Object(ref map) => map.get(attr),
+        Value::Array(ref items) => attr.parse::<usize>().ok().and_then(|i| items.get(i)),
+        _ => None,
+    };
+    match found {
+        Some(val) => Ok(val.clone()),
+        None => error(format!("no field `{}` in {}", attr, obj)),
+    }
+}
+
+fn num_lit(s: &str) -> Result<Value> {
+    let digits = s.replace('_', "");
+    let digits = digits.trim_end_matches(|c: char| c.is_ascii_alphabetic() && c != 'e');
+    if let Ok(i) = digits.parse::<i64>() {
+        return Ok(i.into());
+    }
+    match digits.parse::<f64>() {
+        Ok(f) => Ok(number(f)),
+        Err(_) => error(format!("invalid number literal `{}`", s)),
+    }
+}
+
+// Resolves the escape sequences in the contents of a string or char literal
+fn unescape(s: &str) -> Result<String> {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some('0') => out.push('\0'),
+            Some(c @ '\\') | Some(c @ '"') | Some(c @ '\'') => out.push(c),
+            Some('x') => {
+                let hex = chars.by_ref().take(2).collect::<String>();
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(b) if b < 0x80 => out.push(b as char),
+                    _ => return error(format!("invalid escape in `{}`", s)),
+                }
+            }
+            Some('u') => {
+                let hex = chars
+                    .by_ref()
+                    .skip(1)
+                    .take_while(|&c| c != '}')
+                    .collect::<String>();
+                match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                    Some(c) => out.push(c),
+                    None => return error(format!("invalid escape in `{}`", s)),
+                }
+            }
+            Some('\n') => {
+                let rest = chars.as_str().trim_start();
+                chars = rest.chars();
+            }
+            _ => return error(format!("invalid escape in `{}`", s)),
+        }
+    }
+    Ok(out)
+}
@@ -0,0 +1,140 @@
+use askama_runtime::{render, Engine, Error};
+use askama_shared::Syntax;
+use serde::Serialize;
+use serde_json::json;
+
+#[derive(Serialize)]
+struct User<'a> {
+    name: &'a str,
+    roles: Vec<&'a str>,
+}
+
+#[test]
+fn test_struct_context() {
+    let user = User {
+        name: "Ada",
+        roles: vec!["admin", "dev"],
+    };
+    let out = render(
+        "{{ name|upper }}: {{ roles|join(\", \") }} ({{ roles.len() }})",
+        &user,
+    )
+    .unwrap();
+    assert_eq!(out, "ADA: admin, dev (2)");
+}
+
+#[test]
+fn test_escaping() {
+    let ctx = json!({ "s": "<b>&</b>" });
+    assert_eq!(
+        render("{{ s }} {{ s|safe }}", &ctx).unwrap(),
+        "&lt;b&gt;&amp;&lt;&#x2f;b&gt; <b>&</b>"
+    );
+    assert_eq!(
+        Engine::new().escape(false).render("{{ s }}", &ctx).unwrap(),
+        "<b>&</b>"
+    );
+    assert_eq!(
+        render("{{ s|e(\"js\") }} {{ s|e(\"url\") }}", &ctx).unwrap(),
+        "\\x3Cb\\x3E\\x26\\x3C\\x2Fb\\x3E %3Cb%3E%26%3C%2Fb%3E"
+    );
+}
+
+#[test]
+fn test_control_flow() {
+    let ctx = json!({
+        "items": [{ "name": "a", "n": 1 }, { "name": "b", "n": 2 }, { "name": "c", "n": 3 }],
+        "limit": 2,
+    });
+    let src = "{% for item in items -%}
+        {% if loop.index > limit %}{% break %}{% endif -%}
+        {{ loop.index0 }}{{ item.name }}{% if item.n is odd %}!{% endif %}
+        {%- if !loop.last %},{% endif %}
+    {%- endfor %}";
+    assert_eq!(render(src, &ctx).unwrap(), "0a!,1b,");
+
+    let src =
+        "{% let (a, b) = pair %}{% if (a == 1) && (b ~ \"\") == \"x\" %}yes{% else %}no{% endif %}";
+    assert_eq!(render(src, &json!({ "pair": [1, "x"] })).unwrap(), "yes");
+
+    let src = "{% for i in 1..=3 %}{{ (i * 10) / 4 }}{{ loop.cycle([\"a\", \"b\"]) }} {% endfor %}";
+    assert_eq!(render(src, &json!({})).unwrap(), "2a 5b 7a ");
+}
+
+#[test]
+fn test_json() {
+    let ctx = json!({ "s": "</script>&\u{2028}", "n": [1] });
+    assert_eq!(
+        render("{{ s|json }} {{ n|json }} {{ n|json(0) }}", &ctx).unwrap(),
+        "\"\\u003c/script\\u003e\\u0026\\u2028\" [\n  1\n] [1]"
+    );
+}
+
+#[test]
+fn test_default() {
+    let ctx = json!({ "empty": "", "zero": 0, "no": false, "one": 1, "none": null });
+    let src = "{{ empty|default(\"-\") }}{{ zero|default(\"-\") }}{{ no|default(\"-\") }}\
+               {{ none|default(\"-\") }}{{ one|default(\"-\") }}";
+    assert_eq!(render(src, &ctx).unwrap(), "----1");
+}
+
+#[test]
+fn test_custom_syntax() {
+    let syntax = Syntax {
+        expr_start: "[[",
+        expr_end: "]]",
+        ..Syntax::default()
+    };
+    let out = Engine::new()
+        .syntax(syntax)
+        .render("[[ x ]] {{ x }}", &json!({ "x": 1 }))
+        .unwrap();
+    assert_eq!(out, "1 {{ x }}");
+}
+
+#[test]
+fn test_errors() {
+    match render("{% if x %}", &json!({ "x": true })) {
+        Err(Error::Parse(_)) => {}
+        res => panic!("expected a parse error, got {:?}", res),
+    }
+    match render("{{ missing }}", &json!({})) {
+        Err(Error::Render(msg)) => assert_eq!(msg, "unknown variable `missing`"),
+        res => panic!("expected a render error, got {:?}", res),
+    }
+    assert!(render("{% include \"a.html\" %}", &json!({})).is_err());
+}
+
+#[test]
+fn test_invalid_input() {
+    match render("{% macro super() %}{% endmacro %}", &json!({})) {
+        Err(Error::Parse(msg)) => assert!(msg.starts_with("invalid macro name 'super'"), "{}", msg),
+        res => panic!("expected a parse error, got {:?}", res),
+    }
+    assert!(render("{{ \u{e9}", &json!({})).is_err());
+    assert_eq!(
+        render("{{ \u{e9} }}", &json!({ "\u{e9}": 1 })).unwrap(),
+        "1"
+    );
+
+    let ctx = json!({ "min": i64::MIN, "max": i64::MAX });
+    assert_eq!(
+        render("{{ min is divisibleby(-1) }} {{ min|abs }}", &ctx).unwrap(),
+        "true 9223372036854775808"
+    );
+    for src in &[
+        "{{ -min }}",
+        "{{ min - 1 }}",
+        "{% for i in 0..=max %}{% endfor %}",
+        "{% for i in min..max %}{% endfor %}",
+    ] {
+        match render(src, &ctx) {
+            Err(Error::Render(_)) => {}
+            res => panic!("expected a render error for {}, got {:?}", src, res),
+        }
+    }
+    assert_eq!(
+        render("{% for i in max - 2..=max %}{{ i % 10 }}{% endfor %}", &ctx).unwrap(),
+        "567"
+    );
+}
@@ -0,0 +1,37 @@
+pub use askama::*;
+pub use salvo;
+
+use salvo::http::header::{HeaderValue, CONTENT_TYPE};
+use salvo::http::StatusCode;
+use salvo::Response;
+
+pub fn render<T: askama::Template>(t: &T, ext: &str, res: &mut Response) {
+    match t.render() {
+        Ok(body) => {
+            let mime = mime::extension_to_mime_type(ext).to_string();
+            res.status_code(StatusCode::OK);
+            res.headers_mut()
+                .insert(CONTENT_TYPE, HeaderValue::from_str(&mime).unwrap());
+            res.write_body(body).ok();
+        }
+        Err(_) => {
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+}
+
+/// Renders the block named `block` into `res`, using its declared content type
+pub fn render_block<T: askama::Template>(t: &T, block: &str, res: &mut Response) {
+    match t.render_block(block) {
+        Ok(body) => {
+            let mime = mime::block_content_type(t, block);
+            res.status_code(StatusCode::OK);
+            res.headers_mut()
+                .insert(CONTENT_TYPE, HeaderValue::from_str(&mime).unwrap());
+            res.write_body(body).ok();
+        }
+        Err(_) => {
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use askama::Template;
+use salvo::http::header::CONTENT_TYPE;
+use salvo::http::{ResBody, StatusCode};
+use salvo::Response;
+
+#[derive(Template)]
+#[template(path = "hello.html")]
+struct HelloTemplate<'a> {
+    name: &'a str,
+}
+
+#[test]
+fn test_salvo() {
+    let mut res = Response::new();
+    res.render(HelloTemplate { name: "world" });
+
+    assert_eq!(res.status_code, Some(StatusCode::OK));
+    assert_eq!(
+        res.headers().get(CONTENT_TYPE).unwrap(),
+        "text/html; charset=utf-8"
+    );
+    match &res.body {
+        ResBody::Once(bytes) => assert_eq!(bytes.as_ref(), b"Hello, world!"),
+        _ => panic!("unexpected response body"),
+    }
+}
+
+#[derive(Template)]
+#[template(
+    source = "<p>{% block icon type \"image/svg+xml\" %}<svg></svg>{% endblock %}</p>",
+    ext = "html"
+)]
+struct IconTemplate;
+
+#[test]
+fn test_salvo_block() {
+    let mut res = Response::new();
+    askama_salvo::render_block(&IconTemplate, "icon", &mut res);
+
+    assert_eq!(res.status_code, Some(StatusCode::OK));
+    assert_eq!(res.headers().get(CONTENT_TYPE).unwrap(), "image/svg+xml");
+    match &res.body {
+        ResBody::Once(bytes) => assert_eq!(bytes.as_ref(), b"<svg></svg>"),
+        _ => panic!("unexpected response body"),
+    }
+}
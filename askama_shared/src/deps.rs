@@ -0,0 +1,88 @@
+use crate::parser::{Expr, Node, WithSpan};
+use crate::visitor::{walk_node, walk_nodes, Visitor};
+
+/// The tag through which a template refers to another template
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DependencyKind {
+    Extends,
+    Include,
+    Import,
+}
+
+/// A template referred to by another template
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Dependency<'a> {
+    pub kind: DependencyKind,
+    /// The path as written in the tag, before it's resolved with
+    /// `Config::find_template()`
+    pub path: &'a str,
+}
+
+/// Lists the templates that `nodes` extend, include and import, in source order
+///
+/// Templates referred to more than once are listed each time. Only the given
+/// template is looked at: callers that need the full set of dependencies
+/// resolve each path and parse the template it refers to in turn. Note that
+/// the code generator resolves `include` paths relative to the template being
+/// derived, rather than the template containing the tag.
+pub fn dependencies<'a>(nodes: &'a [WithSpan<Node<'a>>]) -> Vec<Dependency<'a>> {
+    let mut deps = Dependencies(Vec::new());
+    walk_nodes(&mut deps, nodes);
+    deps.0
+}
+
+struct Dependencies<'a>(Vec<Dependency<'a>>);
+
+impl<'a> Visitor<'a> for Dependencies<'a> {
+    fn visit_node(&mut self, node: &'a Node<'a>) {
+        let dep = match *node {
+            Node::Extends(WithSpan {
+                inner: Expr::StrLit(path),
+                ..
+            }) => Some((DependencyKind::Extends, path)),
+            Node::Include(_, path) => Some((DependencyKind::Include, path)),
+            Node::Import(_, path, _) | Node::FromImport(_, path, _) => {
+                Some((DependencyKind::Import, path))
+            }
+            _ => None,
+        };
+        if let Some((kind, path)) = dep {
+            self.0.push(Dependency { kind, path });
+        }
+        walk_node(self, node);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dependencies, Dependency, DependencyKind};
+    use crate::parser::parse;
+    use crate::Syntax;
+
+    #[test]
+    fn test_dependencies() {
+        let syntax = Syntax::default();
+        let nodes = parse(
+            "{% extends \"base.html\" %}\
+             {% import \"macros.html\" as m %}\
+             {% from \"forms.html\" import input %}\
+             {% block content %}\
+             {% for x in xs %}{% include \"item.html\" %}{% endfor %}\
+             {% include \"item.html\" %}\
+             {% endblock %}",
+            &syntax,
+        );
+        let dep = |kind, path| Dependency { kind, path };
+        assert_eq!(
+            dependencies(&nodes),
+            vec![
+                dep(DependencyKind::Extends, "base.html"),
+                dep(DependencyKind::Import, "macros.html"),
+                dep(DependencyKind::Import, "forms.html"),
+                dep(DependencyKind::Include, "item.html"),
+                dep(DependencyKind::Include, "item.html"),
+            ]
+        );
+        assert_eq!(dependencies(&parse("{{ a }}", &syntax)), vec![]);
+    }
+}
@@ -28,6 +28,17 @@ pub enum Error {
     /// formatting error
     Fmt(fmt::Error),
 
+    /// error propagated with the `?` operator in a template
+    Custom(Box<dyn std::error::Error + Send + Sync>),
+
+    /// error raised while rendering the given line of a template file,
+    /// which may be included in or extended by the rendered template
+    Located {
+        template: &'static str,
+        line: usize,
+        source: Box<Error>,
+    },
+
     /// json conversion error
     #[cfg(feature = "serde_json")]
     Json(::serde_json::Error),
@@ -41,6 +52,8 @@ impl std::error::Error for Error {
     fn cause(&self) -> Option<&dyn std::error::Error> {
         match *self {
             Error::Fmt(ref err) => err.source(),
+            Error::Custom(ref err) => Some(err.as_ref()),
+            Error::Located { ref source, .. } => Some(source.as_ref()),
             #[cfg(feature = "serde_json")]
             Error::Json(ref err) => err.source(),
             #[cfg(feature = "serde_yaml")]
@@ -53,6 +66,12 @@ impl Display for Error {
     fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
         match *self {
             Error::Fmt(ref err) => write!(formatter, "formatting error: {}", err),
+            Error::Custom(ref err) => write!(formatter, "{}", err),
+            Error::Located {
+                template,
+                line,
+                ref source,
+            } => write!(formatter, "{} (in {} at line {})", source, template, line),
             #[cfg(feature = "serde_json")]
             Error::Json(ref err) => write!(formatter, "json conversion error: {}", err),
             #[cfg(feature = "serde_yaml")]
@@ -1,35 +1,118 @@
 use crate::error::{Error, Result};
-use askama_escape::{Escaper, MarkupDisplay};
+use askama_escape::{Escaper, Html, MarkupDisplay};
 use serde::Serialize;
+use serde_json::ser::{PrettyFormatter, Serializer};
 
 /// Serialize to JSON (requires `serde_json` feature)
 ///
+/// The output is indented by `indent` spaces per level, or written on a
+/// single line if `indent` is 0. If it is not given in the template, the
+/// Askama code generator passes 2.
+///
+/// `<`, `>`, `&` and the line and paragraph separators are written as `\u`
+/// escapes, so that the output can be embedded in a `<script>` element.
+///
 /// ## Errors
 ///
 /// This will panic if `S`'s implementation of `Serialize` decides to fail,
 /// or if `T` contains a map with non-string keys.
-pub fn json<E: Escaper, S: Serialize>(e: E, s: &S) -> Result<MarkupDisplay<E, String>> {
-    match serde_json::to_string_pretty(s) {
-        Ok(s) => Ok(MarkupDisplay::new_safe(s, e)),
+pub fn json<E: Escaper, S: Serialize>(
+    e: E,
+    s: &S,
+    indent: &usize,
+) -> Result<MarkupDisplay<E, String>> {
+    let json = if *indent == 0 {
+        serde_json::to_vec(s)
+    } else {
+        let indent = vec![b' '; *indent];
+        let mut buf = Vec::new();
+        let formatter = PrettyFormatter::with_indent(&indent);
+        let mut ser = Serializer::with_formatter(&mut buf, formatter);
+        s.serialize(&mut ser).map(|_| buf)
+    };
+    match json {
+        Ok(json) => {
+            let json = String::from_utf8(json).unwrap();
+            Ok(MarkupDisplay::new_safe(script_safe(&json), e))
+        }
         Err(e) => Err(Error::from(e)),
     }
 }
 
+// These characters can only occur in JSON strings, where they can be escaped
+fn script_safe(json: &str) -> String {
+    let mut rv = String::with_capacity(json.len());
+    for c in json.chars() {
+        match c {
+            '<' => rv.push_str("\\u003c"),
+            '>' => rv.push_str("\\u003e"),
+            '&' => rv.push_str("\\u0026"),
+            '\u{2028}' => rv.push_str("\\u2028"),
+            '\u{2029}' => rv.push_str("\\u2029"),
+            c => rv.push(c),
+        }
+    }
+    rv
+}
+
+/// Serialize to JSON for use in an HTML attribute (requires `serde_json` feature)
+///
+/// The JSON is written on a single line and HTML-escaped, so quotes in the
+/// output don't end a double-quoted attribute value early. The escaping is
+/// applied regardless of the template's escaper.
+///
+/// ## Errors
+///
+/// Fails in the same cases as the `json` filter.
+pub fn to_json_attr<E: Escaper, S: Serialize>(e: E, s: &S) -> Result<MarkupDisplay<E, String>> {
+    let json = serde_json::to_string(s)?;
+    let mut escaped = String::new();
+    Html.write_escaped(&mut escaped, &json)?;
+    Ok(MarkupDisplay::new_safe(escaped, e))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use askama_escape::Html;
+    use askama_escape::Text;
 
     #[test]
     fn test_json() {
-        assert_eq!(json(Html, &true).unwrap().to_string(), "true");
-        assert_eq!(json(Html, &"foo").unwrap().to_string(), r#""foo""#);
+        assert_eq!(json(Html, &true, &2).unwrap().to_string(), "true");
+        assert_eq!(json(Html, &"foo", &2).unwrap().to_string(), r#""foo""#);
         assert_eq!(
-            json(Html, &vec!["foo", "bar"]).unwrap().to_string(),
+            json(Html, &vec!["foo", "bar"], &2).unwrap().to_string(),
             r#"[
   "foo",
   "bar"
 ]"#
         );
+        assert_eq!(
+            json(Html, &vec!["foo", "bar"], &0).unwrap().to_string(),
+            r#"["foo","bar"]"#
+        );
+        assert_eq!(json(Html, &vec![1], &4).unwrap().to_string(), "[\n    1\n]");
+    }
+
+    #[test]
+    fn test_json_script_safe() {
+        assert_eq!(
+            json(Html, &"</script>&\u{2028}", &0).unwrap().to_string(),
+            r#""\u003c/script\u003e\u0026\u2028""#
+        );
+    }
+
+    #[test]
+    fn test_to_json_attr() {
+        assert_eq!(
+            to_json_attr(Html, &vec!["a\"b", "<c>"])
+                .unwrap()
+                .to_string(),
+            "[&quot;a\\&quot;b&quot;,&quot;&lt;c&gt;&quot;]"
+        );
+        assert_eq!(
+            to_json_attr(Text, &"it's").unwrap().to_string(),
+            "&quot;it&#x27;s&quot;"
+        );
     }
 }
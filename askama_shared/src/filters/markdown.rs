@@ -0,0 +1,43 @@
+use std::fmt;
+
+use crate::error::Result;
+use askama_escape::{Escaper, MarkupDisplay};
+use pulldown_cmark::{html, Event, Options, Parser};
+
+/// Render Markdown to HTML (requires `markdown` feature)
+///
+/// Raw HTML in the input is not passed through, but rendered as text, so
+/// the output is safe to include in a page as is.
+pub fn markdown<E, T>(e: E, s: T) -> Result<MarkupDisplay<E, String>>
+where
+    E: Escaper,
+    T: fmt::Display,
+{
+    let s = s.to_string();
+    let events = Parser::new_ext(&s, Options::empty()).map(|event| match event {
+        Event::Html(raw) => Event::Text(raw),
+        event => event,
+    });
+
+    let mut rendered = String::new();
+    html::push_html(&mut rendered, events);
+    Ok(MarkupDisplay::new_safe(rendered, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use askama_escape::Html;
+
+    #[test]
+    fn test_markdown() {
+        assert_eq!(
+            markdown(Html, "**bold**").unwrap().to_string(),
+            "<p><strong>bold</strong></p>\n"
+        );
+        assert_eq!(
+            markdown(Html, "a <script>x</script>").unwrap().to_string(),
+            "<p>a &lt;script&gt;x&lt;/script&gt;</p>\n"
+        );
+    }
+}
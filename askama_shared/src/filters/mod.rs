@@ -5,12 +5,19 @@
 //! For more information, read the [book](https://djc.github.io/askama/filters.html).
 #![allow(clippy::trivially_copy_pass_by_ref)]
 
+use std::collections::HashSet;
 use std::fmt;
+use std::hash::Hash;
 
 #[cfg(feature = "serde_json")]
 mod json;
 #[cfg(feature = "serde_json")]
-pub use self::json::json;
+pub use self::json::{json, to_json_attr};
+
+#[cfg(feature = "pulldown-cmark")]
+mod markdown;
+#[cfg(feature = "pulldown-cmark")]
+pub use self::markdown::markdown;
 
 #[cfg(feature = "serde_yaml")]
 mod yaml;
@@ -58,32 +65,101 @@ const ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
 // Askama or should refer to a local `filters` module. It should contain all the
 // filters shipped with Askama, even the optional ones (since optional inclusion
 // in the const vector based on features seems impossible right now).
-pub const BUILT_IN_FILTERS: [&str; 25] = [
+pub const BUILT_IN_FILTERS: [&str; 63] = [
     "abs",
+    "boolean",
+    "camel_case",
     "capitalize",
     "center",
+    "chars",
+    "coalesce",
+    "dedup",
+    "default",
+    "default_attr",
+    "dictsort",
     "e",
+    "enumerate",
     "escape",
+    "escape_if",
+    "escape_json_string",
     "filesizeformat",
+    "flatten",
     "fmt",
     "format",
+    "groupby",
     "indent",
     "into_f64",
     "into_isize",
+    "into_u64",
+    "into_usize",
     "join",
+    "kebab_case",
     "linebreaks",
     "linebreaksbr",
+    "ljust",
     "lower",
     "lowercase",
+    "map",
+    "nl2br",
+    "ordinal",
+    "pad_number",
+    "partition",
+    "pascal_case",
+    "repeat",
+    "rjust",
     "safe",
+    "skip",
+    "snake_case",
+    "strip_prefix",
+    "strip_suffix",
+    "take",
+    "title_with_exceptions",
     "trim",
     "truncate",
+    "truncate_words",
+    "unique",
     "upper",
     "uppercase",
     "urlencode",
     "wordcount",
-    "json", // Optional feature; reserve the name anyway
-    "yaml", // Optional feature; reserve the name anyway
+    "wordwrap",
+    "wrap_in",
+    "zip_longest",
+    "json",         // Optional feature; reserve the name anyway
+    "markdown",     // Optional feature; reserve the name anyway
+    "to_json_attr", // Optional feature; reserve the name anyway
+    "yaml",         // Optional feature; reserve the name anyway
+];
+
+/// The names of the arguments of built-in filters after the first, which can
+/// be passed by name, as in `{{ s|truncate(length = 10) }}`
+pub const BUILT_IN_FILTER_ARGS: [(&str, &[&str]); 26] = [
+    ("center", &["width"]),
+    ("default", &["fallback"]),
+    ("default_attr", &["name"]),
+    ("e", &["context"]),
+    ("escape", &["context"]),
+    ("groupby", &["attribute"]),
+    ("escape_if", &["cond"]),
+    ("indent", &["width", "first"]),
+    ("join", &["separator"]),
+    ("json", &["indent"]),
+    ("ljust", &["width", "fill"]),
+    ("map", &["attribute"]),
+    ("pad_number", &["width"]),
+    ("partition", &["test"]),
+    ("repeat", &["n"]),
+    ("rjust", &["width", "fill"]),
+    ("skip", &["n"]),
+    ("strip_prefix", &["prefix"]),
+    ("strip_suffix", &["suffix"]),
+    ("take", &["n"]),
+    ("title_with_exceptions", &["exceptions"]),
+    ("truncate", &["length", "ellipsis", "words"]),
+    ("truncate_words", &["n", "end"]),
+    ("wordwrap", &["width"]),
+    ("wrap_in", &["tag"]),
+    ("zip_longest", &["other", "fill"]),
 ];
 
 /// Marks a string (or other `Display` type) as safe
@@ -106,7 +182,8 @@ where
 ///
 /// Askama will automatically insert the first (`Escaper`) argument,
 /// so this filter only takes a single argument of any type that implements
-/// `Display`.
+/// `Display`. In templates, a context such as `escape("js")` selects one of
+/// the `Html`, `Js`, `Css` or `Url` escapers instead.
 pub fn escape<E, T>(e: E, v: T) -> Result<MarkupDisplay<E, T>>
 where
     E: Escaper,
@@ -115,6 +192,46 @@ where
     Ok(MarkupDisplay::new_unsafe(v, e))
 }
 
+/// Escapes the value if `cond` is true, and marks it as safe otherwise
+///
+/// Askama will automatically insert the first (`Escaper`) argument.
+pub fn escape_if<E, T>(e: E, v: T, cond: &bool) -> Result<MarkupDisplay<E, T>>
+where
+    E: Escaper,
+    T: fmt::Display,
+{
+    if *cond {
+        Ok(MarkupDisplay::new_unsafe(v, e))
+    } else {
+        Ok(MarkupDisplay::new_safe(v, e))
+    }
+}
+
+/// Quotes and escapes the value as a JSON string literal
+///
+/// Unlike the `json` filter, this does not need the `serde_json` feature,
+/// and the output is still escaped by the template's escaper.
+pub fn escape_json_string(s: &dyn fmt::Display) -> Result<String> {
+    let s = s.to_string();
+    let mut rv = String::with_capacity(s.len() + 2);
+    rv.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => rv.push_str("\\\""),
+            '\\' => rv.push_str("\\\\"),
+            '\n' => rv.push_str("\\n"),
+            '\r' => rv.push_str("\\r"),
+            '\t' => rv.push_str("\\t"),
+            '\u{8}' => rv.push_str("\\b"),
+            '\u{c}' => rv.push_str("\\f"),
+            c if c < ' ' => rv.push_str(&format!("\\u{:04x}", c as u32)),
+            c => rv.push(c),
+        }
+    }
+    rv.push('"');
+    Ok(rv)
+}
+
 /// Alias for the `escape()` filter
 pub fn e<E, T>(e: E, v: T) -> Result<MarkupDisplay<E, T>>
 where
@@ -153,6 +270,84 @@ pub fn urlencode(s: &dyn fmt::Display) -> Result<String> {
 /// Compare with [format](./fn.format.html).
 pub fn fmt() {}
 
+/// Returns the value, or a fallback if the value is `None`, an empty string,
+/// `false` or zero (see [`DefaultValue`])
+///
+/// The fallback is only evaluated when it is needed: the Askama code
+/// generator wraps the filter argument in a closure. The `??` operator
+/// is a shorthand for this filter that only accepts `Option` values.
+///
+/// ```ignore
+/// {{ value | default(expensive()) }}
+/// {{ value ?? expensive() }}
+/// ```
+pub fn default<T, F, D>(value: &T, fallback: F) -> Result<String>
+where
+    T: DefaultValue + ?Sized,
+    F: FnOnce() -> D,
+    D: fmt::Display,
+{
+    Ok(match value.default_value() {
+        Some(value) => value,
+        None => fallback().to_string(),
+    })
+}
+
+/// Values that the `default` filter renders, unless they are `None`, empty,
+/// `false` or zero
+///
+/// An `Option` holding a value is rendered, even if the value is empty.
+pub trait DefaultValue {
+    /// Renders the value, or returns `None` if the fallback should be used
+    fn default_value(&self) -> Option<String>;
+}
+
+impl<T: DefaultValue + ?Sized> DefaultValue for &T {
+    fn default_value(&self) -> Option<String> {
+        (**self).default_value()
+    }
+}
+
+impl<T: fmt::Display> DefaultValue for Option<T> {
+    fn default_value(&self) -> Option<String> {
+        self.as_ref().map(|v| v.to_string())
+    }
+}
+
+impl DefaultValue for str {
+    fn default_value(&self) -> Option<String> {
+        Some(self.to_string()).filter(|s| !s.is_empty())
+    }
+}
+
+impl DefaultValue for String {
+    fn default_value(&self) -> Option<String> {
+        self.as_str().default_value()
+    }
+}
+
+impl DefaultValue for bool {
+    fn default_value(&self) -> Option<String> {
+        Some(self.to_string()).filter(|_| *self)
+    }
+}
+
+macro_rules! impl_default_value_for_num {
+    ( $( $ty:ty ),* ) => {
+        $(
+            impl DefaultValue for $ty {
+                fn default_value(&self) -> Option<String> {
+                    Some(self.to_string()).filter(|_| self.is_truthy())
+                }
+            }
+        )*
+    };
+}
+
+impl_default_value_for_num!(
+    i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize, f32, f64
+);
+
 /// Formats arguments according to the specified format
 ///
 /// The first argument to this filter must be a string literal (as in normal
@@ -184,6 +379,68 @@ pub fn linebreaksbr(s: &dyn fmt::Display) -> Result<String> {
     Ok(s.replace("\n", "<br/>"))
 }
 
+/// Escapes a piece of plain text and converts its newlines to HTML line breaks
+///
+/// Like `linebreaksbr`, but the rest of the content is escaped first, so the
+/// result can be output as is. Askama will automatically insert the first
+/// (`Escaper`) argument.
+pub fn nl2br<E, T>(e: E, v: T) -> Result<MarkupDisplay<E, String>>
+where
+    E: Escaper,
+    T: fmt::Display,
+{
+    let mut escaped = String::new();
+    e.write_escaped(&mut escaped, &v.to_string())?;
+    Ok(MarkupDisplay::new_safe(linebreaksbr(&escaped)?, e))
+}
+
+/// Escapes a value and wraps it in an HTML element, such as `<em>...</em>`
+///
+/// Askama will automatically insert the first (`Escaper`) argument. The tag
+/// name is output as is, so it may only contain ASCII letters and digits.
+pub fn wrap_in<E, T>(e: E, v: T, tag: &dyn fmt::Display) -> Result<MarkupDisplay<E, String>>
+where
+    E: Escaper,
+    T: fmt::Display,
+{
+    let tag = tag.to_string();
+    if tag.is_empty() || !tag.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(Fmt(fmt::Error));
+    }
+
+    let mut wrapped = format!("<{}>", tag);
+    e.write_escaped(&mut wrapped, &v.to_string())?;
+    wrapped.push_str(&format!("</{}>", tag));
+    Ok(MarkupDisplay::new_safe(wrapped, e))
+}
+
+/// Renders an HTML attribute, or nothing at all if the value is empty
+///
+/// Askama will automatically insert the first (`Escaper`) argument. The
+/// output starts with a space, so `<div{{ cls|default_attr("class") }}>`
+/// renders either `<div class="...">` or `<div>`. The attribute name may
+/// only contain ASCII letters, digits and dashes.
+pub fn default_attr<E, T>(e: E, v: T, name: &dyn fmt::Display) -> Result<MarkupDisplay<E, String>>
+where
+    E: Escaper,
+    T: fmt::Display,
+{
+    let name = name.to_string();
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(Fmt(fmt::Error));
+    }
+
+    let value = v.to_string();
+    if value.is_empty() {
+        return Ok(MarkupDisplay::new_safe(String::new(), e));
+    }
+
+    let mut attr = format!(" {}=\"", name);
+    e.write_escaped(&mut attr, &value)?;
+    attr.push('"');
+    Ok(MarkupDisplay::new_safe(attr, e))
+}
+
 /// Converts to lowercase
 pub fn lower(s: &dyn fmt::Display) -> Result<String> {
     let s = s.to_string();
@@ -212,8 +469,38 @@ pub fn trim(s: &dyn fmt::Display) -> Result<String> {
     Ok(s.trim().to_owned())
 }
 
-/// Limit string length, appends '...' if truncated
-pub fn truncate(s: &dyn fmt::Display, len: &usize) -> Result<String> {
+/// Removes the given prefix, if present
+pub fn strip_prefix(s: &dyn fmt::Display, prefix: &dyn fmt::Display) -> Result<String> {
+    let s = s.to_string();
+    let prefix = prefix.to_string();
+    Ok(match s.strip_prefix(prefix.as_str()) {
+        Some(stripped) => stripped.to_owned(),
+        None => s,
+    })
+}
+
+/// Removes the given suffix, if present
+pub fn strip_suffix(s: &dyn fmt::Display, suffix: &dyn fmt::Display) -> Result<String> {
+    let s = s.to_string();
+    let suffix = suffix.to_string();
+    Ok(match s.strip_suffix(suffix.as_str()) {
+        Some(stripped) => stripped.to_owned(),
+        None => s,
+    })
+}
+
+/// Limit string length to `len` bytes, extended to the end of a character,
+/// and appends `ellipsis` if truncated
+///
+/// With `words`, the string is cut after the last whole word that fits, if
+/// any. If `ellipsis` and `words` are not given in the template, the Askama
+/// code generator passes `"..."` and `false`.
+pub fn truncate(
+    s: &dyn fmt::Display,
+    len: &usize,
+    ellipsis: &dyn fmt::Display,
+    words: &bool,
+) -> Result<String> {
     let mut s = s.to_string();
     if s.len() <= *len {
         Ok(s)
@@ -222,17 +509,54 @@ pub fn truncate(s: &dyn fmt::Display, len: &usize) -> Result<String> {
         while !s.is_char_boundary(real_len) {
             real_len += 1;
         }
+        if *words && !s[real_len..].starts_with(char::is_whitespace) {
+            if let Some(end) = s[..real_len].rfind(char::is_whitespace) {
+                real_len = end;
+            }
+        }
         s.truncate(real_len);
-        s.push_str("...");
+        if *words {
+            s.truncate(s.trim_end().len());
+        }
+        s.push_str(&ellipsis.to_string());
         Ok(s)
     }
 }
 
-/// Indent lines with `width` spaces
-pub fn indent(s: &dyn fmt::Display, width: &usize) -> Result<String> {
+/// Keeps the first `n` whitespace-separated words, appends `end` if truncated
+///
+/// The words are joined by single spaces. If `end` is not given in the
+/// template, the Askama code generator passes `"..."`.
+pub fn truncate_words(s: &dyn fmt::Display, n: &usize, end: &dyn fmt::Display) -> Result<String> {
+    let s = s.to_string();
+    let mut words = s.split_whitespace();
+    let mut rv = words.by_ref().take(*n).collect::<Vec<_>>().join(" ");
+    if words.next().is_some() {
+        rv.push_str(&end.to_string());
+    }
+    Ok(rv)
+}
+
+/// Repeats the value `n` times
+pub fn repeat(s: &dyn fmt::Display, n: &usize) -> Result<String> {
+    let s = s.to_string();
+    Ok(s.repeat(*n))
+}
+
+/// Indent lines with `width` spaces, except for the first one unless `first`
+/// is true
+///
+/// If `first` is not given in the template, the Askama code generator passes
+/// `false`.
+pub fn indent(s: &dyn fmt::Display, width: &usize, first: &bool) -> Result<String> {
     let s = s.to_string();
 
     let mut indented = String::new();
+    if *first && !s.is_empty() {
+        for _ in 0..*width {
+            indented.push(' ');
+        }
+    }
 
     for (i, c) in s.char_indices() {
         indented.push(c);
@@ -247,6 +571,32 @@ pub fn indent(s: &dyn fmt::Display, width: &usize) -> Result<String> {
     Ok(indented)
 }
 
+/// Wraps each line at whitespace so that it is at most `width` characters
+/// long, except for words that are longer by themselves
+pub fn wordwrap(s: &dyn fmt::Display, width: &usize) -> Result<String> {
+    let s = s.to_string();
+    let mut rv = String::with_capacity(s.len());
+    for (i, line) in s.split('\n').enumerate() {
+        if i > 0 {
+            rv.push('\n');
+        }
+        let mut len = 0;
+        for word in line.split_whitespace() {
+            let word_len = word.chars().count();
+            if len > 0 && len + 1 + word_len > *width {
+                rv.push('\n');
+                len = 0;
+            } else if len > 0 {
+                rv.push(' ');
+                len += 1;
+            }
+            rv.push_str(word);
+            len += word_len;
+        }
+    }
+    Ok(rv)
+}
+
 #[cfg(feature = "num-traits")]
 /// Casts number to f64
 pub fn into_f64<T>(number: &T) -> Result<f64>
@@ -265,6 +615,24 @@ where
     number.to_isize().ok_or(Fmt(fmt::Error))
 }
 
+#[cfg(feature = "num-traits")]
+/// Casts number to u64
+pub fn into_u64<T>(number: &T) -> Result<u64>
+where
+    T: NumCast,
+{
+    number.to_u64().ok_or(Fmt(fmt::Error))
+}
+
+#[cfg(feature = "num-traits")]
+/// Casts number to usize
+pub fn into_usize<T>(number: &T) -> Result<usize>
+where
+    T: NumCast,
+{
+    number.to_usize().ok_or(Fmt(fmt::Error))
+}
+
 /// Joins iterable into a string separated by provided argument
 pub fn join<T, I, S>(input: I, separator: S) -> Result<String>
 where
@@ -287,6 +655,167 @@ where
     Ok(rv)
 }
 
+/// Pairs each item of an iterable with its index, starting from 0
+pub fn enumerate<I>(input: I) -> Result<Vec<(usize, I::Item)>>
+where
+    I: Iterator,
+{
+    Ok(input.enumerate().collect())
+}
+
+/// Keeps the first `n` items of an iterable
+pub fn take<I>(input: I, n: &usize) -> Result<Vec<I::Item>>
+where
+    I: Iterator,
+{
+    Ok(input.take(*n).collect())
+}
+
+/// Skips the first `n` items of an iterable
+pub fn skip<I>(input: I, n: &usize) -> Result<Vec<I::Item>>
+where
+    I: Iterator,
+{
+    Ok(input.skip(*n).collect())
+}
+
+/// Flattens one level of nesting from an iterable of iterables
+pub fn flatten<I>(input: I) -> Result<Vec<<I::Item as IntoIterator>::Item>>
+where
+    I: Iterator,
+    I::Item: IntoIterator,
+{
+    Ok(input.flatten().collect())
+}
+
+/// Collapses runs of consecutive equal items into their first item
+pub fn dedup<I>(input: I) -> Result<Vec<I::Item>>
+where
+    I: Iterator,
+    I::Item: PartialEq,
+{
+    let mut rv: Vec<I::Item> = Vec::new();
+    for item in input {
+        if rv.last() != Some(&item) {
+            rv.push(item);
+        }
+    }
+    Ok(rv)
+}
+
+/// Keeps the first of the equal items of an iterable, wherever they are
+pub fn unique<I>(input: I) -> Result<Vec<I::Item>>
+where
+    I: Iterator,
+    I::Item: Eq + Hash + Clone,
+{
+    let mut seen = HashSet::new();
+    Ok(input.filter(|item| seen.insert(item.clone())).collect())
+}
+
+/// Applies `f` to each item of an iterable
+///
+/// The code generator turns the attribute name passed to `map` into `f`.
+pub fn map<I, F, T>(input: I, f: F) -> Result<Vec<T>>
+where
+    I: Iterator,
+    F: FnMut(I::Item) -> T,
+{
+    Ok(input.map(f).collect())
+}
+
+/// Groups the items of an iterable by the key `f` returns for them, sorted
+/// by key, keeping the order of the items within each group
+///
+/// The code generator turns the attribute name passed to `groupby` into `f`.
+pub fn groupby<I, F, K>(input: I, mut f: F) -> Result<Vec<(K, Vec<I::Item>)>>
+where
+    I: Iterator,
+    I::Item: Clone,
+    F: FnMut(I::Item) -> K,
+    K: Ord,
+{
+    let mut keyed: Vec<(K, I::Item)> = input.map(|item| (f(item.clone()), item)).collect();
+    keyed.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut rv: Vec<(K, Vec<I::Item>)> = Vec::new();
+    for (key, item) in keyed {
+        match rv.last_mut() {
+            Some((last, group)) if *last == key => group.push(item),
+            _ => rv.push((key, vec![item])),
+        }
+    }
+    Ok(rv)
+}
+
+/// Sorts the `(key, value)` pairs of a map, or any iterable of pairs, by key
+pub fn dictsort<I, K, V>(input: I) -> Result<Vec<(K, V)>>
+where
+    I: Iterator<Item = (K, V)>,
+    K: Ord,
+{
+    let mut rv: Vec<(K, V)> = input.collect();
+    rv.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(rv)
+}
+
+/// Formats a number with leading zeros up to a minimum width
+pub fn pad_number(n: &dyn fmt::Display, width: &usize) -> Result<String> {
+    Ok(format!("{:01$}", n, *width))
+}
+
+/// Pairs up the items of two iterables, padding the shorter one with `fill`
+pub fn zip_longest<A, B, F>(a: A, b: B, fill: &F) -> Result<Vec<(String, String)>>
+where
+    A: Iterator,
+    A::Item: fmt::Display,
+    B: Iterator,
+    B::Item: fmt::Display,
+    F: fmt::Display + ?Sized,
+{
+    let (mut a, mut b) = (a.fuse(), b.fuse());
+    let mut rv = Vec::new();
+    loop {
+        let pair = match (a.next(), b.next()) {
+            (None, None) => return Ok(rv),
+            (Some(x), Some(y)) => (x.to_string(), y.to_string()),
+            (Some(x), None) => (x.to_string(), fill.to_string()),
+            (None, Some(y)) => (fill.to_string(), y.to_string()),
+        };
+        rv.push(pair);
+    }
+}
+
+/// Converts an integer into its English ordinal form ("1st", "2nd", "11th")
+pub fn ordinal(n: &dyn fmt::Display) -> Result<String> {
+    let mut s = n.to_string();
+    let mut digits = s.bytes().rev();
+    let suffix = match (digits.next(), digits.next()) {
+        (_, Some(b'1')) => "th",
+        (Some(b'1'), _) => "st",
+        (Some(b'2'), _) => "nd",
+        (Some(b'3'), _) => "rd",
+        _ => "th",
+    };
+    s.push_str(suffix);
+    Ok(s)
+}
+
+/// Splits an iterable into the items that pass `test` and those that don't
+///
+/// The code generator builds `test` from a comparison operator and operand:
+///
+/// ```ignore
+/// {% let (positive, rest) = numbers|partition(">", 0) %}
+/// ```
+pub fn partition<T, I, F>(input: I, test: F) -> Result<(Vec<T>, Vec<T>)>
+where
+    I: Iterator<Item = T>,
+    F: FnMut(&T) -> bool,
+{
+    Ok(input.partition(test))
+}
+
 #[cfg(feature = "num-traits")]
 /// Absolute value
 pub fn abs<T>(number: T) -> Result<T>
@@ -296,6 +825,123 @@ where
     Ok(number.abs())
 }
 
+/// Types that have a notion of truthiness, for the `boolean` filter
+///
+/// Strings and collections are truthy if they are non-empty, numbers if
+/// they are non-zero, and `Option`s if they are `Some`.
+pub trait Truthy {
+    fn is_truthy(&self) -> bool;
+}
+
+impl<T: Truthy + ?Sized> Truthy for &T {
+    fn is_truthy(&self) -> bool {
+        (**self).is_truthy()
+    }
+}
+
+impl Truthy for bool {
+    fn is_truthy(&self) -> bool {
+        *self
+    }
+}
+
+impl Truthy for str {
+    fn is_truthy(&self) -> bool {
+        !self.is_empty()
+    }
+}
+
+impl Truthy for String {
+    fn is_truthy(&self) -> bool {
+        !self.is_empty()
+    }
+}
+
+impl<T> Truthy for [T] {
+    fn is_truthy(&self) -> bool {
+        !self.is_empty()
+    }
+}
+
+impl<T> Truthy for Vec<T> {
+    fn is_truthy(&self) -> bool {
+        !self.is_empty()
+    }
+}
+
+impl<T> Truthy for Option<T> {
+    fn is_truthy(&self) -> bool {
+        self.is_some()
+    }
+}
+
+macro_rules! impl_truthy_for_num {
+    ( $( $ty:ty ),* ) => {
+        $(
+            impl Truthy for $ty {
+                fn is_truthy(&self) -> bool {
+                    *self != 0 as $ty
+                }
+            }
+        )*
+    };
+}
+
+impl_truthy_for_num!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize, f32, f64);
+
+/// Coerces a value to a `bool` based on its truthiness (see [`Truthy`])
+pub fn boolean<T: Truthy + ?Sized>(v: &T) -> Result<bool> {
+    Ok(v.is_truthy())
+}
+
+/// Values that the `coalesce` filter can pick from
+///
+/// A value is rendered to a string, and is skipped if it is `None` or if the
+/// string is empty.
+pub trait Coalesce {
+    fn coalesce_value(&self) -> Option<String>;
+}
+
+impl<T: Coalesce + ?Sized> Coalesce for &T {
+    fn coalesce_value(&self) -> Option<String> {
+        (**self).coalesce_value()
+    }
+}
+
+impl Coalesce for str {
+    fn coalesce_value(&self) -> Option<String> {
+        Some(self.to_string()).filter(|s| !s.is_empty())
+    }
+}
+
+impl Coalesce for String {
+    fn coalesce_value(&self) -> Option<String> {
+        self.as_str().coalesce_value()
+    }
+}
+
+impl<T: fmt::Display> Coalesce for Option<T> {
+    fn coalesce_value(&self) -> Option<String> {
+        self.as_ref()
+            .map(|v| v.to_string())
+            .filter(|s| !s.is_empty())
+    }
+}
+
+macro_rules! impl_coalesce_for_num {
+    ( $( $ty:ty ),* ) => {
+        $(
+            impl Coalesce for $ty {
+                fn coalesce_value(&self) -> Option<String> {
+                    Some(self.to_string())
+                }
+            }
+        )*
+    };
+}
+
+impl_coalesce_for_num!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize, f32, f64);
+
 /// Capitalize a value. The first character will be uppercase, all others lowercase.
 pub fn capitalize(s: &dyn fmt::Display) -> Result<String> {
     let mut s = s.to_string();
@@ -315,10 +961,95 @@ pub fn capitalize(s: &dyn fmt::Display) -> Result<String> {
     }
 }
 
-/// Centers the value in a field of a given width
-pub fn center(src: &dyn fmt::Display, dst_len: usize) -> Result<String> {
+/// Title-cases a value, capitalizing each word except for the given
+/// exceptions, which are lowercased unless they are the first word.
+pub fn title_with_exceptions(s: &dyn fmt::Display, exceptions: &[&str]) -> Result<String> {
+    let s = s.to_string();
+    let mut rv = String::with_capacity(s.len());
+    for (i, word) in s.split(' ').enumerate() {
+        if i > 0 {
+            rv.push(' ');
+        }
+        let lower = word.to_lowercase();
+        if i > 0 && exceptions.iter().any(|e| e.to_lowercase() == lower) {
+            rv.push_str(&lower);
+            continue;
+        }
+        let mut chars = lower.chars();
+        if let Some(c) = chars.next() {
+            rv.extend(c.to_uppercase());
+            rv.push_str(chars.as_str());
+        }
+    }
+    Ok(rv)
+}
+
+/// Converts to camelCase
+pub fn camel_case(s: &dyn fmt::Display) -> Result<String> {
+    let mut rv = String::new();
+    for (i, word) in words(&s.to_string()).iter().enumerate() {
+        if i == 0 {
+            rv.push_str(&word.to_lowercase());
+        } else {
+            push_capitalized(&mut rv, word);
+        }
+    }
+    Ok(rv)
+}
+
+/// Converts to PascalCase
+pub fn pascal_case(s: &dyn fmt::Display) -> Result<String> {
+    let mut rv = String::new();
+    for word in words(&s.to_string()) {
+        push_capitalized(&mut rv, word);
+    }
+    Ok(rv)
+}
+
+/// Converts to snake_case
+pub fn snake_case(s: &dyn fmt::Display) -> Result<String> {
+    Ok(words(&s.to_string()).join("_").to_lowercase())
+}
+
+/// Converts to kebab-case
+pub fn kebab_case(s: &dyn fmt::Display) -> Result<String> {
+    Ok(words(&s.to_string()).join("-").to_lowercase())
+}
+
+fn push_capitalized(buf: &mut String, word: &str) {
+    let mut chars = word.chars();
+    if let Some(first) = chars.next() {
+        buf.extend(first.to_uppercase());
+        buf.push_str(&chars.as_str().to_lowercase());
+    }
+}
+
+// Splits a string into words on non-alphanumeric characters and on case
+// boundaries, so that "HTTPServer error" becomes `["HTTP", "Server", "error"]`.
+fn words(s: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    for part in s.split(|c: char| !c.is_alphanumeric()) {
+        let chars: Vec<(usize, char)> = part.char_indices().collect();
+        let mut start = 0;
+        for (i, &(idx, c)) in chars.iter().enumerate().skip(1) {
+            let prev = chars[i - 1].1;
+            let next_lower = matches!(chars.get(i + 1), Some((_, n)) if n.is_lowercase());
+            if c.is_uppercase() && (!prev.is_uppercase() || next_lower) {
+                words.push(&part[start..idx]);
+                start = idx;
+            }
+        }
+        words.push(&part[start..]);
+    }
+    words.retain(|w| !w.is_empty());
+    words
+}
+
+/// Centers the value in a field of a given width, counted in characters
+pub fn center(src: &dyn fmt::Display, dst_len: &usize) -> Result<String> {
+    let dst_len = *dst_len;
     let src = src.to_string();
-    let len = src.len();
+    let len = src.chars().count();
 
     if dst_len <= len {
         Ok(src)
@@ -342,6 +1073,29 @@ pub fn center(src: &dyn fmt::Display, dst_len: usize) -> Result<String> {
     }
 }
 
+/// Collects the characters of a value, to iterate over them in a `for` loop
+pub fn chars(s: &dyn fmt::Display) -> Result<Vec<char>> {
+    let s = s.to_string();
+    Ok(s.chars().collect())
+}
+
+/// Pads the value on the right with `fill` up to `width` characters
+pub fn ljust(s: &dyn fmt::Display, width: &usize, fill: &char) -> Result<String> {
+    let mut s = s.to_string();
+    let len = s.chars().count();
+    s.extend((len..*width).map(|_| *fill));
+    Ok(s)
+}
+
+/// Pads the value on the left with `fill` up to `width` characters
+pub fn rjust(s: &dyn fmt::Display, width: &usize, fill: &char) -> Result<String> {
+    let s = s.to_string();
+    let len = s.chars().count();
+    let mut padded = (len..*width).map(|_| *fill).collect::<String>();
+    padded.push_str(&s);
+    Ok(padded)
+}
+
 /// Count the words in that string
 pub fn wordcount(s: &dyn fmt::Display) -> Result<usize> {
     let s = s.to_string();
@@ -352,6 +1106,7 @@ pub fn wordcount(s: &dyn fmt::Display) -> Result<usize> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use askama_escape::{Html, Text};
     #[cfg(feature = "num-traits")]
     use std::f64::INFINITY;
 
@@ -399,6 +1154,223 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_nl2br() {
+        assert_eq!(
+            nl2br(Html, "a < b\nc").unwrap().to_string(),
+            "a &lt; b<br/>c"
+        );
+        assert_eq!(nl2br(Text, "a < b\nc").unwrap().to_string(), "a < b<br/>c");
+    }
+
+    #[cfg(feature = "num-traits")]
+    #[test]
+    fn test_into_u64() {
+        assert_eq!(into_u64(&1usize).unwrap(), 1u64);
+        assert_eq!(into_u64(&1.9).unwrap(), 1u64);
+        match into_u64(&-1) {
+            Err(Fmt(fmt::Error)) => {}
+            _ => panic!("Should return error of type Err(Fmt(fmt::Error))"),
+        };
+    }
+
+    #[cfg(feature = "num-traits")]
+    #[test]
+    fn test_into_usize() {
+        assert_eq!(into_usize(&1u64).unwrap(), 1usize);
+        assert_eq!(into_usize(&1.9).unwrap(), 1usize);
+        match into_usize(&f64::INFINITY) {
+            Err(Fmt(fmt::Error)) => {}
+            _ => panic!("Should return error of type Err(Fmt(fmt::Error))"),
+        };
+    }
+
+    #[test]
+    fn test_truncate_words() {
+        assert_eq!(truncate_words(&"", &2, &"...").unwrap(), "");
+        assert_eq!(truncate_words(&"a  b\nc", &3, &"...").unwrap(), "a b c");
+        assert_eq!(truncate_words(&" a b c", &2, &"...").unwrap(), "a b...");
+        assert_eq!(truncate_words(&"a b c", &0, &"…").unwrap(), "…");
+    }
+
+    #[test]
+    fn test_flatten() {
+        let nested = vec![vec![1, 2], vec![], vec![3]];
+        assert_eq!(flatten(nested.into_iter()).unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_boolean() {
+        assert!(!boolean("").unwrap());
+        assert!(boolean(&"a").unwrap());
+        assert!(!boolean(&0).unwrap());
+        assert!(boolean(&-1.5).unwrap());
+        assert!(!boolean(&Vec::<u8>::new()).unwrap());
+        assert!(boolean(&Some("")).unwrap());
+        assert!(!boolean(&None::<u8>).unwrap());
+    }
+
+    #[test]
+    fn test_strip_prefix_suffix() {
+        assert_eq!(strip_prefix(&"foobar", &"foo").unwrap(), "bar");
+        assert_eq!(strip_prefix(&"foobar", &"bar").unwrap(), "foobar");
+        assert_eq!(strip_suffix(&"foobar", &"bar").unwrap(), "foo");
+        assert_eq!(strip_suffix(&"foobar", &"foo").unwrap(), "foobar");
+        assert_eq!(strip_suffix(&"foo", &"").unwrap(), "foo");
+    }
+
+    #[test]
+    fn test_default() {
+        assert_eq!(default(&Some(1), || -> u8 { panic!() }).unwrap(), "1");
+        assert_eq!(default(&None::<u8>, || "none").unwrap(), "none");
+        assert_eq!(default(&Some(""), || "none").unwrap(), "");
+        assert_eq!(default("", || "none").unwrap(), "none");
+        assert_eq!(default(&0, || "none").unwrap(), "none");
+        assert_eq!(default(&false, || "none").unwrap(), "none");
+        assert_eq!(default(&true, || "none").unwrap(), "true");
+    }
+
+    #[test]
+    fn test_wrap_in() {
+        assert_eq!(
+            wrap_in(Html, "a < b", &"em").unwrap().to_string(),
+            "<em>a &lt; b</em>"
+        );
+        assert!(wrap_in(Html, "a", &"em onclick=x").is_err());
+        assert!(wrap_in(Html, "a", &"").is_err());
+    }
+
+    #[test]
+    fn test_default_attr() {
+        assert_eq!(
+            default_attr(Html, "a\"b", &"class").unwrap().to_string(),
+            " class=\"a&quot;b\""
+        );
+        assert_eq!(default_attr(Html, "", &"class").unwrap().to_string(), "");
+        assert!(default_attr(Html, "a", &"on click").is_err());
+    }
+
+    #[test]
+    fn test_ljust_rjust() {
+        assert_eq!(ljust(&"ab", &4, &' ').unwrap(), "ab  ");
+        assert_eq!(rjust(&"ab", &4, &'.').unwrap(), "..ab");
+        assert_eq!(ljust(&"äöü", &4, &'-').unwrap(), "äöü-");
+        assert_eq!(rjust(&"abcdef", &4, &' ').unwrap(), "abcdef");
+    }
+
+    #[test]
+    fn test_title_with_exceptions() {
+        assert_eq!(
+            title_with_exceptions(&"the LORD of the rings", &["the", "of"]).unwrap(),
+            "The Lord of the Rings"
+        );
+        assert_eq!(
+            title_with_exceptions(&"war and  peace", &[]).unwrap(),
+            "War And  Peace"
+        );
+        assert_eq!(title_with_exceptions(&"", &["a"]).unwrap(), "");
+    }
+
+    #[test]
+    fn test_escape_if() {
+        assert_eq!(
+            escape_if(Html, "<b>", &true).unwrap().to_string(),
+            "&lt;b&gt;"
+        );
+        assert_eq!(escape_if(Html, "<b>", &false).unwrap().to_string(), "<b>");
+    }
+
+    #[test]
+    fn test_dedup() {
+        assert_eq!(
+            dedup(vec![1, 1, 2, 2, 2, 3, 1].into_iter()).unwrap(),
+            vec![1, 2, 3, 1]
+        );
+        assert!(dedup(Vec::<u8>::new().into_iter()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_unique() {
+        assert_eq!(
+            unique(vec![3, 1, 3, 2, 1].into_iter()).unwrap(),
+            vec![3, 1, 2]
+        );
+    }
+
+    #[test]
+    fn test_groupby() {
+        let words = ["bb", "a", "cc", "d"];
+        let groups = groupby(words.iter(), |w| w.len()).unwrap();
+        assert_eq!(groups, vec![(1, vec![&"a", &"d"]), (2, vec![&"bb", &"cc"])]);
+    }
+
+    #[test]
+    fn test_dictsort() {
+        let map: std::collections::HashMap<_, _> =
+            vec![("b", 2), ("a", 1), ("c", 3)].into_iter().collect();
+        assert_eq!(
+            dictsort(map.iter()).unwrap(),
+            vec![(&"a", &1), (&"b", &2), (&"c", &3)]
+        );
+    }
+
+    #[test]
+    fn test_pad_number() {
+        assert_eq!(pad_number(&7, &4).unwrap(), "0007");
+        assert_eq!(pad_number(&-7, &4).unwrap(), "-007");
+        assert_eq!(pad_number(&12345, &4).unwrap(), "12345");
+    }
+
+    #[test]
+    fn test_zip_longest() {
+        let pairs = |v: &[(&str, &str)]| {
+            v.iter()
+                .map(|&(a, b)| (a.to_string(), b.to_string()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            zip_longest([1, 2, 3].iter(), ["x"].iter(), "-").unwrap(),
+            pairs(&[("1", "x"), ("2", "-"), ("3", "-")])
+        );
+        assert_eq!(
+            zip_longest(["x"].iter(), [1, 2].iter(), &0).unwrap(),
+            pairs(&[("x", "1"), ("0", "2")])
+        );
+    }
+
+    #[test]
+    fn test_enumerate() {
+        assert_eq!(
+            enumerate(["a", "b"].iter()).unwrap(),
+            vec![(0, &"a"), (1, &"b")]
+        );
+    }
+
+    #[test]
+    fn test_escape_json_string() {
+        assert_eq!(
+            escape_json_string(&"a \"b\"\n\\ ü\u{1}").unwrap(),
+            r#""a \"b\"\n\\ ü\u0001""#
+        );
+        assert_eq!(escape_json_string(&"").unwrap(), r#""""#);
+    }
+
+    #[test]
+    fn test_take_skip() {
+        assert_eq!(take([1, 2, 3].iter(), &2).unwrap(), vec![&1, &2]);
+        assert_eq!(take([1].iter(), &2).unwrap(), vec![&1]);
+        assert_eq!(skip([1, 2, 3].iter(), &2).unwrap(), vec![&3]);
+    }
+
+    #[test]
+    fn test_coalesce_value() {
+        assert_eq!(None::<&str>.coalesce_value(), None);
+        assert_eq!(Some("").coalesce_value(), None);
+        assert_eq!(Some("a").coalesce_value(), Some("a".to_string()));
+        assert_eq!((&"").coalesce_value(), None);
+        assert_eq!(0.coalesce_value(), Some("0".to_string()));
+    }
+
     #[test]
     fn test_lower() {
         assert_eq!(lower(&"Foo").unwrap(), "foo");
@@ -422,36 +1394,70 @@ mod tests {
 
     #[test]
     fn test_truncate() {
-        assert_eq!(truncate(&"hello", &2).unwrap(), "he...");
+        assert_eq!(truncate(&"hello", &2, &"...", &false).unwrap(), "he...");
         let a = String::from("您好");
         assert_eq!(a.len(), 6);
         assert_eq!(String::from("您").len(), 3);
-        assert_eq!(truncate(&"您好", &1).unwrap(), "您...");
-        assert_eq!(truncate(&"您好", &2).unwrap(), "您...");
-        assert_eq!(truncate(&"您好", &3).unwrap(), "您...");
-        assert_eq!(truncate(&"您好", &4).unwrap(), "您好...");
-        assert_eq!(truncate(&"您好", &6).unwrap(), "您好");
-        assert_eq!(truncate(&"您好", &7).unwrap(), "您好");
+        assert_eq!(truncate(&"您好", &1, &"...", &false).unwrap(), "您...");
+        assert_eq!(truncate(&"您好", &2, &"...", &false).unwrap(), "您...");
+        assert_eq!(truncate(&"您好", &3, &"...", &false).unwrap(), "您...");
+        assert_eq!(truncate(&"您好", &4, &"...", &false).unwrap(), "您好...");
+        assert_eq!(truncate(&"您好", &6, &"...", &false).unwrap(), "您好");
+        assert_eq!(truncate(&"您好", &7, &"...", &false).unwrap(), "您好");
         let s = String::from("🤚a🤚");
         assert_eq!(s.len(), 9);
         assert_eq!(String::from("🤚").len(), 4);
-        assert_eq!(truncate(&"🤚a🤚", &1).unwrap(), "🤚...");
-        assert_eq!(truncate(&"🤚a🤚", &2).unwrap(), "🤚...");
-        assert_eq!(truncate(&"🤚a🤚", &3).unwrap(), "🤚...");
-        assert_eq!(truncate(&"🤚a🤚", &4).unwrap(), "🤚...");
-        assert_eq!(truncate(&"🤚a🤚", &5).unwrap(), "🤚a...");
-        assert_eq!(truncate(&"🤚a🤚", &6).unwrap(), "🤚a🤚...");
-        assert_eq!(truncate(&"🤚a🤚", &9).unwrap(), "🤚a🤚");
-        assert_eq!(truncate(&"🤚a🤚", &10).unwrap(), "🤚a🤚");
+        assert_eq!(truncate(&"🤚a🤚", &1, &"...", &false).unwrap(), "🤚...");
+        assert_eq!(truncate(&"🤚a🤚", &2, &"...", &false).unwrap(), "🤚...");
+        assert_eq!(truncate(&"🤚a🤚", &3, &"...", &false).unwrap(), "🤚...");
+        assert_eq!(truncate(&"🤚a🤚", &4, &"...", &false).unwrap(), "🤚...");
+        assert_eq!(truncate(&"🤚a🤚", &5, &"...", &false).unwrap(), "🤚a...");
+        assert_eq!(truncate(&"🤚a🤚", &6, &"...", &false).unwrap(), "🤚a🤚...");
+        assert_eq!(truncate(&"🤚a🤚", &9, &"...", &false).unwrap(), "🤚a🤚");
+        assert_eq!(truncate(&"🤚a🤚", &10, &"...", &false).unwrap(), "🤚a🤚");
+    }
+
+    #[test]
+    fn test_repeat() {
+        assert_eq!(repeat(&"ab", &3).unwrap(), "ababab");
+        assert_eq!(repeat(&"ab", &1).unwrap(), "ab");
+        assert_eq!(repeat(&"ab", &0).unwrap(), "");
+        assert_eq!(repeat(&"", &5).unwrap(), "");
+    }
+
+    #[test]
+    fn test_truncate_options() {
+        assert_eq!(truncate(&"hello", &2, &"…", &false).unwrap(), "he…");
+        assert_eq!(truncate(&"hello", &5, &"…", &false).unwrap(), "hello");
+        let s = "hello big world";
+        assert_eq!(truncate(&s, &11, &"", &true).unwrap(), "hello big");
+        assert_eq!(truncate(&s, &9, &"...", &true).unwrap(), "hello big...");
+        assert_eq!(truncate(&s, &10, &"...", &true).unwrap(), "hello big...");
+        assert_eq!(truncate(&s, &3, &"...", &true).unwrap(), "hel...");
+    }
+
+    #[test]
+    fn test_wordwrap() {
+        assert_eq!(
+            wordwrap(&"the quick brown fox", &10).unwrap(),
+            "the quick\nbrown fox"
+        );
+        assert_eq!(wordwrap(&"a\nb c", &1).unwrap(), "a\nb\nc");
+        assert_eq!(
+            wordwrap(&"extraordinary day", &5).unwrap(),
+            "extraordinary\nday"
+        );
     }
 
     #[test]
     fn test_indent() {
-        assert_eq!(indent(&"hello", &2).unwrap(), "hello");
-        assert_eq!(indent(&"hello\n", &2).unwrap(), "hello\n");
-        assert_eq!(indent(&"hello\nfoo", &2).unwrap(), "hello\n  foo");
+        assert_eq!(indent(&"a\nb", &2, &true).unwrap(), "  a\n  b");
+        assert_eq!(indent(&"", &2, &true).unwrap(), "");
+        assert_eq!(indent(&"hello", &2, &false).unwrap(), "hello");
+        assert_eq!(indent(&"hello\n", &2, &false).unwrap(), "hello\n");
+        assert_eq!(indent(&"hello\nfoo", &2, &false).unwrap(), "hello\n  foo");
         assert_eq!(
-            indent(&"hello\nfoo\n bar", &4).unwrap(),
+            indent(&"hello\nfoo\n bar", &4, &false).unwrap(),
             "hello\n    foo\n     bar"
         );
     }
@@ -514,6 +1520,30 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_ordinal() {
+        assert_eq!(ordinal(&0).unwrap(), "0th");
+        assert_eq!(ordinal(&1).unwrap(), "1st");
+        assert_eq!(ordinal(&2).unwrap(), "2nd");
+        assert_eq!(ordinal(&3).unwrap(), "3rd");
+        assert_eq!(ordinal(&4).unwrap(), "4th");
+        assert_eq!(ordinal(&11).unwrap(), "11th");
+        assert_eq!(ordinal(&112).unwrap(), "112th");
+        assert_eq!(ordinal(&-23).unwrap(), "-23rd");
+    }
+
+    #[test]
+    fn test_partition() {
+        let (pos, rest) = partition([3, -1, 0, 5].iter(), |n| **n > 0).unwrap();
+        assert_eq!(pos, vec![&3, &5]);
+        assert_eq!(rest, vec![&-1, &0]);
+
+        let empty: &[i32] = &[];
+        let (pos, rest) = partition(empty.iter(), |n| **n > 0).unwrap();
+        assert!(pos.is_empty());
+        assert!(rest.is_empty());
+    }
+
     #[cfg(feature = "num-traits")]
     #[test]
     #[allow(clippy::float_cmp)]
@@ -536,12 +1566,35 @@ mod tests {
         assert_eq!(capitalize(&"foO BAR").unwrap(), "Foo bar".to_string());
     }
 
+    #[test]
+    fn test_case_conversions() {
+        for input in &["hello world", "HelloWorld", "hello_world", "hello-World"] {
+            assert_eq!(camel_case(input).unwrap(), "helloWorld");
+            assert_eq!(pascal_case(input).unwrap(), "HelloWorld");
+            assert_eq!(snake_case(input).unwrap(), "hello_world");
+            assert_eq!(kebab_case(input).unwrap(), "hello-world");
+        }
+        assert_eq!(
+            snake_case(&"HTTPServer2 error").unwrap(),
+            "http_server2_error"
+        );
+        assert_eq!(camel_case(&"").unwrap(), "");
+    }
+
     #[test]
     fn test_center() {
-        assert_eq!(center(&"f", 3).unwrap(), " f ".to_string());
-        assert_eq!(center(&"f", 4).unwrap(), " f  ".to_string());
-        assert_eq!(center(&"foo", 1).unwrap(), "foo".to_string());
-        assert_eq!(center(&"foo bar", 8).unwrap(), "foo bar ".to_string());
+        assert_eq!(center(&"f", &3).unwrap(), " f ".to_string());
+        assert_eq!(center(&"f", &4).unwrap(), " f  ".to_string());
+        assert_eq!(center(&"foo", &1).unwrap(), "foo".to_string());
+        assert_eq!(center(&"foo bar", &8).unwrap(), "foo bar ".to_string());
+        assert_eq!(center(&"äö", &4).unwrap(), " äö ".to_string());
+    }
+
+    #[test]
+    fn test_chars() {
+        assert!(chars(&"").unwrap().is_empty());
+        assert_eq!(chars(&"abc").unwrap(), vec!['a', 'b', 'c']);
+        assert_eq!(chars(&"您好").unwrap(), vec!['您', '好']);
     }
 
     #[test]
@@ -1,17 +1,29 @@
-use super::{get_template_source, Integrations};
+use super::Integrations;
 use crate::filters;
 use crate::heritage::{Context, Heritage};
 use crate::input::{Source, TemplateInput};
 use crate::parser::{
-    parse, Cond, Expr, MatchParameter, MatchParameters, MatchVariant, Node, Target, When, WS,
+    block_bindings, referenced_vars, Cond, Expr, Loop, MatchParameter, MatchParameters,
+    MatchVariant, Node, Target, When, WithSpan, WS,
 };
+use crate::visitor::{walk_expr, walk_node, walk_nodes, Visitor};
 
 use proc_macro2::Span;
 
 use quote::{quote, ToTokens};
 
+#[cfg(feature = "i18n")]
+use std::cell::OnceCell;
+#[cfg(feature = "i18n")]
+use std::collections::BTreeSet;
 use std::collections::{HashMap, HashSet};
+#[cfg(feature = "i18n")]
+use std::fs;
+#[cfg(feature = "i18n")]
+use std::path::Path;
 use std::path::PathBuf;
+#[cfg(feature = "i18n")]
+use std::rc::Rc;
 use std::{cmp, hash, mem, str};
 
 pub fn generate<S: std::hash::BuildHasher>(
@@ -48,6 +60,14 @@ struct Generator<'a, S: std::hash::BuildHasher> {
     buf_writable: Vec<Writable<'a>>,
     // Counter for write! hash named arguments
     named: usize,
+    // Labels of the enclosing loops, and whether each of them is a parallel loop
+    loops: Vec<(Option<&'a str>, bool)>,
+    // The escaper in effect, which can be changed by autoescape blocks
+    escaper: &'a str,
+    // The i18n resources, loaded by the first `trans` tag and shared with
+    // child generators, so that they are read once per template
+    #[cfg(feature = "i18n")]
+    i18n: Rc<OnceCell<Vec<(String, PathBuf, askama_i18n::Resource)>>>,
 }
 
 impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
@@ -69,22 +89,39 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
             super_block: None,
             buf_writable: vec![],
             named: 0,
+            loops: vec![],
+            escaper: input.escaper,
+            #[cfg(feature = "i18n")]
+            i18n: Rc::default(),
         }
     }
 
     fn child(&mut self) -> Generator<'_, S> {
         let locals = SetChain::with_parent(&self.locals);
-        Self::new(
+        let mut child = Self::new(
             self.input,
             self.contexts,
             self.heritage,
             self.integrations,
             locals,
-        )
+        );
+        child.loops = self.loops.clone();
+        child.escaper = self.escaper;
+        #[cfg(feature = "i18n")]
+        {
+            child.i18n = Rc::clone(&self.i18n);
+        }
+        child
     }
 
     // Takes a Context and generates the relevant implementations.
     fn build(mut self, ctx: &'a Context) -> String {
+        if self.input.deny_shadowing {
+            for ctx in self.contexts.values() {
+                check_shadowing(ctx.nodes);
+            }
+        }
+
         let mut buf = Buffer::new(0);
         if !ctx.blocks.is_empty() {
             if let Some(parent) = self.input.parent {
@@ -92,8 +129,19 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
             }
         };
 
+        if self.input.is_async {
+            // Expressions may use `.await`, which is only possible in an
+            // `async fn`, so the synchronous traits cannot be implemented
+            self.impl_render_async(ctx, &mut buf);
+            self.write_i18n_bundles(&mut buf);
+            return buf.buf;
+        }
+
         self.impl_template(ctx, &mut buf);
         self.impl_display(&mut buf);
+        if let Some(ref name) = self.input.register {
+            self.impl_registered_template(name, &mut buf);
+        }
 
         if self.integrations.actix {
             self.impl_actix_web_responder(&mut buf);
@@ -107,15 +155,22 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
         if self.integrations.mendes {
             self.impl_mendes_responder(&mut buf);
         }
+        if self.integrations.poem {
+            self.impl_poem_into_response(&mut buf);
+        }
         if self.integrations.rocket {
             self.impl_rocket_responder(&mut buf);
         }
+        if self.integrations.salvo {
+            self.impl_salvo_scribe(&mut buf);
+        }
         if self.integrations.tide {
             self.impl_tide_integrations(&mut buf);
         }
         if self.integrations.warp {
             self.impl_warp_reply(&mut buf);
         }
+        self.write_i18n_bundles(&mut buf);
         buf.buf
     }
 
@@ -127,24 +182,13 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
              ::askama::Result<()> {",
         );
 
-        // Make sure the compiler understands that the generated code depends on the template files.
-        for path in self.contexts.keys() {
-            // Skip the fake path of templates defined in rust source.
-            let path_is_valid = match self.input.source {
-                Source::Path(_) => true,
-                Source::Source(_) => *path != &self.input.path,
-            };
-            if path_is_valid {
-                let path = path.to_str().unwrap();
-                buf.writeln(
-                    &quote! {
-                        include_bytes!(#path);
-                    }
-                    .to_string(),
-                );
-            }
+        self.write_dependencies(buf);
+        self.write_filter_imports(buf);
+        if self.integrations.hot_reload {
+            self.write_hot_reload(buf);
         }
 
+        self.write_located_start(buf);
         let size_hint = if let Some(heritage) = self.heritage {
             self.handle(heritage.root, heritage.root.nodes, buf, AstLevel::Top)
         } else {
@@ -152,7 +196,7 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
         };
 
         self.flush_ws(WS(false, false));
-        buf.writeln("Ok(())");
+        self.write_located_end(buf);
         buf.writeln("}");
 
         buf.writeln("fn extension(&self) -> Option<&'static str> {");
@@ -166,6 +210,16 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
         buf.writeln(&format!("{}", size_hint));
         buf.writeln("}");
 
+        // Rendering single blocks is rarely needed, so the code for it can be
+        // left out with the `fragments` feature, and is kept out of the way
+        // of the code rendering whole templates
+        match self.heritage {
+            Some(heritage) if self.integrations.fragments => {
+                self.write_render_block(heritage, buf);
+            }
+            _ => {}
+        }
+
         buf.writeln("}");
 
         self.write_header(buf, "::askama::SizedTemplate", None);
@@ -184,6 +238,326 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
         buf.writeln("}");
     }
 
+    // In debug builds, render the template file as it is on disk at runtime, so
+    // that edits show up without recompiling. Templates that don't implement
+    // `Serialize`, or that the runtime engine can't handle, fall back to the
+    // compiled code.
+    fn write_hot_reload(&mut self, buf: &mut Buffer) {
+        let escape = match self.input.escaper {
+            "::askama::Html" => true,
+            "::askama::Text" => false,
+            _ => return,
+        };
+        if let Source::Source(_) = self.input.source {
+            return;
+        }
+        // The runtime engine only sees the fields of the struct, by the names
+        // they are serialized as
+        if !self.input.config.globals.is_empty() || has_serde_attrs(self.input.ast) {
+            return;
+        }
+        let relative = self
+            .input
+            .config
+            .dirs
+            .iter()
+            .find_map(|dir| self.input.path.strip_prefix(dir).ok())
+            .unwrap_or(&self.input.path);
+
+        let syntax = &self.input.syntax;
+        buf.writeln("#[cfg(debug_assertions)]");
+        buf.writeln("{");
+        buf.writeln("use ::askama::runtime::reload::{Fallback as _, Reload, Serialized as _};");
+        buf.writeln("let path = match ::std::env::var_os(\"ASKAMA_RELOAD_DIR\") {");
+        buf.writeln(&format!(
+            "Some(dir) => ::std::path::Path::new(&dir).join({:?}),",
+            relative
+        ));
+        buf.writeln(&format!(
+            "None => ::std::path::PathBuf::from({:?}),",
+            self.input.path
+        ));
+        buf.writeln("};");
+        buf.writeln("if let Ok(source) = ::std::fs::read_to_string(path) {");
+        buf.writeln("let syntax = ::askama::shared::Syntax {");
+        buf.writeln(&format!("block_start: {:?},", syntax.block_start));
+        buf.writeln(&format!("block_end: {:?},", syntax.block_end));
+        buf.writeln(&format!("expr_start: {:?},", syntax.expr_start));
+        buf.writeln(&format!("expr_end: {:?},", syntax.expr_end));
+        buf.writeln(&format!("comment_start: {:?},", syntax.comment_start));
+        buf.writeln(&format!("comment_end: {:?},", syntax.comment_end));
+        buf.writeln(&format!("lenient: {:?},", syntax.lenient));
+        buf.writeln(&format!("trim_blocks: {:?},", syntax.trim_blocks));
+        buf.writeln(&format!("lstrip_blocks: {:?},", syntax.lstrip_blocks));
+        buf.writeln(&format!("max_depth: {:?},", syntax.max_depth));
+        buf.writeln("};");
+        buf.writeln(&format!(
+            "let engine = ::askama::runtime::Engine::new().syntax(syntax).escape({});",
+            escape
+        ));
+        buf.writeln("match (&Reload(self)).reload(&engine, &source) {");
+        buf.writeln("Some(Ok(rendered)) => return Ok(writer.write_str(&rendered)?),");
+        buf.writeln("Some(Err(::askama::runtime::Error::Unsupported(_))) | None => {}");
+        buf.writeln("Some(Err(err)) => return Err(::askama::Error::Custom(Box::new(err))),");
+        buf.writeln("}");
+        buf.writeln("}");
+        buf.writeln("}");
+    }
+
+    // Make sure the compiler understands that the generated code depends on the template files.
+    fn write_dependencies(&mut self, buf: &mut Buffer) {
+        for path in self.contexts.keys() {
+            // Skip the fake path of templates defined in rust source.
+            let path_is_valid = match self.input.source {
+                Source::Path(_) => true,
+                Source::Source(_) => *path != &self.input.path,
+            };
+            if path_is_valid {
+                let path = path.to_str().unwrap();
+                buf.writeln(
+                    &quote! {
+                        include_bytes!(#path);
+                    }
+                    .to_string(),
+                );
+            }
+        }
+    }
+
+    // Custom filters from the configured modules are called by their bare
+    // names, so glob-import the modules into the function body
+    fn write_filter_imports(&mut self, buf: &mut Buffer) {
+        for path in &self.input.filters {
+            buf.writeln("#[allow(unused_imports)]");
+            buf.writeln(&format!("use {}::*;", path));
+        }
+    }
+
+    // Implement an inherent `render_async()` method for an `async` template.
+    fn impl_render_async(&mut self, ctx: &'a Context, buf: &mut Buffer) {
+        let (impl_generics, ty_generics, where_clause) = self.input.ast.generics.split_for_impl();
+        buf.writeln(&format!(
+            "{} {}{} {{",
+            quote!(impl#impl_generics),
+            self.input.ast.ident,
+            quote!(#ty_generics #where_clause),
+        ));
+        buf.writeln(
+            "pub async fn render_async(&self) -> ::askama::Result<::std::string::String> {",
+        );
+        self.write_dependencies(buf);
+        self.write_filter_imports(buf);
+
+        // The size hint is only known after generating the body
+        let mut body = Buffer::new(buf.indent + 1);
+        let size_hint = if let Some(heritage) = self.heritage {
+            self.handle(heritage.root, heritage.root.nodes, &mut body, AstLevel::Top)
+        } else {
+            self.handle(ctx, ctx.nodes, &mut body, AstLevel::Top)
+        };
+        self.flush_ws(WS(false, false));
+
+        buf.writeln(&format!(
+            "let mut _buf = ::std::string::String::with_capacity({});",
+            size_hint
+        ));
+        // A `String` rather than a `dyn Write` keeps the future `Send`
+        buf.writeln("use ::std::fmt::Write as _;");
+        buf.writeln("let writer = &mut _buf;");
+        // Like `write_located_start()`, but the body may use `.await`
+        buf.writeln("#[allow(unused_mut, unused_assignments)]");
+        buf.writeln("let mut _loc = (\"\", 0);");
+        buf.writeln("let res = async {");
+        buf.buf.push_str(&body.buf);
+        buf.writeln("Ok::<(), ::askama::Error>(())");
+        buf.dedent();
+        buf.writeln("}.await;");
+        buf.writeln("res.map_err(|err| ::askama::helpers::locate(err, _loc))?;");
+        buf.writeln("Ok(_buf)");
+        buf.writeln("}");
+        buf.writeln("}");
+    }
+
+    // Implement `render_block_into()`, which renders a single block with the
+    // template's context. A block using names bound by the code around one of
+    // its definitions cannot be rendered on its own, so asking for it returns
+    // an error, while the template as a whole still renders it in place.
+    fn write_render_block(&mut self, heritage: &'a Heritage, buf: &mut Buffer) {
+        let mut bindings: HashMap<&str, HashSet<&str>> = HashMap::new();
+        for ctx in self.contexts.values() {
+            for (name, bound) in block_bindings(ctx.nodes) {
+                bindings.entry(name).or_default().extend(bound);
+            }
+        }
+        let mut names: Vec<&str> = heritage.blocks.keys().copied().collect();
+        names.sort_unstable();
+
+        buf.writeln("#[inline(never)]");
+        buf.writeln("#[cold]");
+        buf.writeln(
+            "fn render_block_into(&self, block: &str, writer: &mut dyn ::std::fmt::Write) -> \
+             ::askama::Result<()> {",
+        );
+        self.write_filter_imports(buf);
+        buf.writeln("match block {");
+        for &name in &names {
+            let bound = bindings.remove(name).unwrap_or_default();
+            let mut outer: Vec<_> = heritage.blocks[name]
+                .iter()
+                .flat_map(|(_, def)| referenced_vars(&def.nodes))
+                .filter(|var| bound.contains(var))
+                .collect();
+            if !outer.is_empty() {
+                outer.sort_unstable();
+                outer.dedup();
+                let msg = format!(
+                    "block '{}' cannot be rendered on its own, since it uses names bound \
+                     outside of it: {}",
+                    name,
+                    outer.join(", ")
+                );
+                buf.writeln(&format!(
+                    "{:?} => Err(::askama::Error::Custom({:?}.into())),",
+                    name, msg
+                ));
+                continue;
+            }
+
+            buf.writeln(&format!("{:?} => {{", name));
+            let mut gen = Generator::new(
+                self.input,
+                self.contexts,
+                self.heritage,
+                self.integrations,
+                SetChain::new(),
+            );
+            gen.write_located_start(buf);
+            gen.write_block(buf, Some(name), WS(false, false));
+            gen.flush_ws(WS(false, false));
+            gen.write_buf_writable(buf);
+            gen.write_located_end(buf);
+            buf.writeln("}");
+        }
+        buf.writeln(
+            "_ => Err(::askama::Error::Custom(\
+             format!(\"no block named '{}'\", block).into())),",
+        );
+        buf.writeln("}");
+        buf.writeln("}");
+
+        // The most derived definition declaring a content type wins
+        let content_types: Vec<_> = names
+            .iter()
+            .filter_map(|name| {
+                let def = heritage.blocks[name]
+                    .iter()
+                    .find(|(_, def)| def.content_type.is_some())?;
+                Some((name, def.1.content_type?))
+            })
+            .collect();
+        if !content_types.is_empty() {
+            buf.writeln("#[cold]");
+            buf.writeln("fn block_content_type(&self, block: &str) -> Option<&'static str> {");
+            buf.writeln("match block {");
+            for (name, content_type) in content_types {
+                buf.writeln(&format!("{:?} => Some({:?}),", name, content_type));
+            }
+            buf.writeln("_ => None,");
+            buf.writeln("}");
+            buf.writeln("}");
+        }
+    }
+
+    // Renders the code that follows in a closure, so that any error it returns
+    // can be tagged with the location kept in `_loc` by `write_location()`
+    fn write_located_start(&mut self, buf: &mut Buffer) {
+        buf.writeln("#[allow(unused_mut, unused_assignments)]");
+        buf.writeln("let mut _loc = (\"\", 0);");
+        buf.writeln("let res = (|| -> ::askama::Result<()> {");
+    }
+
+    fn write_located_end(&mut self, buf: &mut Buffer) {
+        buf.writeln("Ok(())");
+        buf.dedent();
+        buf.writeln("})();");
+        buf.writeln("res.map_err(|err| ::askama::helpers::locate(err, _loc))");
+    }
+
+    // Sets `_loc` to the template file and line that `expr` comes from. The
+    // bodies of parallel loops are closures that cannot update it.
+    fn write_location(&mut self, buf: &mut Buffer, expr: &Expr) {
+        if self.loops.iter().any(|&(_, parallel)| parallel) {
+            return;
+        }
+        if let Some((template, line)) = expr_source(expr).and_then(|s| self.locate(s)) {
+            buf.writeln(&format!("_loc = ({:?}, {});", template, line));
+        }
+    }
+
+    // Finds the template that `s` was parsed from, and the line it starts on
+    fn locate(&self, s: &str) -> Option<(String, usize)> {
+        self.position(s)
+            .map(|(template, _, line, _)| (template, line))
+    }
+
+    // Finds the template that `s` was parsed from, its source, and the line
+    // and column that `s` starts on
+    fn position(&self, s: &str) -> Option<(String, &'a str, usize, usize)> {
+        let ptr = s.as_ptr() as usize;
+        self.contexts.iter().find_map(|(path, ctx)| {
+            let start = ctx.source.as_ptr() as usize;
+            if ptr < start || ptr > start + ctx.source.len() {
+                return None;
+            }
+            let before = &ctx.source[..ptr - start];
+            let line = before.matches('\n').count() + 1;
+            let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+            let column = before[line_start..].chars().count() + 1;
+            let name = self
+                .input
+                .config
+                .dirs
+                .iter()
+                .find_map(|dir| path.strip_prefix(dir).ok())
+                .unwrap_or(path);
+            Some((name.to_str().unwrap().to_string(), ctx.source, line, column))
+        })
+    }
+
+    // Rejects a numeric built-in filter applied to something that is known
+    // to be a string, naming the filter and where it is in the template, as
+    // rustc would otherwise report the mismatch in the generated code.
+    fn check_filter_input(&self, name: &str, args: &[WithSpan<Expr>]) {
+        if !NUMERIC_FILTERS.contains(&name) {
+            return;
+        }
+        let input = match args.first() {
+            Some(input) => input,
+            None => return,
+        };
+        let what = match **input {
+            Expr::StrLit(_) | Expr::RawStrLit(_) => "a string literal".to_string(),
+            Expr::Filter(inner, _) if STRING_FILTERS.contains(&inner) => {
+                format!("the string returned by filter '{}'", inner)
+            }
+            _ => return,
+        };
+        let mut msg = format!(
+            "filter '{}' expects a number, but its input is {}",
+            name, what
+        );
+        if let Some((template, source, line, column)) = self.position(name) {
+            if let Some(text) = source.get(input.span.clone()).filter(|s| !s.is_empty()) {
+                msg.push_str(&format!(": `{}`", text.trim()));
+            }
+            msg.push_str(&format!(
+                " (in {} at line {}, column {})",
+                template, line, column
+            ));
+        }
+        panic!("{}", msg);
+    }
+
     // Implement `Deref<Parent>` for an inheriting context struct.
     fn deref_to_parent(&mut self, buf: &mut Buffer, parent_type: &syn::Type) {
         self.write_header(buf, "::std::ops::Deref", None);
@@ -206,6 +580,13 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
         buf.writeln("}");
     }
 
+    // Implement `RegisteredTemplate` for templates with a `register` name.
+    fn impl_registered_template(&mut self, name: &str, buf: &mut Buffer) {
+        self.write_header(buf, "::askama::RegisteredTemplate", None);
+        buf.writeln(&format!("const NAME: &'static str = {:?};", name));
+        buf.writeln("}");
+    }
+
     // Implement Actix-web's `Responder`.
     fn impl_actix_web_responder(&mut self, buf: &mut Buffer) {
         self.write_header(buf, "::actix_web::Responder", None);
@@ -315,6 +696,21 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
         buf.writeln("}");
     }
 
+    // Implement poem's `IntoResponse`.
+    fn impl_poem_into_response(&mut self, buf: &mut Buffer) {
+        self.write_header(buf, "::askama_poem::poem::IntoResponse", None);
+        buf.writeln("fn into_response(self) -> ::askama_poem::poem::Response {");
+        let ext = self
+            .input
+            .path
+            .extension()
+            .and_then(|s| s.to_str())
+            .unwrap_or("txt");
+        buf.writeln(&format!("::askama_poem::into_response(&self, {:?})", ext));
+        buf.writeln("}");
+        buf.writeln("}");
+    }
+
     // Implement Rocket's `Responder`.
     fn impl_rocket_responder(&mut self, buf: &mut Buffer) {
         let lifetime = syn::Lifetime::new("'askama", Span::call_site());
@@ -339,6 +735,21 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
         buf.writeln("}");
     }
 
+    // Implement Salvo's `Scribe`, which makes templates usable as handler output.
+    fn impl_salvo_scribe(&mut self, buf: &mut Buffer) {
+        self.write_header(buf, "::askama_salvo::salvo::Scribe", None);
+        buf.writeln("fn render(self, res: &mut ::askama_salvo::salvo::Response) {");
+        let ext = self
+            .input
+            .path
+            .extension()
+            .and_then(|s| s.to_str())
+            .unwrap_or("txt");
+        buf.writeln(&format!("::askama_salvo::render(&self, {:?}, res)", ext));
+        buf.writeln("}");
+        buf.writeln("}");
+    }
+
     fn impl_tide_integrations(&mut self, buf: &mut Buffer) {
         let ext = self
             .input
@@ -390,7 +801,13 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
         let mut generics = self.input.ast.generics.clone();
         if let Some(params) = params {
             for param in params {
-                generics.params.push(param);
+                if let syn::GenericParam::Lifetime(_) = param {
+                    // Lifetimes must come before type and const parameters
+                    let pos = generics.lifetimes().count();
+                    generics.params.insert(pos, param);
+                } else {
+                    generics.params.push(param);
+                }
             }
         }
         let (_, orig_ty_generics, _) = self.input.ast.generics.split_for_impl();
@@ -412,45 +829,87 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
     fn handle(
         &mut self,
         ctx: &'a Context,
-        nodes: &'a [Node],
+        nodes: &'a [WithSpan<Node>],
         buf: &mut Buffer,
         level: AstLevel,
     ) -> usize {
         let mut size_hint = 0;
         for n in nodes {
-            match *n {
+            match **n {
                 Node::Lit(lws, val, rws) => {
                     self.visit_lit(lws, val, rws);
                 }
-                Node::Comment(ws) => {
+                Node::Comment(ws, _) => {
                     self.write_comment(ws);
                 }
+                Node::Expr(
+                    ws,
+                    WithSpan {
+                        inner: Expr::VarCall("super", ref args),
+                        ..
+                    },
+                ) if args.is_empty() => {
+                    size_hint += self.write_block(buf, None, ws);
+                }
+                // `{{ block.super }}` is an alternative spelling of `{{ super() }}`
+                Node::Expr(
+                    ws,
+                    WithSpan {
+                        inner: Expr::Attr(ref obj, "super"),
+                        ..
+                    },
+                ) if **obj == Expr::Var("block") => {
+                    size_hint += self.write_block(buf, None, ws);
+                }
                 Node::Expr(ws, ref val) => {
                     self.write_expr(ws, val);
                 }
                 Node::LetDecl(ws, ref var) => {
-                    self.write_let_decl(buf, ws, var);
+                    size_hint += self.write_let_decl(buf, ws, var);
                 }
                 Node::Let(ws, ref var, ref val) => {
                     self.write_let(buf, ws, var, val);
                 }
+                Node::Do(ws, ref val) => {
+                    size_hint += self.write_do(buf, ws, val);
+                }
+                Node::Trans(ws, id, ref args) => {
+                    size_hint += self.write_trans(buf, ws, id, args);
+                }
                 Node::Cond(ref conds, ws) => {
-                    self.write_cond(ctx, buf, conds, ws);
+                    size_hint += self.write_cond(ctx, buf, conds, ws);
                 }
                 Node::Match(ws1, ref expr, inter, ref arms, ws2) => {
-                    self.write_match(ctx, buf, ws1, expr, inter, arms, ws2);
+                    size_hint += self.write_match(ctx, buf, ws1, expr, inter, arms, ws2);
+                }
+                Node::Loop(ref loop_block) => {
+                    size_hint += self.write_loop(ctx, buf, loop_block);
+                }
+                Node::FilterBlock(ws1, ref filters, ref nodes, ws2) => {
+                    size_hint += self.write_filter_block(ctx, buf, ws1, filters, nodes, ws2);
+                }
+                Node::LetBlock(ws1, name, ref nodes, ws2) => {
+                    size_hint += self.write_let_block(ctx, buf, ws1, name, nodes, ws2);
                 }
-                Node::Loop(ws1, ref var, ref iter, ref body, ws2) => {
-                    self.write_loop(ctx, buf, ws1, var, iter, body, ws2);
+                Node::AutoEscape(ws1, ref mode, ref nodes, ws2) => {
+                    size_hint += self.write_autoescape(ctx, buf, ws1, mode, nodes, ws2);
                 }
-                Node::BlockDef(ws1, name, _, ws2) => {
-                    self.write_block(buf, Some(name), WS(ws1.0, ws2.1));
+                Node::With(ws1, ref vars, ref nodes, ws2) => {
+                    size_hint += self.write_with(ctx, buf, ws1, vars, nodes, ws2);
+                }
+                Node::BlockDef(ref b) => {
+                    size_hint += self.write_block(buf, Some(b.name), WS(b.ws1.0, b.ws2.1));
                 }
                 Node::Include(ws, path) => {
                     size_hint += self.handle_include(ctx, buf, ws, path);
                 }
                 Node::Call(ws, scope, name, ref args) => {
-                    size_hint += self.write_call(ctx, buf, ws, scope, name, args);
+                    size_hint += self.write_call(ctx, buf, ws, scope, name, args, None);
+                }
+                Node::CallBlock(ws1, scope, name, ref args, ref nodes, ws2) => {
+                    let ws = WS(ws1.0, ws2.1);
+                    let caller = Some((ws1.1, &nodes[..], ws2.0));
+                    size_hint += self.write_call(ctx, buf, ws, scope, name, args, caller);
                 }
                 Node::Macro(_, ref m) => {
                     if level != AstLevel::Top {
@@ -461,10 +920,25 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
                 }
                 Node::Raw(ws1, contents, ws2) => {
                     self.handle_ws(ws1);
-                    self.buf_writable.push(Writable::Lit(contents));
+                    let mut contents = contents;
+                    if ws1.1 {
+                        contents = contents.trim_start();
+                    }
+                    if ws2.0 {
+                        contents = contents.trim_end();
+                    }
+                    if !contents.is_empty() {
+                        self.buf_writable.push(Writable::Lit(contents));
+                    }
                     self.handle_ws(ws2);
                 }
-                Node::Import(ws, _, _) => {
+                Node::Break(ws, label) => {
+                    size_hint += self.write_loop_control(buf, ws, "break", label);
+                }
+                Node::Continue(ws, label) => {
+                    size_hint += self.write_loop_control(buf, ws, "continue", label);
+                }
+                Node::Import(ws, _, _) | Node::FromImport(ws, _, _) => {
                     if level != AstLevel::Top {
                         panic!("import blocks only allowed at the top level");
                     }
@@ -506,6 +980,7 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
             match *cond {
                 Some(ref expr) => {
                     if i == 0 {
+                        self.write_location(buf, expr);
                         buf.write("if ");
                     } else {
                         buf.dedent();
@@ -559,54 +1034,21 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
             }
         }
 
+        self.write_location(buf, expr);
         let expr_code = self.visit_expr_root(expr);
         buf.writeln(&format!("match &{} {{", expr_code));
         for arm in arms {
-            let &(ws, ref variant, ref params, ref body) = arm;
+            let &(ws, ref patterns, ref guard, ref body) = arm;
             self.locals.push();
-            match *variant {
-                Some(ref param) => {
-                    self.visit_match_variant(buf, param);
-                }
-                None => buf.write("_"),
-            };
-
-            match params {
-                MatchParameters::Simple(params) => {
-                    if !params.is_empty() {
-                        buf.write("(");
-                        for (i, param) in params.iter().enumerate() {
-                            if let MatchParameter::Name(p) = *param {
-                                self.locals.insert(p);
-                            }
-                            if i > 0 {
-                                buf.write(", ");
-                            }
-                            self.visit_match_param(buf, param);
-                        }
-                        buf.write(")");
-                    }
-                }
-                MatchParameters::Named(params) => {
-                    buf.write("{");
-                    for (i, param) in params.iter().enumerate() {
-                        if let Some(MatchParameter::Name(p)) = param.1 {
-                            self.locals.insert(p);
-                        } else {
-                            self.locals.insert(param.0);
-                        }
-
-                        if i > 0 {
-                            buf.write(", ");
-                        }
-                        buf.write(param.0);
-                        if let Some(param) = &param.1 {
-                            buf.write(":");
-                            self.visit_match_param(buf, &param);
-                        }
-                    }
-                    buf.write("}");
+            for (i, (variant, params)) in patterns.iter().enumerate() {
+                if i > 0 {
+                    buf.write(" | ");
                 }
+                self.write_match_pattern(buf, variant, params);
+            }
+            if let Some(guard) = guard {
+                let guard = self.visit_expr_root(guard);
+                buf.write(&format!(" if {}", guard));
             }
             buf.writeln(" => {");
             self.handle_ws(ws);
@@ -621,53 +1063,326 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
         flushed + median(&mut arm_sizes)
     }
 
-    #[allow(clippy::too_many_arguments)]
-    fn write_loop(
+    fn write_match_pattern(
         &mut self,
-        ctx: &'a Context,
         buf: &mut Buffer,
-        ws1: WS,
-        var: &'a Target,
-        iter: &Expr,
-        body: &'a [Node],
-        ws2: WS,
-    ) -> usize {
-        self.handle_ws(ws1);
+        variant: &'a Option<MatchVariant>,
+        params: &'a MatchParameters,
+    ) {
+        match *variant {
+            Some(ref param) => {
+                self.visit_match_variant(buf, param);
+            }
+            None => buf.write("_"),
+        };
+
+        match params {
+            MatchParameters::Simple(params) => {
+                if !params.is_empty() {
+                    buf.write("(");
+                    for (i, param) in params.iter().enumerate() {
+                        if let MatchParameter::Name(p) = *param {
+                            self.locals.insert(p);
+                        }
+                        if i > 0 {
+                            buf.write(", ");
+                        }
+                        self.visit_match_param(buf, param);
+                    }
+                    buf.write(")");
+                }
+            }
+            MatchParameters::Named(params) => {
+                buf.write("{");
+                for (i, param) in params.iter().enumerate() {
+                    if let Some(MatchParameter::Name(p)) = param.1 {
+                        self.locals.insert(p);
+                    } else {
+                        self.locals.insert(param.0);
+                    }
+
+                    if i > 0 {
+                        buf.write(", ");
+                    }
+                    buf.write(param.0);
+                    if let Some(param) = &param.1 {
+                        buf.write(":");
+                        self.visit_match_param(buf, &param);
+                    }
+                }
+                buf.write("}");
+            }
+        }
+    }
+
+    fn write_loop(&mut self, ctx: &'a Context, buf: &mut Buffer, loop_block: &'a Loop) -> usize {
+        self.handle_ws(loop_block.ws1);
         self.locals.push();
 
-        let expr_code = self.visit_expr_root(iter);
+        check_loop_iter(&loop_block.iter);
+        // `range()` is only built in if no local variable or field shadows it
+        let range_call = match *loop_block.iter {
+            Expr::VarCall("range", ref args)
+                if !self.locals.contains("range") && !self.is_field("range") =>
+            {
+                Some(args)
+            }
+            _ => None,
+        };
+        let expr_code = match range_call {
+            Some(args) => self.visit_range_call(args),
+            None => self.visit_expr_root(&loop_block.iter),
+        };
 
-        let flushed = self.write_buf_writable(buf);
-        buf.write("for (");
-        self.visit_target(buf, var);
-        match iter {
-            Expr::Range(_, _, _) => buf.writeln(&format!(
-                ", _loop_item) in ::askama::helpers::TemplateLoop::new({}) {{",
+        let iter_code = match *loop_block.iter {
+            _ if range_call.is_some() => expr_code,
+            Expr::Range(_, _, _) => expr_code,
+            _ => format!(
+                "::askama::helpers::check_iterable(&{}).into_iter()",
                 expr_code
-            )),
-            _ => buf.writeln(&format!(
-                ", _loop_item) in ::askama::helpers::TemplateLoop::new((&{}).into_iter()) {{",
-                expr_code
-            )),
+            ),
         };
 
-        let mut size_hint = self.handle(ctx, body, buf, AstLevel::Nested);
-        self.handle_ws(ws2);
+        let flushed = self.write_buf_writable(buf);
+        self.write_location(buf, &loop_block.iter);
+        if loop_block.parallel && !self.integrations.rayon {
+            panic!("parallel loops require askama's `rayon` feature");
+        }
+        if loop_block.parallel && loop_block.label.is_some() {
+            panic!("parallel loops cannot be labeled");
+        }
+        if let Some(label) = loop_block.label {
+            if !uses_loop_label(&loop_block.body, label) {
+                panic!(
+                    "loop label '{}' is not used by any break or continue",
+                    label
+                );
+            }
+        }
+        self.loops.push((loop_block.label, loop_block.parallel));
+        if loop_block.parallel {
+            // Each iteration is rendered into its own buffer by a closure
+            buf.write(&format!(
+                "::askama::helpers::render_parallel(writer, {}, |",
+                iter_code
+            ));
+            self.visit_target(buf, &loop_block.var);
+            buf.writeln(", _loop_item, writer| -> ::askama::Result<()> {");
+        } else {
+            // The iterator is bound to a name, so `loop.nextitem` can peek at it.
+            // Like a `for` loop, this matches on it to keep temporaries alive.
+            // `loop.remaining` needs an iterator of known length, which the
+            // constructor requires, so that other iterators fail to compile.
+            let constructor = if uses_loop_remaining(&loop_block.body) {
+                "with_remaining"
+            } else {
+                "new"
+            };
+            buf.writeln(&format!(
+                "match ::askama::helpers::TemplateLoop::{}({}) {{",
+                constructor, iter_code
+            ));
+            buf.writeln("mut _loop_iter => {");
+            if let Some(label) = loop_block.label {
+                buf.write(&format!("'{}: ", label));
+            }
+            buf.write("while let Some((");
+            self.visit_target(buf, &loop_block.var);
+            buf.writeln(", _loop_item)) = _loop_iter.next() {");
+        }
+        if let Some(index) = loop_block.index {
+            buf.writeln(&format!("let {} = _loop_item.index;", index));
+            self.locals.insert(index);
+        }
+
+        let mut size_hint = self.handle(ctx, &loop_block.body, buf, AstLevel::Nested);
+        self.handle_ws(loop_block.ws2);
 
         size_hint += self.write_buf_writable(buf);
-        buf.writeln("}");
+        if loop_block.parallel {
+            buf.writeln("Ok(())");
+            buf.dedent();
+            buf.writeln("})?;");
+        } else {
+            buf.writeln("}");
+            buf.writeln("}");
+            buf.writeln("}");
+        }
+        self.loops.pop();
         self.locals.pop();
         flushed + (size_hint * 3)
     }
 
-    fn write_call(
+    fn write_filter_block(
         &mut self,
         ctx: &'a Context,
         buf: &mut Buffer,
-        ws: WS,
-        scope: Option<&str>,
-        name: &str,
-        args: &[Expr],
+        ws1: WS,
+        filters: &'a [(&'a str, Vec<WithSpan<Expr<'a>>>)],
+        nodes: &'a [WithSpan<Node<'a>>],
+        ws2: WS,
+    ) -> usize {
+        self.handle_ws(ws1);
+        let flushed = self.write_buf_writable(buf);
+
+        // The body is rendered into a string, which is then written out
+        // through the filters
+        buf.writeln("{");
+        buf.writeln("let mut _filter_block = ::std::string::String::new();");
+        buf.writeln("{");
+        buf.writeln("let writer: &mut dyn ::std::fmt::Write = &mut _filter_block;");
+        self.locals.push();
+        let mut size_hint = self.handle(ctx, nodes, buf, AstLevel::Nested);
+        self.handle_ws(ws2);
+        size_hint += self.write_buf_writable(buf);
+        self.locals.pop();
+        buf.writeln("}");
+
+        let mut expr = WithSpan::no_span(Expr::Var("_filter_block"));
+        for (name, args) in filters {
+            let mut args = args.clone();
+            args.insert(0, expr);
+            expr = WithSpan::no_span(Expr::Filter(name, args));
+        }
+        self.locals.push();
+        self.locals.insert("_filter_block");
+        let mut expr_buf = Buffer::new(0);
+        // The body has been escaped already, so only an explicit escaper
+        // filter in the chain escapes it again
+        let expression = match self.visit_expr(&mut expr_buf, &expr) {
+            DisplayWrap::Wrapped => expr_buf.buf,
+            DisplayWrap::Unwrapped => format!(
+                "::askama::MarkupDisplay::new_safe(&{}, {})",
+                expr_buf.buf, self.escaper
+            ),
+        };
+        self.locals.pop();
+        buf.writeln(&format!("write!(writer, \"{{}}\", {})?;", expression));
+        buf.writeln("}");
+        flushed + size_hint
+    }
+
+    fn write_with(
+        &mut self,
+        ctx: &'a Context,
+        buf: &mut Buffer,
+        ws1: WS,
+        vars: &'a [(&'a str, WithSpan<Expr<'a>>)],
+        nodes: &'a [WithSpan<Node<'a>>],
+        ws2: WS,
+    ) -> usize {
+        self.handle_ws(ws1);
+        let flushed = self.write_buf_writable(buf);
+
+        buf.writeln("{");
+        self.locals.push();
+        for (name, val) in vars {
+            let mut expr_buf = Buffer::new(0);
+            self.visit_expr(&mut expr_buf, val);
+            buf.writeln(&format!("let {} = {};", name, expr_buf.buf));
+            self.locals.insert(name);
+        }
+        let mut size_hint = self.handle(ctx, nodes, buf, AstLevel::Nested);
+        self.handle_ws(ws2);
+        size_hint += self.write_buf_writable(buf);
+        self.locals.pop();
+        buf.writeln("}");
+        flushed + size_hint
+    }
+
+    fn write_autoescape(
+        &mut self,
+        ctx: &'a Context,
+        buf: &mut Buffer,
+        ws1: WS,
+        mode: &Expr,
+        nodes: &'a [WithSpan<Node<'a>>],
+        ws2: WS,
+    ) -> usize {
+        let escaper = match *mode {
+            Expr::BoolLit("true") => self.input.escaper,
+            Expr::BoolLit(_) => "::askama::Text",
+            Expr::StrLit(ext) => self
+                .input
+                .config
+                .escapers
+                .iter()
+                .find(|(extensions, _)| extensions.contains(ext))
+                .map(|(_, path)| path.as_str())
+                .unwrap_or_else(|| panic!("no escaper defined for extension '{}'", ext)),
+            _ => panic!("autoescape takes true, false or an extension"),
+        };
+
+        // Expressions are escaped when the writable buffer is flushed, so
+        // flush it whenever the escaper changes
+        self.handle_ws(ws1);
+        let flushed = self.write_buf_writable(buf);
+        let outer = mem::replace(&mut self.escaper, escaper);
+        let mut size_hint = self.handle(ctx, nodes, buf, AstLevel::Nested);
+        self.handle_ws(ws2);
+        size_hint += self.write_buf_writable(buf);
+        self.escaper = outer;
+        flushed + size_hint
+    }
+
+    fn write_loop_control(
+        &mut self,
+        buf: &mut Buffer,
+        ws: WS,
+        kw: &str,
+        label: Option<&str>,
+    ) -> usize {
+        let depth = match label {
+            Some(label) => self
+                .loops
+                .iter()
+                .rposition(|&(l, _)| l == Some(label))
+                .unwrap_or_else(|| panic!("no enclosing loop labeled '{}'", label)),
+            None if self.loops.is_empty() => panic!("{} used outside of a loop", kw),
+            None => self.loops.len() - 1,
+        };
+        if self.loops[depth..].iter().any(|&(_, parallel)| parallel) {
+            panic!("{} cannot be used in parallel loops", kw);
+        }
+
+        self.handle_ws(ws);
+        let flushed = self.write_buf_writable(buf);
+        match label {
+            Some(label) => buf.writeln(&format!("{} '{};", kw, label)),
+            None => buf.writeln(&format!("{};", kw)),
+        }
+        flushed
+    }
+
+    // Generates the iterator for `range(start, stop)` or `range(start, stop, step)`
+    // in the iterable position of a `for` loop.
+    fn visit_range_call(&mut self, args: &[WithSpan<Expr>]) -> String {
+        if args.len() != 2 && args.len() != 3 {
+            panic!("range() takes a start, a stop and an optional step argument");
+        }
+        let start = self.visit_expr_root(&args[0]);
+        let stop = self.visit_expr_root(&args[1]);
+        match args.get(2) {
+            Some(step) => format!(
+                "::askama::helpers::range_step({}, {}, {})?",
+                start,
+                stop,
+                self.visit_expr_root(step)
+            ),
+            None => format!("({})..({})", start, stop),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn write_call(
+        &mut self,
+        ctx: &'a Context,
+        buf: &mut Buffer,
+        ws: WS,
+        scope: Option<&str>,
+        name: &str,
+        args: &[WithSpan<Expr>],
+        caller: Option<(bool, &'a [WithSpan<Node<'a>>], bool)>,
     ) -> usize {
         if name == "super" {
             return self.write_block(buf, None, ws);
@@ -688,31 +1403,104 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
                     .unwrap_or_else(|| panic!("macro '{}' not found in scope '{}'", s, name)),
                 mctx,
             )
-        } else {
+        } else if let Some(def) = ctx.macros.get(name) {
+            (def, ctx)
+        } else if let Some((path, imported)) = ctx.from_imports.get(name) {
+            let mctx = self
+                .contexts
+                .get(path)
+                .unwrap_or_else(|| panic!("context for '{:?}' not found", path));
             (
-                ctx.macros
-                    .get(name)
-                    .unwrap_or_else(|| panic!("macro '{}' not found", name)),
-                ctx,
+                mctx.macros
+                    .get(imported)
+                    .unwrap_or_else(|| panic!("macro '{}' not found in {:?}", imported, path)),
+                mctx,
             )
+        } else {
+            panic!("macro '{}' not found", name)
         };
 
         self.flush_ws(ws); // Cannot handle_ws() here: whitespace from macro definition comes first
         self.locals.push();
-        self.write_buf_writable(buf);
+        let mut size_hint = self.write_buf_writable(buf);
         buf.writeln("{");
+
+        // The body of the call is rendered up front in the caller's scope,
+        // before any of the macro's arguments are bound
+        if let Some((trim_start, nodes, trim_end)) = caller {
+            self.prepare_ws(WS(false, trim_start));
+            buf.writeln("let _caller = {");
+            buf.writeln("let mut _caller = ::std::string::String::new();");
+            buf.writeln("{");
+            buf.writeln("let writer: &mut dyn ::std::fmt::Write = &mut _caller;");
+            self.locals.push();
+            size_hint += self.handle(ctx, nodes, buf, AstLevel::Nested);
+            self.flush_ws(WS(trim_end, false));
+            size_hint += self.write_buf_writable(buf);
+            self.locals.pop();
+            buf.writeln("}");
+            buf.writeln("_caller");
+            buf.dedent();
+            buf.writeln("};");
+            self.locals.insert("_caller");
+        }
         self.prepare_ws(def.ws1);
 
-        for (i, arg) in def.args.iter().enumerate() {
-            let expr_code = self.visit_expr_root(
-                args.get(i)
-                    .unwrap_or_else(|| panic!("macro '{}' takes more than {} arguments", name, i)),
-            );
+        let mut positional = Vec::new();
+        let mut named = Vec::new();
+        for arg in args {
+            match **arg {
+                Expr::NamedArgument(arg_name, ref value) => named.push((arg_name, &**value)),
+                _ if !named.is_empty() => panic!(
+                    "positional argument follows named arguments in call to macro '{}'",
+                    name
+                ),
+                _ => positional.push(arg),
+            }
+        }
+
+        for (i, (arg, default)) in def.args.iter().enumerate() {
+            let value = match named.iter().position(|&(arg_name, _)| arg_name == *arg) {
+                Some(_) if i < positional.len() => panic!(
+                    "macro '{}' got multiple values for argument '{}'",
+                    name, arg
+                ),
+                Some(pos) => named.remove(pos).1,
+                None => match (positional.get(i), default) {
+                    (Some(value), _) => value,
+                    (None, Some(default)) => default,
+                    (None, None) => panic!("macro '{}' takes more than {} arguments", name, i),
+                },
+            };
+            let expr_code = self.visit_expr_root(value);
             buf.writeln(&format!("let {} = &{};", arg, expr_code));
             self.locals.insert(arg);
         }
 
-        let mut size_hint = self.handle(own_ctx, &def.nodes, buf, AstLevel::Nested);
+        // Named arguments that don't match a parameter are collected into
+        // the `**kwargs` parameter, if the macro has one
+        match def.kwargs {
+            Some(kwargs) => {
+                let mut entries = Vec::with_capacity(named.len());
+                for (arg_name, value) in named {
+                    let expr_code = self.visit_expr_root(value);
+                    entries.push(format!("({:?}, ({}).to_string())", arg_name, expr_code));
+                }
+                buf.writeln(&format!(
+                    "let {}: ::std::collections::BTreeMap<&str, String> = vec![{}].into_iter().collect();",
+                    kwargs,
+                    entries.join(", ")
+                ));
+                self.locals.insert(kwargs);
+            }
+            None => {
+                if let Some((arg_name, _)) = named.first() {
+                    panic!("macro '{}' has no argument named '{}'", name, arg_name);
+                }
+            }
+        }
+
+        size_hint += self.handle(own_ctx, &def.nodes, buf, AstLevel::Nested);
 
         self.flush_ws(def.ws2);
         size_hint += self.write_buf_writable(buf);
@@ -724,13 +1512,18 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
 
     fn handle_include(&mut self, ctx: &'a Context, buf: &mut Buffer, ws: WS, path: &str) -> usize {
         self.flush_ws(ws);
-        self.write_buf_writable(buf);
+        let flushed = self.write_buf_writable(buf);
         let path = self
             .input
             .config
             .find_template(path, Some(&self.input.path));
-        let src = get_template_source(&path);
-        let nodes = parse(&src, self.input.syntax);
+        // Included templates are parsed up front, once per file, along with
+        // the other templates this one depends on
+        let nodes = self
+            .contexts
+            .get(&path)
+            .unwrap_or_else(|| panic!("included template {:?} was not parsed", path))
+            .nodes;
 
         // Make sure the compiler understands that the generated code depends on the template file.
         {
@@ -744,41 +1537,32 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
         }
 
         let size_hint = {
-            // Since nodes must not outlive the Generator, we instantiate
-            // a nested Generator here to handle the include's nodes.
+            // Use a nested Generator, so the included nodes get their own
+            // scope for local variables.
             let mut gen = self.child();
-            let mut size_hint = gen.handle(ctx, &nodes, buf, AstLevel::Nested);
+            let mut size_hint = gen.handle(ctx, nodes, buf, AstLevel::Nested);
             size_hint += gen.write_buf_writable(buf);
             size_hint
         };
         self.prepare_ws(ws);
-        size_hint
+        flushed + size_hint
     }
 
-    fn write_let_decl(&mut self, buf: &mut Buffer, ws: WS, var: &'a Target) {
+    fn write_let_decl(&mut self, buf: &mut Buffer, ws: WS, var: &'a Target) -> usize {
         self.handle_ws(ws);
-        self.write_buf_writable(buf);
+        let flushed = self.write_buf_writable(buf);
         buf.write("let ");
         match *var {
-            Target::Name(name) => {
-                self.locals.insert(name);
-                buf.write(name);
-            }
-            Target::Tuple(ref targets) => {
-                buf.write("(");
-                for name in targets {
-                    self.locals.insert(name);
-                    buf.write(name);
-                    buf.write(",");
-                }
-                buf.write(")");
-            }
+            Target::Struct(..) => panic!("cannot declare struct pattern without a value"),
+            ref target => self.visit_target(buf, target),
         }
         buf.writeln(";");
+        flushed
     }
 
     fn write_let(&mut self, buf: &mut Buffer, ws: WS, var: &'a Target, val: &Expr) {
         self.handle_ws(ws);
+        self.write_location(buf, val);
         let mut expr_buf = Buffer::new(0);
         self.visit_expr(&mut expr_buf, val);
 
@@ -790,19 +1574,165 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
                 }
                 buf.write(name);
             }
-            Target::Tuple(ref targets) => {
-                buf.write("let (");
-                for name in targets {
-                    self.locals.insert(name);
-                    buf.write(name);
-                    buf.write(",");
-                }
-                buf.write(")");
+            ref target => {
+                buf.write("let ");
+                self.visit_target(buf, target);
             }
         }
         buf.writeln(&format!(" = {};", &expr_buf.buf));
     }
 
+    fn write_do(&mut self, buf: &mut Buffer, ws: WS, val: &Expr) -> usize {
+        self.handle_ws(ws);
+        let flushed = self.write_buf_writable(buf);
+        self.write_location(buf, val);
+        let expr = self.visit_expr_root(val);
+        buf.writeln(&format!("let _ = {};", expr));
+        flushed
+    }
+
+    // Formats a message for the `locale` in scope. The message and its arguments
+    // are checked against the resources of all locales here, and the resources
+    // are embedded in the generated code.
+    #[cfg(feature = "i18n")]
+    fn write_trans(
+        &mut self,
+        buf: &mut Buffer,
+        ws: WS,
+        id: &str,
+        args: &[(&str, WithSpan<Expr>)],
+    ) -> usize {
+        self.handle_ws(ws);
+        let flushed = self.write_buf_writable(buf);
+
+        let i18n = &self.input.config.i18n;
+        let resources = Rc::clone(&self.i18n);
+        let resources = resources.get_or_init(|| i18n_resources(&i18n.dir));
+        match resources
+            .iter()
+            .find(|(locale, _, _)| locale == i18n.fallback)
+        {
+            Some((_, _, res)) if res.has_message(id) => {}
+            Some(_) => panic!("no message '{}' for locale '{}'", id, i18n.fallback),
+            None => panic!(
+                "no resource for fallback locale '{}' in {:?}",
+                i18n.fallback, i18n.dir
+            ),
+        }
+
+        let mut expected = BTreeSet::new();
+        for (_, _, res) in resources {
+            expected.extend(res.message_args(id).unwrap_or_default());
+        }
+        for (name, _) in args {
+            if !expected.contains(*name) {
+                panic!("message '{}' has no argument '{}'", id, name);
+            }
+        }
+        for name in &expected {
+            if !args.iter().any(|(arg, _)| arg == name) {
+                panic!("message '{}' needs argument '{}'", id, name);
+            }
+        }
+
+        buf.writeln("{");
+        let locale = self.visit_expr_root(&Expr::Var(&self.input.locale));
+        buf.write(&format!(
+            "let _msg = {}.format(&{}, {:?}, &[",
+            self.i18n_bundles_name(),
+            locale,
+            id
+        ));
+        for (name, arg) in args {
+            let arg = self.visit_expr_root(arg);
+            buf.write(&format!(
+                "({:?}, ::askama::i18n::ToValue::to_value(&({}))), ",
+                name, arg
+            ));
+        }
+        buf.writeln("]);");
+        buf.writeln(&format!(
+            "write!(writer, \"{{}}\", ::askama::MarkupDisplay::new_unsafe(&_msg, {}))?;",
+            self.escaper
+        ));
+        buf.writeln("}");
+        flushed + 3
+    }
+
+    // The resources used by `trans` tags are parsed once for the template,
+    // in a static next to the generated impls
+    #[cfg(feature = "i18n")]
+    fn write_i18n_bundles(&self, buf: &mut Buffer) {
+        let resources = match self.i18n.get() {
+            Some(resources) => resources,
+            None => return,
+        };
+        buf.writeln(&format!(
+            "static {}: ::askama::i18n::Bundles = ::askama::i18n::Bundles::new({:?}, &[",
+            self.i18n_bundles_name(),
+            self.input.config.i18n.fallback
+        ));
+        for (locale, path, _) in resources {
+            buf.writeln(&format!("({:?}, include_str!({:?})),", locale, path));
+        }
+        buf.writeln("]);");
+    }
+
+    #[cfg(feature = "i18n")]
+    fn i18n_bundles_name(&self) -> String {
+        format!(
+            "ASKAMA_I18N_{}",
+            self.input.ast.ident.to_string().to_uppercase()
+        )
+    }
+
+    #[cfg(not(feature = "i18n"))]
+    fn write_i18n_bundles(&self, _: &mut Buffer) {}
+
+    #[cfg(not(feature = "i18n"))]
+    fn write_trans(
+        &mut self,
+        _: &mut Buffer,
+        _: WS,
+        _: &str,
+        _: &[(&str, WithSpan<Expr>)],
+    ) -> usize {
+        panic!("the `trans` tag requires the `i18n` feature");
+    }
+
+    fn write_let_block(
+        &mut self,
+        ctx: &'a Context,
+        buf: &mut Buffer,
+        ws1: WS,
+        name: &'a str,
+        nodes: &'a [WithSpan<Node<'a>>],
+        ws2: WS,
+    ) -> usize {
+        self.handle_ws(ws1);
+        let flushed = self.write_buf_writable(buf);
+
+        if !self.locals.contains(name) {
+            buf.write("let ");
+        }
+        buf.writeln(&format!("{} = {{", name));
+        buf.writeln("let mut _let_block = ::std::string::String::new();");
+        buf.writeln("{");
+        buf.writeln("let writer: &mut dyn ::std::fmt::Write = &mut _let_block;");
+        self.locals.push();
+        self.handle(ctx, nodes, buf, AstLevel::Nested);
+        self.handle_ws(ws2);
+        self.write_buf_writable(buf);
+        self.locals.pop();
+        buf.writeln("}");
+        buf.writeln("_let_block");
+        buf.dedent();
+        buf.writeln("};");
+
+        self.locals.insert(name);
+        flushed
+    }
+
     // If `name` is `Some`, this is a call to a block definition, and we have to find
     // the first block for that name from the ancestry chain. If name is `None`, this
     // is from a `super()` call, and we can get the name from `self.super_block`.
@@ -839,31 +1769,66 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
                 Some(name) => panic!("no block found for name '{}'", name),
             });
 
-        // Get the nodes and whitespace suppression data from the block definition
-        let (ws1, nodes, ws2) = if let Node::BlockDef(ws1, _, nodes, ws2) = def {
-            (ws1, nodes, ws2)
-        } else {
-            unreachable!()
-        };
+        // A cached block is rendered once per template instance into its
+        // `block_cache` field. Its output may depend on the fields, but not on
+        // the variables of the code around the block (including `loop`), which
+        // can change between places where it is rendered, or on functions
+        // called by path, like `chrono::Utc::now()`.
+        let mut flushed = 0;
+        if def.cache {
+            let locals: Vec<_> = referenced_vars(&def.nodes)
+                .into_iter()
+                .filter(|&var| {
+                    self.locals.contains(var) || (var == "loop" && !self.loops.is_empty())
+                })
+                .collect();
+            if !locals.is_empty() {
+                panic!(
+                    "cached block '{}' cannot reference variables bound outside it: {}",
+                    def.name,
+                    locals.join(", ")
+                );
+            }
+            if let Some(path) = find_path_call(&def.nodes) {
+                panic!(
+                    "cached block '{}' cannot call '{}'",
+                    def.name,
+                    path.join("::")
+                );
+            }
+
+            flushed += self.write_buf_writable(buf);
+            buf.writeln(&format!(
+                "self.{}.render_into({:?}, writer, |writer| {{",
+                self.input.block_cache, def.name
+            ));
+        }
 
         // Handle inner whitespace suppression spec and process block nodes
-        self.prepare_ws(*ws1);
+        self.prepare_ws(def.ws1);
         self.locals.push();
-        let size_hint = self.handle(ctx, nodes, buf, AstLevel::Block);
+        let mut size_hint = self.handle(ctx, &def.nodes, buf, AstLevel::Block);
 
         if !self.locals.is_current_empty() {
             // Need to flush the buffer before popping the variable stack
-            self.write_buf_writable(buf);
+            size_hint += self.write_buf_writable(buf);
         }
 
         self.locals.pop();
-        self.flush_ws(*ws2);
+        self.flush_ws(def.ws2);
+
+        if def.cache {
+            size_hint += self.write_buf_writable(buf);
+            buf.writeln("Ok(())");
+            buf.dedent();
+            buf.writeln("})?;");
+        }
 
         // Restore original block context and set whitespace suppression for
         // succeeding whitespace according to the outer WS spec
         self.super_block = prev_block;
         self.prepare_ws(outer);
-        size_hint
+        flushed + size_hint
     }
 
     fn write_expr(&mut self, ws: WS, s: &'a Expr<'a>) {
@@ -892,6 +1857,14 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
             return buf_lit.buf.len();
         }
 
+        let first = self.buf_writable.iter().find_map(|w| match *w {
+            Writable::Expr(expr) => Some(expr),
+            Writable::Lit(_) => None,
+        });
+        if let Some(expr) = first {
+            self.write_location(buf, expr);
+        }
+
         let mut size_hint = 0;
         let mut buf_format = Buffer::new(0);
         let mut buf_expr = Buffer::new(buf.indent + 1);
@@ -906,11 +1879,19 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
                     use self::DisplayWrap::*;
                     let mut expr_buf = Buffer::new(0);
                     let wrapped = self.visit_expr(&mut expr_buf, s);
-                    let expression = match wrapped {
-                        Wrapped => expr_buf.buf,
-                        Unwrapped => format!(
+                    let number = match self.escaper {
+                        "::askama::Html" | "::askama::Text" => number_kind(s),
+                        _ => None,
+                    };
+                    let expression = match (wrapped, number) {
+                        // Numbers never need escaping
+                        (Unwrapped, Some(kind)) => {
+                            format!("::askama::helpers::{}({})", kind, expr_buf.buf)
+                        }
+                        (Wrapped, _) => expr_buf.buf,
+                        (Unwrapped, None) => format!(
                             "::askama::MarkupDisplay::new_unsafe(&{}, {})",
-                            expr_buf.buf, self.input.escaper
+                            expr_buf.buf, self.escaper
                         ),
                     };
 
@@ -927,7 +1908,7 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
                     });
 
                     buf_format.write(&format!("{{expr{}}}", id));
-                    size_hint += 3;
+                    size_hint += expr_size_hint(s);
                 }
             }
         }
@@ -981,6 +1962,10 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
             Expr::BoolLit(s) => self.visit_bool_lit(buf, s),
             Expr::NumLit(s) => self.visit_num_lit(buf, s),
             Expr::StrLit(s) => self.visit_str_lit(buf, s),
+            Expr::RawStrLit(s) => {
+                buf.write(s);
+                DisplayWrap::Unwrapped
+            }
             Expr::CharLit(s) => self.visit_char_lit(buf, s),
             Expr::Var(s) => self.visit_var(buf, s),
             Expr::VarCall(var, ref args) => self.visit_var_call(buf, var, args),
@@ -990,7 +1975,9 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
             Expr::Attr(ref obj, name) => self.visit_attr(buf, obj, name),
             Expr::Index(ref obj, ref key) => self.visit_index(buf, obj, key),
             Expr::Filter(name, ref args) => self.visit_filter(buf, name, args),
+            Expr::Test(name, ref args) => self.visit_test(buf, name, args),
             Expr::Unary(op, ref inner) => self.visit_unary(buf, op, inner),
+            Expr::As(ref expr, target) => self.visit_as(buf, expr, target),
             Expr::BinOp(op, ref left, ref right) => self.visit_binop(buf, op, left, right),
             Expr::Range(op, ref left, ref right) => self.visit_range(buf, op, left, right),
             Expr::Group(ref inner) => self.visit_group(buf, inner),
@@ -998,6 +1985,13 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
                 self.visit_method_call(buf, obj, method, args)
             }
             Expr::RustMacro(name, args) => self.visit_rust_macro(buf, name, args),
+            Expr::IfElse(ref cond, ref then, ref otherwise) => {
+                self.visit_if_else(buf, cond, then, otherwise)
+            }
+            Expr::Try(ref expr) => self.visit_try(buf, expr),
+            Expr::NamedArgument(name, _) => {
+                panic!("named argument '{}' only allowed in macro calls", name)
+            }
         }
     }
 
@@ -1047,7 +2041,15 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
         wrapped
     }
 
-    fn visit_filter(&mut self, buf: &mut Buffer, name: &str, args: &[Expr]) -> DisplayWrap {
+    fn visit_filter(
+        &mut self,
+        buf: &mut Buffer,
+        name: &str,
+        args: &[WithSpan<Expr>],
+    ) -> DisplayWrap {
+        self.check_filter_input(name, args);
+        let args = filter_positional_args(name, args);
+        let args = &args[..];
         if name == "format" {
             self._visit_format_filter(buf, args);
             return DisplayWrap::Unwrapped;
@@ -1057,31 +2059,89 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
         } else if name == "join" {
             self._visit_join_filter(buf, args);
             return DisplayWrap::Unwrapped;
+        } else if name == "partition" {
+            self._visit_partition_filter(buf, args);
+            return DisplayWrap::Unwrapped;
+        } else if name == "map" || name == "groupby" {
+            self._visit_attr_filter(buf, name, args);
+            return DisplayWrap::Unwrapped;
+        } else if name == "flatten"
+            || name == "dedup"
+            || name == "enumerate"
+            || name == "unique"
+            || name == "dictsort"
+            || name == "take"
+            || name == "skip"
+        {
+            self._visit_iter_filter(buf, name, args);
+            return DisplayWrap::Unwrapped;
+        } else if name == "zip_longest" {
+            self._visit_zip_longest_filter(buf, args);
+            return DisplayWrap::Unwrapped;
+        } else if name == "truncate_words" {
+            self._visit_truncate_words_filter(buf, args);
+            return DisplayWrap::Unwrapped;
+        } else if name == "truncate" {
+            self._visit_truncate_filter(buf, args);
+            return DisplayWrap::Unwrapped;
+        } else if name == "indent" {
+            self._visit_indent_filter(buf, args);
+            return DisplayWrap::Unwrapped;
+        } else if name == "ljust" || name == "rjust" {
+            self._visit_pad_filter(buf, name, args);
+            return DisplayWrap::Unwrapped;
+        } else if name == "json" {
+            self._visit_json_filter(buf, args);
+            return DisplayWrap::Wrapped;
+        } else if name == "coalesce" {
+            return self._visit_coalesce(buf, args);
+        } else if name == "default" {
+            return match args {
+                [value] => self._visit_default(buf, value, &Expr::StrLit(""), false),
+                [value, fallback] => self._visit_default(buf, value, fallback, false),
+                _ => panic!("default filter takes at most one argument"),
+            };
         }
 
-        if name == "escape" || name == "safe" || name == "e" || name == "json" {
-            buf.write(&format!(
-                "::askama::filters::{}({}, ",
-                name, self.input.escaper
-            ));
+        if name == "escape" || name == "e" {
+            self._visit_escape_filter(buf, name, args);
+            return DisplayWrap::Wrapped;
+        } else if name == "safe"
+            || name == "to_json_attr"
+            || name == "nl2br"
+            || name == "wrap_in"
+            || name == "default_attr"
+            || name == "escape_if"
+            || name == "markdown"
+        {
+            buf.write(&format!("::askama::filters::{}({}, ", name, self.escaper));
         } else if filters::BUILT_IN_FILTERS.contains(&name) {
             buf.write(&format!("::askama::filters::{}(", name));
-        } else {
+        } else if self.input.filters.is_empty() {
             buf.write(&format!("filters::{}(", name));
+        } else {
+            buf.write(&format!("{}(", name));
         }
 
         self._visit_args(buf, args);
         buf.write(")?");
-        if name == "safe" || name == "escape" || name == "e" || name == "json" {
+        if name == "safe"
+            || name == "to_json_attr"
+            || name == "nl2br"
+            || name == "wrap_in"
+            || name == "default_attr"
+            || name == "escape_if"
+            || name == "markdown"
+        {
             DisplayWrap::Wrapped
         } else {
             DisplayWrap::Unwrapped
         }
     }
 
-    fn _visit_format_filter(&mut self, buf: &mut Buffer, args: &[Expr]) {
+    fn _visit_format_filter(&mut self, buf: &mut Buffer, args: &[WithSpan<Expr>]) {
         buf.write("format!(");
-        if let Some(Expr::StrLit(v)) = args.first() {
+        if let Some(Expr::StrLit(v)) = args.first().map(|arg| &**arg) {
             self.visit_str_lit(buf, v);
             if args.len() > 1 {
                 buf.write(", ");
@@ -1093,9 +2153,9 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
         buf.write(")");
     }
 
-    fn _visit_fmt_filter(&mut self, buf: &mut Buffer, args: &[Expr]) {
+    fn _visit_fmt_filter(&mut self, buf: &mut Buffer, args: &[WithSpan<Expr>]) {
         buf.write("format!(");
-        if let Some(Expr::StrLit(v)) = args.get(1) {
+        if let Some(Expr::StrLit(v)) = args.get(1).map(|arg| &**arg) {
             self.visit_str_lit(buf, v);
             buf.write(", ");
         } else {
@@ -1109,7 +2169,7 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
     }
 
     // Force type coercion on first argument to `join` filter (see #39).
-    fn _visit_join_filter(&mut self, buf: &mut Buffer, args: &[Expr]) {
+    fn _visit_join_filter(&mut self, buf: &mut Buffer, args: &[WithSpan<Expr>]) {
         buf.write("::askama::filters::join((&");
         for (i, arg) in args.iter().enumerate() {
             if i > 0 {
@@ -1123,30 +2183,239 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
         buf.write(")?");
     }
 
-    fn _visit_args(&mut self, buf: &mut Buffer, args: &[Expr]) {
-        if args.is_empty() {
-            return;
-        }
-
-        for (i, arg) in args.iter().enumerate() {
-            if i > 0 {
-                buf.write(", &");
-            } else {
-                buf.write("&");
-            }
-
-            let scoped = matches!(arg,
-                Expr::Filter(_, _)
-                | Expr::MethodCall(_, _, _)
-                | Expr::VarCall(_, _)
-                | Expr::PathCall(_, _));
-
-            if scoped {
-                buf.writeln("{");
-                self.visit_expr(buf, arg);
-                buf.writeln("}");
-            } else {
-                self.visit_expr(buf, arg);
+    // Filters taking an iterator over the items of the value, such as `flatten`
+    fn _visit_iter_filter(&mut self, buf: &mut Buffer, name: &str, args: &[WithSpan<Expr>]) {
+        match name {
+            "take" | "skip" if args.len() != 2 => {
+                panic!("{} filter takes exactly one argument", name)
+            }
+            "take" | "skip" => {}
+            _ if args.len() != 1 => panic!("{} filter takes no arguments", name),
+            _ => {}
+        }
+        buf.write(&format!("::askama::filters::{}((&", name));
+        self.visit_expr(buf, &args[0]);
+        buf.write(").into_iter()");
+        for arg in &args[1..] {
+            buf.write(", &");
+            self.visit_expr(buf, arg);
+        }
+        buf.write(")?");
+    }
+
+    // The attribute name given to `map` or `groupby` (like `"user.name"`) is
+    // turned into a closure that gets it from each item
+    fn _visit_attr_filter(&mut self, buf: &mut Buffer, name: &str, args: &[WithSpan<Expr>]) {
+        let attr = match args {
+            [_, WithSpan {
+                inner: Expr::StrLit(attr),
+                ..
+            }] => attr,
+            _ => panic!("{} filter takes an attribute name as its argument", name),
+        };
+        let valid = |part: &str| match part.chars().next() {
+            Some(c) if c.is_ascii_digit() => part.chars().all(|c| c.is_ascii_digit()),
+            Some(_) => part.chars().all(|c| c.is_alphanumeric() || c == '_'),
+            None => false,
+        };
+        if !attr.split('.').all(valid) {
+            panic!("invalid attribute name '{}' for {} filter", attr, name);
+        }
+
+        buf.write(&format!("::askama::filters::{}((&", name));
+        self.visit_expr(buf, &args[0]);
+        buf.write(&format!(").into_iter(), |_item| &_item.{})?", attr));
+    }
+
+    fn _visit_zip_longest_filter(&mut self, buf: &mut Buffer, args: &[WithSpan<Expr>]) {
+        if args.len() != 3 {
+            panic!("zip_longest filter takes exactly two arguments");
+        }
+        buf.write("::askama::filters::zip_longest((&");
+        self.visit_expr(buf, &args[0]);
+        buf.write(").into_iter(), (&");
+        self.visit_expr(buf, &args[1]);
+        buf.write(").into_iter(), &");
+        self.visit_expr(buf, &args[2]);
+        buf.write(")?");
+    }
+
+    // The test for `partition` is a comparison operator (as a string literal) and an
+    // operand, which are turned into a closure over each item.
+    fn _visit_partition_filter(&mut self, buf: &mut Buffer, args: &[WithSpan<Expr>]) {
+        let op = match args.get(1).map(|arg| &**arg) {
+            Some(Expr::StrLit(op)) if ["==", "!=", "<", "<=", ">", ">="].contains(op) => op,
+            _ => panic!("partition filter takes a comparison operator as its first argument"),
+        };
+        if args.len() != 3 {
+            panic!("partition filter takes exactly two arguments");
+        }
+
+        buf.write("::askama::filters::partition((&");
+        self.visit_expr(buf, &args[0]);
+        buf.write(&format!(").into_iter(), |_item| *_item {} &(", op));
+        self.visit_expr(buf, &args[2]);
+        buf.write("))?");
+    }
+
+    // The ending appended to truncated text is optional, defaulting to "...".
+    fn _visit_truncate_words_filter(&mut self, buf: &mut Buffer, args: &[WithSpan<Expr>]) {
+        if args.len() != 2 && args.len() != 3 {
+            panic!("truncate_words filter takes one or two arguments");
+        }
+        buf.write("::askama::filters::truncate_words(");
+        self._visit_args(buf, args);
+        if args.len() == 2 {
+            buf.write(", &\"...\"");
+        }
+        buf.write(")?");
+    }
+
+    // The ellipsis defaults to "...", and text is cut inside words by default.
+    fn _visit_truncate_filter(&mut self, buf: &mut Buffer, args: &[WithSpan<Expr>]) {
+        if args.len() < 2 || args.len() > 4 {
+            panic!("truncate filter takes a length, an optional ellipsis and an optional flag");
+        }
+        buf.write("::askama::filters::truncate(");
+        self._visit_args(buf, args);
+        if args.len() == 2 {
+            buf.write(", &\"...\"");
+        }
+        if args.len() < 4 {
+            buf.write(", &false");
+        }
+        buf.write(")?");
+    }
+
+    // The first line is not indented by default.
+    fn _visit_indent_filter(&mut self, buf: &mut Buffer, args: &[WithSpan<Expr>]) {
+        if args.len() != 2 && args.len() != 3 {
+            panic!("indent filter takes a width and an optional flag");
+        }
+        buf.write("::askama::filters::indent(");
+        self._visit_args(buf, args);
+        if args.len() == 2 {
+            buf.write(", &false");
+        }
+        buf.write(")?");
+    }
+
+    // Explicit escaping uses the template's escaper, even inside an autoescape
+    // block that turned automatic escaping off, unless another context is
+    // given with a string literal
+    fn _visit_escape_filter(&mut self, buf: &mut Buffer, name: &str, args: &[WithSpan<Expr>]) {
+        let escaper = match args {
+            [_] => self.input.escaper,
+            [_, WithSpan {
+                inner: Expr::StrLit(context),
+                ..
+            }] => match *context {
+                "html" => "::askama::Html",
+                "js" => "::askama::Js",
+                "css" => "::askama::Css",
+                "url" => "::askama::Url",
+                _ => panic!("unknown escaping context '{}'", context),
+            },
+            _ => panic!("{} filter takes an optional context string literal", name),
+        };
+        buf.write(&format!("::askama::filters::{}({}, ", name, escaper));
+        self._visit_args(buf, &args[..1]);
+        buf.write(")?");
+    }
+
+    fn _visit_json_filter(&mut self, buf: &mut Buffer, args: &[WithSpan<Expr>]) {
+        if args.len() != 1 && args.len() != 2 {
+            panic!("json filter takes an optional indent width");
+        }
+        buf.write(&format!("::askama::filters::json({}, ", self.escaper));
+        self._visit_args(buf, args);
+        if args.len() == 1 {
+            buf.write(", &2");
+        }
+        buf.write(")?");
+    }
+
+    fn _visit_pad_filter(&mut self, buf: &mut Buffer, name: &str, args: &[WithSpan<Expr>]) {
+        if args.len() != 2 && args.len() != 3 {
+            panic!(
+                "{} filter takes a width and an optional fill character",
+                name
+            );
+        }
+        buf.write(&format!("::askama::filters::{}(", name));
+        self._visit_args(buf, args);
+        if args.len() == 2 {
+            buf.write(", &' '");
+        }
+        buf.write(")?");
+    }
+
+    // Each candidate after the first is wrapped in a closure, so candidates are
+    // only evaluated until one has a value.
+    fn _visit_coalesce(&mut self, buf: &mut Buffer, args: &[WithSpan<Expr>]) -> DisplayWrap {
+        if args.len() < 2 {
+            panic!("coalesce filter takes at least one argument");
+        }
+        buf.write("::askama::filters::Coalesce::coalesce_value(&(");
+        self.visit_expr(buf, &args[0]);
+        buf.write("))");
+        for arg in &args[1..] {
+            buf.write(".or_else(|| ::askama::filters::Coalesce::coalesce_value(&(");
+            self.visit_expr(buf, arg);
+            buf.write(")))");
+        }
+        buf.write(".unwrap_or_default()");
+        DisplayWrap::Unwrapped
+    }
+
+    // Used for both the `default` filter and the `??` operator. The fallback is
+    // wrapped in a closure, so it is only evaluated if the value is falsy, or
+    // for `??` (`option`), if the value is `None`.
+    fn _visit_default(
+        &mut self,
+        buf: &mut Buffer,
+        value: &Expr,
+        fallback: &Expr,
+        option: bool,
+    ) -> DisplayWrap {
+        buf.write("::askama::filters::default(&(");
+        self.visit_expr(buf, value);
+        buf.write(if option {
+            ").as_ref(), || ("
+        } else {
+            "), || ("
+        });
+        self.visit_expr(buf, fallback);
+        buf.write("))?");
+        DisplayWrap::Unwrapped
+    }
+
+    fn _visit_args(&mut self, buf: &mut Buffer, args: &[WithSpan<Expr>]) {
+        if args.is_empty() {
+            return;
+        }
+
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                buf.write(", &");
+            } else {
+                buf.write("&");
+            }
+
+            let scoped = matches!(
+                **arg,
+                Expr::Filter(_, _)
+                    | Expr::MethodCall(_, _, _)
+                    | Expr::VarCall(_, _)
+                    | Expr::PathCall(_, _)
+            );
+
+            if scoped {
+                buf.writeln("{");
+                self.visit_expr(buf, arg);
+                buf.writeln("}");
+            } else {
+                self.visit_expr(buf, arg);
             }
         }
     }
@@ -1166,6 +2435,15 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
                 } else if attr == "last" {
                     buf.write("_loop_item.last");
                     return DisplayWrap::Unwrapped;
+                } else if attr == "nextitem" {
+                    if let Some(&(_, true)) = self.loops.last() {
+                        panic!("loop.nextitem cannot be used in parallel loops");
+                    }
+                    buf.write("_loop_iter.peek_item()");
+                    return DisplayWrap::Unwrapped;
+                } else if attr == "remaining" {
+                    buf.write("_loop_item.remaining()");
+                    return DisplayWrap::Unwrapped;
                 } else {
                     panic!("unknown loop variable");
                 }
@@ -1190,8 +2468,18 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
         buf: &mut Buffer,
         obj: &Expr,
         method: &str,
-        args: &[Expr],
+        args: &[WithSpan<Expr>],
     ) -> DisplayWrap {
+        if let Expr::Var("loop") = obj {
+            if method == "cycle" {
+                return self.visit_loop_cycle(buf, args);
+            }
+            if method == "changed" {
+                return self.visit_loop_changed(buf, args);
+            }
+            panic!("unknown loop method: {}", method);
+        }
+
         if let Expr::Var("self") = obj {
             buf.write("self");
         } else {
@@ -1204,18 +2492,130 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
         DisplayWrap::Unwrapped
     }
 
+    fn visit_loop_changed(&mut self, buf: &mut Buffer, args: &[WithSpan<Expr>]) -> DisplayWrap {
+        if let Some(&(_, true)) = self.loops.last() {
+            panic!("loop.changed() cannot be used in parallel loops");
+        }
+        let value = match args {
+            [value] => value,
+            _ => panic!("loop.changed() takes exactly one argument"),
+        };
+        // Each use gets its own id, to track the values it has seen
+        let site = self.named;
+        self.named += 1;
+        buf.write(&format!("_loop_iter.changed({}, &(", site));
+        self.visit_expr(buf, value);
+        buf.write("))");
+        DisplayWrap::Unwrapped
+    }
+
+    // `loop.cycle(values)` takes a single list of values, while
+    // `loop.cycle("a", "b")` lists them inline
+    fn visit_loop_cycle(&mut self, buf: &mut Buffer, args: &[WithSpan<Expr>]) -> DisplayWrap {
+        buf.write("_loop_item.cycle(&");
+        match args {
+            [] => panic!("loop.cycle() requires at least one argument"),
+            [values] => {
+                buf.write("(");
+                self.visit_expr(buf, values);
+                buf.write(")");
+            }
+            values => {
+                buf.write("[");
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        buf.write(", ");
+                    }
+                    self.visit_expr(buf, value);
+                }
+                buf.write("]");
+            }
+        }
+        buf.write(")?");
+        DisplayWrap::Unwrapped
+    }
+
+    fn visit_test(&mut self, buf: &mut Buffer, name: &str, args: &[WithSpan<Expr>]) -> DisplayWrap {
+        let method = match name {
+            "some" => Some("is_some"),
+            "none" => Some("is_none"),
+            "empty" => Some("is_empty"),
+            _ => None,
+        };
+        let (obj, args) = args.split_first().unwrap();
+        if let Some(method) = method {
+            if !args.is_empty() {
+                panic!("the '{}' test takes no arguments", name);
+            }
+            buf.write("(");
+            self.visit_expr(buf, obj);
+            buf.write(&format!(").{}()", method));
+            return DisplayWrap::Unwrapped;
+        }
+
+        let (divisor, cmp) = match (name, args) {
+            ("odd", []) => (None, "!="),
+            ("even", []) => (None, "=="),
+            ("divisibleby", [divisor]) => (Some(divisor), "=="),
+            ("odd", _) | ("even", _) => panic!("the '{}' test takes no arguments", name),
+            ("divisibleby", _) => panic!("the 'divisibleby' test takes one argument"),
+            _ => {
+                // Other tests are looked up in a `tests` module in scope,
+                // the same way as custom filters
+                buf.write(&format!("tests::{}(&(", name));
+                self.visit_expr(buf, obj);
+                buf.write(")");
+                if !args.is_empty() {
+                    buf.write(", ");
+                    self._visit_args(buf, args);
+                }
+                buf.write(")?");
+                return DisplayWrap::Unwrapped;
+            }
+        };
+        buf.write("((");
+        self.visit_expr(buf, obj);
+        buf.write(") % ");
+        match divisor {
+            Some(divisor) => {
+                buf.write("(");
+                self.visit_expr(buf, divisor);
+                buf.write(")");
+            }
+            None => buf.write("2"),
+        }
+        buf.write(&format!(" {} 0)", cmp));
+        DisplayWrap::Unwrapped
+    }
+
     fn visit_unary(&mut self, buf: &mut Buffer, op: &str, inner: &Expr) -> DisplayWrap {
         buf.write(op);
         self.visit_expr(buf, inner);
         DisplayWrap::Unwrapped
     }
 
+    // Any error type is boxed into `Error::Custom`, since there is no
+    // `From` conversion from arbitrary errors into `askama::Error`
+    fn visit_try(&mut self, buf: &mut Buffer, expr: &Expr) -> DisplayWrap {
+        buf.write("::std::result::Result::map_err(");
+        self.visit_expr(buf, expr);
+        buf.write(", |err| ::askama::Error::Custom(::std::convert::Into::into(err)))?");
+        DisplayWrap::Unwrapped
+    }
+
+    fn visit_as(&mut self, buf: &mut Buffer, expr: &Expr, target: &str) -> DisplayWrap {
+        buf.write("(");
+        self.visit_expr(buf, expr);
+        buf.write(&format!(" as {})", target));
+        DisplayWrap::Unwrapped
+    }
+
     fn visit_range(
         &mut self,
         buf: &mut Buffer,
         op: &str,
-        left: &Option<Box<Expr>>,
-        right: &Option<Box<Expr>>,
+        left: &Option<Box<WithSpan<Expr>>>,
+        right: &Option<Box<WithSpan<Expr>>>,
     ) -> DisplayWrap {
         if let Some(left) = left {
             self.visit_expr(buf, left);
@@ -1234,12 +2634,76 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
         left: &Expr,
         right: &Expr,
     ) -> DisplayWrap {
+        if op == "??" {
+            return self._visit_default(buf, left, right, true);
+        }
+        if op == "~" {
+            return self._visit_concat(buf, left, right);
+        }
         self.visit_expr(buf, left);
         buf.write(&format!(" {} ", op));
         self.visit_expr(buf, right);
         DisplayWrap::Unwrapped
     }
 
+    // A chain of concatenations is formatted into a single string
+    fn _visit_concat(&mut self, buf: &mut Buffer, left: &Expr, right: &Expr) -> DisplayWrap {
+        let mut parts = vec![left];
+        let mut rest = right;
+        while let Expr::BinOp("~", left, right) = rest {
+            parts.push(left);
+            rest = right;
+        }
+        parts.push(rest);
+
+        buf.write(&format!("::std::format!({:?}", "{}".repeat(parts.len())));
+        for part in parts {
+            buf.write(", &(");
+            self.visit_expr(buf, part);
+            buf.write(")");
+        }
+        buf.write(")");
+        DisplayWrap::Unwrapped
+    }
+
+    // Both branches are converted to strings, so they have the same type. If only
+    // one of them is already escaped, the other one is escaped here.
+    fn visit_if_else(
+        &mut self,
+        buf: &mut Buffer,
+        cond: &Expr,
+        then: &Expr,
+        otherwise: &Expr,
+    ) -> DisplayWrap {
+        let mut branches = Vec::with_capacity(2);
+        for branch in &[then, otherwise] {
+            let mut expr_buf = Buffer::new(0);
+            let wrapped = self.visit_expr(&mut expr_buf, branch);
+            branches.push((expr_buf.buf, wrapped));
+        }
+        let wrapped = if branches[0].1 == branches[1].1 {
+            branches[0].1
+        } else {
+            DisplayWrap::Wrapped
+        };
+
+        buf.write("(if ");
+        self.visit_expr(buf, cond);
+        for (i, (code, branch_wrapped)) in branches.into_iter().enumerate() {
+            buf.write(if i == 0 { " { " } else { " } else { " });
+            if branch_wrapped == wrapped {
+                buf.write(&format!("::std::string::ToString::to_string(&{})", code));
+            } else {
+                buf.write(&format!(
+                    "::askama::MarkupDisplay::new_unsafe(&{}, {}).to_string()",
+                    code, self.escaper
+                ));
+            }
+        }
+        buf.write(" })");
+        wrapped
+    }
+
     fn visit_group(&mut self, buf: &mut Buffer, inner: &Expr) -> DisplayWrap {
         buf.write("(");
         self.visit_expr(buf, inner);
@@ -1247,7 +2711,7 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
         DisplayWrap::Unwrapped
     }
 
-    fn visit_array(&mut self, buf: &mut Buffer, elements: &[Expr]) -> DisplayWrap {
+    fn visit_array(&mut self, buf: &mut Buffer, elements: &[WithSpan<Expr>]) -> DisplayWrap {
         buf.write("[");
         for (i, el) in elements.iter().enumerate() {
             if i > 0 {
@@ -1269,7 +2733,12 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
         DisplayWrap::Unwrapped
     }
 
-    fn visit_path_call(&mut self, buf: &mut Buffer, path: &[&str], args: &[Expr]) -> DisplayWrap {
+    fn visit_path_call(
+        &mut self,
+        buf: &mut Buffer,
+        path: &[&str],
+        args: &[WithSpan<Expr>],
+    ) -> DisplayWrap {
         for (i, part) in path.iter().enumerate() {
             if i > 0 {
                 buf.write("::");
@@ -1283,8 +2752,12 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
     }
 
     fn visit_var(&mut self, buf: &mut Buffer, s: &str) -> DisplayWrap {
-        if self.locals.contains(s) || s == "self" {
+        // Const generic parameters of the struct are in scope as values
+        let is_const_param = self.input.ast.generics.const_params().any(|p| p.ident == s);
+        if self.locals.contains(s) || s == "self" || is_const_param {
             buf.write(s);
+        } else if let Some(value) = self.global(s) {
+            buf.write(value);
         } else {
             buf.write("self.");
             buf.write(s);
@@ -1292,7 +2765,37 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
         DisplayWrap::Unwrapped
     }
 
-    fn visit_var_call(&mut self, buf: &mut Buffer, s: &str, args: &[Expr]) -> DisplayWrap {
+    // Globals from the configuration are shadowed by fields of the struct
+    fn global(&self, name: &str) -> Option<&'a str> {
+        if self.is_field(name) {
+            return None;
+        }
+        self.input.config.globals.get(name).map(String::as_str)
+    }
+
+    fn is_field(&self, name: &str) -> bool {
+        match self.input.ast.data {
+            syn::Data::Struct(ref data) => data
+                .fields
+                .iter()
+                .any(|f| matches!(f.ident, Some(ref ident) if ident == name)),
+            _ => false,
+        }
+    }
+
+    fn visit_var_call(
+        &mut self,
+        buf: &mut Buffer,
+        s: &str,
+        args: &[WithSpan<Expr>],
+    ) -> DisplayWrap {
+        if s == "caller" && args.is_empty() && self.locals.contains("_caller") {
+            buf.write(&format!(
+                "::askama::MarkupDisplay::new_safe(&_caller, {})",
+                self.escaper
+            ));
+            return DisplayWrap::Wrapped;
+        }
         buf.write("(");
         if self.locals.contains(s) || s == "self" {
             buf.write(s);
@@ -1332,15 +2835,34 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
                 self.locals.insert(name);
                 buf.write(name);
             }
-            Target::Tuple(ref targets) => {
+            Target::Tuple(ref path, ref targets) => {
+                buf.write(&path.join("::"));
                 buf.write("(");
-                for name in targets {
-                    self.locals.insert(name);
-                    buf.write(name);
+                for (i, target) in targets.iter().enumerate() {
+                    if i > 0 {
+                        buf.write(", ");
+                    }
+                    self.visit_target(buf, target);
+                }
+                if path.is_empty() && targets.len() == 1 {
                     buf.write(",");
                 }
                 buf.write(")");
             }
+            Target::Struct(ref path, ref fields) => {
+                buf.write(&path.join("::"));
+                buf.write(" { ");
+                for (name, target) in fields {
+                    if *target != Target::Name(name) {
+                        buf.write(name);
+                        buf.write(": ");
+                    }
+                    self.visit_target(buf, target);
+                    buf.write(", ");
+                }
+                buf.write(".. }");
+            }
+            Target::Rest => buf.write(".."),
         }
     }
 
@@ -1374,6 +2896,45 @@ impl<'a, S: std::hash::BuildHasher> Generator<'a, S> {
     }
 }
 
+// Whether serde attributes on the template type or its fields could change
+// how it is serialized
+fn has_serde_attrs(ast: &syn::DeriveInput) -> bool {
+    let is_serde = |attrs: &[syn::Attribute]| attrs.iter().any(|attr| attr.path.is_ident("serde"));
+    let fields_have_serde =
+        |fields: &syn::Fields| fields.iter().any(|field| is_serde(&field.attrs));
+    is_serde(&ast.attrs)
+        || match ast.data {
+            syn::Data::Struct(ref data) => fields_have_serde(&data.fields),
+            syn::Data::Enum(ref data) => data
+                .variants
+                .iter()
+                .any(|variant| is_serde(&variant.attrs) || fields_have_serde(&variant.fields)),
+            syn::Data::Union(ref data) => data.fields.named.iter().any(|f| is_serde(&f.attrs)),
+        }
+}
+
+// Loads the `<locale>.ftl` resources in `dir`, sorted by locale
+#[cfg(feature = "i18n")]
+fn i18n_resources(dir: &Path) -> Vec<(String, PathBuf, askama_i18n::Resource)> {
+    let entries =
+        fs::read_dir(dir).unwrap_or_else(|_| panic!("unable to read i18n directory {:?}", dir));
+    let mut resources = Vec::new();
+    for entry in entries {
+        let path = entry.unwrap().path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("ftl") {
+            continue;
+        }
+        let locale = path.file_stem().unwrap().to_str().unwrap().to_owned();
+        let src = fs::read_to_string(&path)
+            .unwrap_or_else(|_| panic!("unable to read i18n resource {:?}", path));
+        let res = askama_i18n::Resource::parse(&src)
+            .unwrap_or_else(|err| panic!("invalid i18n resource {:?}: {}", path, err));
+        resources.push((locale, path, res));
+    }
+    resources.sort_by(|a, b| a.0.cmp(&b.0));
+    resources
+}
+
 struct Buffer {
     // The buffer to generate the code into
     buf: String,
@@ -1490,6 +3051,324 @@ fn median(sizes: &mut [usize]) -> usize {
     }
 }
 
+// Puts the named arguments of a built-in filter in the place of its parameter,
+// so that the filter can be generated like one called with positional arguments
+fn filter_positional_args<'a>(name: &str, args: &[WithSpan<Expr<'a>>]) -> Vec<WithSpan<Expr<'a>>> {
+    let mut positional = Vec::with_capacity(args.len());
+    let mut named = Vec::new();
+    for arg in args {
+        match **arg {
+            Expr::NamedArgument(arg_name, ref value) => named.push((arg_name, &**value)),
+            _ if !named.is_empty() => panic!(
+                "positional argument follows named arguments in call to filter '{}'",
+                name
+            ),
+            _ => positional.push(arg.clone()),
+        }
+    }
+    if named.is_empty() {
+        return positional;
+    }
+
+    let params = match filters::BUILT_IN_FILTER_ARGS
+        .iter()
+        .find(|(n, _)| *n == name)
+    {
+        Some((_, params)) => params,
+        None => panic!("filter '{}' does not take named arguments", name),
+    };
+    // The first argument is the filtered value, which has no name. Optional
+    // arguments left out before a named one get their default value.
+    for (i, param) in params.iter().enumerate().skip(positional.len() - 1) {
+        if let Some(pos) = named.iter().position(|&(arg_name, _)| arg_name == *param) {
+            for skipped in &params[positional.len() - 1..i] {
+                match filter_arg_default(name, skipped) {
+                    Some(default) => positional.push(WithSpan::no_span(default)),
+                    None => panic!("filter '{}' is missing argument '{}'", name, skipped),
+                }
+            }
+            positional.push(named.remove(pos).1.clone());
+        }
+    }
+    if let Some((arg_name, _)) = named.first() {
+        match params.iter().position(|param| param == arg_name) {
+            Some(_) => panic!(
+                "filter '{}' got multiple values for argument '{}'",
+                name, arg_name
+            ),
+            None => panic!("filter '{}' has no argument named '{}'", name, arg_name),
+        }
+    }
+    positional
+}
+
+// The default values of optional filter arguments, which are otherwise filled
+// in by the visitor of each filter
+fn filter_arg_default(name: &str, param: &str) -> Option<Expr<'static>> {
+    match (name, param) {
+        ("ljust", "fill") | ("rjust", "fill") => Some(Expr::CharLit(" ")),
+        ("truncate", "ellipsis") | ("truncate_words", "end") => Some(Expr::StrLit("...")),
+        ("truncate", "words") | ("indent", "first") => Some(Expr::BoolLit("false")),
+        _ => None,
+    }
+}
+
+// Returns the first slice of the template source in `expr`, to find out where
+// it comes from
+fn expr_source<'a>(expr: &Expr<'a>) -> Option<&'a str> {
+    match *expr {
+        Expr::BoolLit(s)
+        | Expr::NumLit(s)
+        | Expr::StrLit(s)
+        | Expr::RawStrLit(s)
+        | Expr::CharLit(s)
+        | Expr::Var(s)
+        | Expr::VarCall(s, _)
+        | Expr::RustMacro(s, _)
+        | Expr::NamedArgument(s, _) => Some(s),
+        Expr::Path(ref path) | Expr::PathCall(ref path, _) => path.first().copied(),
+        Expr::Array(ref elems) => elems.first().and_then(|elem| expr_source(elem)),
+        Expr::Filter(name, ref args) | Expr::Test(name, ref args) => {
+            args.first().and_then(|arg| expr_source(arg)).or(Some(name))
+        }
+        Expr::Attr(ref obj, _)
+        | Expr::Index(ref obj, _)
+        | Expr::MethodCall(ref obj, _, _)
+        | Expr::As(ref obj, _)
+        | Expr::BinOp(_, ref obj, _)
+        | Expr::Group(ref obj)
+        | Expr::IfElse(ref obj, _, _)
+        | Expr::Try(ref obj) => expr_source(obj),
+        Expr::Unary(op, _) | Expr::Range(op, _, _) => Some(op),
+    }
+}
+
+// Literals render to roughly their source length; anything else is a guess
+fn expr_size_hint(expr: &Expr) -> usize {
+    match *expr {
+        Expr::BoolLit(s) | Expr::NumLit(s) | Expr::StrLit(s) | Expr::RawStrLit(s) => s.len(),
+        Expr::CharLit(_) => 1,
+        _ => 3,
+    }
+}
+
+// Expressions whose type is known to be a primitive number can be written
+// with the `Integer` or `Float` helpers instead of the escaper
+fn number_kind(expr: &Expr) -> Option<&'static str> {
+    match *expr {
+        Expr::NumLit(lit) if is_float_lit(lit) => Some("Float"),
+        Expr::NumLit(_) => Some("Integer"),
+        Expr::As(_, "f32") | Expr::As(_, "f64") => Some("Float"),
+        Expr::As(_, ty) if INTEGER_TYPES.contains(&ty) => Some("Integer"),
+        Expr::Attr(ref obj, "index") | Expr::Attr(ref obj, "index0") => match ***obj {
+            Expr::Var("loop") => Some("Integer"),
+            _ => None,
+        },
+        Expr::Group(ref inner) => number_kind(inner),
+        _ => None,
+    }
+}
+
+const INTEGER_TYPES: [&str; 12] = [
+    "i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16", "u32", "u64", "u128", "usize",
+];
+
+// Built-in filters that only accept numbers
+const NUMERIC_FILTERS: [&str; 6] = [
+    "abs",
+    "filesizeformat",
+    "into_f64",
+    "into_isize",
+    "into_u64",
+    "into_usize",
+];
+
+// Built-in filters that always return a `String`
+const STRING_FILTERS: [&str; 24] = [
+    "camel_case",
+    "capitalize",
+    "center",
+    "escape_json_string",
+    "filesizeformat",
+    "indent",
+    "kebab_case",
+    "linebreaks",
+    "linebreaksbr",
+    "lower",
+    "lowercase",
+    "ordinal",
+    "pad_number",
+    "pascal_case",
+    "repeat",
+    "snake_case",
+    "strip_prefix",
+    "strip_suffix",
+    "title_with_exceptions",
+    "trim",
+    "upper",
+    "uppercase",
+    "urlencode",
+    "wordwrap",
+];
+
+fn is_float_lit(lit: &str) -> bool {
+    let radix_prefixed = ["0x", "0o", "0b"].iter().any(|p| lit.starts_with(p));
+    !radix_prefixed
+        && (lit.contains(&['.', 'e', 'E'][..]) || lit.ends_with("f32") || lit.ends_with("f64"))
+}
+
+// Reject loop iterables that can't be iterated with a clearer message than
+// rustc's. Without type information, this only catches literals: ranges
+// over floating point numbers, and plain numbers, booleans and strings.
+// Other values are checked by `helpers::check_iterable()` when the generated
+// code is compiled.
+fn check_loop_iter(iter: &Expr) {
+    match *iter {
+        Expr::Range(_, ref start, ref end) => {
+            for bound in start.iter().chain(end.iter()) {
+                if let Expr::NumLit(lit) = ***bound {
+                    if is_float_lit(lit) {
+                        panic!("for loop range bounds must be integers, found '{}'", lit);
+                    }
+                }
+            }
+        }
+        Expr::NumLit(lit) => panic!(
+            "cannot iterate over the number {}; use a range such as 0..{} instead",
+            lit, lit
+        ),
+        Expr::BoolLit(lit) => panic!("cannot iterate over the boolean {}", lit),
+        Expr::StrLit(_) | Expr::RawStrLit(_) | Expr::CharLit(_) => {
+            panic!("cannot iterate over a string literal; use the chars filter instead")
+        }
+        _ => {}
+    }
+}
+
+// Finds a function called by path, like `chrono::Utc::now()`, which may return
+// something else each time it is called.
+fn find_path_call<'a>(nodes: &'a [WithSpan<Node<'a>>]) -> Option<&'a [&'a str]> {
+    struct Finder<'a>(Option<&'a [&'a str]>);
+
+    impl<'a> Visitor<'a> for Finder<'a> {
+        fn visit_expr(&mut self, expr: &'a Expr<'a>) {
+            match expr {
+                Expr::PathCall(path, _) if self.0.is_none() => self.0 = Some(path),
+                _ => walk_expr(self, expr),
+            }
+        }
+    }
+
+    let mut finder = Finder(None);
+    walk_nodes(&mut finder, nodes);
+    finder.0
+}
+
+// Whether the body of a loop uses `loop.remaining`. In the body of a nested
+// loop, `loop` refers to the nested loop.
+fn uses_loop_remaining<'a>(body: &'a [WithSpan<Node<'a>>]) -> bool {
+    struct Finder(bool);
+
+    impl<'a> Visitor<'a> for Finder {
+        fn visit_node(&mut self, node: &'a Node<'a>) {
+            match node {
+                Node::Loop(l) => self.visit_expr(&l.iter),
+                _ => walk_node(self, node),
+            }
+        }
+
+        fn visit_expr(&mut self, expr: &'a Expr<'a>) {
+            match expr {
+                Expr::Attr(obj, "remaining") if **obj == Expr::Var("loop") => self.0 = true,
+                _ => walk_expr(self, expr),
+            }
+        }
+    }
+
+    let mut finder = Finder(false);
+    walk_nodes(&mut finder, body);
+    finder.0
+}
+
+// Whether a `break` or `continue` in the body of a loop targets `label`. The
+// label of a loop is given like a cast of its iterable, so an unused label is
+// more likely to be a misspelled cast.
+fn uses_loop_label<'a>(body: &'a [WithSpan<Node<'a>>], label: &str) -> bool {
+    struct Finder<'l>(&'l str, bool);
+
+    impl<'a, 'l> Visitor<'a> for Finder<'l> {
+        fn visit_node(&mut self, node: &'a Node<'a>) {
+            match *node {
+                Node::Break(_, Some(l)) | Node::Continue(_, Some(l)) if l == self.0 => {
+                    self.1 = true
+                }
+                _ => walk_node(self, node),
+            }
+        }
+    }
+
+    let mut finder = Finder(label, false);
+    walk_nodes(&mut finder, body);
+    finder.1
+}
+
+// With `deny_shadowing`, reject a `let` that binds a name which an earlier
+// `let` already bound in the same scope. A declaration without a value, as in
+// `{% let x %}`, may be followed by one assignment.
+fn check_shadowing(nodes: &[WithSpan<Node>]) {
+    fn bind<'a>(bound: &mut HashSet<&'a str>, name: &'a str) {
+        if !bound.insert(name) {
+            panic!("let binding '{}' shadows a binding in the same scope", name);
+        }
+    }
+
+    let mut declared = HashSet::new();
+    let mut bound = HashSet::new();
+
+    for n in nodes {
+        match &**n {
+            Node::LetDecl(_, Target::Name(name)) => {
+                declared.insert(*name);
+            }
+            Node::Let(_, Target::Name(name), _) if declared.remove(name) => {
+                bound.insert(*name);
+            }
+            Node::Let(_, Target::Name(name), _) => bind(&mut bound, name),
+            Node::Let(_, target, _) => {
+                let mut names = Vec::new();
+                target.names(&mut names);
+                for name in names {
+                    bind(&mut bound, name);
+                }
+            }
+            Node::Cond(branches, _) => {
+                for (_, _, nodes) in branches {
+                    check_shadowing(nodes);
+                }
+            }
+            Node::Match(_, _, _, arms, _) => {
+                for (_, _, _, nodes) in arms {
+                    check_shadowing(nodes);
+                }
+            }
+            Node::Loop(l) => check_shadowing(&l.body),
+            Node::FilterBlock(_, _, nodes, _)
+            | Node::CallBlock(_, _, _, _, nodes, _)
+            | Node::With(_, _, nodes, _)
+            | Node::AutoEscape(_, _, nodes, _) => check_shadowing(nodes),
+            Node::LetBlock(_, name, nodes, _) => {
+                check_shadowing(nodes);
+                if !declared.remove(name) {
+                    bind(&mut bound, name);
+                }
+            }
+            Node::BlockDef(b) => check_shadowing(&b.nodes),
+            Node::Macro(_, m) => check_shadowing(&m.nodes),
+            _ => {}
+        }
+    }
+}
+
 #[derive(Clone, PartialEq)]
 enum AstLevel {
     Top,
@@ -1499,7 +3378,7 @@ enum AstLevel {
 
 impl Copy for AstLevel {}
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 enum DisplayWrap {
     Wrapped,
     Unwrapped,
@@ -1512,3 +3391,322 @@ enum Writable<'a> {
     Lit(&'a str),
     Expr(&'a Expr<'a>),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{check_loop_iter, check_shadowing, generate, uses_loop_remaining};
+    use crate::heritage::{Context, Heritage};
+    use crate::input::TemplateInput;
+    use crate::parser::{parse, Node};
+    use crate::{Config, Integrations, Syntax};
+    use std::collections::HashMap;
+
+    fn check_loop(src: &str) {
+        let syntax = Syntax::default();
+        match parse(src, &syntax).first().map(|n| &n.inner) {
+            Some(Node::Loop(l)) => check_loop_iter(&l.iter),
+            _ => panic!("expected a loop"),
+        }
+    }
+
+    #[test]
+    fn test_loop_range_int() {
+        check_loop("{% for i in 1..=5 %}{% endfor %}");
+        check_loop("{% for i in start..end %}{% endfor %}");
+        check_loop("{% for i in 0x0..0xE %}{% endfor %}");
+    }
+
+    #[test]
+    #[should_panic(expected = "for loop range bounds must be integers, found '1e3'")]
+    fn test_loop_range_exponent() {
+        check_loop("{% for i in 0..1e3 %}{% endfor %}");
+    }
+
+    #[test]
+    #[should_panic(expected = "for loop range bounds must be integers, found '1.0'")]
+    fn test_loop_range_float() {
+        check_loop("{% for i in 1.0..=5.0 %}{% endfor %}");
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot iterate over the number 5; use a range such as 0..5 instead")]
+    fn test_loop_over_number() {
+        check_loop("{% for i in 5 %}{% endfor %}");
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot iterate over a string literal; use the chars filter instead")]
+    fn test_loop_over_string() {
+        check_loop("{% for c in \"abc\" %}{% endfor %}");
+    }
+
+    #[test]
+    fn test_uses_loop_remaining() {
+        let syntax = Syntax::default();
+        let uses = |src| match parse(src, &syntax).first().map(|n| &n.inner) {
+            Some(Node::Loop(l)) => uses_loop_remaining(&l.body),
+            _ => panic!("expected a loop"),
+        };
+        assert!(uses("{% for x in xs %}{{ loop.remaining }}{% endfor %}"));
+        assert!(uses(
+            "{% for x in xs %}{% if x %}{{ loop.remaining + 1 }}{% endif %}{% endfor %}"
+        ));
+        assert!(uses(
+            "{% for x in xs %}{% for y in 0..loop.remaining %}{% endfor %}{% endfor %}"
+        ));
+        assert!(!uses("{% for x in xs %}{{ loop.index }}{% endfor %}"));
+        assert!(!uses("{% for x in xs %}{{ x.remaining }}{% endfor %}"));
+        assert!(!uses(
+            "{% for x in xs %}{% for y in ys %}{{ loop.remaining }}{% endfor %}{% endfor %}"
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "loop label 'usze' is not used by any break or continue")]
+    fn test_unused_loop_label() {
+        generate_source(
+            "{% for i in 0..rows.len() as usze %}{% endfor %}",
+            INTEGRATIONS,
+        );
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "filter 'abs' expects a number, but its input is the string \
+                               returned by filter 'lower': `\"X\"|trim|lower` \
+                               (in Table.txt at line 2, column 19)"
+    )]
+    fn test_numeric_filter_on_string() {
+        generate_source(
+            "{{ rows.len()|abs }}\n{{ \"X\"|trim|lower|abs }}",
+            INTEGRATIONS,
+        );
+    }
+
+    #[test]
+    fn test_shadowing_distinct() {
+        let syntax = Syntax::default();
+        check_shadowing(&parse(
+            "{% let x = 1 %}{% let y = 2 %}{% let z %}{% let z = 3 %}\
+             {% if x %}{% let x = 4 %}{% endif %}",
+            &syntax,
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "let binding 'x' shadows a binding in the same scope")]
+    fn test_shadowing_double_let() {
+        let syntax = Syntax::default();
+        check_shadowing(&parse("{% let x = 1 %}{% let x = 2 %}", &syntax));
+    }
+
+    #[test]
+    fn test_integration_lifetime_first() {
+        // Lifetimes added for an integration go before type and const parameters
+        let code = generate_struct(
+            "struct Generic<'a, T, const N: usize> { t: &'a [T; N] }",
+            &Config::new(""),
+            "{{ t }}",
+            Integrations {
+                rocket: true,
+                ..INTEGRATIONS
+            },
+        );
+        assert!(code.contains(
+            "impl < 'a , 'askama , T , const N : usize > ::askama_rocket::Responder<'askama>"
+        ));
+    }
+
+    #[test]
+    fn test_globals() {
+        let mut config = Config::new("");
+        for (name, value) in &[
+            ("site", "\"Demo\""),
+            ("version", "3"),
+            ("limit", "crate::LIMIT"),
+        ] {
+            config.globals.insert(name.to_string(), value.to_string());
+        }
+
+        // Fields and local variables take precedence over globals
+        let code = generate_struct(
+            "struct Page { version: u32 }",
+            &config,
+            "{{ site }} {{ version }} {{ limit }}{% for site in 0..1 %}{{ site }}{% endfor %}",
+            INTEGRATIONS,
+        );
+        assert!(code.contains("&\"Demo\""), "{}", code);
+        assert!(code.contains("&self.version"), "{}", code);
+        assert!(code.contains("&crate::LIMIT"), "{}", code);
+        assert!(code.contains("&site"), "{}", code);
+    }
+
+    const INTEGRATIONS: Integrations = Integrations {
+        actix: false,
+        gotham: false,
+        iron: false,
+        mendes: false,
+        poem: false,
+        rocket: false,
+        salvo: false,
+        tide: false,
+        warp: false,
+        hot_reload: false,
+        rayon: false,
+        fragments: true,
+    };
+
+    fn generate_source(src: &str, integrations: Integrations) -> String {
+        let config = Config::new("");
+        generate_struct(
+            "struct Table { rows: Vec<u32> }",
+            &config,
+            src,
+            integrations,
+        )
+    }
+
+    fn generate_struct(
+        item: &str,
+        config: &Config<'_>,
+        src: &str,
+        integrations: Integrations,
+    ) -> String {
+        let ast: syn::DeriveInput = syn::parse_str(&format!(
+            "#[template(source = \"\", ext = \"txt\")] {}",
+            item
+        ))
+        .unwrap();
+        let input = TemplateInput::new(&ast, config, None);
+        let nodes = parse(src, &input.syntax);
+        let mut contexts = HashMap::new();
+        contexts.insert(&input.path, Context::new(config, &input.path, src, &nodes));
+        let heritage = Some(Heritage::new(&contexts[&input.path], &contexts));
+        generate(&input, &contexts, &heritage, integrations)
+    }
+
+    #[test]
+    fn test_loop_over_variable() {
+        let code = generate_source("{% for x in some_int %}{{ x }}{% endfor %}", INTEGRATIONS);
+        assert!(
+            code.contains("::askama::helpers::check_iterable(&self.some_int).into_iter()"),
+            "{}",
+            code
+        );
+        let code = generate_source(
+            "{% for x in 0..some_int %}{{ x }}{% endfor %}",
+            INTEGRATIONS,
+        );
+        assert!(!code.contains("check_iterable"), "{}", code);
+    }
+
+    #[test]
+    fn test_cached_block() {
+        let code = generate_source(
+            "{% block sidebar cache %}{{ rows.len() }}{% endblock %}",
+            INTEGRATIONS,
+        );
+        assert!(
+            code.contains("self.block_cache.render_into(\"sidebar\", writer, |writer| {"),
+            "{}",
+            code
+        );
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "cached block 'row' cannot reference variables bound outside it: row"
+    )]
+    fn test_cached_block_local() {
+        generate_source(
+            "{% for row in rows %}{% block row cache %}{{ row }}{% endblock %}{% endfor %}",
+            INTEGRATIONS,
+        );
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "cached block 'row' cannot reference variables bound outside it: loop"
+    )]
+    fn test_cached_block_loop() {
+        generate_source(
+            "{% for row in rows %}{% block row cache %}{{ loop.index }}{% endblock %}{% endfor %}",
+            INTEGRATIONS,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "cached block 'time' cannot call 'chrono::Utc::now'")]
+    fn test_cached_block_path_call() {
+        generate_source(
+            "{% block time cache %}{{ chrono::Utc::now() }}{% endblock %}",
+            INTEGRATIONS,
+        );
+    }
+
+    #[test]
+    fn test_render_block_bound_outside() {
+        let code = generate_source(
+            "{% for row in rows %}{% block row %}{{ row }}{% endblock %}{% endfor %}",
+            INTEGRATIONS,
+        );
+        assert!(
+            code.contains(
+                "\"row\" => Err(::askama::Error::Custom(\"block 'row' cannot be rendered on \
+                 its own, since it uses names bound outside of it: row\".into())),"
+            ),
+            "{}",
+            code
+        );
+    }
+
+    #[test]
+    fn test_fragments() {
+        let src = "{% block content %}{{ rows.len() }}{% endblock %}";
+        let code = generate_source(src, INTEGRATIONS);
+        let method = code.find("fn render_block_into(").unwrap();
+        assert!(
+            code[..method]
+                .trim_end()
+                .ends_with("#[inline(never)]\n    #[cold]"),
+            "{}",
+            code
+        );
+
+        // Without the `fragments` feature, only whole templates are rendered
+        let code = generate_source(
+            src,
+            Integrations {
+                fragments: false,
+                ..INTEGRATIONS
+            },
+        );
+        assert!(!code.contains("render_block_into"), "{}", code);
+        assert!(code.contains("fn render_into("), "{}", code);
+    }
+
+    #[test]
+    fn test_parallel_loop() {
+        let code = generate_source(
+            "{% for row in rows parallel %}{{ row }}{% endfor %}",
+            Integrations {
+                rayon: true,
+                ..INTEGRATIONS
+            },
+        );
+        assert!(
+            code.contains("::askama::helpers::render_parallel("),
+            "{}",
+            code
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "parallel loops require askama's `rayon` feature")]
+    fn test_parallel_loop_without_rayon() {
+        generate_source(
+            "{% for row in rows parallel %}{{ row }}{% endfor %}",
+            INTEGRATIONS,
+        );
+    }
+}
@@ -1,11 +1,21 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
 use std::iter::Enumerate;
 use std::iter::Peekable;
+use std::marker::PhantomData;
+use std::sync::Mutex;
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 
 pub struct TemplateLoop<I>
 where
     I: Iterator,
 {
     iter: Peekable<Enumerate<I>>,
+    changed: Vec<(usize, String)>,
 }
 
 impl<I> TemplateLoop<I>
@@ -16,6 +26,42 @@ where
     pub fn new(iter: I) -> Self {
         TemplateLoop {
             iter: iter.enumerate().peekable(),
+            changed: Vec::new(),
+        }
+    }
+
+    /// Like `new()`, for loops using `loop.remaining`, which need to know
+    /// how many items are left
+    #[inline]
+    pub fn with_remaining(iter: I) -> Self
+    where
+        I: ExactSizeIterator,
+    {
+        Self::new(iter)
+    }
+
+    /// Looks at the item after the one last returned, for `loop.nextitem`
+    #[inline]
+    pub fn peek_item(&mut self) -> Option<&I::Item> {
+        self.iter.peek().map(|(_, item)| item)
+    }
+
+    /// Tells whether `value` differs from the value passed from the same
+    /// call `site` the last time, for `loop.changed(value)`
+    ///
+    /// Values are compared by their string representation.
+    pub fn changed<T: fmt::Display + ?Sized>(&mut self, site: usize, value: &T) -> bool {
+        let value = value.to_string();
+        match self.changed.iter_mut().find(|(s, _)| *s == site) {
+            Some((_, last)) if *last == value => false,
+            Some((_, last)) => {
+                *last = value;
+                true
+            }
+            None => {
+                self.changed.push((site, value));
+                true
+            }
         }
     }
 }
@@ -29,21 +75,360 @@ where
     #[inline]
     fn next(&mut self) -> Option<(<I as Iterator>::Item, LoopItem)> {
         self.iter.next().map(|(index, item)| {
+            let last = self.iter.peek().is_none();
+            let remaining = match self.iter.size_hint() {
+                (lower, Some(upper)) if lower == upper => Some(lower),
+                _ => None,
+            };
             (
                 item,
                 LoopItem {
                     index,
                     first: index == 0,
-                    last: self.iter.peek().is_none(),
+                    last,
+                    remaining,
                 },
             )
         })
     }
 }
 
+/// Iterates from `start` up to, but not including, `stop`, adding `step` each
+/// time, for `range(start, stop, step)`
+///
+/// A negative step counts down from `start` to `stop`. A step of zero is an
+/// error, since the loop would never end.
+pub fn range_step<T: RangeInt>(start: T, stop: T, step: T) -> crate::Result<RangeStep<T>> {
+    let (from, to, by) = (start.to_i128(), stop.to_i128(), step.to_i128());
+    let (span, by) = match by {
+        0 => return Err(crate::Error::Custom("range() step must not be zero".into())),
+        by if by > 0 => (to - from, by),
+        by => (from - to, -by),
+    };
+    let left = if span > 0 { (span + by - 1) / by } else { 0 };
+    Ok(RangeStep {
+        next: start,
+        step,
+        left: usize::try_from(left).unwrap_or(usize::MAX),
+    })
+}
+
+/// The iterator returned by `range_step()`
+pub struct RangeStep<T> {
+    next: T,
+    step: T,
+    left: usize,
+}
+
+impl<T: RangeInt> Iterator for RangeStep<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.left == 0 {
+            return None;
+        }
+        self.left -= 1;
+        let item = self.next;
+        // Only the step past the last item may overflow
+        self.next = item.checked_add(self.step).unwrap_or(item);
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.left, Some(self.left))
+    }
+}
+
+impl<T: RangeInt> ExactSizeIterator for RangeStep<T> {}
+
+/// The integer types `range()` can step through
+pub trait RangeInt: Copy {
+    fn to_i128(self) -> i128;
+    fn checked_add(self, rhs: Self) -> Option<Self>;
+}
+
+macro_rules! range_int {
+    ($($ty:ty)*) => {
+        $(
+            impl RangeInt for $ty {
+                fn to_i128(self) -> i128 {
+                    self as i128
+                }
+
+                fn checked_add(self, rhs: Self) -> Option<Self> {
+                    <$ty>::checked_add(self, rhs)
+                }
+            }
+        )*
+    };
+}
+
+range_int!(i8 i16 i32 i64 isize u8 u16 u32 u64 usize);
+
 #[derive(Copy, Clone)]
 pub struct LoopItem {
     pub index: usize,
     pub first: bool,
     pub last: bool,
+    remaining: Option<usize>,
+}
+
+impl LoopItem {
+    /// The number of iterations left after this one, for `loop.remaining`
+    ///
+    /// Only loops created with `TemplateLoop::with_remaining()` and parallel
+    /// loops know it.
+    pub fn remaining(&self) -> usize {
+        self.remaining
+            .expect("loop.remaining used without TemplateLoop::with_remaining()")
+    }
+
+    /// Picks the value for this iteration from `values`, starting over
+    /// at the first value after the last one, for `loop.cycle(...)`
+    pub fn cycle<'a, T>(&self, values: &'a [T]) -> crate::Result<&'a T> {
+        if values.is_empty() {
+            return Err(fmt::Error.into());
+        }
+        Ok(&values[self.index % values.len()])
+    }
+}
+
+/// Holds the rendered output of the `{% block name cache %}` blocks of a
+/// template instance, as its `block_cache` field (or the field named by the
+/// `block_cache` attribute of `template()`)
+///
+/// Each block is rendered on first use; later renders of the same instance
+/// write the stored output instead of evaluating the block body again.
+#[derive(Debug, Default)]
+pub struct BlockCache(Mutex<HashMap<&'static str, String>>);
+
+impl BlockCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn render_into<F>(
+        &self,
+        name: &'static str,
+        writer: &mut dyn fmt::Write,
+        render: F,
+    ) -> crate::Result<()>
+    where
+        F: FnOnce(&mut dyn fmt::Write) -> crate::Result<()>,
+    {
+        if let Some(output) = self.0.lock().unwrap_or_else(|e| e.into_inner()).get(name) {
+            writer.write_str(output)?;
+            return Ok(());
+        }
+
+        // The lock isn't held while rendering, since the block may contain
+        // other cached blocks
+        let mut rendered = String::new();
+        render(&mut rendered)?;
+        writer.write_str(&rendered)?;
+        let mut cached = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        cached.entry(name).or_insert(rendered);
+        Ok(())
+    }
+}
+
+impl Clone for BlockCache {
+    fn clone(&self) -> Self {
+        let cached = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        BlockCache(Mutex::new(cached.clone()))
+    }
+}
+
+/// Collects rendered output into chunks, for `Template::render_chunks()`
+///
+/// Each chunk is passed to the callback as soon as it holds at least `size`
+/// bytes. An error returned by the callback stops rendering, and is kept to
+/// be returned by `finish()`.
+pub struct ChunkWriter<'a> {
+    buf: String,
+    size: usize,
+    f: &'a mut dyn FnMut(&str) -> crate::Result<()>,
+    error: Option<crate::Error>,
+}
+
+impl<'a> ChunkWriter<'a> {
+    pub fn new(size: usize, f: &'a mut dyn FnMut(&str) -> crate::Result<()>) -> Self {
+        ChunkWriter {
+            buf: String::with_capacity(size),
+            size,
+            f,
+            error: None,
+        }
+    }
+
+    /// Passes the last chunk to the callback, given the result of rendering
+    pub fn finish(mut self, res: crate::Result<()>) -> crate::Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        res?;
+        if !self.buf.is_empty() {
+            (self.f)(&self.buf)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> fmt::Write for ChunkWriter<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.buf.push_str(s);
+        if self.buf.len() < self.size {
+            return Ok(());
+        }
+        let res = (self.f)(&self.buf);
+        self.buf.clear();
+        res.map_err(|err| {
+            self.error = Some(err);
+            fmt::Error
+        })
+    }
+}
+
+/// Writes rendered output as UTF-8 bytes to an `io::Write`
+///
+/// An I/O error stops rendering, and is kept to be returned by `finish()`.
+pub struct IoWriter<'a, W: io::Write + ?Sized> {
+    writer: &'a mut W,
+    error: Option<io::Error>,
+}
+
+impl<'a, W: io::Write + ?Sized> IoWriter<'a, W> {
+    pub fn new(writer: &'a mut W) -> Self {
+        IoWriter {
+            writer,
+            error: None,
+        }
+    }
+
+    /// Returns the I/O error that stopped rendering, if any, or else the
+    /// result of rendering
+    pub fn finish(self, res: crate::Result<()>) -> crate::Result<()> {
+        match self.error {
+            Some(err) => Err(crate::Error::Custom(Box::new(err))),
+            None => res,
+        }
+    }
+}
+
+impl<'a, W: io::Write + ?Sized> fmt::Write for IoWriter<'a, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.writer.write_all(s.as_bytes()).map_err(|err| {
+            self.error = Some(err);
+            fmt::Error
+        })
+    }
+}
+
+/// Adds the template file and line that was being rendered to an error
+///
+/// Generated code keeps track of the location of the last expression or tag
+/// it started on, with a line of 0 before any.
+pub fn locate(err: crate::Error, (template, line): (&'static str, usize)) -> crate::Error {
+    match err {
+        crate::Error::Located { .. } => err,
+        _ if line == 0 => err,
+        _ => crate::Error::Located {
+            template,
+            line,
+            source: Box::new(err),
+        },
+    }
+}
+
+/// Implemented by the values a template's `for` loop can iterate over
+///
+/// Like the `into_iter()` call of the loop, this looks through references to
+/// find a type whose reference implements `IntoIterator`, with `I` recording
+/// how many references were skipped.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` cannot be iterated over in a template's `for` loop",
+    label = "the iterable of a `for` loop in this template",
+    note = "a `for` loop iterates over a reference to a value such as a `Vec`, a slice or a map"
+)]
+pub trait Iterable<I> {}
+
+pub struct Here;
+
+pub struct There<I>(PhantomData<I>);
+
+#[diagnostic::do_not_recommend]
+impl<T: ?Sized> Iterable<Here> for T where for<'a> &'a T: IntoIterator {}
+
+#[diagnostic::do_not_recommend]
+impl<T: ?Sized + Iterable<I>, I> Iterable<There<I>> for &T {}
+
+/// Passes the iterable of a `{% for %}` loop through, so that values which
+/// can't be iterated over fail to compile with a message about the loop
+///
+/// ```compile_fail
+/// let some_int = 5;
+/// askama_shared::helpers::check_iterable(&some_int);
+/// ```
+#[inline]
+pub fn check_iterable<T: Iterable<I> + ?Sized, I>(iterable: &T) -> &T {
+    iterable
+}
+
+/// Displays an integer using `itoa`, bypassing the escaper
+///
+/// Used by generated code for expressions that are known to be integers.
+pub struct Integer<T>(pub T);
+
+impl<T: itoa::Integer + Copy> fmt::Display for Integer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(itoa::Buffer::new().format(self.0))
+    }
+}
+
+/// Displays a float using `ryu`, bypassing the escaper
+///
+/// The output is the same as `Display` for `f32` and `f64`: values that `ryu`
+/// would print in exponent notation are left to the standard formatting.
+pub struct Float<T>(pub T);
+
+impl<T: ryu::Float + fmt::Display + Copy> fmt::Display for Float<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut buf = ryu::Buffer::new();
+        let s = buf.format(self.0);
+        if s.contains('e') {
+            return fmt::Display::fmt(&self.0, f);
+        }
+        f.write_str(s.strip_suffix(".0").unwrap_or(s))
+    }
+}
+
+/// Renders the iterations of a `{% for x in items parallel %}` loop
+///
+/// Each iteration is rendered into its own buffer on the rayon thread pool,
+/// after which the buffers are written out in order.
+#[cfg(feature = "rayon")]
+pub fn render_parallel<I, F>(writer: &mut dyn fmt::Write, iter: I, render: F) -> crate::Result<()>
+where
+    I: Iterator,
+    I::Item: Send,
+    F: Fn(I::Item, LoopItem, &mut dyn fmt::Write) -> crate::Result<()> + Sync,
+{
+    let mut items = TemplateLoop::new(iter).collect::<Vec<_>>();
+    // All items are collected, so the number left is known for any iterator
+    let len = items.len();
+    for (_, loop_item) in &mut items {
+        loop_item.remaining = Some(len - loop_item.index - 1);
+    }
+    let rendered = items
+        .into_par_iter()
+        .map(|(item, loop_item)| {
+            let mut buf = String::new();
+            render(item, loop_item, &mut buf)?;
+            Ok(buf)
+        })
+        .collect::<crate::Result<Vec<_>>>()?;
+    for part in rendered {
+        writer.write_str(&part)?;
+    }
+    Ok(())
 }
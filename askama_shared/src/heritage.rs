@@ -1,7 +1,7 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 
-use crate::parser::{Expr, Macro, Node};
+use crate::parser::{BlockDef, Expr, Macro, Node, WithSpan};
 use crate::Config;
 
 pub struct Heritage<'a> {
@@ -20,40 +20,72 @@ impl<'a> Heritage<'a> {
             .map(|(name, def)| (*name, vec![(ctx, *def)]))
             .collect();
 
+        let mut included: Vec<&PathBuf> = ctx.includes.iter().collect();
         while let Some(ref path) = ctx.extends {
             ctx = &contexts[&path];
             for (name, def) in &ctx.blocks {
                 blocks.entry(name).or_insert_with(Vec::new).push((ctx, def));
             }
+            included.extend(&ctx.includes);
+        }
+
+        // Blocks defined in included templates come after those of the
+        // inheritance chain, so any template in the chain can override them
+        let mut seen = HashSet::new();
+        let mut i = 0;
+        while i < included.len() {
+            let path = included[i];
+            i += 1;
+            if !seen.insert(path) {
+                continue;
+            }
+            let inc = &contexts[path];
+            for (name, def) in &inc.blocks {
+                blocks.entry(name).or_default().push((inc, def));
+            }
+            included.extend(&inc.includes);
         }
 
         Heritage { root: ctx, blocks }
     }
 }
 
-type BlockAncestry<'a> = HashMap<&'a str, Vec<(&'a Context<'a>, &'a Node<'a>)>>;
+type BlockAncestry<'a> = HashMap<&'a str, Vec<(&'a Context<'a>, &'a BlockDef<'a>)>>;
 
 pub struct Context<'a> {
-    pub nodes: &'a [Node<'a>],
+    pub source: &'a str,
+    pub nodes: &'a [WithSpan<Node<'a>>],
     pub extends: Option<PathBuf>,
-    pub blocks: HashMap<&'a str, &'a Node<'a>>,
+    pub blocks: HashMap<&'a str, &'a BlockDef<'a>>,
     pub macros: HashMap<&'a str, &'a Macro<'a>>,
     pub imports: HashMap<&'a str, PathBuf>,
+    pub from_imports: HashMap<&'a str, (PathBuf, &'a str)>,
+    pub includes: Vec<PathBuf>,
 }
 
 impl<'a> Context<'a> {
-    pub fn new<'n>(config: &Config, path: &Path, nodes: &'n [Node<'n>]) -> Context<'n> {
+    pub fn new<'n>(
+        config: &Config,
+        path: &Path,
+        source: &'n str,
+        nodes: &'n [WithSpan<Node<'n>>],
+    ) -> Context<'n> {
         let mut extends = None;
         let mut blocks = Vec::new();
         let mut macros = HashMap::new();
         let mut imports = HashMap::new();
+        let mut from_imports = HashMap::new();
+        let mut includes = Vec::new();
         let mut nested = vec![nodes];
         let mut top = true;
 
         while let Some(nodes) = nested.pop() {
             for n in nodes {
-                match n {
-                    Node::Extends(Expr::StrLit(extends_path)) if top => match extends {
+                match &**n {
+                    Node::Extends(WithSpan {
+                        inner: Expr::StrLit(extends_path),
+                        ..
+                    }) if top => match extends {
                         Some(_) => panic!("multiple extend blocks found"),
                         None => {
                             extends = Some(config.find_template(extends_path, Some(path)));
@@ -66,21 +98,40 @@ impl<'a> Context<'a> {
                         let path = config.find_template(import_path, Some(path));
                         imports.insert(*scope, path);
                     }
-                    Node::Extends(_) | Node::Macro(_, _) | Node::Import(_, _, _) if !top => {
+                    Node::FromImport(_, import_path, names) if top => {
+                        let path = config.find_template(import_path, Some(path));
+                        for (name, alias) in names {
+                            from_imports.insert(*alias, (path.clone(), *name));
+                        }
+                    }
+                    Node::Extends(_)
+                    | Node::Macro(_, _)
+                    | Node::Import(_, _, _)
+                    | Node::FromImport(_, _, _)
+                        if !top =>
+                    {
                         panic!("extends, macro or import blocks not allowed below top level");
                     }
-                    def @ Node::BlockDef(_, _, _, _) => {
+                    Node::Include(_, include_path) => {
+                        includes.push(config.find_template(include_path, Some(path)));
+                    }
+                    Node::BlockDef(def) => {
                         blocks.push(def);
-                        if let Node::BlockDef(_, _, nodes, _) = def {
-                            nested.push(nodes);
-                        }
+                        nested.push(&def.nodes);
                     }
                     Node::Cond(branches, _) => {
                         for (_, _, nodes) in branches {
                             nested.push(nodes);
                         }
                     }
-                    Node::Loop(_, _, _, nodes, _) => {
+                    Node::Loop(l) => {
+                        nested.push(&l.body);
+                    }
+                    Node::FilterBlock(_, _, nodes, _)
+                    | Node::LetBlock(_, _, nodes, _)
+                    | Node::With(_, _, nodes, _)
+                    | Node::AutoEscape(_, _, nodes, _)
+                    | Node::CallBlock(_, _, _, _, nodes, _) => {
                         nested.push(nodes);
                     }
                     Node::Match(_, _, _, arms, _) => {
@@ -94,23 +145,17 @@ impl<'a> Context<'a> {
             top = false;
         }
 
-        let blocks: HashMap<_, _> = blocks
-            .iter()
-            .map(|def| {
-                if let Node::BlockDef(_, name, _, _) = def {
-                    (*name, *def)
-                } else {
-                    unreachable!()
-                }
-            })
-            .collect();
+        let blocks: HashMap<_, _> = blocks.iter().map(|def| (def.name, *def)).collect();
 
         Context {
+            source,
             nodes,
             extends,
             blocks,
             macros,
             imports,
+            from_imports,
+            includes,
         }
     }
 }
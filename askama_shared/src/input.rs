@@ -1,27 +1,38 @@
 use crate::{Config, Syntax};
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use quote::ToTokens;
 
 pub struct TemplateInput<'a> {
     pub ast: &'a syn::DeriveInput,
     pub config: &'a Config<'a>,
-    pub syntax: &'a Syntax<'a>,
+    pub syntax: Syntax<'a>,
     pub source: Source,
     pub print: Print,
     pub escaper: &'a str,
     pub ext: Option<String>,
     pub parent: Option<&'a syn::Type>,
     pub path: PathBuf,
+    pub deny_shadowing: bool,
+    pub is_async: bool,
+    pub filters: Vec<String>,
+    pub register: Option<String>,
+    pub block_cache: String,
+    pub locale: String,
 }
 
 impl<'a> TemplateInput<'a> {
     /// Extract the template metadata from the `DeriveInput` structure. This
     /// mostly recovers the data for the `TemplateInput` fields from the
     /// `template()` attribute list fields; it also finds the of the `_parent`
-    /// field, if any.
-    pub fn new<'n>(ast: &'n syn::DeriveInput, config: &'n Config) -> TemplateInput<'n> {
+    /// field, if any. `source_file` is the Rust file containing the struct,
+    /// if known, which `relative` template paths are resolved against.
+    pub fn new<'n>(
+        ast: &'n syn::DeriveInput,
+        config: &'n Config,
+        source_file: Option<&Path>,
+    ) -> TemplateInput<'n> {
         // Check that an attribute called `template()` exists and that it is
         // the proper type (list).
         let meta = ast
@@ -52,6 +63,15 @@ impl<'a> TemplateInput<'a> {
         let mut escaping = None;
         let mut ext = None;
         let mut syntax = None;
+        let mut deny_shadowing = false;
+        let mut is_async = false;
+        let mut trim_blocks = None;
+        let mut lstrip_blocks = None;
+        let mut filters = None;
+        let mut register = None;
+        let mut relative = false;
+        let mut block_cache = None;
+        let mut locale = None;
         for item in meta_list.nested {
             let pair = match item {
                 syn::NestedMeta::Meta(syn::Meta::NameValue(ref pair)) => pair,
@@ -103,6 +123,75 @@ impl<'a> TemplateInput<'a> {
                 } else {
                     panic!("syntax value must be string literal");
                 }
+            } else if pair.path.is_ident("deny_shadowing") {
+                if let syn::Lit::Bool(ref b) = pair.lit {
+                    deny_shadowing = b.value;
+                } else {
+                    panic!("deny_shadowing value must be boolean literal");
+                }
+            } else if pair.path.is_ident("relative") {
+                if let syn::Lit::Bool(ref b) = pair.lit {
+                    relative = b.value;
+                } else {
+                    panic!("relative value must be boolean literal");
+                }
+            } else if pair.path.is_ident("async") {
+                if let syn::Lit::Bool(ref b) = pair.lit {
+                    is_async = b.value;
+                } else {
+                    panic!("async value must be boolean literal");
+                }
+            } else if pair.path.is_ident("trim_blocks") {
+                if let syn::Lit::Bool(ref b) = pair.lit {
+                    trim_blocks = Some(b.value);
+                } else {
+                    panic!("trim_blocks value must be boolean literal");
+                }
+            } else if pair.path.is_ident("lstrip_blocks") {
+                if let syn::Lit::Bool(ref b) = pair.lit {
+                    lstrip_blocks = Some(b.value);
+                } else {
+                    panic!("lstrip_blocks value must be boolean literal");
+                }
+            } else if pair.path.is_ident("filters") {
+                if let syn::Lit::Str(ref s) = pair.lit {
+                    filters = Some(
+                        s.value()
+                            .split(',')
+                            .map(|path| path.trim().to_string())
+                            .filter(|path| !path.is_empty())
+                            .collect(),
+                    );
+                } else {
+                    panic!("filters value must be string literal");
+                }
+            } else if pair.path.is_ident("register") {
+                if let syn::Lit::Str(ref s) = pair.lit {
+                    if s.value().is_empty() {
+                        panic!("register name must not be empty");
+                    }
+                    register = Some(s.value());
+                } else {
+                    panic!("register value must be string literal");
+                }
+            } else if pair.path.is_ident("block_cache") {
+                if let syn::Lit::Str(ref s) = pair.lit {
+                    if syn::parse_str::<syn::Ident>(&s.value()).is_err() {
+                        panic!("invalid block_cache field name '{}'", s.value());
+                    }
+                    block_cache = Some(s.value());
+                } else {
+                    panic!("block_cache value must be string literal");
+                }
+            } else if pair.path.is_ident("locale") {
+                if let syn::Lit::Str(ref s) = pair.lit {
+                    if syn::parse_str::<syn::Ident>(&s.value()).is_err() {
+                        panic!("invalid locale variable name '{}'", s.value());
+                    }
+                    locale = Some(s.value());
+                } else {
+                    panic!("locale value must be string literal");
+                }
             } else {
                 panic!(
                     "unsupported attribute key '{}' found",
@@ -115,7 +204,11 @@ impl<'a> TemplateInput<'a> {
         // related. In case `source` was used instead of `path`, the value
         // of `ext` is merged into a synthetic `path` value here.
         let source = source.expect("template path or source not found in attributes");
+        if let (Source::Source(_), true) = (&source, relative) {
+            panic!("'relative' attribute cannot be used with 'source' attribute");
+        }
         let path = match (&source, &ext) {
+            (Source::Path(path), None) if relative => find_relative_template(path, source_file),
             (&Source::Path(ref path), None) => config.find_template(path, None),
             (&Source::Source(_), Some(ext)) => PathBuf::from(format!("{}.{}", ast.ident, ext)),
             (&Source::Path(_), Some(_)) => {
@@ -148,15 +241,19 @@ impl<'a> TemplateInput<'a> {
         }
 
         // Validate syntax
-        let syntax = syntax.map_or_else(
-            || config.syntaxes.get(config.default_syntax).unwrap(),
-            |s| {
-                config
-                    .syntaxes
-                    .get(&s)
-                    .unwrap_or_else(|| panic!("attribute syntax {} not exist", s))
-            },
-        );
+        let mut syntax = syntax
+            .map_or_else(
+                || config.syntaxes.get(config.default_syntax).unwrap(),
+                |s| {
+                    config
+                        .syntaxes
+                        .get(&s)
+                        .unwrap_or_else(|| panic!("attribute syntax {} not exist", s))
+                },
+            )
+            .clone();
+        syntax.trim_blocks = trim_blocks.unwrap_or(syntax.trim_blocks);
+        syntax.lstrip_blocks = lstrip_blocks.unwrap_or(syntax.lstrip_blocks);
 
         // Match extension against defined output formats
 
@@ -179,6 +276,20 @@ impl<'a> TemplateInput<'a> {
             panic!("no escaper defined for extension '{}'", extension);
         });
 
+        if is_async && register.is_some() {
+            panic!("'register' attribute cannot be used with async templates");
+        }
+
+        // Custom filters come from the `filters` module next to the struct,
+        // unless other modules are listed
+        let filters: Vec<String> =
+            filters.unwrap_or_else(|| config.filters.iter().map(|s| (*s).to_string()).collect());
+        for path in &filters {
+            if syn::parse_str::<syn::Path>(path).is_err() {
+                panic!("invalid filter module path '{}'", path);
+            }
+        }
+
         TemplateInput {
             ast,
             config,
@@ -189,10 +300,33 @@ impl<'a> TemplateInput<'a> {
             parent,
             path,
             syntax,
+            deny_shadowing,
+            is_async,
+            filters,
+            register,
+            block_cache: block_cache.unwrap_or_else(|| "block_cache".to_string()),
+            locale: locale.unwrap_or_else(|| "locale".to_string()),
         }
     }
 }
 
+// Finds a template path given with `relative = true` next to the Rust file
+// that derives it
+fn find_relative_template(path: &str, source_file: Option<&Path>) -> PathBuf {
+    let dir = match source_file.and_then(Path::parent) {
+        Some(dir) => dir,
+        None => panic!(
+            "relative template path '{}' used, but the location of the source file is unknown",
+            path
+        ),
+    };
+    let found = dir.join(path);
+    if !found.exists() {
+        panic!("template {:?} not found in directory {:?}", path, dir);
+    }
+    found
+}
+
 pub enum Source {
     Path(String),
     Source(String),
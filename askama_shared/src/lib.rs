@@ -10,6 +10,8 @@ use serde::Deserialize;
 
 pub use askama_escape::MarkupDisplay;
 
+#[doc(hidden)]
+pub mod deps;
 mod error;
 pub use crate::error::{Error, Result};
 pub mod filters;
@@ -22,6 +24,10 @@ pub mod heritage;
 pub mod input;
 #[doc(hidden)]
 pub mod parser;
+#[doc(hidden)]
+pub mod printer;
+#[doc(hidden)]
+pub mod visitor;
 
 #[derive(Debug)]
 pub struct Config<'a> {
@@ -29,6 +35,22 @@ pub struct Config<'a> {
     pub syntaxes: BTreeMap<String, Syntax<'a>>,
     pub default_syntax: &'a str,
     pub escapers: Vec<(HashSet<String>, String)>,
+    pub i18n: I18nConfig<'a>,
+    /// Modules that custom filters are imported from, instead of the
+    /// `filters` module next to the template struct
+    pub filters: Vec<&'a str>,
+    /// Values available in every template, as the Rust expressions that
+    /// replace their names
+    pub globals: BTreeMap<String, String>,
+}
+
+/// Where the `{% trans %}` tag finds its messages
+#[derive(Debug)]
+pub struct I18nConfig<'a> {
+    /// Directory holding one `<locale>.ftl` file per locale
+    pub dir: PathBuf,
+    /// Locale used for messages that another locale doesn't define
+    pub fallback: &'a str,
 }
 
 impl<'a> Config<'a> {
@@ -45,17 +67,23 @@ impl<'a> Config<'a> {
             RawConfig::from_toml_str(s)
         };
 
-        let (dirs, default_syntax) = match raw.general {
+        let (dirs, default_syntax, trim_blocks, lstrip_blocks, filters) = match raw.general {
             Some(General {
                 dirs,
                 default_syntax,
+                trim_blocks,
+                lstrip_blocks,
+                filters,
             }) => (
                 dirs.map_or(default_dirs, |v| {
                     v.into_iter().map(|dir| root.join(dir)).collect()
                 }),
                 default_syntax.unwrap_or(DEFAULT_SYNTAX_NAME),
+                trim_blocks.unwrap_or(false),
+                lstrip_blocks.unwrap_or(false),
+                filters.unwrap_or_default(),
             ),
-            None => (default_dirs, DEFAULT_SYNTAX_NAME),
+            None => (default_dirs, DEFAULT_SYNTAX_NAME, false, false, Vec::new()),
         };
 
         if let Some(raw_syntaxes) = raw.syntax {
@@ -71,6 +99,11 @@ impl<'a> Config<'a> {
             }
         }
 
+        for syntax in syntaxes.values_mut() {
+            syntax.trim_blocks = trim_blocks;
+            syntax.lstrip_blocks = lstrip_blocks;
+        }
+
         if !syntaxes.contains_key(default_syntax) {
             panic!("default syntax \"{}\" not found", default_syntax)
         }
@@ -92,37 +125,105 @@ impl<'a> Config<'a> {
             escapers.push((str_set(extensions), (*path).to_string()));
         }
 
+        let i18n = match raw.i18n {
+            Some(RawI18n { dir, fallback }) => I18nConfig {
+                dir: root.join(dir.unwrap_or(DEFAULT_I18N_DIR)),
+                fallback: fallback.unwrap_or(DEFAULT_I18N_FALLBACK),
+            },
+            None => I18nConfig {
+                dir: root.join(DEFAULT_I18N_DIR),
+                fallback: DEFAULT_I18N_FALLBACK,
+            },
+        };
+
+        let mut globals = BTreeMap::new();
+        for (name, value) in raw.globals.unwrap_or_default() {
+            if syn::parse_str::<syn::Ident>(&name).is_err() {
+                panic!("global name '{}' is not an identifier", name);
+            }
+            let code = match value {
+                RawGlobal::Bool(b) => b.to_string(),
+                RawGlobal::Int(n) => n.to_string(),
+                RawGlobal::Float(f) if f.is_finite() => format!("{:?}", f),
+                RawGlobal::Float(_) => panic!("global '{}' is not a finite number", name),
+                RawGlobal::Str(s) => format!("{:?}", s),
+                RawGlobal::Path { path } => {
+                    if syn::parse_str::<syn::Path>(&path).is_err() {
+                        panic!("global '{}' has an invalid path: {:?}", name, path);
+                    }
+                    path
+                }
+            };
+            // Keeps `{{ g.abs() }}` from applying to the number only
+            let code = if code.starts_with('-') {
+                format!("({})", code)
+            } else {
+                code
+            };
+            globals.insert(name, code);
+        }
+
         Config {
             dirs,
             syntaxes,
             default_syntax,
             escapers,
+            i18n,
+            filters,
+            globals,
         }
     }
 
     pub fn find_template(&self, path: &str, start_at: Option<&Path>) -> PathBuf {
+        self.try_find_template(path, start_at).unwrap_or_else(|| {
+            panic!(
+                "template {:?} not found in directories {:?}",
+                path, self.dirs
+            )
+        })
+    }
+
+    /// Like `find_template()`, but returns `None` if the template doesn't exist
+    pub fn try_find_template(&self, path: &str, start_at: Option<&Path>) -> Option<PathBuf> {
         if let Some(root) = start_at {
-            let relative = root.with_file_name(path);
-            if relative.exists() {
-                return relative;
+            // A template in one of the directories finds its neighbours in
+            // the same subdirectory of each directory in turn, so that earlier
+            // directories can override them
+            let sub = root.parent().and_then(|parent| {
+                self.dirs
+                    .iter()
+                    .find_map(|dir| parent.strip_prefix(dir).ok())
+            });
+            match sub {
+                Some(sub) => {
+                    for dir in &self.dirs {
+                        let relative = dir.join(sub).join(path);
+                        if relative.exists() {
+                            return Some(relative);
+                        }
+                    }
+                }
+                None => {
+                    let relative = root.with_file_name(path);
+                    if relative.exists() {
+                        return Some(relative);
+                    }
+                }
             }
         }
 
         for dir in &self.dirs {
             let rooted = dir.join(path);
             if rooted.exists() {
-                return rooted;
+                return Some(rooted);
             }
         }
 
-        panic!(
-            "template {:?} not found in directories {:?}",
-            path, self.dirs
-        )
+        None
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Syntax<'a> {
     pub block_start: &'a str,
     pub block_end: &'a str,
@@ -130,6 +231,15 @@ pub struct Syntax<'a> {
     pub expr_end: &'a str,
     pub comment_start: &'a str,
     pub comment_end: &'a str,
+    /// Whether a start delimiter that doesn't begin a valid tag is kept as literal
+    /// text instead of failing the parse
+    pub lenient: bool,
+    /// Whether the first newline after a block tag is removed
+    pub trim_blocks: bool,
+    /// Whether spaces and tabs between the start of a line and a block tag are removed
+    pub lstrip_blocks: bool,
+    /// How deeply blocks and expressions may be nested before parsing fails
+    pub max_depth: usize,
 }
 
 impl<'a> Default for Syntax<'a> {
@@ -141,6 +251,10 @@ impl<'a> Default for Syntax<'a> {
             expr_end: "}}",
             comment_start: "{#",
             comment_end: "#}",
+            lenient: false,
+            trim_blocks: false,
+            lstrip_blocks: false,
+            max_depth: 128,
         }
     }
 }
@@ -155,6 +269,10 @@ impl<'a> From<RawSyntax<'a>> for Syntax<'a> {
             expr_end: raw.expr_end.unwrap_or(default.expr_end),
             comment_start: raw.comment_start.unwrap_or(default.comment_start),
             comment_end: raw.comment_end.unwrap_or(default.comment_end),
+            lenient: raw.lenient.unwrap_or(default.lenient),
+            trim_blocks: default.trim_blocks,
+            lstrip_blocks: default.lstrip_blocks,
+            max_depth: raw.max_depth.unwrap_or(default.max_depth),
         };
 
         if syntax.block_start.len() != 2
@@ -188,6 +306,8 @@ struct RawConfig<'d> {
     general: Option<General<'d>>,
     syntax: Option<Vec<RawSyntax<'d>>>,
     escaper: Option<Vec<RawEscaper<'d>>>,
+    i18n: Option<RawI18n<'d>>,
+    globals: Option<BTreeMap<String, RawGlobal>>,
 }
 
 impl<'d> RawConfig<'d> {
@@ -207,6 +327,9 @@ struct General<'a> {
     #[cfg_attr(feature = "serde", serde(borrow))]
     dirs: Option<Vec<&'a str>>,
     default_syntax: Option<&'a str>,
+    trim_blocks: Option<bool>,
+    lstrip_blocks: Option<bool>,
+    filters: Option<Vec<&'a str>>,
 }
 
 #[cfg_attr(feature = "serde", derive(Deserialize))]
@@ -218,6 +341,14 @@ struct RawSyntax<'a> {
     expr_end: Option<&'a str>,
     comment_start: Option<&'a str>,
     comment_end: Option<&'a str>,
+    lenient: Option<bool>,
+    max_depth: Option<usize>,
+}
+
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+struct RawI18n<'a> {
+    dir: Option<&'a str>,
+    fallback: Option<&'a str>,
 }
 
 #[cfg_attr(feature = "serde", derive(Deserialize))]
@@ -226,6 +357,18 @@ struct RawEscaper<'a> {
     extensions: Vec<&'a str>,
 }
 
+// Owned, since untagged enums can't borrow from the input
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(untagged))]
+#[cfg_attr(not(feature = "serde"), allow(dead_code))]
+enum RawGlobal {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Path { path: String },
+}
+
 pub fn read_config_file() -> String {
     let root = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
     let filename = root.join(CONFIG_FILE_NAME);
@@ -266,17 +409,28 @@ pub struct Integrations {
     pub gotham: bool,
     pub iron: bool,
     pub mendes: bool,
+    pub poem: bool,
     pub rocket: bool,
+    pub salvo: bool,
     pub tide: bool,
     pub warp: bool,
+    pub hot_reload: bool,
+    pub rayon: bool,
+    pub fragments: bool,
 }
 
 static CONFIG_FILE_NAME: &str = "askama.toml";
 static DEFAULT_SYNTAX_NAME: &str = "default";
+static DEFAULT_I18N_DIR: &str = "i18n";
+static DEFAULT_I18N_FALLBACK: &str = "en-US";
 static DEFAULT_ESCAPERS: &[(&[&str], &str)] = &[
     (&["html", "htm", "xml"], "::askama::Html"),
-    (&["md", "none", "txt", "yml", ""], "::askama::Text"),
+    (&["none", "txt", "yml", ""], "::askama::Text"),
     (&["j2", "jinja", "jinja2"], "::askama::Html"),
+    (&["tex"], "::askama::Latex"),
+    (&["md"], "::askama::Markdown"),
+    (&["csv"], "::askama::Csv"),
+    (&["sh"], "::askama::Shell"),
 ];
 
 #[cfg(test)]
@@ -309,6 +463,14 @@ mod tests {
         assert_eq!(config.dirs, vec![root]);
     }
 
+    #[cfg(feature = "config")]
+    #[test]
+    fn test_config_filters() {
+        let config = Config::new("[general]\nfilters = [\"crate::filters\", \"shared\"]");
+        assert_eq!(config.filters, vec!["crate::filters", "shared"]);
+        assert!(Config::new("").filters.is_empty());
+    }
+
     fn assert_eq_rooted(actual: &Path, expected: &str) {
         let mut root = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
         root.push("templates");
@@ -349,6 +511,21 @@ mod tests {
         assert_eq_rooted(&path, "sub/sub1/d.html");
     }
 
+    #[cfg(feature = "config")]
+    #[test]
+    fn find_in_dirs_order() {
+        let root = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+        let config = Config::new("[general]\ndirs = [\"overrides\", \"templates\"]");
+        let base = config.find_template("sub/b.html", None);
+        assert_eq!(base, root.join("templates/sub/b.html"));
+        let path = config.find_template("c.html", Some(&base));
+        assert_eq!(path, root.join("overrides/sub/c.html"));
+        let path = config.find_template("sub1/d.html", Some(&base));
+        assert_eq!(path, root.join("templates/sub/sub1/d.html"));
+        let path = config.find_template("a.html", Some(&base));
+        assert_eq!(path, root.join("templates/a.html"));
+    }
+
     #[cfg(feature = "config")]
     #[test]
     fn add_syntax() {
@@ -363,6 +540,8 @@ mod tests {
         [[syntax]]
         name = "bar"
         expr_start = "{!"
+        lenient = true
+        max_depth = 16
         "#;
 
         let default_syntax = Syntax::default();
@@ -376,6 +555,8 @@ mod tests {
         assert_eq!(foo.expr_end, default_syntax.expr_end);
         assert_eq!(foo.comment_start, default_syntax.comment_start);
         assert_eq!(foo.comment_end, default_syntax.comment_end);
+        assert!(!foo.lenient);
+        assert_eq!(foo.max_depth, default_syntax.max_depth);
 
         let bar = config.syntaxes.get("bar").unwrap();
         assert_eq!(bar.block_start, default_syntax.block_start);
@@ -384,6 +565,77 @@ mod tests {
         assert_eq!(bar.expr_end, default_syntax.expr_end);
         assert_eq!(bar.comment_start, default_syntax.comment_start);
         assert_eq!(bar.comment_end, default_syntax.comment_end);
+        assert!(bar.lenient);
+        assert_eq!(bar.max_depth, 16);
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn trim_blocks() {
+        let raw_config = r#"
+        [general]
+        trim_blocks = true
+        lstrip_blocks = true
+
+        [[syntax]]
+        name = "foo"
+        block_start = "{<"
+        "#;
+
+        let config = Config::new(raw_config);
+        for name in &["default", "foo"] {
+            let syntax = config.syntaxes.get(*name).unwrap();
+            assert!(syntax.trim_blocks);
+            assert!(syntax.lstrip_blocks);
+        }
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn i18n() {
+        let config = Config::new("");
+        assert!(config.i18n.dir.ends_with("i18n"));
+        assert_eq!(config.i18n.fallback, "en-US");
+
+        let raw_config = r#"
+        [i18n]
+        dir = "locales"
+        fallback = "de"
+        "#;
+        let config = Config::new(raw_config);
+        assert!(config.i18n.dir.ends_with("locales"));
+        assert_eq!(config.i18n.fallback, "de");
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn globals() {
+        assert!(Config::new("").globals.is_empty());
+
+        let raw_config = r#"
+        [globals]
+        site_name = "Jane's \"site\""
+        asset_version = 3
+        offset = -2
+        ratio = 0.5
+        beta = false
+        max_items = { path = "crate::MAX_ITEMS" }
+        "#;
+        let config = Config::new(raw_config);
+        let global = |name| config.globals.get(name).map(String::as_str);
+        assert_eq!(global("site_name"), Some(r#""Jane's \"site\"""#));
+        assert_eq!(global("asset_version"), Some("3"));
+        assert_eq!(global("offset"), Some("(-2)"));
+        assert_eq!(global("ratio"), Some("0.5"));
+        assert_eq!(global("beta"), Some("false"));
+        assert_eq!(global("max_items"), Some("crate::MAX_ITEMS"));
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    #[should_panic(expected = "global 'x' has an invalid path: \"crate::\"")]
+    fn invalid_global_path() {
+        Config::new("[globals]\nx = { path = \"crate::\" }");
     }
 
     #[cfg(feature = "config")]
@@ -469,10 +721,14 @@ mod tests {
                 (str_set(&["js"]), "::askama::Js".into()),
                 (str_set(&["html", "htm", "xml"]), "::askama::Html".into()),
                 (
-                    str_set(&["md", "none", "txt", "yml", ""]),
+                    str_set(&["none", "txt", "yml", ""]),
                     "::askama::Text".into()
                 ),
                 (str_set(&["j2", "jinja", "jinja2"]), "::askama::Html".into()),
+                (str_set(&["tex"]), "::askama::Latex".into()),
+                (str_set(&["md"]), "::askama::Markdown".into()),
+                (str_set(&["csv"]), "::askama::Csv".into()),
+                (str_set(&["sh"]), "::askama::Shell".into()),
             ]
         );
     }
@@ -1,63 +1,109 @@
 use nom::branch::alt;
-use nom::bytes::complete::{escaped, is_not, tag, take_until};
-use nom::character::complete::{anychar, char, digit1};
-use nom::combinator::{complete, map, opt};
-use nom::error::ParseError;
+use nom::bytes::complete::{tag, take_until, take_while, take_while1};
+use nom::character::complete::{char, digit1, one_of};
+use nom::combinator::{complete, map, not, opt, peek, recognize};
 use nom::multi::{many0, many1, separated_list, separated_nonempty_list};
-use nom::sequence::{delimited, pair, tuple};
+use nom::sequence::{delimited, pair, preceded, terminated, tuple};
 use nom::{self, error_position, Compare, IResult, InputTake};
-use std::str;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::ops::{Deref, DerefMut, Range};
+use std::path::PathBuf;
+use std::{fmt, str};
 
 use crate::Syntax;
 
 #[derive(Debug, PartialEq)]
 pub enum Node<'a> {
     Lit(&'a str, &'a str, &'a str),
-    Comment(WS),
-    Expr(WS, Expr<'a>),
-    Call(WS, Option<&'a str>, &'a str, Vec<Expr<'a>>),
+    Comment(WS, &'a str),
+    Expr(WS, WithSpan<Expr<'a>>),
+    Call(WS, Option<&'a str>, &'a str, Vec<WithSpan<Expr<'a>>>),
     LetDecl(WS, Target<'a>),
-    Let(WS, Target<'a>, Expr<'a>),
-    Cond(Vec<(WS, Option<Expr<'a>>, Vec<Node<'a>>)>, WS),
-    Match(WS, Expr<'a>, Option<&'a str>, Vec<When<'a>>, WS),
-    Loop(WS, Target<'a>, Expr<'a>, Vec<Node<'a>>, WS),
-    Extends(Expr<'a>),
-    BlockDef(WS, &'a str, Vec<Node<'a>>, WS),
+    Let(WS, Target<'a>, WithSpan<Expr<'a>>),
+    Do(WS, WithSpan<Expr<'a>>),
+    Trans(WS, &'a str, Vec<(&'a str, WithSpan<Expr<'a>>)>),
+    Cond(Vec<Cond<'a>>, WS),
+    Match(WS, WithSpan<Expr<'a>>, Option<&'a str>, Vec<When<'a>>, WS),
+    Loop(Loop<'a>),
+    Extends(WithSpan<Expr<'a>>),
+    BlockDef(BlockDef<'a>),
     Include(WS, &'a str),
     Import(WS, &'a str, &'a str),
+    FromImport(WS, &'a str, Vec<(&'a str, &'a str)>),
     Macro(&'a str, Macro<'a>),
     Raw(WS, &'a str, WS),
-}
-
-#[derive(Debug, PartialEq)]
+    Break(WS, Option<&'a str>),
+    Continue(WS, Option<&'a str>),
+    FilterBlock(
+        WS,
+        Vec<(&'a str, Vec<WithSpan<Expr<'a>>>)>,
+        Vec<WithSpan<Node<'a>>>,
+        WS,
+    ),
+    LetBlock(WS, &'a str, Vec<WithSpan<Node<'a>>>, WS),
+    With(
+        WS,
+        Vec<(&'a str, WithSpan<Expr<'a>>)>,
+        Vec<WithSpan<Node<'a>>>,
+        WS,
+    ),
+    AutoEscape(WS, WithSpan<Expr<'a>>, Vec<WithSpan<Node<'a>>>, WS),
+    CallBlock(
+        WS,
+        Option<&'a str>,
+        &'a str,
+        Vec<WithSpan<Expr<'a>>>,
+        Vec<WithSpan<Node<'a>>>,
+        WS,
+    ),
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Expr<'a> {
     BoolLit(&'a str),
     NumLit(&'a str),
     StrLit(&'a str),
+    RawStrLit(&'a str),
     CharLit(&'a str),
     Var(&'a str),
-    VarCall(&'a str, Vec<Expr<'a>>),
+    VarCall(&'a str, Vec<WithSpan<Expr<'a>>>),
     Path(Vec<&'a str>),
-    PathCall(Vec<&'a str>, Vec<Expr<'a>>),
-    Array(Vec<Expr<'a>>),
-    Attr(Box<Expr<'a>>, &'a str),
-    Index(Box<Expr<'a>>, Box<Expr<'a>>),
-    Filter(&'a str, Vec<Expr<'a>>),
-    Unary(&'a str, Box<Expr<'a>>),
-    BinOp(&'a str, Box<Expr<'a>>, Box<Expr<'a>>),
-    Range(&'a str, Option<Box<Expr<'a>>>, Option<Box<Expr<'a>>>),
-    Group(Box<Expr<'a>>),
-    MethodCall(Box<Expr<'a>>, &'a str, Vec<Expr<'a>>),
+    PathCall(Vec<&'a str>, Vec<WithSpan<Expr<'a>>>),
+    Array(Vec<WithSpan<Expr<'a>>>),
+    Attr(Box<WithSpan<Expr<'a>>>, &'a str),
+    Index(Box<WithSpan<Expr<'a>>>, Box<WithSpan<Expr<'a>>>),
+    Filter(&'a str, Vec<WithSpan<Expr<'a>>>),
+    Test(&'a str, Vec<WithSpan<Expr<'a>>>),
+    Unary(&'a str, Box<WithSpan<Expr<'a>>>),
+    As(Box<WithSpan<Expr<'a>>>, &'a str),
+    BinOp(&'a str, Box<WithSpan<Expr<'a>>>, Box<WithSpan<Expr<'a>>>),
+    Range(
+        &'a str,
+        Option<Box<WithSpan<Expr<'a>>>>,
+        Option<Box<WithSpan<Expr<'a>>>>,
+    ),
+    Group(Box<WithSpan<Expr<'a>>>),
+    MethodCall(Box<WithSpan<Expr<'a>>>, &'a str, Vec<WithSpan<Expr<'a>>>),
     RustMacro(&'a str, &'a str),
+    NamedArgument(&'a str, Box<WithSpan<Expr<'a>>>),
+    IfElse(
+        Box<WithSpan<Expr<'a>>>,
+        Box<WithSpan<Expr<'a>>>,
+        Box<WithSpan<Expr<'a>>>,
+    ),
+    Try(Box<WithSpan<Expr<'a>>>),
 }
 
 pub type When<'a> = (
     WS,
-    Option<MatchVariant<'a>>,
-    MatchParameters<'a>,
-    Vec<Node<'a>>,
+    Vec<MatchPattern<'a>>,
+    Option<WithSpan<Expr<'a>>>,
+    Vec<WithSpan<Node<'a>>>,
 );
 
+pub type MatchPattern<'a> = (Option<MatchVariant<'a>>, MatchParameters<'a>);
+
 #[derive(Debug, PartialEq)]
 pub enum MatchParameters<'a> {
     Simple(Vec<MatchParameter<'a>>),
@@ -90,27 +136,120 @@ pub enum MatchVariant<'a> {
 #[derive(Debug, PartialEq)]
 pub struct Macro<'a> {
     pub ws1: WS,
-    pub args: Vec<&'a str>,
-    pub nodes: Vec<Node<'a>>,
+    pub args: Vec<(&'a str, Option<WithSpan<Expr<'a>>>)>,
+    pub kwargs: Option<&'a str>,
+    pub nodes: Vec<WithSpan<Node<'a>>>,
+    pub ws2: WS,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct BlockDef<'a> {
+    pub ws1: WS,
+    pub name: &'a str,
+    pub cache: bool,
+    /// The content type of the block when it is rendered on its own, from
+    /// `{% block name type "image/svg+xml" %}`
+    pub content_type: Option<&'a str>,
+    pub nodes: Vec<WithSpan<Node<'a>>>,
+    pub ws2: WS,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Loop<'a> {
+    pub ws1: WS,
+    pub var: Target<'a>,
+    pub index: Option<&'a str>,
+    pub iter: WithSpan<Expr<'a>>,
+    pub label: Option<&'a str>,
+    pub parallel: bool,
+    pub body: Vec<WithSpan<Node<'a>>>,
     pub ws2: WS,
 }
 
 #[derive(Debug, PartialEq)]
 pub enum Target<'a> {
     Name(&'a str),
-    Tuple(Vec<&'a str>),
+    Tuple(Vec<&'a str>, Vec<Target<'a>>),
+    Struct(Vec<&'a str>, Vec<(&'a str, Target<'a>)>),
+    Rest,
+}
+
+impl<'a> Target<'a> {
+    /// Collects the names bound by this target, including nested patterns
+    pub fn names(&self, names: &mut Vec<&'a str>) {
+        match self {
+            Target::Name(name) => names.push(name),
+            Target::Tuple(_, targets) => targets.iter().for_each(|t| t.names(names)),
+            Target::Struct(_, fields) => fields.iter().for_each(|(_, t)| t.names(names)),
+            Target::Rest => {}
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct WS(pub bool, pub bool);
 
-pub type Cond<'a> = (WS, Option<Expr<'a>>, Vec<Node<'a>>);
+pub type Cond<'a> = (WS, Option<WithSpan<Expr<'a>>>, Vec<WithSpan<Node<'a>>>);
+
+/// A parsed node or expression, along with the byte range of the template
+/// source it was parsed from
+///
+/// Spans are left out of comparisons and `Debug` output, so that trees parsed
+/// from differently laid out sources compare equal and print the same.
+#[derive(Clone)]
+pub struct WithSpan<T> {
+    pub inner: T,
+    pub span: Range<usize>,
+}
+
+impl<T> WithSpan<T> {
+    pub fn new(inner: T, span: Range<usize>) -> Self {
+        WithSpan { inner, span }
+    }
+
+    /// Wraps a value that wasn't parsed from any source
+    pub fn no_span(inner: T) -> Self {
+        WithSpan { inner, span: 0..0 }
+    }
+}
+
+impl<T> Deref for WithSpan<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T> DerefMut for WithSpan<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for WithSpan<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+impl<T: PartialEq> PartialEq for WithSpan<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.inner == other.inner
+    }
+}
+
+impl<T: PartialEq> PartialEq<T> for WithSpan<T> {
+    fn eq(&self, other: &T) -> bool {
+        self.inner == *other
+    }
+}
 
 fn ws<F, I, O, E>(inner: F) -> impl Fn(I) -> IResult<I, O, E>
 where
     F: Fn(I) -> IResult<I, O, E>,
     I: InputTake + Clone + PartialEq + for<'a> Compare<&'a [u8; 1]>,
-    E: ParseError<I>,
+    E: nom::error::ParseError<I>,
 {
     move |i: I| {
         let ws = many0(alt::<_, _, (), _>((
@@ -126,6 +265,110 @@ where
     }
 }
 
+// Nesting depth of the blocks and expressions being parsed, and the limit
+// from `Syntax::max_depth` that keeps pathological templates from overflowing
+// the stack. The expression parsers don't get the syntax, hence thread locals.
+// `TOO_DEEP` holds the length of the input left where the limit was last hit.
+// `INVALID` holds the lengths of the input left at and after a tag that
+// parsed but is not allowed, and the message to report for it.
+// `SOURCE_LEN` is the length of the whole template, which turns the length of
+// the input left into an offset for spans.
+thread_local! {
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+    static MAX_DEPTH: Cell<usize> = Cell::new(Syntax::default().max_depth);
+    static TOO_DEEP: Cell<Option<usize>> = const { Cell::new(None) };
+    static INVALID: RefCell<Option<(usize, usize, String)>> = const { RefCell::new(None) };
+    static SOURCE_LEN: Cell<usize> = const { Cell::new(0) };
+}
+
+// Sets the nesting limit and source length for as long as it is alive,
+// restoring the previous ones when dropped (or when a parser panics)
+struct ParseScope(usize, usize);
+
+impl ParseScope {
+    fn enter(max_depth: usize, src_len: usize) -> Self {
+        ParseScope(
+            MAX_DEPTH.with(|max| max.replace(max_depth)),
+            SOURCE_LEN.with(|len| len.replace(src_len)),
+        )
+    }
+}
+
+impl Drop for ParseScope {
+    fn drop(&mut self) {
+        MAX_DEPTH.with(|max| max.set(self.0));
+        SOURCE_LEN.with(|len| len.set(self.1));
+    }
+}
+
+// Wraps the output of `f` with the span of the input it consumed
+fn spanned<'a, O>(
+    f: impl Fn(&'a [u8]) -> IResult<&'a [u8], O>,
+) -> impl Fn(&'a [u8]) -> IResult<&'a [u8], WithSpan<O>> {
+    move |i| {
+        let (rest, inner) = f(i)?;
+        let len = SOURCE_LEN.with(Cell::get);
+        Ok((rest, WithSpan::new(inner, len - i.len()..len - rest.len())))
+    }
+}
+
+// Like `spanned()`, but leaves out the whitespace around the consumed input,
+// which expression parsers skip with `ws()`
+fn spanned_trim<'a, O>(
+    f: impl Fn(&'a [u8]) -> IResult<&'a [u8], O>,
+) -> impl Fn(&'a [u8]) -> IResult<&'a [u8], WithSpan<O>> {
+    move |i| {
+        let (rest, inner) = f(i)?;
+        let consumed = &i[..i.len() - rest.len()];
+        let is_ws = |c: &u8| *c == b' ' || *c == b'\t' || *c == b'\r' || *c == b'\n';
+        let leading = consumed.iter().take_while(|c| is_ws(c)).count();
+        let trailing = consumed[leading..]
+            .iter()
+            .rev()
+            .take_while(|c| is_ws(c))
+            .count();
+        let start = SOURCE_LEN.with(Cell::get) - i.len() + leading;
+        let end = start - leading + consumed.len() - trailing;
+        Ok((rest, WithSpan::new(inner, start..end)))
+    }
+}
+
+// The span from the start of `first` to the end of `last`, for expressions
+// built from several parsed parts
+fn join<T, U>(first: &WithSpan<T>, last: &WithSpan<U>) -> Range<usize> {
+    first.span.start..last.span.end
+}
+
+// Runs `f` one nesting level deeper, failing once the limit is exceeded.
+// This is a recoverable error, since parsers also go deeper speculatively:
+// the `%` in `a %}` is first tried as an operator.
+fn nested<'a, O>(
+    i: &'a [u8],
+    f: impl FnOnce(&'a [u8]) -> IResult<&'a [u8], O>,
+) -> IResult<&'a [u8], O> {
+    // Restores the depth on return, and when a parser panics
+    struct Level;
+    impl Drop for Level {
+        fn drop(&mut self) {
+            DEPTH.with(|depth| depth.set(depth.get() - 1));
+        }
+    }
+
+    let depth = DEPTH.with(|depth| {
+        depth.set(depth.get() + 1);
+        depth.get()
+    });
+    let _level = Level;
+    if depth > MAX_DEPTH.with(Cell::get) {
+        TOO_DEEP.with(|too_deep| too_deep.set(Some(i.len())));
+        return Err(nom::Err::Error(error_position!(
+            i,
+            nom::error::ErrorKind::TooLarge
+        )));
+    }
+    f(i)
+}
+
 fn split_ws_parts(s: &[u8]) -> Node {
     if s.is_empty() {
         let rs = str::from_utf8(s).unwrap();
@@ -160,7 +403,7 @@ enum ContentState {
     End(usize),
 }
 
-fn take_content<'a>(i: &'a [u8], s: &'a Syntax<'a>) -> ParserError<'a, Node<'a>> {
+fn take_content<'a>(i: &'a [u8], s: &Syntax<'_>) -> ParserError<'a, Node<'a>> {
     use crate::parser::ContentState::*;
     let bs = s.block_start.as_bytes()[0];
     let be = s.block_start.as_bytes()[1];
@@ -188,8 +431,11 @@ fn take_content<'a>(i: &'a [u8], s: &'a Syntax<'a>) -> ParserError<'a, Node<'a>>
             }
             End(_) => panic!("cannot happen"),
         };
-        if let End(_) = state {
-            break;
+        if let End(start) = state {
+            if !s.lenient || is_tag(&i[start..], s) {
+                break;
+            }
+            state = Any;
         }
     }
 
@@ -203,8 +449,23 @@ fn take_content<'a>(i: &'a [u8], s: &'a Syntax<'a>) -> ParserError<'a, Node<'a>>
     }
 }
 
+// Checks whether the input starts with something that will be parsed as a tag.
+// Block tags only need to look like one, since closing tags like `endif` are
+// parsed by their opening block rather than on their own.
+fn is_tag(i: &[u8], s: &Syntax<'_>) -> bool {
+    let block = tuple((
+        |i| tag_block_start(i, s),
+        opt(tag("-")),
+        ws(identifier),
+        take_until(s.block_end),
+    ));
+    block_comment(i, s).is_ok() || expr_node(i, s).is_ok() || block(i).is_ok()
+}
+
 fn identifier(input: &[u8]) -> ParserError<&str> {
-    if !nom::character::is_alphabetic(input[0]) && input[0] != b'_' && !non_ascii(input[0]) {
+    if input.is_empty()
+        || !nom::character::is_alphabetic(input[0]) && input[0] != b'_' && !non_ascii(input[0])
+    {
         return Err(nom::Err::Error(error_position!(
             input,
             nom::error::ErrorKind::AlphaNumeric
@@ -216,7 +477,7 @@ fn identifier(input: &[u8]) -> ParserError<&str> {
         }
         return Ok((&input[i..], str::from_utf8(&input[..i]).unwrap()));
     }
-    Ok((&input[1..], str::from_utf8(&input[..1]).unwrap()))
+    Ok((&input[input.len()..], str::from_utf8(input).unwrap()))
 }
 
 #[inline]
@@ -230,8 +491,60 @@ fn expr_bool_lit(i: &[u8]) -> IResult<&[u8], Expr> {
     })(i)
 }
 
+// Rust's numeric literals: decimal numbers with an optional fraction and
+// exponent, or hexadecimal, octal and binary integers, all of which may
+// contain `_` separators and end in a type suffix
 fn num_lit(i: &[u8]) -> IResult<&[u8], &str> {
-    map(digit1, |s| str::from_utf8(s).unwrap())(i)
+    let integer_suffix = |i| {
+        alt((
+            tag("i8"),
+            tag("i16"),
+            tag("i32"),
+            tag("i64"),
+            tag("i128"),
+            tag("isize"),
+            tag("u8"),
+            tag("u16"),
+            tag("u32"),
+            tag("u64"),
+            tag("u128"),
+            tag("usize"),
+        ))(i)
+    };
+    let float_suffix = |i| alt((tag("f32"), tag("f64")))(i);
+    let prefixed = tuple((
+        char('0'),
+        alt((
+            pair(
+                char('x'),
+                take_while1(|c: u8| c.is_ascii_hexdigit() || c == b'_'),
+            ),
+            pair(
+                char('o'),
+                take_while1(|c: u8| (b'0'..=b'7').contains(&c) || c == b'_'),
+            ),
+            pair(
+                char('b'),
+                take_while1(|c: u8| c == b'0' || c == b'1' || c == b'_'),
+            ),
+        )),
+        opt(integer_suffix),
+    ));
+    let digits = |i| {
+        recognize(pair(
+            digit1,
+            take_while(|c: u8| c.is_ascii_digit() || c == b'_'),
+        ))(i)
+    };
+    let decimal = tuple((
+        digits,
+        opt(pair(char('.'), digits)),
+        opt(tuple((one_of("eE"), opt(one_of("+-")), digits))),
+        opt(alt((integer_suffix, float_suffix))),
+    ));
+    map(alt((recognize(prefixed), recognize(decimal))), |s| {
+        str::from_utf8(s).unwrap()
+    })(i)
 }
 
 fn expr_num_lit(i: &[u8]) -> IResult<&[u8], Expr> {
@@ -257,14 +570,7 @@ fn param_num_lit(i: &[u8]) -> IResult<&[u8], MatchParameter> {
 }
 
 fn str_lit(i: &[u8]) -> IResult<&[u8], &str> {
-    map(
-        delimited(
-            char('\"'),
-            opt(escaped(is_not("\\\""), '\\', anychar)),
-            char('\"'),
-        ),
-        |s| s.map(|s| str::from_utf8(s).unwrap()).unwrap_or(""),
-    )(i)
+    quoted_lit(i, b'"')
 }
 
 fn expr_str_lit(i: &[u8]) -> IResult<&[u8], Expr> {
@@ -280,14 +586,89 @@ fn param_str_lit(i: &[u8]) -> IResult<&[u8], MatchParameter> {
 }
 
 fn char_lit(i: &[u8]) -> IResult<&[u8], &str> {
-    map(
-        delimited(
-            char('\''),
-            opt(escaped(is_not("\\\'"), '\\', anychar)),
-            char('\''),
-        ),
-        |s| s.map(|s| str::from_utf8(s).unwrap()).unwrap_or(""),
-    )(i)
+    quoted_lit(i, b'\'')
+}
+
+// Scans a literal delimited by `quote` in a single pass, skipping over escaped
+// characters. A literal that is missing its closing quote, or that contains
+// an invalid or dangling escape, fails with the position of the opening quote.
+fn quoted_lit(i: &[u8], quote: u8) -> ParserError<&str> {
+    if i.first() != Some(&quote) {
+        return Err(nom::Err::Error(error_position!(
+            i,
+            nom::error::ErrorKind::Char
+        )));
+    }
+
+    let mut idx = 1;
+    while idx < i.len() {
+        match i[idx] {
+            b'\\' => match escape_len(&i[idx + 1..]) {
+                Some(len) => idx += 1 + len,
+                None => {
+                    return Err(nom::Err::Failure(error_position!(
+                        i,
+                        nom::error::ErrorKind::Escaped
+                    )))
+                }
+            },
+            c if c == quote => {
+                return Ok((&i[idx + 1..], str::from_utf8(&i[1..idx]).unwrap()));
+            }
+            _ => idx += 1,
+        }
+    }
+    Err(nom::Err::Failure(error_position!(
+        i,
+        nom::error::ErrorKind::Char
+    )))
+}
+
+// Returns the length of a valid escape sequence following a backslash, as
+// accepted by Rust in string and character literals
+fn escape_len(i: &[u8]) -> Option<usize> {
+    match i.first()? {
+        b'n' | b'r' | b't' | b'\\' | b'0' | b'\'' | b'"' | b'\n' => Some(1),
+        b'x' => match i.get(1..3)? {
+            [b'0'..=b'7', lo] if lo.is_ascii_hexdigit() => Some(3),
+            _ => None,
+        },
+        b'u' => {
+            let len = i
+                .iter()
+                .skip(2)
+                .take_while(|c| c.is_ascii_hexdigit())
+                .count();
+            if i.get(1) == Some(&b'{') && (1..=6).contains(&len) && i.get(len + 2) == Some(&b'}') {
+                Some(len + 3)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+// Raw strings are kept as written, including the `r`, hashes and quotes
+fn raw_str_lit(i: &[u8]) -> IResult<&[u8], &str> {
+    let (rest, (_, hashes, _)) = tuple((char('r'), take_while(|c| c == b'#'), char('"')))(i)?;
+    let mut end = b"\"".to_vec();
+    end.extend(hashes);
+    match (0..rest.len()).find(|&pos| rest[pos..].starts_with(&end)) {
+        Some(pos) => {
+            let rest = &rest[pos + end.len()..];
+            let len = i.len() - rest.len();
+            Ok((rest, str::from_utf8(&i[..len]).unwrap()))
+        }
+        None => Err(nom::Err::Failure(error_position!(
+            i,
+            nom::error::ErrorKind::TakeUntil
+        ))),
+    }
+}
+
+fn expr_raw_str_lit(i: &[u8]) -> IResult<&[u8], Expr> {
+    map(raw_str_lit, Expr::RawStrLit)(i)
 }
 
 fn expr_char_lit(i: &[u8]) -> IResult<&[u8], Expr> {
@@ -340,13 +721,48 @@ fn target_single(i: &[u8]) -> IResult<&[u8], Target> {
     map(identifier, |s| Target::Name(s))(i)
 }
 
+fn target_rest(i: &[u8]) -> IResult<&[u8], Target> {
+    map(tag(".."), |_| Target::Rest)(i)
+}
+
 fn target_tuple(i: &[u8]) -> IResult<&[u8], Target> {
-    let parts = separated_list(tag(","), ws(identifier));
+    let path = opt(separated_nonempty_list(ws(tag("::")), identifier));
+    let parts = separated_list(tag(","), ws(target));
+    let trailing = opt(ws(tag(",")));
+    let full = tuple((
+        path,
+        ws(delimited(tag("("), tuple((parts, trailing)), tag(")"))),
+    ));
+
+    let (i, (path, (elems, _))) = full(i)?;
+    Ok((i, Target::Tuple(path.unwrap_or_default(), elems)))
+}
+
+fn target_field(i: &[u8]) -> IResult<&[u8], (&str, Target)> {
+    let (i, (name, target)) = tuple((identifier, opt(preceded(ws(tag(":")), target))))(i)?;
+    Ok((i, (name, target.unwrap_or(Target::Name(name)))))
+}
+
+fn target_struct(i: &[u8]) -> IResult<&[u8], Target> {
+    let path = separated_nonempty_list(ws(tag("::")), identifier);
+    let fields = separated_list(tag(","), ws(target_field));
     let trailing = opt(ws(tag(",")));
-    let full = delimited(tag("("), tuple((parts, trailing)), tag(")"));
+    let rest = opt(pair(ws(tag("..")), opt(ws(tag(",")))));
+    let full = tuple((
+        path,
+        ws(delimited(
+            tag("{"),
+            tuple((fields, trailing, rest)),
+            tag("}"),
+        )),
+    ));
 
-    let (i, (elems, _)) = full(i)?;
-    Ok((i, Target::Tuple(elems)))
+    let (i, (path, (fields, _, _))) = full(i)?;
+    Ok((i, Target::Struct(path, fields)))
+}
+
+fn target(i: &[u8]) -> IResult<&[u8], Target> {
+    alt((target_struct, target_tuple, target_rest, target_single))(i)
 }
 
 fn variant_name(i: &[u8]) -> IResult<&[u8], MatchVariant> {
@@ -357,7 +773,7 @@ fn param_name(i: &[u8]) -> IResult<&[u8], MatchParameter> {
     map(identifier, |s| MatchParameter::Name(s))(i)
 }
 
-fn arguments(i: &[u8]) -> IResult<&[u8], Vec<Expr>> {
+fn arguments(i: &[u8]) -> IResult<&[u8], Vec<WithSpan<Expr>>> {
     delimited(
         ws(tag("(")),
         separated_list(tag(","), ws(expr_any)),
@@ -365,6 +781,19 @@ fn arguments(i: &[u8]) -> IResult<&[u8], Vec<Expr>> {
     )(i)
 }
 
+fn named_argument(i: &[u8]) -> IResult<&[u8], Expr> {
+    let (i, (name, _, _, value)) = tuple((identifier, ws(char('=')), not(char('=')), expr_any))(i)?;
+    Ok((i, Expr::NamedArgument(name, Box::new(value))))
+}
+
+fn call_arguments(i: &[u8]) -> IResult<&[u8], Vec<WithSpan<Expr>>> {
+    delimited(
+        ws(tag("(")),
+        separated_list(tag(","), ws(alt((spanned_trim(named_argument), expr_any)))),
+        ws(tag(")")),
+    )(i)
+}
+
 fn macro_arguments(i: &[u8]) -> IResult<&[u8], &str> {
     delimited(char('('), nested_parenthesis, char(')'))(i)
 }
@@ -417,12 +846,18 @@ fn nested_parenthesis(i: &[u8]) -> ParserError<&str> {
     }
 }
 
-fn parameters(i: &[u8]) -> IResult<&[u8], Vec<&str>> {
-    delimited(
+type MacroParameters<'a> = (Vec<(&'a str, Option<WithSpan<Expr<'a>>>)>, Option<&'a str>);
+
+fn macro_parameters(i: &[u8]) -> IResult<&[u8], MacroParameters> {
+    let kwargs = preceded(ws(tag("**")), ws(identifier));
+    let default = preceded(ws(char('=')), ws(expr_any));
+    let (i, (_, args, kwargs, _)) = tuple((
         ws(tag("(")),
-        separated_list(tag(","), ws(identifier)),
+        separated_list(tag(","), ws(pair(identifier, opt(default)))),
+        opt(preceded(opt(tag(",")), kwargs)),
         ws(tag(")")),
-    )(i)
+    ))(i)?;
+    Ok((i, (args, kwargs)))
 }
 
 fn with_parameters(i: &[u8]) -> IResult<&[u8], MatchParameters> {
@@ -459,11 +894,12 @@ fn expr_group(i: &[u8]) -> IResult<&[u8], Expr> {
     })(i)
 }
 
-fn expr_single(i: &[u8]) -> IResult<&[u8], Expr> {
-    alt((
+fn expr_single(i: &[u8]) -> IResult<&[u8], WithSpan<Expr>> {
+    spanned_trim(alt((
         expr_bool_lit,
         expr_num_lit,
         expr_str_lit,
+        expr_raw_str_lit,
         expr_char_lit,
         expr_path_call,
         expr_path,
@@ -472,7 +908,7 @@ fn expr_single(i: &[u8]) -> IResult<&[u8], Expr> {
         expr_var_call,
         expr_var,
         expr_group,
-    ))(i)
+    )))(i)
 }
 
 fn match_variant(i: &[u8]) -> IResult<&[u8], MatchVariant> {
@@ -495,149 +931,350 @@ fn match_named_parameter(i: &[u8]) -> IResult<&[u8], (&str, Option<MatchParamete
     Ok((i, (name, param.map(|s| s.1))))
 }
 
-fn attr(i: &[u8]) -> IResult<&[u8], (&str, Option<Vec<Expr>>)> {
-    let (i, (_, attr, args)) =
-        tuple((ws(tag(".")), alt((num_lit, identifier)), ws(opt(arguments))))(i)?;
-    Ok((i, (attr, args)))
+fn generic_args(i: &[u8]) -> IResult<&[u8], &[u8]> {
+    recognize(tuple((
+        ws(tag("<")),
+        separated_list(ws(tag(",")), ws(generic_arg)),
+        tag(">"),
+    )))(i)
+}
+
+fn generic_arg(i: &[u8]) -> IResult<&[u8], &[u8]> {
+    recognize(pair(cast_type, opt(generic_args)))(i)
+}
+
+// A method name, including any turbofish as in `parse::<u32>`
+fn method_name(i: &[u8]) -> IResult<&[u8], &str> {
+    map(
+        recognize(pair(identifier, opt(pair(ws(tag("::")), generic_args)))),
+        |s| str::from_utf8(s).unwrap(),
+    )(i)
+}
+
+enum Suffix<'a> {
+    Attr(&'a str, Option<Vec<WithSpan<Expr<'a>>>>),
+    Try,
+}
+
+fn attr(i: &[u8]) -> IResult<&[u8], Suffix> {
+    let (i, (_, attr, args)) = tuple((
+        ws(tag(".")),
+        alt((num_lit, method_name)),
+        ws(opt(arguments)),
+    ))(i)?;
+    Ok((i, Suffix::Attr(attr, args)))
+}
+
+// The `?` operator, which must not be confused with the `??` operator
+fn try_suffix(i: &[u8]) -> IResult<&[u8], Suffix> {
+    let (i, _) = ws(terminated(char('?'), not(char('?'))))(i)?;
+    Ok((i, Suffix::Try))
 }
 
-fn expr_attr(i: &[u8]) -> IResult<&[u8], Expr> {
-    let (i, (obj, attrs)) = tuple((expr_single, many0(attr)))(i)?;
+fn expr_attr(i: &[u8]) -> IResult<&[u8], WithSpan<Expr>> {
+    let suffix = spanned_trim(alt((attr, try_suffix)));
+    let (i, (obj, suffixes)) = tuple((expr_single, many0(suffix)))(i)?;
 
     let mut res = obj;
-    for (aname, args) in attrs {
-        res = if let Some(args) = args {
-            Expr::MethodCall(Box::new(res), aname, args)
-        } else {
-            Expr::Attr(Box::new(res), aname)
+    for suffix in suffixes {
+        let span = join(&res, &suffix);
+        let expr = match suffix.inner {
+            Suffix::Attr(aname, Some(args)) => Expr::MethodCall(Box::new(res), aname, args),
+            Suffix::Attr(aname, None) => Expr::Attr(Box::new(res), aname),
+            Suffix::Try => Expr::Try(Box::new(res)),
         };
+        res = WithSpan::new(expr, span);
     }
 
     Ok((i, res))
 }
 
-fn expr_index(i: &[u8]) -> IResult<&[u8], Expr> {
-    let key = opt(tuple((ws(tag("[")), expr_any, ws(tag("]")))));
+fn expr_index(i: &[u8]) -> IResult<&[u8], WithSpan<Expr>> {
+    let key = opt(spanned_trim(tuple((ws(tag("[")), expr_any, ws(tag("]"))))));
     let (i, (obj, key)) = tuple((expr_attr, key))(i)?;
-    let key = key.map(|(_, key, _)| key);
 
     Ok((
         i,
         match key {
-            Some(key) => Expr::Index(Box::new(obj), Box::new(key)),
+            Some(key) => {
+                let span = join(&obj, &key);
+                let (_, key, _) = key.inner;
+                WithSpan::new(Expr::Index(Box::new(obj), Box::new(key)), span)
+            }
             None => obj,
         },
     ))
 }
 
-fn filter(i: &[u8]) -> IResult<&[u8], (&str, Option<Vec<Expr>>)> {
-    let (i, (_, fname, args)) = tuple((tag("|"), ws(identifier), opt(arguments)))(i)?;
-    Ok((i, (fname, args)))
+type FilterCall<'a> = (&'a str, Option<Vec<WithSpan<Expr<'a>>>>);
+
+enum FilterPart<'a> {
+    Call(FilterCall<'a>),
+    // `(a if cond else b)` selects one of two filters at runtime
+    Cond(WithSpan<Expr<'a>>, FilterCall<'a>, FilterCall<'a>),
+}
+
+fn filter_call(i: &[u8]) -> IResult<&[u8], FilterCall> {
+    tuple((ws(identifier), opt(call_arguments)))(i)
+}
+
+fn filter_cond(i: &[u8]) -> IResult<&[u8], FilterPart> {
+    let (i, (_, then, _, cond, _, otherwise, _)) = tuple((
+        tag("("),
+        filter_call,
+        ws(keyword("if")),
+        expr_any,
+        ws(keyword("else")),
+        filter_call,
+        ws(tag(")")),
+    ))(i)?;
+    Ok((i, FilterPart::Cond(cond, then, otherwise)))
+}
+
+fn filter(i: &[u8]) -> IResult<&[u8], FilterPart> {
+    let (i, (_, part)) = tuple((
+        tag("|"),
+        alt((filter_cond, map(filter_call, FilterPart::Call))),
+    ))(i)?;
+    Ok((i, part))
+}
+
+fn apply_filter<'a>(
+    obj: WithSpan<Expr<'a>>,
+    (fname, args): FilterCall<'a>,
+    span: Range<usize>,
+) -> WithSpan<Expr<'a>> {
+    let mut args = args.unwrap_or_default();
+    args.insert(0, obj);
+    WithSpan::new(Expr::Filter(fname, args), span)
 }
 
-fn expr_filtered(i: &[u8]) -> IResult<&[u8], Expr> {
-    let (i, (obj, filters)) = tuple((expr_index, many0(filter)))(i)?;
+fn expr_filtered(i: &[u8]) -> IResult<&[u8], WithSpan<Expr>> {
+    let (i, (obj, filters)) = tuple((expr_index, many0(spanned_trim(filter))))(i)?;
 
     let mut res = obj;
-    for (fname, args) in filters {
-        res = Expr::Filter(fname, {
-            let mut args = match args {
-                Some(inner) => inner,
-                None => Vec::new(),
-            };
-            args.insert(0, res);
-            args
-        });
+    for part in filters {
+        let span = join(&res, &part);
+        res = match part.inner {
+            FilterPart::Call(call) => apply_filter(res, call, span),
+            FilterPart::Cond(cond, then, otherwise) => {
+                let then = apply_filter(res.clone(), then, span.clone());
+                let otherwise = apply_filter(res, otherwise, span.clone());
+                let expr = Expr::IfElse(Box::new(cond), Box::new(then), Box::new(otherwise));
+                WithSpan::new(expr, span)
+            }
+        };
     }
 
     Ok((i, res))
 }
 
-fn expr_unary(i: &[u8]) -> IResult<&[u8], Expr> {
-    let (i, (op, expr)) = tuple((opt(alt((ws(tag("!")), ws(tag("-"))))), expr_filtered))(i)?;
+fn expr_unary(i: &[u8]) -> IResult<&[u8], WithSpan<Expr>> {
+    let op = opt(alt((ws(tag("!")), ws(tag("-")))));
+    let (i, unary) = spanned_trim(tuple((op, expr_filtered)))(i)?;
     Ok((
         i,
-        match op {
-            Some(op) => Expr::Unary(str::from_utf8(op).unwrap(), Box::new(expr)),
-            None => expr,
+        match unary.inner {
+            (Some(op), expr) => WithSpan::new(
+                Expr::Unary(str::from_utf8(op).unwrap(), Box::new(expr)),
+                unary.span,
+            ),
+            (None, expr) => expr,
         },
     ))
 }
 
+fn keyword<'a>(k: &'a str) -> impl Fn(&'a [u8]) -> IResult<&'a [u8], &'a [u8]> {
+    move |i: &'a [u8]| {
+        let (i, k) = tag(k)(i)?;
+        let (i, _) = not(peek(identifier_tail))(i)?;
+        Ok((i, k))
+    }
+}
+
+fn identifier_tail(i: &[u8]) -> IResult<&[u8], u8> {
+    match i.first() {
+        Some(&c) if nom::character::is_alphanumeric(c) || c == b'_' || non_ascii(c) => {
+            Ok((&i[1..], c))
+        }
+        _ => Err(nom::Err::Error(error_position!(
+            i,
+            nom::error::ErrorKind::AlphaNumeric
+        ))),
+    }
+}
+
+fn cast_type(i: &[u8]) -> IResult<&[u8], &str> {
+    map(
+        recognize(alt((map(path, |_| ()), map(identifier, |_| ())))),
+        |s| str::from_utf8(s).unwrap(),
+    )(i)
+}
+
+fn expr_cast(i: &[u8]) -> IResult<&[u8], WithSpan<Expr>> {
+    let cast = spanned_trim(tuple((ws(keyword("as")), ws(cast_type))));
+    let (i, (expr, types)) = tuple((expr_unary, many0(cast)))(i)?;
+
+    let mut res = expr;
+    for cast in types {
+        let span = join(&res, &cast);
+        res = WithSpan::new(Expr::As(Box::new(res), cast.inner.1), span);
+    }
+    Ok((i, res))
+}
+
 fn expr_rust_macro(i: &[u8]) -> IResult<&[u8], Expr> {
     let (i, (mname, _, args)) = tuple((identifier, tag("!"), macro_arguments))(i)?;
     Ok((i, Expr::RustMacro(mname, args)))
 }
 
+// The right operand is parsed by `$right`: arithmetic and bitwise layers parse
+// it at their own level, so that `is` tests, which bind less tightly, apply to
+// the whole operation
 macro_rules! expr_prec_layer {
-    ( $name:ident, $inner:ident, $op:expr ) => {
-        fn $name(i: &[u8]) -> IResult<&[u8], Expr> {
+    ( $name:ident, $inner:ident, $right:ident, $op:expr ) => {
+        fn $name(i: &[u8]) -> IResult<&[u8], WithSpan<Expr>> {
             let (i, (left, op_and_right)) = tuple((
                 $inner,
                 opt(pair(
                     ws(tag($op)),
-                    expr_any,
+                    $right,
                 ))
             ))(i)?;
             Ok((i, match op_and_right {
-                Some((op, right)) => Expr::BinOp(
-                    str::from_utf8(op).unwrap(), Box::new(left), Box::new(right)
-                ),
+                Some((op, right)) => {
+                    let span = join(&left, &right);
+                    let op = str::from_utf8(op).unwrap();
+                    WithSpan::new(Expr::BinOp(op, Box::new(left), Box::new(right)), span)
+                }
                 None => left,
             }))
         }
     };
-    ( $name:ident, $inner:ident, $( $op:expr ),+ ) => {
-        fn $name(i: &[u8]) -> IResult<&[u8], Expr> {
+    ( $name:ident, $inner:ident, $right:ident, $( $op:expr ),+ ) => {
+        fn $name(i: &[u8]) -> IResult<&[u8], WithSpan<Expr>> {
             let (i, (left, op_and_right)) = tuple((
                 $inner,
                 opt(pair(
                     ws(alt(($( tag($op) ),*,))),
-                    expr_any
+                    $right
                 ))
             ))(i)?;
             Ok((i, match op_and_right {
-                Some((op, right)) => Expr::BinOp(
-                    str::from_utf8(op).unwrap(), Box::new(left), Box::new(right)
-                ),
+                Some((op, right)) => {
+                    let span = join(&left, &right);
+                    let op = str::from_utf8(op).unwrap();
+                    WithSpan::new(Expr::BinOp(op, Box::new(left), Box::new(right)), span)
+                }
                 None => left,
             }))
         }
     }
 }
 
-expr_prec_layer!(expr_muldivmod, expr_unary, "*", "/", "%");
-expr_prec_layer!(expr_addsub, expr_muldivmod, "+", "-");
-expr_prec_layer!(expr_shifts, expr_addsub, ">>", "<<");
-expr_prec_layer!(expr_band, expr_shifts, "&");
-expr_prec_layer!(expr_bxor, expr_band, "^");
-expr_prec_layer!(expr_bor, expr_bxor, "|");
-expr_prec_layer!(expr_compare, expr_bor, "==", "!=", ">=", ">", "<=", "<");
-expr_prec_layer!(expr_and, expr_compare, "&&");
-expr_prec_layer!(expr_or, expr_and, "||");
-
-fn range_right(i: &[u8]) -> IResult<&[u8], Expr> {
-    let (i, (_, incl, right)) = tuple((ws(tag("..")), opt(ws(tag("="))), opt(expr_or)))(i)?;
+expr_prec_layer!(expr_muldivmod, expr_cast, expr_muldivmod, "*", "/", "%");
+expr_prec_layer!(expr_addsub, expr_muldivmod, expr_addsub, "+", "-", "~");
+expr_prec_layer!(expr_shifts, expr_addsub, expr_shifts, ">>", "<<");
+expr_prec_layer!(expr_band, expr_shifts, expr_band, "&");
+expr_prec_layer!(expr_bxor, expr_band, expr_bxor, "^");
+expr_prec_layer!(expr_bor, expr_bxor, expr_bor, "|");
+fn expr_test(i: &[u8]) -> IResult<&[u8], WithSpan<Expr>> {
+    let (i, (obj, test)) = tuple((
+        expr_bor,
+        opt(spanned_trim(tuple((
+            ws(keyword("is")),
+            opt(ws(keyword("not"))),
+            identifier,
+            opt(arguments),
+        )))),
+    ))(i)?;
     Ok((
         i,
-        Expr::Range(
-            if incl.is_some() { "..=" } else { ".." },
-            None,
-            right.map(Box::new),
-        ),
+        match test {
+            Some(test) => {
+                let span = join(&obj, &test);
+                let (_, not, name, args) = test.inner;
+                let mut args = args.unwrap_or_default();
+                args.insert(0, obj);
+                let test = WithSpan::new(Expr::Test(name, args), span.clone());
+                match not {
+                    Some(_) => WithSpan::new(Expr::Unary("!", Box::new(test)), span),
+                    None => test,
+                }
+            }
+            None => obj,
+        },
     ))
 }
 
-fn expr_any(i: &[u8]) -> IResult<&[u8], Expr> {
-    let compound = map(tuple((expr_or, range_right)), |(left, rest)| match rest {
-        Expr::Range(op, _, right) => Expr::Range(op, Some(Box::new(left)), right),
-        _ => unreachable!(),
-    });
-    let p = alt((range_right, compound, expr_or));
-    Ok(p(i)?)
+expr_prec_layer!(
+    expr_compare,
+    expr_test,
+    expr_any,
+    "==",
+    "!=",
+    ">=",
+    ">",
+    "<=",
+    "<"
+);
+expr_prec_layer!(expr_and, expr_compare, expr_any, "&&");
+expr_prec_layer!(expr_or, expr_and, expr_any, "||");
+expr_prec_layer!(expr_coalesce, expr_or, expr_any, "??");
+
+fn range_right(i: &[u8]) -> IResult<&[u8], WithSpan<Expr>> {
+    let range = tuple((ws(tag("..")), opt(ws(tag("="))), opt(expr_or)));
+    map(spanned_trim(range), |range| {
+        let (_, incl, right) = range.inner;
+        let op = if incl.is_some() { "..=" } else { ".." };
+        WithSpan::new(Expr::Range(op, None, right.map(Box::new)), range.span)
+    })(i)
+}
+
+fn expr_any(i: &[u8]) -> IResult<&[u8], WithSpan<Expr>> {
+    nested(i, |i| {
+        // The left operand is only parsed once, since parsing it again after
+        // failing to find a range is exponential in the nesting depth
+        let compound = map(
+            tuple((expr_coalesce, opt(range_right))),
+            |(left, rest)| match rest {
+                Some(rest) => {
+                    let span = join(&left, &rest);
+                    match rest.inner {
+                        Expr::Range(op, _, right) => {
+                            WithSpan::new(Expr::Range(op, Some(Box::new(left)), right), span)
+                        }
+                        _ => unreachable!(),
+                    }
+                }
+                None => left,
+            },
+        );
+        let p = alt((range_right, compound));
+        let (i, (expr, cond)) = tuple((
+            p,
+            opt(tuple((
+                ws(keyword("if")),
+                expr_coalesce,
+                ws(keyword("else")),
+                expr_any,
+            ))),
+        ))(i)?;
+        Ok((
+            i,
+            match cond {
+                Some((_, cond, _, otherwise)) => {
+                    let span = join(&expr, &otherwise);
+                    let expr = Expr::IfElse(Box::new(cond), Box::new(expr), Box::new(otherwise));
+                    WithSpan::new(expr, span)
+                }
+                None => expr,
+            },
+        ))
+    })
 }
 
-fn expr_node<'a>(i: &'a [u8], s: &'a Syntax<'a>) -> IResult<&'a [u8], Node<'a>> {
+fn expr_node<'a>(i: &'a [u8], s: &Syntax<'_>) -> IResult<&'a [u8], Node<'a>> {
     let p = tuple((
         |i| tag_expr_start(i, s),
         opt(tag("-")),
@@ -655,7 +1292,7 @@ fn block_call(i: &[u8]) -> IResult<&[u8], Node> {
         ws(tag("call")),
         opt(tuple((ws(identifier), ws(tag("::"))))),
         ws(identifier),
-        ws(arguments),
+        ws(call_arguments),
         opt(tag("-")),
     ));
     let (i, (pws, _, scope, name, args, nws)) = p(i)?;
@@ -666,12 +1303,45 @@ fn block_call(i: &[u8]) -> IResult<&[u8], Node> {
     ))
 }
 
-fn cond_if(i: &[u8]) -> IResult<&[u8], Expr> {
+// A call with a body, which the macro can render with `caller()`
+fn block_call_body<'a>(i: &'a [u8], s: &Syntax<'_>) -> IResult<&'a [u8], Node<'a>> {
+    let start = tuple((
+        block_call,
+        |i| tag_block_end(i, s),
+        |i| parse_template(i, s),
+    ));
+    let (i, (call, _, nodes)) = start(i)?;
+
+    let end = tuple((
+        |i| tag_block_start(i, s),
+        opt(tag("-")),
+        ws(keyword("endcall")),
+        opt(tag("-")),
+    ));
+    let (i, (_, pws2, _, nws2)) = end(i)?;
+
+    match call {
+        Node::Call(ws1, scope, name, args) => Ok((
+            i,
+            Node::CallBlock(
+                ws1,
+                scope,
+                name,
+                args,
+                nodes,
+                WS(pws2.is_some(), nws2.is_some()),
+            ),
+        )),
+        _ => unreachable!(),
+    }
+}
+
+fn cond_if(i: &[u8]) -> IResult<&[u8], WithSpan<Expr>> {
     let (i, (_, cond)) = tuple((ws(tag("if")), ws(expr_any)))(i)?;
     Ok((i, cond))
 }
 
-fn cond_block<'a>(i: &'a [u8], s: &'a Syntax<'a>) -> IResult<&'a [u8], Cond<'a>> {
+fn cond_block<'a>(i: &'a [u8], s: &Syntax<'_>) -> IResult<&'a [u8], Cond<'a>> {
     let p = tuple((
         |i| tag_block_start(i, s),
         opt(tag("-")),
@@ -685,7 +1355,7 @@ fn cond_block<'a>(i: &'a [u8], s: &'a Syntax<'a>) -> IResult<&'a [u8], Cond<'a>>
     Ok((i, (WS(pws.is_some(), nws.is_some()), cond, block)))
 }
 
-fn block_if<'a>(i: &'a [u8], s: &'a Syntax<'a>) -> IResult<&'a [u8], Node<'a>> {
+fn block_if<'a>(i: &'a [u8], s: &Syntax<'_>) -> IResult<&'a [u8], Node<'a>> {
     let p = tuple((
         opt(tag("-")),
         cond_if,
@@ -706,7 +1376,7 @@ fn block_if<'a>(i: &'a [u8], s: &'a Syntax<'a>) -> IResult<&'a [u8], Node<'a>> {
     Ok((i, Node::Cond(res, WS(pws2.is_some(), nws2.is_some()))))
 }
 
-fn match_else_block<'a>(i: &'a [u8], s: &'a Syntax<'a>) -> IResult<&'a [u8], When<'a>> {
+fn match_else_block<'a>(i: &'a [u8], s: &Syntax<'_>) -> IResult<&'a [u8], When<'a>> {
     let p = tuple((
         |i| tag_block_start(i, s),
         opt(tag("-")),
@@ -720,37 +1390,40 @@ fn match_else_block<'a>(i: &'a [u8], s: &'a Syntax<'a>) -> IResult<&'a [u8], Whe
         i,
         (
             WS(pws.is_some(), nws.is_some()),
+            vec![(None, MatchParameters::default())],
             None,
-            MatchParameters::Simple(vec![]),
             block,
         ),
     ))
 }
 
-fn when_block<'a>(i: &'a [u8], s: &'a Syntax<'a>) -> IResult<&'a [u8], When<'a>> {
+fn match_pattern(i: &[u8]) -> IResult<&[u8], MatchPattern<'_>> {
+    let (i, (variant, params)) = tuple((
+        ws(match_variant),
+        opt(ws(alt((with_parameters, match_named_parameters)))),
+    ))(i)?;
+    Ok((i, (Some(variant), params.unwrap_or_default())))
+}
+
+fn when_block<'a>(i: &'a [u8], s: &Syntax<'_>) -> IResult<&'a [u8], When<'a>> {
     let p = tuple((
         |i| tag_block_start(i, s),
         opt(tag("-")),
         ws(tag("when")),
-        ws(match_variant),
-        opt(ws(with_parameters)),
+        separated_nonempty_list(ws(tag("|")), match_pattern),
+        opt(preceded(ws(keyword("if")), ws(expr_any))),
         opt(tag("-")),
         |i| tag_block_end(i, s),
         |i| parse_template(i, s),
     ));
-    let (i, (_, pws, _, variant, params, nws, _, block)) = p(i)?;
+    let (i, (_, pws, _, patterns, guard, nws, _, block)) = p(i)?;
     Ok((
         i,
-        (
-            WS(pws.is_some(), nws.is_some()),
-            Some(variant),
-            params.unwrap_or_default(),
-            block,
-        ),
+        (WS(pws.is_some(), nws.is_some()), patterns, guard, block),
     ))
 }
 
-fn block_match<'a>(i: &'a [u8], s: &'a Syntax<'a>) -> IResult<&'a [u8], Node<'a>> {
+fn block_match<'a>(start: &'a [u8], s: &Syntax<'_>) -> IResult<&'a [u8], Node<'a>> {
     let p = tuple((
         opt(tag("-")),
         ws(tag("match")),
@@ -765,7 +1438,7 @@ fn block_match<'a>(i: &'a [u8], s: &'a Syntax<'a>) -> IResult<&'a [u8], Node<'a>
         ws(tag("endmatch")),
         opt(tag("-")),
     ));
-    let (i, (pws1, _, expr, nws1, _, inter, arms, else_arm, _, pws2, _, nws2)) = p(i)?;
+    let (i, (pws1, _, expr, nws1, _, inter, arms, else_arm, _, pws2, _, nws2)) = p(start)?;
 
     let mut arms = arms;
     if let Some(arm) = else_arm {
@@ -774,20 +1447,26 @@ fn block_match<'a>(i: &'a [u8], s: &'a Syntax<'a>) -> IResult<&'a [u8], Node<'a>
 
     let inter = match inter {
         Some(Node::Lit(lws, val, rws)) => {
-            assert!(
-                val.is_empty(),
-                "only whitespace allowed between match and first when, found {}",
-                val
-            );
-            assert!(
-                rws.is_empty(),
-                "only whitespace allowed between match and first when, found {}",
-                rws
-            );
+            if !val.is_empty() || !rws.is_empty() {
+                return invalid(
+                    start,
+                    i,
+                    format!(
+                        "only whitespace allowed between match and first when, found {}",
+                        if val.is_empty() { rws } else { val }
+                    ),
+                );
+            }
             Some(lws)
         }
         None => None,
-        _ => panic!("only literals allowed between match and first when"),
+        _ => {
+            return invalid(
+                start,
+                i,
+                "only literals allowed between match and first when".into(),
+            )
+        }
     };
 
     Ok((
@@ -805,8 +1484,8 @@ fn block_match<'a>(i: &'a [u8], s: &'a Syntax<'a>) -> IResult<&'a [u8], Node<'a>
 fn block_let(i: &[u8]) -> IResult<&[u8], Node> {
     let p = tuple((
         opt(tag("-")),
-        ws(tag("let")),
-        ws(alt((target_single, target_tuple))),
+        ws(alt((keyword("let"), keyword("set")))),
+        ws(target),
         opt(tuple((ws(tag("=")), ws(expr_any)))),
         opt(tag("-")),
     ));
@@ -822,49 +1501,167 @@ fn block_let(i: &[u8]) -> IResult<&[u8], Node> {
     ))
 }
 
-fn block_for<'a>(i: &'a [u8], s: &'a Syntax<'a>) -> IResult<&'a [u8], Node<'a>> {
+fn block_do(i: &[u8]) -> IResult<&[u8], Node> {
     let p = tuple((
         opt(tag("-")),
-        ws(tag("for")),
-        ws(alt((target_single, target_tuple))),
-        ws(tag("in")),
+        ws(keyword("do")),
         ws(expr_any),
         opt(tag("-")),
-        |i| tag_block_end(i, s),
-        |i| parse_template(i, s),
-        |i| tag_block_start(i, s),
+    ));
+    let (i, (pws, _, expr, nws)) = p(i)?;
+    Ok((i, Node::Do(WS(pws.is_some(), nws.is_some()), expr)))
+}
+
+fn block_trans(i: &[u8]) -> IResult<&[u8], Node> {
+    let arg = pair(ws(identifier), preceded(ws(tag("=")), ws(expr_any)));
+    let p = tuple((
         opt(tag("-")),
-        ws(tag("endfor")),
+        ws(keyword("trans")),
+        ws(str_lit),
+        separated_list(ws(tag(",")), arg),
         opt(tag("-")),
     ));
-    let (i, (pws1, _, var, _, iter, nws1, _, block, _, pws2, _, nws2)) = p(i)?;
-    Ok((
-        i,
-        Node::Loop(
+    let (i, (pws, _, id, args, nws)) = p(i)?;
+    Ok((i, Node::Trans(WS(pws.is_some(), nws.is_some()), id, args)))
+}
+
+fn block_let_block<'a>(i: &'a [u8], s: &Syntax<'_>) -> IResult<&'a [u8], Node<'a>> {
+    let start = tuple((
+        opt(tag("-")),
+        ws(alt((keyword("let"), keyword("set")))),
+        ws(identifier),
+        opt(tag("-")),
+        |i| tag_block_end(i, s),
+        |i| parse_template(i, s),
+    ));
+    let (i, (pws1, kind, name, nws1, _, nodes)) = start(i)?;
+
+    let end_tag = if kind == b"let" { "endlet" } else { "endset" };
+    let end = tuple((
+        |i| tag_block_start(i, s),
+        opt(tag("-")),
+        ws(keyword(end_tag)),
+        opt(tag("-")),
+    ));
+    let (i, (_, pws2, _, nws2)) = end(i)?;
+
+    Ok((
+        i,
+        Node::LetBlock(
             WS(pws1.is_some(), nws1.is_some()),
-            var,
-            iter,
-            block,
+            name,
+            nodes,
             WS(pws2.is_some(), nws2.is_some()),
         ),
     ))
 }
 
+fn block_for<'a>(i: &'a [u8], s: &Syntax<'_>) -> IResult<&'a [u8], Node<'a>> {
+    let p = tuple((
+        opt(tag("-")),
+        ws(tag("for")),
+        ws(target),
+        opt(tuple((ws(tag(",")), ws(identifier)))),
+        ws(tag("in")),
+        ws(expr_any),
+        opt(preceded(ws(keyword("labeled")), ws(identifier))),
+        opt(ws(keyword("parallel"))),
+        opt(tag("-")),
+        |i| tag_block_end(i, s),
+        |i| parse_template(i, s),
+        |i| tag_block_start(i, s),
+        opt(tag("-")),
+        ws(tag("endfor")),
+        opt(tag("-")),
+    ));
+    let (i, (pws1, _, var, index, _, iter, labeled, parallel, nws1, _, body, _, pws2, _, nws2)) =
+        p(i)?;
+    // With `labeled`, a trailing cast of the iterable is always a cast
+    let (iter, label) = match labeled {
+        Some(label) => (iter, Some(label)),
+        None => loop_label(iter),
+    };
+    Ok((
+        i,
+        Node::Loop(Loop {
+            ws1: WS(pws1.is_some(), nws1.is_some()),
+            var,
+            index: index.map(|(_, index)| index),
+            iter,
+            label,
+            parallel: parallel.is_some(),
+            body,
+            ws2: WS(pws2.is_some(), nws2.is_some()),
+        }),
+    ))
+}
+
+// `as outer` after the iterable is parsed as a cast by `expr_cast`. Casting
+// the iterable (or the end of a range) to anything but a primitive type makes
+// no sense, so such a trailing cast is taken to be the loop label instead.
+// The code generator rejects labels that no `break` or `continue` uses, which
+// catches misspelled casts.
+fn loop_label(iter: WithSpan<Expr<'_>>) -> (WithSpan<Expr<'_>>, Option<&str>) {
+    const PRIMITIVES: [&str; 16] = [
+        "bool", "char", "f32", "f64", "i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16",
+        "u32", "u64", "u128", "usize",
+    ];
+    let WithSpan { inner, span } = iter;
+    match inner {
+        Expr::As(inner, label) if !label.contains("::") && !PRIMITIVES.contains(&label) => {
+            (*inner, Some(label))
+        }
+        Expr::Range(op, start, Some(end)) => {
+            let (end, label) = loop_label(*end);
+            let span = span.start..end.span.end;
+            (
+                WithSpan::new(Expr::Range(op, start, Some(Box::new(end))), span),
+                label,
+            )
+        }
+        inner => (WithSpan::new(inner, span), None),
+    }
+}
+
+fn block_break(i: &[u8]) -> IResult<&[u8], Node> {
+    let p = tuple((
+        opt(tag("-")),
+        ws(keyword("break")),
+        opt(ws(identifier)),
+        opt(tag("-")),
+    ));
+    let (i, (pws, _, label, nws)) = p(i)?;
+    Ok((i, Node::Break(WS(pws.is_some(), nws.is_some()), label)))
+}
+
+fn block_continue(i: &[u8]) -> IResult<&[u8], Node> {
+    let p = tuple((
+        opt(tag("-")),
+        ws(keyword("continue")),
+        opt(ws(identifier)),
+        opt(tag("-")),
+    ));
+    let (i, (pws, _, label, nws)) = p(i)?;
+    Ok((i, Node::Continue(WS(pws.is_some(), nws.is_some()), label)))
+}
+
 fn block_extends(i: &[u8]) -> IResult<&[u8], Node> {
-    let (i, (_, name)) = tuple((ws(tag("extends")), ws(expr_str_lit)))(i)?;
+    let (i, (_, name)) = tuple((ws(tag("extends")), ws(spanned_trim(expr_str_lit))))(i)?;
     Ok((i, Node::Extends(name)))
 }
 
-fn block_block<'a>(i: &'a [u8], s: &'a Syntax<'a>) -> IResult<&'a [u8], Node<'a>> {
+fn block_block<'a>(i: &'a [u8], s: &Syntax<'_>) -> IResult<&'a [u8], Node<'a>> {
     let start = tuple((
         opt(tag("-")),
         ws(tag("block")),
         ws(identifier),
+        opt(ws(keyword("cache"))),
+        opt(preceded(ws(keyword("type")), ws(str_lit))),
         opt(tag("-")),
         |i| tag_block_end(i, s),
         |i| parse_template(i, s),
     ));
-    let (i, (pws1, _, name, nws1, _, contents)) = start(i)?;
+    let (i, (pws1, _, name, cache, content_type, nws1, _, nodes)) = start(i)?;
 
     let end = tuple((
         |i| tag_block_start(i, s),
@@ -877,10 +1674,109 @@ fn block_block<'a>(i: &'a [u8], s: &'a Syntax<'a>) -> IResult<&'a [u8], Node<'a>
 
     Ok((
         i,
-        Node::BlockDef(
-            WS(pws1.is_some(), nws1.is_some()),
+        Node::BlockDef(BlockDef {
+            ws1: WS(pws1.is_some(), nws1.is_some()),
             name,
-            contents,
+            cache: cache.is_some(),
+            content_type,
+            nodes,
+            ws2: WS(pws2.is_some(), nws2.is_some()),
+        }),
+    ))
+}
+
+fn block_filter<'a>(i: &'a [u8], s: &Syntax<'_>) -> IResult<&'a [u8], Node<'a>> {
+    let start = tuple((
+        opt(tag("-")),
+        ws(keyword("filter")),
+        separated_nonempty_list(tag("|"), filter_call),
+        opt(tag("-")),
+        |i| tag_block_end(i, s),
+        |i| parse_template(i, s),
+    ));
+    let (i, (pws1, _, filters, nws1, _, nodes)) = start(i)?;
+
+    let end = tuple((
+        |i| tag_block_start(i, s),
+        opt(tag("-")),
+        ws(keyword("endfilter")),
+        opt(tag("-")),
+    ));
+    let (i, (_, pws2, _, nws2)) = end(i)?;
+
+    let filters = filters
+        .into_iter()
+        .map(|(name, args)| (name, args.unwrap_or_default()))
+        .collect();
+    Ok((
+        i,
+        Node::FilterBlock(
+            WS(pws1.is_some(), nws1.is_some()),
+            filters,
+            nodes,
+            WS(pws2.is_some(), nws2.is_some()),
+        ),
+    ))
+}
+
+fn block_with<'a>(i: &'a [u8], s: &Syntax<'_>) -> IResult<&'a [u8], Node<'a>> {
+    let start = tuple((
+        opt(tag("-")),
+        ws(keyword("with")),
+        separated_nonempty_list(
+            tag(","),
+            tuple((ws(identifier), ws(tag("=")), ws(expr_any))),
+        ),
+        opt(tag("-")),
+        |i| tag_block_end(i, s),
+        |i| parse_template(i, s),
+    ));
+    let (i, (pws1, _, vars, nws1, _, nodes)) = start(i)?;
+
+    let end = tuple((
+        |i| tag_block_start(i, s),
+        opt(tag("-")),
+        ws(keyword("endwith")),
+        opt(tag("-")),
+    ));
+    let (i, (_, pws2, _, nws2)) = end(i)?;
+
+    Ok((
+        i,
+        Node::With(
+            WS(pws1.is_some(), nws1.is_some()),
+            vars.into_iter().map(|(name, _, val)| (name, val)).collect(),
+            nodes,
+            WS(pws2.is_some(), nws2.is_some()),
+        ),
+    ))
+}
+
+fn block_autoescape<'a>(i: &'a [u8], s: &Syntax<'_>) -> IResult<&'a [u8], Node<'a>> {
+    let start = tuple((
+        opt(tag("-")),
+        ws(keyword("autoescape")),
+        ws(spanned_trim(alt((expr_bool_lit, expr_str_lit)))),
+        opt(tag("-")),
+        |i| tag_block_end(i, s),
+        |i| parse_template(i, s),
+    ));
+    let (i, (pws1, _, mode, nws1, _, nodes)) = start(i)?;
+
+    let end = tuple((
+        |i| tag_block_start(i, s),
+        opt(tag("-")),
+        ws(keyword("endautoescape")),
+        opt(tag("-")),
+    ));
+    let (i, (_, pws2, _, nws2)) = end(i)?;
+
+    Ok((
+        i,
+        Node::AutoEscape(
+            WS(pws1.is_some(), nws1.is_some()),
+            mode,
+            nodes,
             WS(pws2.is_some(), nws2.is_some()),
         ),
     ))
@@ -929,12 +1825,42 @@ fn block_import(i: &[u8]) -> IResult<&[u8], Node> {
     ))
 }
 
-fn block_macro<'a>(i: &'a [u8], s: &'a Syntax<'a>) -> IResult<&'a [u8], Node<'a>> {
+fn block_from_import(i: &[u8]) -> IResult<&[u8], Node> {
+    let name = pair(
+        ws(identifier),
+        opt(preceded(ws(keyword("as")), ws(identifier))),
+    );
+    let p = tuple((
+        opt(tag("-")),
+        ws(keyword("from")),
+        ws(expr_str_lit),
+        ws(keyword("import")),
+        separated_nonempty_list(tag(","), name),
+        opt(tag("-")),
+    ));
+    let (i, (pws, _, path, _, names, nws)) = p(i)?;
+    Ok((
+        i,
+        Node::FromImport(
+            WS(pws.is_some(), nws.is_some()),
+            match path {
+                Expr::StrLit(s) => s,
+                _ => panic!("import path must be a string literal"),
+            },
+            names
+                .into_iter()
+                .map(|(name, alias)| (name, alias.unwrap_or(name)))
+                .collect(),
+        ),
+    ))
+}
+
+fn block_macro<'a>(start: &'a [u8], s: &Syntax<'_>) -> IResult<&'a [u8], Node<'a>> {
     let p = tuple((
         opt(tag("-")),
         ws(tag("macro")),
         ws(identifier),
-        ws(parameters),
+        ws(macro_parameters),
         opt(tag("-")),
         |i| tag_block_end(i, s),
         |i| parse_template(i, s),
@@ -944,9 +1870,18 @@ fn block_macro<'a>(i: &'a [u8], s: &'a Syntax<'a>) -> IResult<&'a [u8], Node<'a>
         opt(tag("-")),
     ));
 
-    let (i, (pws1, _, name, params, nws1, _, contents, _, pws2, _, nws2)) = p(i)?;
+    let (i, (pws1, _, name, (args, kwargs), nws1, _, contents, _, pws2, _, nws2)) = p(start)?;
     if name == "super" {
-        panic!("invalid macro name 'super'");
+        return invalid(start, i, "invalid macro name 'super'".into());
+    }
+    if let Some(pos) = args.iter().position(|(_, default)| default.is_some()) {
+        if let Some((arg, _)) = args[pos..].iter().find(|(_, default)| default.is_none()) {
+            let message = format!(
+                "argument '{}' without a default follows an argument with a default in macro '{}'",
+                arg, name
+            );
+            return invalid(start, i, message);
+        }
     }
 
     Ok((
@@ -955,7 +1890,8 @@ fn block_macro<'a>(i: &'a [u8], s: &'a Syntax<'a>) -> IResult<&'a [u8], Node<'a>
             name,
             Macro {
                 ws1: WS(pws1.is_some(), nws1.is_some()),
-                args: params,
+                args,
+                kwargs,
                 nodes: contents,
                 ws2: WS(pws2.is_some(), nws2.is_some()),
             },
@@ -963,20 +1899,38 @@ fn block_macro<'a>(i: &'a [u8], s: &'a Syntax<'a>) -> IResult<&'a [u8], Node<'a>
     ))
 }
 
-fn block_raw<'a>(i: &'a [u8], s: &'a Syntax<'a>) -> IResult<&'a [u8], Node<'a>> {
-    let p = tuple((
-        opt(tag("-")),
-        ws(tag("raw")),
+fn block_raw<'a>(i: &'a [u8], s: &Syntax<'_>) -> IResult<&'a [u8], Node<'a>> {
+    let start = i;
+    let (i, (pws1, kind)) = tuple((
         opt(tag("-")),
-        |i| tag_block_end(i, s),
-        take_until("{% endraw %}"),
+        ws(alt((keyword("raw"), keyword("verbatim")))),
+    ))(i)?;
+    // `verbatim` is accepted as a synonym for `raw`, closed by its own end tag
+    let end_tag = if kind == b"raw" {
+        "endraw"
+    } else {
+        "endverbatim"
+    };
+    let (i, (nws1, _)) = tuple((opt(tag("-")), |i| tag_block_end(i, s)))(i)?;
+
+    // the end tag may use whitespace control, so look for it at every block start
+    let end = tuple((
         |i| tag_block_start(i, s),
         opt(tag("-")),
-        ws(tag("endraw")),
+        ws(tag(end_tag)),
         opt(tag("-")),
+        peek(|i| tag_block_end(i, s)),
     ));
+    let mut pos = 0;
+    let (i, contents, pws2, nws2) = loop {
+        let (_, skipped) = skip_till(start, s.block_start)(&i[pos..])?;
+        pos += skipped.len();
+        if let Ok((rest, (_, pws2, _, nws2, _))) = end(&i[pos..]) {
+            break (rest, &i[..pos], pws2, nws2);
+        }
+        pos += 1;
+    };
 
-    let (i, (pws1, _, nws1, _, contents, _, pws2, _, nws2)) = p(i)?;
     let str_contents = str::from_utf8(contents).unwrap();
     Ok((
         i,
@@ -988,152 +1942,1936 @@ fn block_raw<'a>(i: &'a [u8], s: &'a Syntax<'a>) -> IResult<&'a [u8], Node<'a>>
     ))
 }
 
-fn block_node<'a>(i: &'a [u8], s: &'a Syntax<'a>) -> IResult<&'a [u8], Node<'a>> {
+fn block_node<'a>(i: &'a [u8], s: &Syntax<'_>) -> IResult<&'a [u8], Node<'a>> {
     let p = tuple((
         |i| tag_block_start(i, s),
         alt((
+            |i| block_call_body(i, s),
             block_call,
+            |i| block_let_block(i, s),
             block_let,
+            block_do,
+            block_trans,
             |i| block_if(i, s),
             |i| block_for(i, s),
+            block_break,
+            block_continue,
+            |i| block_filter(i, s),
+            |i| block_with(i, s),
+            |i| block_autoescape(i, s),
             |i| block_match(i, s),
             block_extends,
             block_include,
             block_import,
+            block_from_import,
             |i| block_block(i, s),
             |i| block_macro(i, s),
             |i| block_raw(i, s),
         )),
         |i| tag_block_end(i, s),
     ));
-    let (i, (_, contents, _)) = p(i)?;
+    let (i, (_, contents, _)) = nested(i, p)?;
     Ok((i, contents))
 }
 
-fn block_comment<'a>(i: &'a [u8], s: &'a Syntax<'a>) -> IResult<&'a [u8], Node<'a>> {
+fn block_comment<'a>(i: &'a [u8], s: &Syntax<'_>) -> IResult<&'a [u8], Node<'a>> {
+    let start = i;
     let p = tuple((
         |i| tag_comment_start(i, s),
         opt(tag("-")),
-        take_until(s.comment_end),
+        skip_till(start, s.comment_end),
         |i| tag_comment_end(i, s),
     ));
     let (i, (_, pws, inner, _)) = p(i)?;
+    let nws = inner.len() > 1 && inner[inner.len() - 1] == b'-';
+    let text = if nws {
+        &inner[..inner.len() - 1]
+    } else {
+        inner
+    };
     Ok((
         i,
-        Node::Comment(WS(
-            pws.is_some(),
-            inner.len() > 1 && inner[inner.len() - 1] == b'-',
-        )),
+        Node::Comment(WS(pws.is_some(), nws), str::from_utf8(text).unwrap()),
     ))
 }
 
-fn parse_template<'a>(i: &'a [u8], s: &'a Syntax<'a>) -> IResult<&'a [u8], Vec<Node<'a>>> {
-    many0(alt((
+// Takes input until `end` is found, like `take_until()`. If `end` never shows up,
+// this fails (rather than errors) with the position set to `start`, so that the
+// caller can report where the unterminated region began instead of the end of
+// the input.
+fn skip_till<'a: 'b, 'b>(
+    start: &'a [u8],
+    end: &'b str,
+) -> impl Fn(&'a [u8]) -> ParserError<'a, &'a [u8]> + 'b {
+    move |i: &'a [u8]| match take_until::<_, _, (&[u8], nom::error::ErrorKind)>(end)(i) {
+        Ok(res) => Ok(res),
+        Err(_) => Err(nom::Err::Failure(error_position!(
+            start,
+            nom::error::ErrorKind::TakeUntil
+        ))),
+    }
+}
+
+// Fails parsing for a tag from `start` to `end` that is well-formed but not
+// allowed. The failure isn't backtracked over, `message` is reported instead
+// of the generic one, and parsing resumes after the tag.
+fn invalid<'a, T>(start: &'a [u8], end: &[u8], message: String) -> IResult<&'a [u8], T> {
+    INVALID.with(|invalid| invalid.replace(Some((start.len(), end.len(), message))));
+    Err(nom::Err::Failure(error_position!(
+        start,
+        nom::error::ErrorKind::Verify
+    )))
+}
+
+fn parse_template<'a>(i: &'a [u8], s: &Syntax<'_>) -> IResult<&'a [u8], Vec<WithSpan<Node<'a>>>> {
+    many0(spanned(alt((
         complete(|i| take_content(i, s)),
         complete(|i| block_comment(i, s)),
         complete(|i| expr_node(i, s)),
         complete(|i| block_node(i, s)),
-    )))(i)
+    ))))(i)
 }
 
-fn tag_block_start<'a>(i: &'a [u8], s: &'a Syntax<'a>) -> IResult<&'a [u8], &'a [u8]> {
-    tag(s.block_start)(i)
+fn tag_block_start<'a>(i: &'a [u8], s: &Syntax<'_>) -> IResult<&'a [u8], &'a [u8]> {
+    // A `+` after the delimiter only opts the tag out of `lstrip_blocks`
+    terminated(tag(s.block_start), opt(tag("+")))(i)
 }
-fn tag_block_end<'a>(i: &'a [u8], s: &'a Syntax<'a>) -> IResult<&'a [u8], &'a [u8]> {
-    tag(s.block_end)(i)
+fn tag_block_end<'a>(i: &'a [u8], s: &Syntax<'_>) -> IResult<&'a [u8], &'a [u8]> {
+    let (i, end) = tag(s.block_end)(i)?;
+    if !s.trim_blocks {
+        return Ok((i, end));
+    }
+    let (i, _) = opt(alt((tag("\r\n"), tag("\n"))))(i)?;
+    Ok((i, end))
 }
-fn tag_comment_start<'a>(i: &'a [u8], s: &'a Syntax<'a>) -> IResult<&'a [u8], &'a [u8]> {
+fn tag_comment_start<'a>(i: &'a [u8], s: &Syntax<'_>) -> IResult<&'a [u8], &'a [u8]> {
     tag(s.comment_start)(i)
 }
-fn tag_comment_end<'a>(i: &'a [u8], s: &'a Syntax<'a>) -> IResult<&'a [u8], &'a [u8]> {
+fn tag_comment_end<'a>(i: &'a [u8], s: &Syntax<'_>) -> IResult<&'a [u8], &'a [u8]> {
     tag(s.comment_end)(i)
 }
-fn tag_expr_start<'a>(i: &'a [u8], s: &'a Syntax<'a>) -> IResult<&'a [u8], &'a [u8]> {
+fn tag_expr_start<'a>(i: &'a [u8], s: &Syntax<'_>) -> IResult<&'a [u8], &'a [u8]> {
     tag(s.expr_start)(i)
 }
-fn tag_expr_end<'a>(i: &'a [u8], s: &'a Syntax<'a>) -> IResult<&'a [u8], &'a [u8]> {
+fn tag_expr_end<'a>(i: &'a [u8], s: &Syntax<'_>) -> IResult<&'a [u8], &'a [u8]> {
     tag(s.expr_end)(i)
 }
 
-pub fn parse<'a>(src: &'a str, syntax: &'a Syntax<'a>) -> Vec<Node<'a>> {
-    match parse_template(src.as_bytes(), syntax) {
-        Ok((left, res)) => {
-            if !left.is_empty() {
-                let s = str::from_utf8(left).unwrap();
-                panic!("unable to parse template:\n\n{:?}", s);
-            } else {
-                res
-            }
+pub fn parse<'a>(src: &'a str, syntax: &Syntax<'_>) -> Vec<WithSpan<Node<'a>>> {
+    let (nodes, errors) = parse_recover(src, syntax);
+    if !errors.is_empty() {
+        let errors: Vec<_> = errors.iter().map(ParseError::to_string).collect();
+        panic!("{}", errors.join("\n\n"));
+    }
+    nodes
+}
+
+/// A syntax error in a template
+///
+/// `Display` renders the message followed by the offending source line, with
+/// the span underlined:
+///
+/// ```text
+/// unable to parse template
+///  --> hello.html:2:4
+///   |
+/// 2 | <p>{% if %}</p>
+///   |    ^^^^^^^^
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    /// The template file, if known: the parser only sees the source, so
+    /// callers fill this in
+    pub file: Option<PathBuf>,
+    /// 1-based line number
+    pub line: usize,
+    /// 1-based column number, counted in characters
+    pub column: usize,
+    /// Byte range of the offending source, which doesn't go past the end of
+    /// its line
+    pub span: Range<usize>,
+    /// The source line containing the error, without its line break
+    pub snippet: String,
+    pub message: String,
+}
+
+impl ParseError {
+    // The span covers the tag starting at `rest`, or runs up to the next tag
+    fn new(src: &str, rest: &[u8], s: &Syntax<'_>, message: String) -> Self {
+        let start = src.len() - rest.len();
+        let line_start = src[..start].rfind('\n').map_or(0, |pos| pos + 1);
+        let line_end = src[start..].find('\n').map_or(src.len(), |pos| start + pos);
+        let delims = [
+            (s.block_start, s.block_end),
+            (s.expr_start, s.expr_end),
+            (s.comment_start, s.comment_end),
+        ];
+        let tag_len = delims.iter().find_map(|(open, close)| {
+            let tag = src[start..].strip_prefix(open)?;
+            Some(open.len() + tag.find(close)? + close.len())
+        });
+        let end = match tag_len {
+            Some(len) => start + len,
+            None => next_tag_start(rest, s).map_or(src.len(), |next| src.len() - next.len()),
+        };
+        let end = end.min(line_end);
+        ParseError {
+            file: None,
+            line: src[..start].matches('\n').count() + 1,
+            column: src[line_start..start].chars().count() + 1,
+            span: start..end.max(start),
+            snippet: src[line_start..line_end].to_string(),
+            message,
         }
-        Err(nom::Err::Error(err)) => panic!("problems parsing template source: {:?}", err),
-        Err(nom::Err::Failure(err)) => panic!("problems parsing template source: {:?}", err),
-        Err(nom::Err::Incomplete(_)) => panic!("parsing incomplete"),
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use crate::Syntax;
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let line = self.line.to_string();
+        let gutter = " ".repeat(line.len());
+        writeln!(f, "{}", self.message)?;
+        write!(f, "{}--> ", gutter)?;
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file.display())?;
+        }
+        writeln!(f, "{}:{}", self.line, self.column)?;
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", line, self.snippet)?;
+
+        // Tabs are kept so that the carets line up with the snippet
+        let (before, span) = match self.snippet.char_indices().nth(self.column - 1) {
+            Some((pos, _)) => (
+                &self.snippet[..pos],
+                &self.snippet[pos..(pos + self.span.len()).min(self.snippet.len())],
+            ),
+            None => (self.snippet.as_str(), ""),
+        };
+        let indent: String = before
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let carets = "^".repeat(span.chars().count().max(1));
+        write!(f, "{} | {}{}", gutter, indent, carets)
+    }
+}
 
-    fn check_ws_split(s: &str, res: &(&str, &str, &str)) {
-        let node = super::split_ws_parts(s.as_bytes());
-        match node {
-            super::Node::Lit(lws, s, rws) => {
-                assert_eq!(lws, res.0);
-                assert_eq!(s, res.1);
-                assert_eq!(rws, res.2);
+/// Parses a template without stopping at the first error.
+///
+/// After a failure, parsing resumes at the next block, expression or comment
+/// start, so that every error in the source is reported in one go. The nodes
+/// returned alongside errors are only a best effort and should not be used
+/// to generate code.
+pub fn parse_recover<'a>(
+    src: &'a str,
+    syntax: &Syntax<'_>,
+) -> (Vec<WithSpan<Node<'a>>>, Vec<ParseError>) {
+    let mut nodes = Vec::new();
+    let mut errors = Vec::new();
+    let mut i = src.as_bytes();
+    let _scope = ParseScope::enter(syntax.max_depth, src.len());
+    loop {
+        TOO_DEEP.with(|too_deep| too_deep.set(None));
+        INVALID.with(|invalid| invalid.replace(None));
+        let result = parse_template(i, syntax);
+        // Hitting the limit at or past the point where parsing stopped is
+        // what made it stop; the rest of the template can't be parsed
+        // without its parents
+        let stopped = match &result {
+            Ok(([], _)) => None,
+            Ok((left, _)) => Some(left.len()),
+            Err(nom::Err::Error(err)) | Err(nom::Err::Failure(err)) => Some(err.0.len()),
+            Err(nom::Err::Incomplete(_)) => None,
+        };
+        if let (Some(stopped), Some(too_deep)) = (stopped, TOO_DEEP.with(Cell::get)) {
+            if too_deep <= stopped {
+                let rest = &src.as_bytes()[src.len() - too_deep..];
+                let message = format!(
+                    "template is nested too deeply; the limit is {} levels",
+                    syntax.max_depth
+                );
+                errors.push(ParseError::new(src, rest, syntax, message));
+                break;
             }
-            _ => {
-                panic!("fail");
+        }
+
+        let (failed, message) = match result {
+            Ok((left, res)) => {
+                nodes.extend(res);
+                if left.is_empty() {
+                    break;
+                }
+                (left, "unable to parse template".to_string())
+            }
+            Err(nom::Err::Error((rest, kind))) | Err(nom::Err::Failure((rest, kind))) => {
+                if let Some((start, end, message)) = INVALID.with(|invalid| invalid.replace(None)) {
+                    // Report the tag from its opening delimiter
+                    let start = src.len() - start;
+                    let start = src[..start].rfind(syntax.block_start).unwrap_or(start);
+                    let rest = &src.as_bytes()[start..];
+                    errors.push(ParseError::new(src, rest, syntax, message));
+                    i = &src.as_bytes()[src.len() - end..];
+                    continue;
+                }
+                let message = format!("problems parsing template source ({:?})", kind);
+                (rest, message)
+            }
+            Err(nom::Err::Incomplete(_)) => {
+                let message = "parsing incomplete".to_string();
+                errors.push(ParseError::new(src, i, syntax, message));
+                break;
             }
+        };
+        errors.push(ParseError::new(src, failed, syntax, message));
+        match next_tag_start(failed, syntax) {
+            Some(rest) => i = rest,
+            None => break,
         }
     }
-
-    #[test]
-    fn test_ws_splitter() {
-        check_ws_split("", &("", "", ""));
-        check_ws_split("a", &("", "a", ""));
-        check_ws_split("\ta", &("\t", "a", ""));
-        check_ws_split("b\n", &("", "b", "\n"));
-        check_ws_split(" \t\r\n", &(" \t\r\n", "", ""));
+    if syntax.lstrip_blocks {
+        lstrip_blocks(&mut nodes, src, syntax);
     }
-
-    #[test]
-    #[should_panic]
-    fn test_invalid_block() {
-        super::parse("{% extend \"blah\" %}", &Syntax::default());
+    (nodes, errors)
+}
+
+// Removes the spaces and tabs between the start of a line and a block tag,
+// unless the tag starts with `+` or `-`. Literals are slices of `src`, which
+// is used to find out what follows and precedes them.
+fn lstrip_blocks<'a>(nodes: &mut [WithSpan<Node<'a>>], src: &'a str, s: &Syntax<'_>) {
+    for node in nodes {
+        match &mut **node {
+            Node::Lit(lws, "", _) => lstrip_ws(lws, true, src, s),
+            Node::Lit(_, _, rws) => lstrip_ws(rws, false, src, s),
+            Node::Cond(conds, _) => {
+                for (_, _, nodes) in conds {
+                    lstrip_blocks(nodes, src, s);
+                }
+            }
+            Node::Match(_, _, inter, arms, _) => {
+                if let Some(inter) = inter {
+                    lstrip_ws(inter, true, src, s);
+                }
+                for (_, _, _, nodes) in arms {
+                    lstrip_blocks(nodes, src, s);
+                }
+            }
+            Node::Loop(l) => lstrip_blocks(&mut l.body, src, s),
+            Node::BlockDef(b) => lstrip_blocks(&mut b.nodes, src, s),
+            Node::Macro(_, m) => lstrip_blocks(&mut m.nodes, src, s),
+            Node::FilterBlock(_, _, nodes, _)
+            | Node::LetBlock(_, _, nodes, _)
+            | Node::CallBlock(_, _, _, _, nodes, _)
+            | Node::With(_, _, nodes, _)
+            | Node::AutoEscape(_, _, nodes, _) => lstrip_blocks(nodes, src, s),
+            _ => {}
+        }
     }
+}
 
-    #[test]
-    fn test_parse_filter() {
-        super::parse("{{ strvar|e }}", &Syntax::default());
+// Strips the last line of `ws` if it only holds spaces and tabs and a block tag
+// follows. Without a newline in `ws`, that line only starts at the start of `ws`
+// if it is a `whole` literal at the start of the source or after a newline.
+fn lstrip_ws<'a>(ws: &mut &'a str, whole: bool, src: &'a str, s: &Syntax<'_>) {
+    let start = (ws.as_ptr() as usize).wrapping_sub(src.as_ptr() as usize);
+    if start + ws.len() > src.len() {
+        return;
+    }
+    let next = &src[start + ws.len()..];
+    if !next.starts_with(s.block_start) || next[s.block_start.len()..].starts_with(&['+', '-'][..])
+    {
+        return;
+    }
+    let line_start = match ws.rfind('\n') {
+        Some(pos) => pos + 1,
+        None if whole && (start == 0 || src[..start].ends_with('\n')) => 0,
+        None => return,
+    };
+    if ws[line_start..].bytes().all(|c| c == b' ' || c == b'\t') {
+        *ws = &ws[..line_start];
     }
+}
 
-    #[test]
-    fn test_parse_var_call() {
-        assert_eq!(
-            super::parse("{{ function(\"123\", 3) }}", &Syntax::default()),
-            vec![super::Node::Expr(
-                super::WS(false, false),
-                super::Expr::VarCall(
-                    "function",
-                    vec![super::Expr::StrLit("123"), super::Expr::NumLit("3")]
-                ),
-            )],
-        );
+fn next_tag_start<'a>(i: &'a [u8], s: &Syntax<'_>) -> Option<&'a [u8]> {
+    let starts = [s.block_start, s.expr_start, s.comment_start];
+    (1..i.len())
+        .find(|&pos| {
+            starts
+                .iter()
+                .any(|start| i[pos..].starts_with(start.as_bytes()))
+        })
+        .map(|pos| &i[pos..])
+}
+
+/// Lists the variables a template reads from its context, in order of first use.
+///
+/// Names bound within the template (by `let`, `for`, `match` arms or macro
+/// parameters) are left out, but only inside the scope that binds them. Uses
+/// of `self` are listed as `self`, since they may read any part of the context.
+pub fn referenced_vars<'a>(nodes: &'a [WithSpan<Node<'a>>]) -> Vec<&'a str> {
+    let mut vars = Vec::new();
+    let mut scopes = vec![HashSet::new()];
+    nodes_vars(nodes, &mut scopes, &mut vars);
+    vars
+}
+
+/// Lists, for each block defined in a template, the names bound by the code
+/// around its definitions.
+pub fn block_bindings<'a>(nodes: &'a [WithSpan<Node<'a>>]) -> HashMap<&'a str, HashSet<&'a str>> {
+    let mut blocks = HashMap::new();
+    nodes_block_bindings(nodes, &HashSet::new(), &mut blocks);
+    blocks
+}
+
+fn nodes_block_bindings<'a>(
+    nodes: &'a [WithSpan<Node<'a>>],
+    outer: &HashSet<&'a str>,
+    blocks: &mut HashMap<&'a str, HashSet<&'a str>>,
+) {
+    let mut bound = outer.clone();
+    for node in nodes {
+        match &**node {
+            Node::LetDecl(_, target) | Node::Let(_, target, _) => target_names(target, &mut bound),
+            Node::LetBlock(_, name, nodes, _) => {
+                nodes_block_bindings(nodes, &bound, blocks);
+                bound.insert(name);
+            }
+            Node::Cond(conds, _) => {
+                for (_, _, nodes) in conds {
+                    nodes_block_bindings(nodes, &bound, blocks);
+                }
+            }
+            Node::Match(_, _, _, arms, _) => {
+                for (_, patterns, _, nodes) in arms {
+                    let mut scope = bound.clone();
+                    for (_, params) in patterns {
+                        match_param_names(params, &mut scope);
+                    }
+                    nodes_block_bindings(nodes, &scope, blocks);
+                }
+            }
+            Node::Loop(l) => {
+                let mut scope = bound.clone();
+                scope.insert("loop");
+                scope.extend(l.index);
+                target_names(&l.var, &mut scope);
+                nodes_block_bindings(&l.body, &scope, blocks);
+            }
+            Node::Macro(_, m) => {
+                let mut scope = bound.clone();
+                scope.extend(m.args.iter().map(|(arg, _)| *arg));
+                scope.extend(m.kwargs);
+                nodes_block_bindings(&m.nodes, &scope, blocks);
+            }
+            Node::With(_, vars, nodes, _) => {
+                let mut scope = bound.clone();
+                scope.extend(vars.iter().map(|(name, _)| *name));
+                nodes_block_bindings(nodes, &scope, blocks);
+            }
+            Node::BlockDef(b) => {
+                blocks.entry(b.name).or_default().extend(&bound);
+                nodes_block_bindings(&b.nodes, &bound, blocks);
+            }
+            Node::FilterBlock(_, _, nodes, _)
+            | Node::CallBlock(_, _, _, _, nodes, _)
+            | Node::AutoEscape(_, _, nodes, _) => nodes_block_bindings(nodes, &bound, blocks),
+            _ => {}
+        }
     }
+}
 
-    #[test]
-    fn test_parse_path_call() {
-        assert_eq!(
-            super::parse("{{ self::function(\"123\", 3) }}", &Syntax::default()),
-            vec![super::Node::Expr(
-                super::WS(false, false),
-                super::Expr::PathCall(
-                    vec!["self", "function"],
-                    vec![super::Expr::StrLit("123"), super::Expr::NumLit("3")],
-                ),
-            )],
+type Scopes<'a> = Vec<HashSet<&'a str>>;
+
+fn nodes_vars<'a>(
+    nodes: &'a [WithSpan<Node<'a>>],
+    scopes: &mut Scopes<'a>,
+    vars: &mut Vec<&'a str>,
+) {
+    for node in nodes {
+        match &**node {
+            Node::Expr(_, expr) => expr_vars(expr, scopes, vars),
+            Node::Call(_, _, _, args) => {
+                for arg in args {
+                    expr_vars(arg, scopes, vars);
+                }
+            }
+            Node::CallBlock(_, _, _, args, nodes, _) => {
+                for arg in args {
+                    expr_vars(arg, scopes, vars);
+                }
+                scoped_vars(nodes, scopes, vars, |_| {});
+            }
+            Node::LetDecl(_, target) => bind_target(target, scopes),
+            Node::Let(_, target, val) => {
+                expr_vars(val, scopes, vars);
+                bind_target(target, scopes);
+            }
+            Node::Do(_, expr) => expr_vars(expr, scopes, vars),
+            Node::Trans(_, _, args) => {
+                // The message is formatted for the `locale` in scope
+                let bound = scopes.iter().any(|scope| scope.contains("locale"));
+                if !bound && !vars.contains(&"locale") {
+                    vars.push("locale");
+                }
+                for (_, arg) in args {
+                    expr_vars(arg, scopes, vars);
+                }
+            }
+            Node::Cond(conds, _) => {
+                for (_, cond, body) in conds {
+                    if let Some(cond) = cond {
+                        expr_vars(cond, scopes, vars);
+                    }
+                    scoped_vars(body, scopes, vars, |_| {});
+                }
+            }
+            Node::Match(_, expr, _, arms, _) => {
+                expr_vars(expr, scopes, vars);
+                for (_, patterns, guard, body) in arms {
+                    let mut scope = HashSet::new();
+                    for (_, params) in patterns {
+                        match_param_names(params, &mut scope);
+                    }
+                    scopes.push(scope);
+                    if let Some(guard) = guard {
+                        expr_vars(guard, scopes, vars);
+                    }
+                    nodes_vars(body, scopes, vars);
+                    scopes.pop();
+                }
+            }
+            Node::Loop(l) => {
+                expr_vars(&l.iter, scopes, vars);
+                scoped_vars(&l.body, scopes, vars, |scope| {
+                    scope.insert("loop");
+                    scope.extend(l.index);
+                    target_names(&l.var, scope);
+                });
+            }
+            Node::BlockDef(b) => scoped_vars(&b.nodes, scopes, vars, |_| {}),
+            Node::LetBlock(_, name, nodes, _) => {
+                scoped_vars(nodes, scopes, vars, |_| {});
+                scopes.last_mut().unwrap().insert(name);
+            }
+            Node::With(_, with_vars, nodes, _) => {
+                // Each value may refer to the variables before it
+                scopes.push(HashSet::new());
+                for (name, val) in with_vars {
+                    expr_vars(val, scopes, vars);
+                    scopes.last_mut().unwrap().insert(*name);
+                }
+                nodes_vars(nodes, scopes, vars);
+                scopes.pop();
+            }
+            Node::AutoEscape(_, _, nodes, _) => scoped_vars(nodes, scopes, vars, |_| {}),
+            Node::FilterBlock(_, filters, nodes, _) => {
+                for (_, args) in filters {
+                    for arg in args {
+                        expr_vars(arg, scopes, vars);
+                    }
+                }
+                scoped_vars(nodes, scopes, vars, |_| {});
+            }
+            Node::Macro(_, m) => {
+                // A default value may refer to the arguments before it
+                scopes.push(HashSet::new());
+                for (arg, default) in &m.args {
+                    if let Some(default) = default {
+                        expr_vars(default, scopes, vars);
+                    }
+                    scopes.last_mut().unwrap().insert(*arg);
+                }
+                scopes.last_mut().unwrap().extend(m.kwargs);
+                nodes_vars(&m.nodes, scopes, vars);
+                scopes.pop();
+            }
+            Node::Lit(_, _, _)
+            | Node::Comment(_, _)
+            | Node::Extends(_)
+            | Node::Include(_, _)
+            | Node::Import(_, _, _)
+            | Node::FromImport(_, _, _)
+            | Node::Raw(_, _, _)
+            | Node::Break(_, _)
+            | Node::Continue(_, _) => {}
+        }
+    }
+}
+
+fn scoped_vars<'a, F>(
+    nodes: &'a [WithSpan<Node<'a>>],
+    scopes: &mut Scopes<'a>,
+    vars: &mut Vec<&'a str>,
+    bind: F,
+) where
+    F: FnOnce(&mut HashSet<&'a str>),
+{
+    let mut scope = HashSet::new();
+    bind(&mut scope);
+    scopes.push(scope);
+    nodes_vars(nodes, scopes, vars);
+    scopes.pop();
+}
+
+fn match_param_names<'a>(params: &'a MatchParameters<'a>, scope: &mut HashSet<&'a str>) {
+    match params {
+        MatchParameters::Simple(params) => {
+            for param in params {
+                if let MatchParameter::Name(name) = param {
+                    scope.insert(*name);
+                }
+            }
+        }
+        MatchParameters::Named(params) => {
+            for (name, param) in params {
+                match param {
+                    Some(MatchParameter::Name(name)) => scope.insert(*name),
+                    _ => scope.insert(*name),
+                };
+            }
+        }
+    }
+}
+
+fn bind_target<'a>(target: &'a Target<'a>, scopes: &mut Scopes<'a>) {
+    target_names(target, scopes.last_mut().unwrap());
+}
+
+fn target_names<'a>(target: &'a Target<'a>, scope: &mut HashSet<&'a str>) {
+    let mut names = Vec::new();
+    target.names(&mut names);
+    scope.extend(names);
+}
+
+fn expr_vars<'a>(expr: &'a Expr<'a>, scopes: &Scopes<'a>, vars: &mut Vec<&'a str>) {
+    match expr {
+        Expr::Var(name) | Expr::VarCall(name, _) => {
+            let bound = scopes.iter().any(|scope| scope.contains(name));
+            if !bound && !vars.contains(name) {
+                vars.push(name);
+            }
+        }
+        _ => {}
+    }
+
+    match expr {
+        Expr::BoolLit(_)
+        | Expr::NumLit(_)
+        | Expr::StrLit(_)
+        | Expr::RawStrLit(_)
+        | Expr::CharLit(_)
+        | Expr::Var(_)
+        | Expr::Path(_)
+        | Expr::RustMacro(_, _) => {}
+        Expr::VarCall(_, args)
+        | Expr::PathCall(_, args)
+        | Expr::Array(args)
+        | Expr::Filter(_, args)
+        | Expr::Test(_, args) => {
+            for arg in args {
+                expr_vars(arg, scopes, vars);
+            }
+        }
+        Expr::Attr(obj, _)
+        | Expr::Unary(_, obj)
+        | Expr::As(obj, _)
+        | Expr::Group(obj)
+        | Expr::Try(obj)
+        | Expr::NamedArgument(_, obj) => expr_vars(obj, scopes, vars),
+        Expr::Index(left, right) | Expr::BinOp(_, left, right) => {
+            expr_vars(left, scopes, vars);
+            expr_vars(right, scopes, vars);
+        }
+        Expr::IfElse(cond, then, otherwise) => {
+            expr_vars(cond, scopes, vars);
+            expr_vars(then, scopes, vars);
+            expr_vars(otherwise, scopes, vars);
+        }
+        Expr::Range(_, left, right) => {
+            for side in left.iter().chain(right.iter()) {
+                expr_vars(side, scopes, vars);
+            }
+        }
+        Expr::MethodCall(obj, _, args) => {
+            expr_vars(obj, scopes, vars);
+            for arg in args {
+                expr_vars(arg, scopes, vars);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Syntax;
+    use std::cell::Cell;
+
+    // Spans are left out of comparisons, so expected trees don't need them
+    fn no_span<T>(inner: T) -> super::WithSpan<T> {
+        super::WithSpan::no_span(inner)
+    }
+
+    // Parses an expression as if it were the whole template source
+    fn expr_any(src: &[u8]) -> nom::IResult<&[u8], super::WithSpan<super::Expr<'_>>> {
+        let _scope = super::ParseScope::enter(Syntax::default().max_depth, src.len());
+        super::expr_any(src)
+    }
+
+    fn check_ws_split(s: &str, res: &(&str, &str, &str)) {
+        let node = super::split_ws_parts(s.as_bytes());
+        match node {
+            super::Node::Lit(lws, s, rws) => {
+                assert_eq!(lws, res.0);
+                assert_eq!(s, res.1);
+                assert_eq!(rws, res.2);
+            }
+            _ => {
+                panic!("fail");
+            }
+        }
+    }
+
+    #[test]
+    fn test_ws_splitter() {
+        check_ws_split("", &("", "", ""));
+        check_ws_split("a", &("", "a", ""));
+        check_ws_split("\ta", &("\t", "a", ""));
+        check_ws_split("b\n", &("", "b", "\n"));
+        check_ws_split(" \t\r\n", &(" \t\r\n", "", ""));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_invalid_block() {
+        super::parse("{% extend \"blah\" %}", &Syntax::default());
+    }
+
+    #[test]
+    fn test_parse_filter() {
+        super::parse("{{ strvar|e }}", &Syntax::default());
+    }
+
+    #[test]
+    fn test_parse_var_call() {
+        assert_eq!(
+            super::parse("{{ function(\"123\", 3) }}", &Syntax::default()),
+            vec![super::Node::Expr(
+                super::WS(false, false),
+                no_span(super::Expr::VarCall(
+                    "function",
+                    vec![
+                        no_span(super::Expr::StrLit("123")),
+                        no_span(super::Expr::NumLit("3"))
+                    ]
+                )),
+            )],
+        );
+    }
+
+    #[test]
+    fn test_parse_path_call() {
+        assert_eq!(
+            super::parse("{{ self::function(\"123\", 3) }}", &Syntax::default()),
+            vec![super::Node::Expr(
+                super::WS(false, false),
+                no_span(super::Expr::PathCall(
+                    vec!["self", "function"],
+                    vec![
+                        no_span(super::Expr::StrLit("123")),
+                        no_span(super::Expr::NumLit("3"))
+                    ],
+                )),
+            )],
+        );
+    }
+
+    #[test]
+    fn test_parse_cast_primitive() {
+        assert_eq!(
+            super::parse("{{ count as f64 }}", &Syntax::default()),
+            vec![super::Node::Expr(
+                super::WS(false, false),
+                no_span(super::Expr::As(
+                    Box::new(no_span(super::Expr::Var("count"))),
+                    "f64"
+                )),
+            )],
+        );
+    }
+
+    #[test]
+    fn test_parse_cast_path() {
+        assert_eq!(
+            super::parse("{{ ptr as std::primitive::usize }}", &Syntax::default()),
+            vec![super::Node::Expr(
+                super::WS(false, false),
+                no_span(super::Expr::As(
+                    Box::new(no_span(super::Expr::Var("ptr"))),
+                    "std::primitive::usize"
+                )),
+            )],
+        );
+    }
+
+    #[test]
+    fn test_parse_cast_precedence() {
+        assert_eq!(
+            super::parse("{{ a + b as u32 }}", &Syntax::default()),
+            vec![super::Node::Expr(
+                super::WS(false, false),
+                no_span(super::Expr::BinOp(
+                    "+",
+                    Box::new(no_span(super::Expr::Var("a"))),
+                    Box::new(no_span(super::Expr::As(
+                        Box::new(no_span(super::Expr::Var("b"))),
+                        "u32"
+                    ))),
+                )),
+            )],
+        );
+        assert_eq!(
+            super::parse("{{ a as u32 + b }}", &Syntax::default()),
+            vec![super::Node::Expr(
+                super::WS(false, false),
+                no_span(super::Expr::BinOp(
+                    "+",
+                    Box::new(no_span(super::Expr::As(
+                        Box::new(no_span(super::Expr::Var("a"))),
+                        "u32"
+                    ))),
+                    Box::new(no_span(super::Expr::Var("b"))),
+                )),
+            )],
+        );
+        assert_eq!(
+            super::parse("{{ assoc }}", &Syntax::default()),
+            vec![super::Node::Expr(
+                super::WS(false, false),
+                no_span(super::Expr::Var("assoc")),
+            )],
+        );
+    }
+
+    #[test]
+    fn test_skip_till_unterminated() {
+        let src = b"{% raw %}never closed";
+        match super::skip_till(src, "{% endraw %}")(&src[9..]) {
+            Err(nom::Err::Failure((pos, _))) => assert_eq!(src.len() - pos.len(), 0),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let src = b"{# never closed";
+        match super::block_comment(src, &Syntax::default()) {
+            Err(nom::Err::Failure((pos, _))) => assert_eq!(pos, &src[..]),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_parse_recover() {
+        let syntax = Syntax::default();
+        let (nodes, errors) = super::parse_recover("a {{ }} b {% if %} c {{ d }}", &syntax);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].message, "unable to parse template");
+        assert_eq!((errors[1].line, errors[1].column), (1, 11));
+        assert_eq!(
+            nodes.last().map(|n| &n.inner),
+            Some(&super::Node::Expr(
+                super::WS(false, false),
+                no_span(super::Expr::Var("d"))
+            ))
+        );
+
+        let (_, errors) = super::parse_recover("{{ }}\n{{ ok }}\n{% for %}", &syntax);
+        assert_eq!(errors.len(), 2);
+        assert_eq!((errors[1].line, errors[1].column), (3, 1));
+
+        let (_, errors) = super::parse_recover("{{ a }}{% if b %}c{% endif %}", &syntax);
+        assert!(errors.is_empty());
+
+        let src = "{% macro super() %}{% endmacro %}
+{% match a %}x{% when 1 %}{% endmatch %}";
+        let (_, errors) = super::parse_recover(src, &syntax);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].message, "invalid macro name 'super'");
+        assert_eq!(&src[errors[0].span.clone()], "{% macro super() %}");
+        assert_eq!(
+            errors[1].message,
+            "only whitespace allowed between match and first when, found x"
+        );
+        assert_eq!((errors[1].line, errors[1].column), (2, 1));
+    }
+
+    #[test]
+    fn test_parse_non_ascii_identifier() {
+        assert_eq!(super::identifier("é".as_bytes()), Ok((&b""[..], "é")));
+        assert_eq!(super::parse_recover("{{ é", &Syntax::default()).1.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let syntax = Syntax::default();
+        let src = "<ul>\n\t<li>{% for %}</li>\n</ul>";
+        let (_, errors) = super::parse_recover(src, &syntax);
+        let mut err = errors.into_iter().next().unwrap();
+        assert_eq!((err.line, err.column), (2, 6));
+        assert_eq!(&src[err.span.clone()], "{% for %}");
+        assert_eq!(err.snippet, "\t<li>{% for %}</li>");
+        assert_eq!(err.file, None);
+
+        err.file = Some("list.html".into());
+        assert_eq!(
+            err.to_string(),
+            "unable to parse template\n \
+             --> list.html:2:6\n  \
+             |\n\
+             2 | \t<li>{% for %}</li>\n  \
+             | \t    ^^^^^^^^^"
+        );
+
+        let (_, errors) = super::parse_recover("{{ a }}\n{{ b", &syntax);
+        assert_eq!(&errors[0].snippet, "{{ b");
+        assert_eq!(errors[0].span, 8..12);
+        assert!(errors[0].to_string().ends_with("\n  |\n2 | {{ b\n  | ^^^^"));
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let syntax = Syntax {
+            max_depth: 8,
+            ..Syntax::default()
+        };
+        let parens = |n| format!("{{{{ {}a{} }}}}", "(".repeat(n), ")".repeat(n));
+        let blocks = |n| format!("{}x{}", "{% if a %}".repeat(n), "{% endif %}".repeat(n));
+
+        for src in &[parens(7), blocks(7)] {
+            let (_, errors) = super::parse_recover(src, &syntax);
+            assert!(errors.is_empty(), "{:?}", errors);
+        }
+        for src in &[parens(9), blocks(9)] {
+            let (_, errors) = super::parse_recover(src, &syntax);
+            assert_eq!(errors.len(), 1);
+            assert_eq!(
+                errors[0].message,
+                "template is nested too deeply; the limit is 8 levels"
+            );
+        }
+
+        // The limit only applies while parsing with that syntax
+        assert_eq!(
+            super::MAX_DEPTH.with(Cell::get),
+            Syntax::default().max_depth
+        );
+        assert!(expr_any(&parens(9).as_bytes()[3..]).is_ok());
+    }
+
+    #[test]
+    fn test_nested_parens() {
+        // Each level used to parse its contents several times over
+        let src = format!("{{{{ {}a{} }}}}", "(".repeat(24), ")".repeat(24));
+        super::parse(&src, &Syntax::default());
+
+        assert_eq!(
+            super::parse("{{ (a..b) }}", &Syntax::default()),
+            vec![super::Node::Expr(
+                super::WS(false, false),
+                no_span(super::Expr::Group(Box::new(no_span(super::Expr::Range(
+                    "..",
+                    Some(Box::new(no_span(super::Expr::Var("a")))),
+                    Some(Box::new(no_span(super::Expr::Var("b")))),
+                ))))),
+            )]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "problems parsing template source")]
+    fn test_unterminated_comment() {
+        super::parse("foo {# bar", &Syntax::default());
+    }
+
+    #[test]
+    fn test_parse_filter_chain_in_argument() {
+        use super::Expr::*;
+        let syntax = Syntax::default();
+        assert_eq!(
+            super::parse("{{ value|default(other|upper) }}", &syntax),
+            vec![super::Node::Expr(
+                super::WS(false, false),
+                no_span(Filter(
+                    "default",
+                    vec![
+                        no_span(Var("value")),
+                        no_span(Filter("upper", vec![no_span(Var("other"))]))
+                    ]
+                )),
+            )],
+        );
+        assert_eq!(
+            super::parse("{{ value|default((other|trim)|upper) }}", &syntax),
+            vec![super::Node::Expr(
+                super::WS(false, false),
+                no_span(Filter(
+                    "default",
+                    vec![
+                        no_span(Var("value")),
+                        no_span(Filter(
+                            "upper",
+                            vec![no_span(Group(Box::new(no_span(Filter(
+                                "trim",
+                                vec![no_span(Var("other"))]
+                            )))))]
+                        )),
+                    ]
+                )),
+            )],
+        );
+        assert_eq!(
+            super::parse("{{ value|default(other)|upper }}", &syntax),
+            vec![super::Node::Expr(
+                super::WS(false, false),
+                no_span(Filter(
+                    "upper",
+                    vec![no_span(Filter(
+                        "default",
+                        vec![no_span(Var("value")), no_span(Var("other"))]
+                    ))]
+                )),
+            )],
+        );
+    }
+
+    #[test]
+    fn test_unterminated_str_lit() {
+        let src = b"foo(\"abc";
+        match expr_any(src) {
+            Err(nom::Err::Failure((pos, kind))) => {
+                assert_eq!(src.len() - pos.len(), 4);
+                assert_eq!(kind, nom::error::ErrorKind::Char);
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_unterminated_char_lit() {
+        let src = b"x == 'a";
+        match expr_any(src) {
+            Err(nom::Err::Failure((pos, kind))) => {
+                assert_eq!(src.len() - pos.len(), 5);
+                assert_eq!(kind, nom::error::ErrorKind::Char);
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_dangling_escape_lit() {
+        let src = b"[1, \"abc\\";
+        match expr_any(src) {
+            Err(nom::Err::Failure((pos, kind))) => {
+                assert_eq!(src.len() - pos.len(), 4);
+                assert_eq!(kind, nom::error::ErrorKind::Escaped);
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+        assert_eq!(
+            super::str_lit(b"\"a\\\"b\" rest"),
+            Ok((&b" rest"[..], "a\\\"b"))
+        );
+        assert_eq!(super::char_lit(b"'\\''"), Ok((&b""[..], "\\'")));
+        assert_eq!(super::str_lit(b"\"\""), Ok((&b""[..], "")));
+    }
+
+    #[test]
+    fn test_referenced_vars() {
+        let syntax = Syntax::default();
+        let nodes = super::parse(
+            "{% for item in items %}{{ item.name }}{{ prefix }}{{ loop.index }}{% endfor %}",
+            &syntax,
+        );
+        assert_eq!(super::referenced_vars(&nodes), vec!["items", "prefix"]);
+
+        let nodes = super::parse(
+            "{% let total = count + 1 %}{{ total }}{{ self.title }}{{ title|upper }}",
+            &syntax,
+        );
+        assert_eq!(
+            super::referenced_vars(&nodes),
+            vec!["count", "self", "title"]
+        );
+
+        let nodes = super::parse(
+            "{% for x in xs %}{{ x }}{% endfor %}{{ x }}{% match opt %}\
+             {% when Some with (val) %}{{ val }}{% when None %}{{ val }}{% endmatch %}",
+            &syntax,
+        );
+        assert_eq!(
+            super::referenced_vars(&nodes),
+            vec!["xs", "x", "opt", "val"]
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        use super::{Expr, Node};
+        let src = "a {{ x + y.z|upper }}{% if c %}b{% endif %}";
+        let syntax = Syntax::default();
+        let nodes = super::parse(src, &syntax);
+        let spanned: Vec<_> = nodes.iter().map(|n| &src[n.span.clone()]).collect();
+        assert_eq!(
+            spanned,
+            ["a ", "{{ x + y.z|upper }}", "{% if c %}b{% endif %}"]
+        );
+
+        match &*nodes[1] {
+            Node::Expr(_, expr) => {
+                assert_eq!(&src[expr.span.clone()], "x + y.z|upper");
+                match &**expr {
+                    Expr::BinOp("+", left, right) => {
+                        assert_eq!(left.span, 5..6);
+                        assert_eq!(&src[right.span.clone()], "y.z|upper");
+                        match &***right {
+                            Expr::Filter("upper", args) => {
+                                assert_eq!(&src[args[0].span.clone()], "y.z");
+                            }
+                            expr => panic!("unexpected expression: {:?}", expr),
+                        }
+                    }
+                    expr => panic!("unexpected expression: {:?}", expr),
+                }
+            }
+            node => panic!("unexpected node: {:?}", node),
+        }
+
+        match &*nodes[2] {
+            Node::Cond(conds, _) => {
+                let (_, cond, body) = &conds[0];
+                assert_eq!(&src[cond.as_ref().unwrap().span.clone()], "c");
+                assert_eq!(&src[body[0].span.clone()], "b");
+            }
+            node => panic!("unexpected node: {:?}", node),
+        }
+    }
+
+    #[test]
+    fn test_block_bindings() {
+        let syntax = Syntax::default();
+        let nodes = super::parse(
+            "{% block head %}{% endblock %}{% let title = 1 %}\
+             {% for row in rows %}{% block row %}{% block cell %}{% endblock %}{% endblock %}\
+             {% endfor %}{% block foot %}{% endblock %}",
+            &syntax,
+        );
+        let bindings = super::block_bindings(&nodes);
+        let names = |block| {
+            let mut names: Vec<_> = bindings[block].iter().copied().collect();
+            names.sort_unstable();
+            names
+        };
+        assert!(names("head").is_empty());
+        assert_eq!(names("row"), vec!["loop", "row", "title"]);
+        assert_eq!(names("cell"), vec!["loop", "row", "title"]);
+        assert_eq!(names("foot"), vec!["title"]);
+    }
+
+    #[test]
+    fn test_lenient_delimiters() {
+        let syntax = Syntax {
+            lenient: true,
+            ..Syntax::default()
+        };
+
+        let css = "@media print{#header{display:none}}";
+        assert_eq!(
+            super::parse(css, &syntax),
+            vec![super::Node::Lit("", css, "")]
+        );
+
+        let js = "var o = {{ not a template }};";
+        assert_eq!(
+            super::parse(js, &syntax),
+            vec![super::Node::Lit("", js, "")]
+        );
+
+        assert_eq!(
+            super::parse("{{ x }}{{ y z }}{% if x %}{# y{% endif %}", &syntax),
+            vec![
+                super::Node::Expr(super::WS(false, false), no_span(super::Expr::Var("x"))),
+                super::Node::Lit("", "{{ y z }}", ""),
+                super::Node::Cond(
+                    vec![(
+                        super::WS(false, false),
+                        Some(no_span(super::Expr::Var("x"))),
+                        vec![no_span(super::Node::Lit("", "{# y", ""))],
+                    )],
+                    super::WS(false, false),
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_strict_delimiters() {
+        super::parse("var o = {{ not a template }};", &Syntax::default());
+    }
+
+    #[test]
+    fn test_parse_for_index() {
+        assert_eq!(
+            super::parse("{% for item, i in items %}{% endfor %}", &Syntax::default()),
+            vec![super::Node::Loop(super::Loop {
+                ws1: super::WS(false, false),
+                var: super::Target::Name("item"),
+                index: Some("i"),
+                iter: no_span(super::Expr::Var("items")),
+                label: None,
+                parallel: false,
+                body: vec![],
+                ws2: super::WS(false, false),
+            })],
+        );
+    }
+
+    #[test]
+    fn test_parse_for_struct_target() {
+        assert_eq!(
+            super::parse(
+                "{% for models::User { name, age } in users parallel %}{% endfor %}",
+                &Syntax::default()
+            ),
+            vec![super::Node::Loop(super::Loop {
+                ws1: super::WS(false, false),
+                var: super::Target::Struct(
+                    vec!["models", "User"],
+                    vec![
+                        ("name", super::Target::Name("name")),
+                        ("age", super::Target::Name("age"))
+                    ]
+                ),
+                index: None,
+                iter: no_span(super::Expr::Var("users")),
+                label: None,
+                parallel: true,
+                body: vec![],
+                ws2: super::WS(false, false),
+            })],
+        );
+    }
+
+    #[test]
+    fn test_parse_macro_kwargs() {
+        let syntax = Syntax::default();
+        assert_eq!(
+            super::parse("{% macro tag(name, **attrs) %}{% endmacro %}", &syntax),
+            vec![super::Node::Macro(
+                "tag",
+                super::Macro {
+                    ws1: super::WS(false, false),
+                    args: vec![("name", None)],
+                    kwargs: Some("attrs"),
+                    nodes: vec![],
+                    ws2: super::WS(false, false),
+                }
+            )],
+        );
+        assert_eq!(
+            super::parse("{% call tag(\"div\", id = \"x\", a == b) %}", &syntax),
+            vec![super::Node::Call(
+                super::WS(false, false),
+                None,
+                "tag",
+                vec![
+                    no_span(super::Expr::StrLit("div")),
+                    no_span(super::Expr::NamedArgument(
+                        "id",
+                        Box::new(no_span(super::Expr::StrLit("x")))
+                    )),
+                    no_span(super::Expr::BinOp(
+                        "==",
+                        Box::new(no_span(super::Expr::Var("a"))),
+                        Box::new(no_span(super::Expr::Var("b")))
+                    )),
+                ],
+            )],
+        );
+    }
+
+    #[test]
+    fn test_parse_verbatim() {
+        let syntax = Syntax::default();
+        assert_eq!(
+            super::parse("{% verbatim %}{{ x }}{% endverbatim %}", &syntax),
+            vec![super::Node::Raw(
+                super::WS(false, false),
+                "{{ x }}",
+                super::WS(false, false)
+            )],
+        );
+        assert_eq!(
+            super::parse("{% raw %}{% endverbatim %}{% endraw %}", &syntax),
+            vec![super::Node::Raw(
+                super::WS(false, false),
+                "{% endverbatim %}",
+                super::WS(false, false)
+            )],
+        );
+    }
+
+    #[test]
+    fn test_parse_coalesce() {
+        use super::Expr::*;
+        assert_eq!(
+            super::parse("{{ a ?? b || c }}", &Syntax::default()),
+            vec![super::Node::Expr(
+                super::WS(false, false),
+                no_span(BinOp(
+                    "??",
+                    Box::new(no_span(Var("a"))),
+                    Box::new(no_span(BinOp(
+                        "||",
+                        Box::new(no_span(Var("b"))),
+                        Box::new(no_span(Var("c")))
+                    )))
+                )),
+            )],
+        );
+    }
+
+    #[test]
+    fn test_parse_filter_cond() {
+        use super::Expr::*;
+        assert_eq!(
+            super::parse(
+                "{{ x|(upper if loud else truncate(2))|trim }}",
+                &Syntax::default()
+            ),
+            vec![super::Node::Expr(
+                super::WS(false, false),
+                no_span(Filter(
+                    "trim",
+                    vec![no_span(IfElse(
+                        Box::new(no_span(Var("loud"))),
+                        Box::new(no_span(Filter("upper", vec![no_span(Var("x"))]))),
+                        Box::new(no_span(Filter(
+                            "truncate",
+                            vec![no_span(Var("x")), no_span(NumLit("2"))]
+                        ))),
+                    ))]
+                )),
+            )],
+        );
+    }
+
+    #[test]
+    fn test_parse_float_range() {
+        use super::Expr::*;
+        assert_eq!(
+            super::parse("{{ 1.5..=2 }}", &Syntax::default()),
+            vec![super::Node::Expr(
+                super::WS(false, false),
+                no_span(Range(
+                    "..=",
+                    Some(Box::new(no_span(NumLit("1.5")))),
+                    Some(Box::new(no_span(NumLit("2"))))
+                )),
+            )],
+        );
+    }
+
+    #[test]
+    fn test_parse_when_struct_without_with() {
+        let syntax = Syntax::default();
+        let nodes = super::parse(
+            "{% match m %}{% when Message::Text { content } %}{{ content }}{% endmatch %}",
+            &syntax,
+        );
+        match &*nodes[0] {
+            super::Node::Match(_, _, _, arms, _) => {
+                assert_eq!(
+                    arms[0].1,
+                    vec![(
+                        Some(super::MatchVariant::Path(vec!["Message", "Text"])),
+                        super::MatchParameters::Named(vec![("content", None)])
+                    )]
+                );
+                assert_eq!(arms[0].2, None);
+            }
+            node => panic!("unexpected node {:?}", node),
+        }
+    }
+
+    #[test]
+    fn test_parse_when_guard_or() {
+        use super::{Expr, MatchParameter, MatchParameters, MatchVariant, Node};
+        let syntax = Syntax::default();
+        let nodes = super::parse(
+            "{% match m %}{% when A | B with (x) if x > 1 %}{% endmatch %}",
+            &syntax,
+        );
+        match &*nodes[0] {
+            Node::Match(_, _, _, arms, _) => {
+                assert_eq!(
+                    arms[0].1,
+                    vec![
+                        (
+                            Some(MatchVariant::Path(vec!["A"])),
+                            MatchParameters::default()
+                        ),
+                        (
+                            Some(MatchVariant::Path(vec!["B"])),
+                            MatchParameters::Simple(vec![MatchParameter::Name("x")])
+                        ),
+                    ]
+                );
+                assert_eq!(
+                    arms[0].2,
+                    Some(no_span(Expr::BinOp(
+                        ">",
+                        Box::new(no_span(Expr::Var("x"))),
+                        Box::new(no_span(Expr::NumLit("1")))
+                    )))
+                );
+            }
+            node => panic!("unexpected node {:?}", node),
+        }
+    }
+
+    #[test]
+    fn test_parse_nested_target() {
+        use super::{Expr, Node, Target, WS};
+        assert_eq!(
+            super::parse(
+                "{% let Point { x, y: Wrapped(y), .. } = p %}{% let (a, (b, ..)) = q %}",
+                &Syntax::default()
+            ),
+            vec![
+                Node::Let(
+                    WS(false, false),
+                    Target::Struct(
+                        vec!["Point"],
+                        vec![
+                            ("x", Target::Name("x")),
+                            ("y", Target::Tuple(vec!["Wrapped"], vec![Target::Name("y")])),
+                        ]
+                    ),
+                    no_span(Expr::Var("p")),
+                ),
+                Node::Let(
+                    WS(false, false),
+                    Target::Tuple(
+                        vec![],
+                        vec![
+                            Target::Name("a"),
+                            Target::Tuple(vec![], vec![Target::Name("b"), Target::Rest]),
+                        ]
+                    ),
+                    no_span(Expr::Var("q")),
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_parse_with() {
+        use super::{Expr, Node, WS};
+        assert_eq!(
+            super::parse(
+                "{% with a = 1, b = c %}{{ a }}{%- endwith %}",
+                &Syntax::default()
+            ),
+            vec![Node::With(
+                WS(false, false),
+                vec![
+                    ("a", no_span(Expr::NumLit("1"))),
+                    ("b", no_span(Expr::Var("c")))
+                ],
+                vec![no_span(Node::Expr(
+                    WS(false, false),
+                    no_span(Expr::Var("a"))
+                ))],
+                WS(true, false),
+            )],
+        );
+    }
+
+    #[test]
+    fn test_parse_trans() {
+        use super::{Expr, Node, WS};
+        let syntax = Syntax::default();
+        assert_eq!(
+            super::parse("{%- trans \"hello\" %}", &syntax),
+            vec![Node::Trans(WS(true, false), "hello", vec![])],
+        );
+        assert_eq!(
+            super::parse(
+                "{% trans \"unread\" count = n, name = user.name %}",
+                &syntax
+            ),
+            vec![Node::Trans(
+                WS(false, false),
+                "unread",
+                vec![
+                    ("count", no_span(Expr::Var("n"))),
+                    (
+                        "name",
+                        no_span(Expr::Attr(Box::new(no_span(Expr::Var("user"))), "name"))
+                    ),
+                ],
+            )],
+        );
+    }
+
+    #[test]
+    fn test_parse_filter_named_args() {
+        use super::{Expr, Node, WS};
+        let syntax = Syntax::default();
+        assert_eq!(
+            super::parse("{{ s|ljust(5, fill = '.') }}", &syntax),
+            vec![Node::Expr(
+                WS(false, false),
+                no_span(Expr::Filter(
+                    "ljust",
+                    vec![
+                        no_span(Expr::Var("s")),
+                        no_span(Expr::NumLit("5")),
+                        no_span(Expr::NamedArgument(
+                            "fill",
+                            Box::new(no_span(Expr::CharLit(".")))
+                        )),
+                    ],
+                )),
+            )],
+        );
+    }
+
+    #[test]
+    fn test_parse_loop_label() {
+        let syntax = Syntax::default();
+        let nodes = super::parse(
+            "{% for i in 0..n as outer %}{% break outer %}{% endfor %}",
+            &syntax,
+        );
+        match &*nodes[0] {
+            super::Node::Loop(l) => {
+                assert_eq!(l.label, Some("outer"));
+                assert_eq!(
+                    l.iter,
+                    no_span(super::Expr::Range(
+                        "..",
+                        Some(Box::new(no_span(super::Expr::NumLit("0")))),
+                        Some(Box::new(no_span(super::Expr::Var("n"))))
+                    ))
+                );
+                assert_eq!(
+                    l.body,
+                    vec![super::Node::Break(super::WS(false, false), Some("outer"))]
+                );
+            }
+            node => panic!("unexpected node {:?}", node),
+        }
+
+        let nodes = super::parse(
+            "{% for i in xs labeled outer %}{% continue outer %}{% endfor %}",
+            &syntax,
+        );
+        match &*nodes[0] {
+            super::Node::Loop(l) => {
+                assert_eq!(l.label, Some("outer"));
+                assert_eq!(l.iter, no_span(super::Expr::Var("xs")));
+            }
+            node => panic!("unexpected node {:?}", node),
+        }
+
+        // Casts to primitive types are kept
+        let nodes = super::parse("{% for i in 0..n as usize %}{% endfor %}", &syntax);
+        match &*nodes[0] {
+            super::Node::Loop(l) => {
+                assert_eq!(l.label, None);
+                assert_eq!(
+                    l.iter,
+                    no_span(super::Expr::Range(
+                        "..",
+                        Some(Box::new(no_span(super::Expr::NumLit("0")))),
+                        Some(Box::new(no_span(super::Expr::As(
+                            Box::new(no_span(super::Expr::Var("n"))),
+                            "usize"
+                        ))))
+                    ))
+                );
+            }
+            node => panic!("unexpected node {:?}", node),
+        }
+    }
+
+    #[test]
+    fn test_parse_turbofish_try() {
+        use super::Expr::*;
+        assert_eq!(
+            super::parse("{{ s.parse::<Vec<u8>>()? ?? 0 }}", &Syntax::default()),
+            vec![super::Node::Expr(
+                super::WS(false, false),
+                no_span(BinOp(
+                    "??",
+                    Box::new(no_span(Try(Box::new(no_span(MethodCall(
+                        Box::new(no_span(Var("s"))),
+                        "parse::<Vec<u8>>",
+                        vec![]
+                    )))))),
+                    Box::new(no_span(NumLit("0"))),
+                )),
+            )],
+        );
+    }
+
+    #[test]
+    fn test_parse_raw() {
+        use super::{Node, WS};
+
+        let syntax = Syntax::default();
+        assert_eq!(
+            super::parse("{% raw %}{{ a }}{% endif %}{%- endraw -%}", &syntax),
+            vec![Node::Raw(
+                WS(false, false),
+                "{{ a }}{% endif %}",
+                WS(true, true)
+            )],
+        );
+
+        let syntax = Syntax {
+            block_start: "<%",
+            block_end: "%>",
+            ..Syntax::default()
+        };
+        assert_eq!(
+            super::parse("<% raw %>{% endraw %}<%endraw%>", &syntax),
+            vec![Node::Raw(
+                WS(false, false),
+                "{% endraw %}",
+                WS(false, false)
+            )],
+        );
+    }
+
+    #[test]
+    fn test_parse_filter_block() {
+        use super::{Expr, Node, WS};
+
+        let syntax = Syntax::default();
+        assert_eq!(
+            super::parse(
+                "{% filter upper|truncate(3) -%} a {%- endfilter %}",
+                &syntax
+            ),
+            vec![Node::FilterBlock(
+                WS(false, true),
+                vec![
+                    ("upper", vec![]),
+                    ("truncate", vec![no_span(Expr::NumLit("3"))])
+                ],
+                vec![no_span(Node::Lit(" ", "a", " "))],
+                WS(true, false),
+            )],
+        );
+    }
+
+    #[test]
+    fn test_parse_let_block() {
+        use super::{Expr, Node, Target, WS};
+
+        let syntax = Syntax::default();
+        assert_eq!(
+            super::parse("{% set a %}{{ b }}{%- endset %}", &syntax),
+            vec![Node::LetBlock(
+                WS(false, false),
+                "a",
+                vec![no_span(Node::Expr(
+                    WS(false, false),
+                    no_span(Expr::Var("b"))
+                ))],
+                WS(true, false),
+            )],
+        );
+        assert_eq!(
+            super::parse("{% let a %}{% endlet %}", &syntax),
+            vec![Node::LetBlock(
+                WS(false, false),
+                "a",
+                vec![],
+                WS(false, false)
+            )],
+        );
+        assert_eq!(
+            super::parse("{% let a %}{% set b = 1 %}", &syntax),
+            vec![
+                Node::LetDecl(WS(false, false), Target::Name("a")),
+                Node::Let(
+                    WS(false, false),
+                    Target::Name("b"),
+                    no_span(Expr::NumLit("1"))
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_parse_if_else_expr() {
+        use super::{Expr, Node, WS};
+
+        let syntax = Syntax::default();
+        assert_eq!(
+            super::parse("{{ a if b == 1 else c if d else 2 }}", &syntax),
+            vec![Node::Expr(
+                WS(false, false),
+                no_span(Expr::IfElse(
+                    Box::new(no_span(Expr::BinOp(
+                        "==",
+                        Box::new(no_span(Expr::Var("b"))),
+                        Box::new(no_span(Expr::NumLit("1")))
+                    ))),
+                    Box::new(no_span(Expr::Var("a"))),
+                    Box::new(no_span(Expr::IfElse(
+                        Box::new(no_span(Expr::Var("d"))),
+                        Box::new(no_span(Expr::Var("c"))),
+                        Box::new(no_span(Expr::NumLit("2")))
+                    ))),
+                )),
+            )],
+        );
+    }
+
+    #[test]
+    fn test_parse_test_expr() {
+        use super::{Expr, Node, WS};
+
+        let syntax = Syntax::default();
+        assert_eq!(
+            super::parse("{{ a + b is divisibleby(3) }}", &syntax),
+            vec![Node::Expr(
+                WS(false, false),
+                no_span(Expr::Test(
+                    "divisibleby",
+                    vec![
+                        no_span(Expr::BinOp(
+                            "+",
+                            Box::new(no_span(Expr::Var("a"))),
+                            Box::new(no_span(Expr::Var("b")))
+                        )),
+                        no_span(Expr::NumLit("3")),
+                    ],
+                )),
+            )],
+        );
+        assert_eq!(
+            super::parse("{{ a is not some && b }}", &syntax),
+            vec![Node::Expr(
+                WS(false, false),
+                no_span(Expr::BinOp(
+                    "&&",
+                    Box::new(no_span(Expr::Unary(
+                        "!",
+                        Box::new(no_span(Expr::Test("some", vec![no_span(Expr::Var("a"))])))
+                    ))),
+                    Box::new(no_span(Expr::Var("b"))),
+                )),
+            )],
+        );
+    }
+
+    #[test]
+    fn test_parse_concat() {
+        use super::{Expr, Node, WS};
+
+        let syntax = Syntax {
+            expr_start: "{~",
+            expr_end: "~}",
+            ..Syntax::default()
+        };
+        assert_eq!(
+            super::parse("{~ a ~ \"b\" ~}", &syntax),
+            vec![Node::Expr(
+                WS(false, false),
+                no_span(Expr::BinOp(
+                    "~",
+                    Box::new(no_span(Expr::Var("a"))),
+                    Box::new(no_span(Expr::StrLit("b")))
+                )),
+            )],
+        );
+    }
+
+    #[test]
+    fn test_parse_num_lit() {
+        for lit in &[
+            "42",
+            "1.5",
+            "0xFF",
+            "0o17",
+            "0b1010",
+            "1_000_000",
+            "1e-3",
+            "2.5E+10",
+            "42u64",
+            "0xffu8",
+            "1.0f32",
+            "7usize",
+        ] {
+            assert_eq!(super::num_lit(lit.as_bytes()), Ok((&b""[..], *lit)));
+        }
+        assert_eq!(super::num_lit(b"1..5"), Ok((&b"..5"[..], "1")));
+        assert_eq!(super::num_lit(b"1.max(2)"), Ok((&b".max(2)"[..], "1")));
+        assert_eq!(super::num_lit(b"2else"), Ok((&b"else"[..], "2")));
+    }
+
+    #[test]
+    fn test_parse_str_lit() {
+        for lit in &[r#""a\"b\\c""#, r#""\n\r\t\0\x7F\u{1F600}""#, r#""\'""#] {
+            let content = &lit[1..lit.len() - 1];
+            assert_eq!(super::str_lit(lit.as_bytes()), Ok((&b""[..], content)));
+        }
+        for lit in &[r#""\q""#, r#""\x80""#, r#""\u{}""#, r#""\u{1234567}""#] {
+            match super::str_lit(lit.as_bytes()) {
+                Err(nom::Err::Failure((pos, nom::error::ErrorKind::Escaped))) => {
+                    assert_eq!(pos, lit.as_bytes())
+                }
+                res => panic!("unexpected result for {}: {:?}", lit, res),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_raw_str_lit() {
+        use super::{Expr, Node, WS};
+
+        let syntax = Syntax::default();
+        assert_eq!(
+            super::parse(r###"{{ r"a\b" }}{{ r#"say "hi""# }}{{ r }}"###, &syntax),
+            vec![
+                Node::Expr(WS(false, false), no_span(Expr::RawStrLit(r#"r"a\b""#))),
+                Node::Expr(
+                    WS(false, false),
+                    no_span(Expr::RawStrLit(r##"r#"say "hi""#"##))
+                ),
+                Node::Expr(WS(false, false), no_span(Expr::Var("r"))),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_parse_macro_defaults() {
+        let syntax = Syntax::default();
+        match super::parse("{% macro m(a, b = 1, c=a) %}{% endmacro %}", &syntax)
+            .first()
+            .map(|n| &n.inner)
+        {
+            Some(super::Node::Macro("m", m)) => assert_eq!(
+                m.args,
+                vec![
+                    ("a", None),
+                    ("b", Some(no_span(super::Expr::NumLit("1")))),
+                    ("c", Some(no_span(super::Expr::Var("a")))),
+                ]
+            ),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "argument 'b' without a default follows an argument with a default in macro 'm'"
+    )]
+    fn test_parse_macro_default_order() {
+        super::parse("{% macro m(a=1, b) %}{% endmacro %}", &Syntax::default());
+    }
+
+    #[test]
+    fn test_parse_call_block() {
+        use super::{Expr, Node, WS};
+
+        let syntax = Syntax::default();
+        assert_eq!(
+            super::parse("{% call m(a) -%} b {% endcall %}{% call n() %}", &syntax),
+            vec![
+                Node::CallBlock(
+                    WS(false, true),
+                    None,
+                    "m",
+                    vec![no_span(Expr::Var("a"))],
+                    vec![no_span(Node::Lit(" ", "b", " "))],
+                    WS(false, false),
+                ),
+                Node::Call(WS(false, false), None, "n", vec![]),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_parse_from_import() {
+        use super::{Node, WS};
+
+        let syntax = Syntax::default();
+        assert_eq!(
+            super::parse("{% from \"macros.html\" import a, b as c -%}", &syntax),
+            vec![Node::FromImport(
+                WS(false, true),
+                "macros.html",
+                vec![("a", "a"), ("b", "c")],
+            )],
+        );
+    }
+
+    #[test]
+    fn test_parse_block_cache() {
+        let syntax = Syntax::default();
+        assert_eq!(
+            super::parse("{% block sidebar cache -%}{% endblock %}", &syntax),
+            vec![super::Node::BlockDef(super::BlockDef {
+                ws1: super::WS(false, true),
+                name: "sidebar",
+                cache: true,
+                content_type: None,
+                nodes: vec![],
+                ws2: super::WS(false, false),
+            })],
+        );
+        assert_eq!(
+            super::parse("{% block cached %}{% endblock %}", &syntax),
+            vec![super::Node::BlockDef(super::BlockDef {
+                ws1: super::WS(false, false),
+                name: "cached",
+                cache: false,
+                content_type: None,
+                nodes: vec![],
+                ws2: super::WS(false, false),
+            })],
+        );
+    }
+
+    #[test]
+    fn test_parse_block_content_type() {
+        let syntax = Syntax::default();
+        assert_eq!(
+            super::parse(
+                "{% block icon cache type \"image/svg+xml\" -%}{% endblock %}",
+                &syntax
+            ),
+            vec![super::Node::BlockDef(super::BlockDef {
+                ws1: super::WS(false, true),
+                name: "icon",
+                cache: true,
+                content_type: Some("image/svg+xml"),
+                nodes: vec![],
+                ws2: super::WS(false, false),
+            })],
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "unable to parse template")]
+    fn test_parse_block_content_type_unquoted() {
+        super::parse(
+            "{% block icon type svg %}{% endblock %}",
+            &Syntax::default(),
         );
     }
 
@@ -0,0 +1,473 @@
+use crate::parser::{
+    Expr, Loop, MatchParameter, MatchParameters, MatchPattern, MatchVariant, Node, Target,
+    WithSpan, WS,
+};
+use crate::Syntax;
+
+/// Prints parsed templates back to source, with the spacing inside tags and
+/// expressions normalized
+///
+/// Everything else is written as it was parsed. In particular, the text
+/// between tags is part of the output and is kept as written, including its
+/// indentation. The source should be parsed with `trim_blocks` and
+/// `lstrip_blocks` turned off, since they remove text from the parse tree.
+pub fn print(nodes: &[WithSpan<Node>], syntax: &Syntax) -> String {
+    let mut printer = Printer {
+        syntax,
+        buf: String::new(),
+    };
+    printer.nodes(nodes);
+    printer.buf
+}
+
+struct Printer<'s> {
+    syntax: &'s Syntax<'s>,
+    buf: String,
+}
+
+impl<'s> Printer<'s> {
+    fn nodes(&mut self, nodes: &[WithSpan<Node>]) {
+        for n in nodes {
+            self.node(n);
+        }
+    }
+
+    fn node(&mut self, node: &Node) {
+        match *node {
+            Node::Lit(lws, val, rws) => {
+                self.buf.push_str(lws);
+                self.buf.push_str(val);
+                self.buf.push_str(rws);
+            }
+            Node::Comment(ws, text) => {
+                self.buf.push_str(self.syntax.comment_start);
+                if ws.0 {
+                    self.buf.push('-');
+                }
+                self.buf.push_str(text);
+                if ws.1 {
+                    self.buf.push('-');
+                }
+                self.buf.push_str(self.syntax.comment_end);
+            }
+            Node::Expr(ws, ref expr) => {
+                self.buf.push_str(self.syntax.expr_start);
+                self.buf.push_str(if ws.0 { "- " } else { " " });
+                self.buf.push_str(&expr_to_string(expr));
+                self.buf.push_str(if ws.1 { " -" } else { " " });
+                self.buf.push_str(self.syntax.expr_end);
+            }
+            Node::Call(ws, scope, name, ref args) => {
+                self.tag(ws, &call_to_string(scope, name, args));
+            }
+            Node::CallBlock(ws1, scope, name, ref args, ref nodes, ws2) => {
+                self.tag(ws1, &call_to_string(scope, name, args));
+                self.nodes(nodes);
+                self.tag(ws2, "endcall");
+            }
+            Node::LetDecl(ws, ref target) => {
+                self.tag(ws, &format!("let {}", target_to_string(target)));
+            }
+            Node::Let(ws, ref target, ref val) => {
+                let body = format!("let {} = {}", target_to_string(target), expr_to_string(val));
+                self.tag(ws, &body);
+            }
+            Node::LetBlock(ws1, name, ref nodes, ws2) => {
+                self.tag(ws1, &format!("let {}", name));
+                self.nodes(nodes);
+                self.tag(ws2, "endlet");
+            }
+            Node::Do(ws, ref expr) => self.tag(ws, &format!("do {}", expr_to_string(expr))),
+            Node::Trans(ws, id, ref args) => {
+                let mut body = format!("trans \"{}\"", id);
+                for (i, (name, val)) in args.iter().enumerate() {
+                    body.push_str(if i == 0 { " " } else { ", " });
+                    body.push_str(&format!("{} = {}", name, expr_to_string(val)));
+                }
+                self.tag(ws, &body);
+            }
+            Node::Cond(ref branches, ws) => {
+                for (i, (ws, cond, nodes)) in branches.iter().enumerate() {
+                    let body = match (i, cond) {
+                        (0, Some(cond)) => format!("if {}", expr_to_string(cond)),
+                        (_, Some(cond)) => format!("else if {}", expr_to_string(cond)),
+                        (_, None) => "else".to_string(),
+                    };
+                    self.tag(*ws, &body);
+                    self.nodes(nodes);
+                }
+                self.tag(ws, "endif");
+            }
+            Node::Match(ws1, ref expr, inter, ref arms, ws2) => {
+                self.tag(ws1, &format!("match {}", expr_to_string(expr)));
+                self.buf.push_str(inter.unwrap_or(""));
+                for (ws, patterns, guard, nodes) in arms {
+                    let mut body = match patterns.first() {
+                        Some((None, _)) => "else".to_string(),
+                        _ => {
+                            let patterns: Vec<_> = patterns.iter().map(pattern_to_string).collect();
+                            format!("when {}", patterns.join(" | "))
+                        }
+                    };
+                    if let Some(guard) = guard {
+                        body.push_str(&format!(" if {}", expr_to_string(guard)));
+                    }
+                    self.tag(*ws, &body);
+                    self.nodes(nodes);
+                }
+                self.tag(ws2, "endmatch");
+            }
+            Node::Loop(ref l) => self.write_loop(l),
+            Node::Break(ws, label) => self.tag(ws, &with_label("break", label)),
+            Node::Continue(ws, label) => self.tag(ws, &with_label("continue", label)),
+            Node::Extends(ref path) => {
+                self.tag(
+                    WS(false, false),
+                    &format!("extends {}", expr_to_string(path)),
+                );
+            }
+            Node::BlockDef(ref def) => {
+                let cache = if def.cache { " cache" } else { "" };
+                let content_type = match def.content_type {
+                    Some(content_type) => format!(" type \"{}\"", content_type),
+                    None => String::new(),
+                };
+                self.tag(
+                    def.ws1,
+                    &format!("block {}{}{}", def.name, cache, content_type),
+                );
+                self.nodes(&def.nodes);
+                self.tag(def.ws2, "endblock");
+            }
+            Node::Include(ws, path) => self.tag(ws, &format!("include \"{}\"", path)),
+            Node::Import(ws, path, scope) => {
+                self.tag(ws, &format!("import \"{}\" as {}", path, scope));
+            }
+            Node::FromImport(ws, path, ref names) => {
+                let names: Vec<_> = names
+                    .iter()
+                    .map(|&(name, alias)| match alias {
+                        alias if alias == name => name.to_string(),
+                        alias => format!("{} as {}", name, alias),
+                    })
+                    .collect();
+                self.tag(
+                    ws,
+                    &format!("from \"{}\" import {}", path, names.join(", ")),
+                );
+            }
+            Node::Macro(name, ref m) => {
+                let mut params: Vec<_> = m
+                    .args
+                    .iter()
+                    .map(|(arg, default)| match default {
+                        Some(default) => format!("{} = {}", arg, expr_to_string(default)),
+                        None => arg.to_string(),
+                    })
+                    .collect();
+                if let Some(kwargs) = m.kwargs {
+                    params.push(format!("**{}", kwargs));
+                }
+                self.tag(m.ws1, &format!("macro {}({})", name, params.join(", ")));
+                self.nodes(&m.nodes);
+                self.tag(m.ws2, "endmacro");
+            }
+            Node::Raw(ws1, contents, ws2) => {
+                self.tag(ws1, "raw");
+                self.buf.push_str(contents);
+                self.tag(ws2, "endraw");
+            }
+            Node::FilterBlock(ws1, ref filters, ref nodes, ws2) => {
+                let filters: Vec<_> = filters
+                    .iter()
+                    .map(|(name, args)| filter_call_to_string(name, args))
+                    .collect();
+                self.tag(ws1, &format!("filter {}", filters.join("|")));
+                self.nodes(nodes);
+                self.tag(ws2, "endfilter");
+            }
+            Node::With(ws1, ref vars, ref nodes, ws2) => {
+                let vars: Vec<_> = vars
+                    .iter()
+                    .map(|(name, val)| format!("{} = {}", name, expr_to_string(val)))
+                    .collect();
+                self.tag(ws1, &format!("with {}", vars.join(", ")));
+                self.nodes(nodes);
+                self.tag(ws2, "endwith");
+            }
+            Node::AutoEscape(ws1, ref mode, ref nodes, ws2) => {
+                self.tag(ws1, &format!("autoescape {}", expr_to_string(mode)));
+                self.nodes(nodes);
+                self.tag(ws2, "endautoescape");
+            }
+        }
+    }
+
+    fn write_loop(&mut self, l: &Loop) {
+        let mut body = format!("for {}", target_to_string(&l.var));
+        if let Some(index) = l.index {
+            body.push_str(&format!(", {}", index));
+        }
+        body.push_str(&format!(" in {}", expr_to_string(&l.iter)));
+        if let Some(label) = l.label {
+            body.push_str(&format!(" as {}", label));
+        }
+        if l.parallel {
+            body.push_str(" parallel");
+        }
+        self.tag(l.ws1, &body);
+        self.nodes(&l.body);
+        self.tag(l.ws2, "endfor");
+    }
+
+    fn tag(&mut self, ws: WS, body: &str) {
+        self.buf.push_str(self.syntax.block_start);
+        self.buf.push_str(if ws.0 { "- " } else { " " });
+        self.buf.push_str(body);
+        self.buf.push_str(if ws.1 { " -" } else { " " });
+        self.buf.push_str(self.syntax.block_end);
+    }
+}
+
+fn with_label(keyword: &str, label: Option<&str>) -> String {
+    match label {
+        Some(label) => format!("{} {}", keyword, label),
+        None => keyword.to_string(),
+    }
+}
+
+fn call_to_string(scope: Option<&str>, name: &str, args: &[WithSpan<Expr>]) -> String {
+    match scope {
+        Some(scope) => format!("call {}::{}({})", scope, name, exprs_to_string(args)),
+        None => format!("call {}({})", name, exprs_to_string(args)),
+    }
+}
+
+fn filter_call_to_string(name: &str, args: &[WithSpan<Expr>]) -> String {
+    if args.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}({})", name, exprs_to_string(args))
+    }
+}
+
+fn exprs_to_string(exprs: &[WithSpan<Expr>]) -> String {
+    exprs
+        .iter()
+        .map(|expr| expr_to_string(expr))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Prints an expression back to source
+pub fn expr_to_string(expr: &Expr) -> String {
+    match *expr {
+        Expr::BoolLit(s) | Expr::NumLit(s) | Expr::RawStrLit(s) | Expr::Var(s) => s.to_string(),
+        Expr::StrLit(s) => format!("\"{}\"", s),
+        Expr::CharLit(s) => format!("'{}'", s),
+        Expr::VarCall(name, ref args) => format!("{}({})", name, exprs_to_string(args)),
+        Expr::Path(ref path) => path.join("::"),
+        Expr::PathCall(ref path, ref args) => {
+            format!("{}({})", path.join("::"), exprs_to_string(args))
+        }
+        Expr::Array(ref elems) => format!("[{}]", exprs_to_string(elems)),
+        Expr::Attr(ref obj, attr) => format!("{}.{}", expr_to_string(obj), attr),
+        Expr::Index(ref obj, ref key) => {
+            format!("{}[{}]", expr_to_string(obj), expr_to_string(key))
+        }
+        Expr::Filter(name, ref args) => format!(
+            "{}|{}",
+            expr_to_string(&args[0]),
+            filter_call_to_string(name, &args[1..])
+        ),
+        Expr::Test(name, ref args) => test_to_string(name, args, false),
+        Expr::Unary("!", ref inner) => match ***inner {
+            Expr::Test(name, ref args) => test_to_string(name, args, true),
+            ref inner => format!("!{}", expr_to_string(inner)),
+        },
+        Expr::Unary(op, ref inner) => format!("{}{}", op, expr_to_string(inner)),
+        Expr::As(ref inner, ty) => format!("{} as {}", expr_to_string(inner), ty),
+        Expr::BinOp(op, ref left, ref right) => {
+            format!("{} {} {}", expr_to_string(left), op, expr_to_string(right))
+        }
+        Expr::Range(op, ref start, ref end) => {
+            let start = start.as_ref().map_or(String::new(), |e| expr_to_string(e));
+            let end = end.as_ref().map_or(String::new(), |e| expr_to_string(e));
+            format!("{}{}{}", start, op, end)
+        }
+        Expr::Group(ref inner) => format!("({})", expr_to_string(inner)),
+        Expr::MethodCall(ref obj, name, ref args) => {
+            format!(
+                "{}.{}({})",
+                expr_to_string(obj),
+                name,
+                exprs_to_string(args)
+            )
+        }
+        Expr::RustMacro(name, args) => format!("{}!({})", name, args),
+        Expr::NamedArgument(name, ref value) => format!("{} = {}", name, expr_to_string(value)),
+        Expr::IfElse(ref cond, ref then, ref otherwise) => match (&***then, &***otherwise) {
+            // `value|(a if cond else b)` applies one of two filters to the same value
+            (Expr::Filter(a, a_args), Expr::Filter(b, b_args)) if a_args[0] == b_args[0] => {
+                format!(
+                    "{}|({} if {} else {})",
+                    expr_to_string(&a_args[0]),
+                    filter_call_to_string(a, &a_args[1..]),
+                    expr_to_string(cond),
+                    filter_call_to_string(b, &b_args[1..])
+                )
+            }
+            _ => format!(
+                "{} if {} else {}",
+                expr_to_string(then),
+                expr_to_string(cond),
+                expr_to_string(otherwise)
+            ),
+        },
+        Expr::Try(ref inner) => format!("{}?", expr_to_string(inner)),
+    }
+}
+
+fn test_to_string(name: &str, args: &[WithSpan<Expr>], negated: bool) -> String {
+    let not = if negated { "not " } else { "" };
+    let obj = expr_to_string(&args[0]);
+    if args.len() > 1 {
+        format!(
+            "{} is {}{}({})",
+            obj,
+            not,
+            name,
+            exprs_to_string(&args[1..])
+        )
+    } else {
+        format!("{} is {}{}", obj, not, name)
+    }
+}
+
+fn target_to_string(target: &Target) -> String {
+    match *target {
+        Target::Name(name) => name.to_string(),
+        Target::Tuple(ref path, ref elems) => {
+            let elems: Vec<_> = elems.iter().map(target_to_string).collect();
+            format!("{}({})", path.join("::"), elems.join(", "))
+        }
+        Target::Struct(ref path, ref fields) => {
+            let fields: Vec<_> = fields
+                .iter()
+                .map(|(name, target)| match target {
+                    Target::Name(inner) if inner == name => name.to_string(),
+                    target => format!("{}: {}", name, target_to_string(target)),
+                })
+                .collect();
+            format!("{} {{ {} }}", path.join("::"), fields.join(", "))
+        }
+        Target::Rest => "..".to_string(),
+    }
+}
+
+fn pattern_to_string((variant, params): &MatchPattern) -> String {
+    let mut s = match variant {
+        Some(MatchVariant::Path(path)) => path.join("::"),
+        Some(MatchVariant::Name(s)) | Some(MatchVariant::NumLit(s)) => s.to_string(),
+        Some(MatchVariant::StrLit(s)) => format!("\"{}\"", s),
+        Some(MatchVariant::CharLit(s)) => format!("'{}'", s),
+        None => String::new(),
+    };
+    match params {
+        MatchParameters::Simple(params) if params.is_empty() => {}
+        MatchParameters::Simple(params) => {
+            let params: Vec<_> = params.iter().map(param_to_string).collect();
+            s.push_str(&format!(" with ({})", params.join(", ")));
+        }
+        MatchParameters::Named(params) => {
+            let params: Vec<_> = params
+                .iter()
+                .map(|(name, param)| match param {
+                    Some(param) => format!("{}: {}", name, param_to_string(param)),
+                    None => name.to_string(),
+                })
+                .collect();
+            s.push_str(&format!(" with {{ {} }}", params.join(", ")));
+        }
+    }
+    s
+}
+
+fn param_to_string(param: &MatchParameter) -> String {
+    match *param {
+        MatchParameter::Name(s) | MatchParameter::NumLit(s) => s.to_string(),
+        MatchParameter::StrLit(s) => format!("\"{}\"", s),
+        MatchParameter::CharLit(s) => format!("'{}'", s),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::print;
+    use crate::parser::parse;
+    use crate::Syntax;
+
+    fn format(src: &str) -> String {
+        let syntax = Syntax::default();
+        let out = print(&parse(src, &syntax), &syntax);
+        // Printing must not change the meaning of the template
+        assert_eq!(parse(&out, &syntax), parse(src, &syntax));
+        out
+    }
+
+    #[test]
+    fn test_print_spacing() {
+        assert_eq!(format("a {{x}} b {{-y  -}}"), "a {{ x }} b {{- y -}}");
+        assert_eq!(
+            format("{%if a&&b%}1{%-else if !c-%}2{%else%}3{%endif%}"),
+            "{% if a && b %}1{%- else if !c -%}2{% else %}3{% endif %}"
+        );
+        assert_eq!(format("{#- note  -#}"), "{#- note  -#}");
+    }
+
+    #[test]
+    fn test_print_exprs() {
+        for src in &[
+            "{{ a.b(1, \"s\")?[0] }}",
+            "{{ x|truncate(length = 5)|upper }}",
+            "{{ x|(upper if loud else lower)|trim }}",
+            "{{ 1 if a is defined else 2 }}",
+            "{{ n is not divisibleby(3) }}",
+            "{{ -(a + b) * 2 as i64 }}",
+            "{{ std::cmp::max(a, 'b') }}",
+            "{{ format!(\"{}\", x) }}",
+            "{{ [1, 2]|join(\", \") }}",
+            "{{ a ?? b }}",
+            "{{ r#\"raw\"# }}",
+        ] {
+            assert_eq!(&format(src), src);
+        }
+        assert_eq!(format("{{ 0 ..= n }}"), "{{ 0..=n }}");
+    }
+
+    #[test]
+    fn test_print_blocks() {
+        for src in &[
+            "{% extends \"base.html\" %}{% block body cache %}x{% endblock %}",
+            "{% for (a, b), i in pairs as outer parallel %}{% break outer %}{% endfor %}",
+            "{% let Point { x, y: (a, ..) } = p %}{% let z %}{% do v.push(1) %}",
+            "{% let s %}text{% endlet %}",
+            "{% match x %}\n  {% when Some with (y) | Ok with { v: 1, w } if y > 0 %}a\
+             {% when \"s\" %}b{% else %}c{% endmatch %}",
+            "{% macro m(a, b = 1, **kw) %}{{ a }}{% endmacro %}{% call m(1) %}",
+            "{% import \"m.html\" as m %}{% from \"m.html\" import a, b as c %}\
+             {% call m::x() %}y{% endcall %}",
+            "{% include \"a.html\" %}{% raw %}{{ x }}{% endraw %}",
+            "{% filter upper|truncate(5) %}x{% endfilter %}",
+            "{% with a = 1, b = 2 %}x{% endwith %}",
+            "{% autoescape false %}x{% endautoescape %}",
+            "{% trans \"hello\" name = user.name, n = 2 %}",
+        ] {
+            assert_eq!(&format(src), src);
+        }
+        assert_eq!(
+            format("{%set x=1%}{%verbatim%}{{%endverbatim-%}"),
+            "{% let x = 1 %}{% raw %}{{% endraw -%}"
+        );
+    }
+}
@@ -0,0 +1,258 @@
+use crate::parser::{Expr, MatchPattern, Node, Target, WithSpan};
+
+/// Traverses parsed templates
+///
+/// Every method has a default implementation that visits the children of its
+/// argument through the matching `walk_*()` function. Implementations
+/// override the methods for the items they care about, and call the `walk_*()`
+/// function from there to keep descending:
+///
+/// ```
+/// use askama_shared::parser::{parse, Expr};
+/// use askama_shared::visitor::{walk_expr, walk_nodes, Visitor};
+/// use askama_shared::Syntax;
+///
+/// struct Vars<'a>(Vec<&'a str>);
+///
+/// impl<'a> Visitor<'a> for Vars<'a> {
+///     fn visit_expr(&mut self, expr: &'a Expr<'a>) {
+///         if let Expr::Var(name) = expr {
+///             self.0.push(name);
+///         }
+///         walk_expr(self, expr);
+///     }
+/// }
+///
+/// let syntax = Syntax::default();
+/// let nodes = parse("{% if a %}{{ b|e }}{% endif %}", &syntax);
+/// let mut vars = Vars(Vec::new());
+/// walk_nodes(&mut vars, &nodes);
+/// assert_eq!(vars.0, vec!["a", "b"]);
+/// ```
+pub trait Visitor<'a> {
+    fn visit_node(&mut self, node: &'a Node<'a>) {
+        walk_node(self, node);
+    }
+
+    fn visit_expr(&mut self, expr: &'a Expr<'a>) {
+        walk_expr(self, expr);
+    }
+
+    fn visit_target(&mut self, target: &'a Target<'a>) {
+        walk_target(self, target);
+    }
+
+    /// Match patterns have no children, so this does nothing by default
+    fn visit_pattern(&mut self, _pattern: &'a MatchPattern<'a>) {}
+}
+
+pub fn walk_nodes<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, nodes: &'a [WithSpan<Node<'a>>]) {
+    for n in nodes {
+        visitor.visit_node(n);
+    }
+}
+
+pub fn walk_node<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, node: &'a Node<'a>) {
+    match node {
+        Node::Lit(_, _, _)
+        | Node::Comment(_, _)
+        | Node::Include(_, _)
+        | Node::Import(_, _, _)
+        | Node::FromImport(_, _, _)
+        | Node::Raw(_, _, _)
+        | Node::Break(_, _)
+        | Node::Continue(_, _) => {}
+        Node::Expr(_, expr) | Node::Do(_, expr) | Node::Extends(expr) => visitor.visit_expr(expr),
+        Node::Call(_, _, _, args) => walk_exprs(visitor, args),
+        Node::LetDecl(_, target) => visitor.visit_target(target),
+        Node::Let(_, target, expr) => {
+            visitor.visit_target(target);
+            visitor.visit_expr(expr);
+        }
+        Node::Trans(_, _, args) => {
+            for (_, expr) in args {
+                visitor.visit_expr(expr);
+            }
+        }
+        Node::Cond(branches, _) => {
+            for (_, cond, nodes) in branches {
+                if let Some(cond) = cond {
+                    visitor.visit_expr(cond);
+                }
+                walk_nodes(visitor, nodes);
+            }
+        }
+        Node::Match(_, expr, _, arms, _) => {
+            visitor.visit_expr(expr);
+            for (_, patterns, guard, nodes) in arms {
+                for pattern in patterns {
+                    visitor.visit_pattern(pattern);
+                }
+                if let Some(guard) = guard {
+                    visitor.visit_expr(guard);
+                }
+                walk_nodes(visitor, nodes);
+            }
+        }
+        Node::Loop(l) => {
+            visitor.visit_target(&l.var);
+            visitor.visit_expr(&l.iter);
+            walk_nodes(visitor, &l.body);
+        }
+        Node::BlockDef(b) => walk_nodes(visitor, &b.nodes),
+        Node::Macro(_, m) => {
+            for (_, default) in &m.args {
+                if let Some(default) = default {
+                    visitor.visit_expr(default);
+                }
+            }
+            walk_nodes(visitor, &m.nodes);
+        }
+        Node::FilterBlock(_, filters, nodes, _) => {
+            for (_, args) in filters {
+                walk_exprs(visitor, args);
+            }
+            walk_nodes(visitor, nodes);
+        }
+        Node::LetBlock(_, _, nodes, _) => walk_nodes(visitor, nodes),
+        Node::With(_, vars, nodes, _) => {
+            for (_, expr) in vars {
+                visitor.visit_expr(expr);
+            }
+            walk_nodes(visitor, nodes);
+        }
+        Node::AutoEscape(_, expr, nodes, _) => {
+            visitor.visit_expr(expr);
+            walk_nodes(visitor, nodes);
+        }
+        Node::CallBlock(_, _, _, args, nodes, _) => {
+            walk_exprs(visitor, args);
+            walk_nodes(visitor, nodes);
+        }
+    }
+}
+
+pub fn walk_expr<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, expr: &'a Expr<'a>) {
+    match expr {
+        Expr::BoolLit(_)
+        | Expr::NumLit(_)
+        | Expr::StrLit(_)
+        | Expr::RawStrLit(_)
+        | Expr::CharLit(_)
+        | Expr::Var(_)
+        | Expr::Path(_)
+        | Expr::RustMacro(_, _) => {}
+        Expr::VarCall(_, args)
+        | Expr::PathCall(_, args)
+        | Expr::Array(args)
+        | Expr::Filter(_, args)
+        | Expr::Test(_, args) => walk_exprs(visitor, args),
+        Expr::Attr(obj, _)
+        | Expr::Unary(_, obj)
+        | Expr::As(obj, _)
+        | Expr::Group(obj)
+        | Expr::NamedArgument(_, obj)
+        | Expr::Try(obj) => visitor.visit_expr(obj),
+        Expr::Index(obj, key) | Expr::BinOp(_, obj, key) => {
+            visitor.visit_expr(obj);
+            visitor.visit_expr(key);
+        }
+        Expr::Range(_, left, right) => {
+            if let Some(left) = left {
+                visitor.visit_expr(left);
+            }
+            if let Some(right) = right {
+                visitor.visit_expr(right);
+            }
+        }
+        Expr::MethodCall(obj, _, args) => {
+            visitor.visit_expr(obj);
+            walk_exprs(visitor, args);
+        }
+        Expr::IfElse(cond, then, otherwise) => {
+            visitor.visit_expr(cond);
+            visitor.visit_expr(then);
+            visitor.visit_expr(otherwise);
+        }
+    }
+}
+
+pub fn walk_target<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, target: &'a Target<'a>) {
+    match target {
+        Target::Name(_) | Target::Rest => {}
+        Target::Tuple(_, targets) => {
+            for target in targets {
+                visitor.visit_target(target);
+            }
+        }
+        Target::Struct(_, fields) => {
+            for (_, target) in fields {
+                visitor.visit_target(target);
+            }
+        }
+    }
+}
+
+fn walk_exprs<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, exprs: &'a [WithSpan<Expr<'a>>]) {
+    for expr in exprs {
+        visitor.visit_expr(expr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{walk_expr, walk_node, walk_nodes, walk_target, Visitor};
+    use crate::parser::{parse, Expr, Node, Target};
+    use crate::Syntax;
+
+    #[derive(Default)]
+    struct Names<'a> {
+        vars: Vec<&'a str>,
+        targets: Vec<&'a str>,
+        includes: Vec<&'a str>,
+    }
+
+    impl<'a> Visitor<'a> for Names<'a> {
+        fn visit_node(&mut self, node: &'a Node<'a>) {
+            if let Node::Include(_, path) = node {
+                self.includes.push(path);
+            }
+            walk_node(self, node);
+        }
+
+        fn visit_expr(&mut self, expr: &'a Expr<'a>) {
+            if let Expr::Var(name) = expr {
+                self.vars.push(name);
+            }
+            walk_expr(self, expr);
+        }
+
+        fn visit_target(&mut self, target: &'a Target<'a>) {
+            if let Target::Name(name) = target {
+                self.targets.push(name);
+            }
+            walk_target(self, target);
+        }
+    }
+
+    #[test]
+    fn test_walk_nodes() {
+        let syntax = Syntax::default();
+        let nodes = parse(
+            "{% for (k, v) in map|sort(rev) %}\
+             {% match v %}{% when Some with (x) if x > lim %}{{ x.y(a, b?) }}{% endmatch %}\
+             {% endfor %}\
+             {% macro m(d = def) %}{% include \"inc.html\" %}{% endmacro %}\
+             {% block b %}{% let Point { x, .. } = p[i] %}{% endblock %}",
+            &syntax,
+        );
+        let mut names = Names::default();
+        walk_nodes(&mut names, &nodes);
+        assert_eq!(
+            names.vars,
+            vec!["map", "rev", "v", "x", "lim", "x", "a", "b", "def", "p", "i"]
+        );
+        assert_eq!(names.targets, vec!["k", "v", "x"]);
+        assert_eq!(names.includes, vec!["inc.html"]);
+    }
+}
@@ -30,3 +30,36 @@ pub fn into_response<T: Template>(t: &T, ext: &str) -> Response {
         }
     }
 }
+
+/// Renders the block named `block` into a body with its declared content type
+pub fn try_into_block_body<T: Template>(t: &T, block: &str) -> Result<Body> {
+    let string = t.render_block(block)?;
+    let mut body = Body::from_string(string);
+
+    let mime = match t.block_content_type(block) {
+        Some(content_type) => content_type.parse().ok(),
+        None => Mime::from_extension(t.extension().unwrap_or("txt")),
+    };
+    if let Some(mime) = mime {
+        body.set_mime(mime);
+    }
+
+    Ok(body)
+}
+
+/// Responds with the block named `block`, using its declared content type
+pub fn into_block_response<T: Template>(t: &T, block: &str) -> Response {
+    match try_into_block_body(t, block) {
+        Ok(body) => {
+            let mut response = Response::new(200);
+            response.set_body(body);
+            response
+        }
+
+        Err(error) => {
+            let mut response = Response::new(500);
+            response.set_error(error);
+            response
+        }
+    }
+}
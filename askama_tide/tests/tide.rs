@@ -1,7 +1,8 @@
 use askama::Template;
 use async_std::prelude::*;
 use std::convert::TryInto;
-use tide::{http::mime::HTML, Body, Response};
+use tide::http::mime::{HTML, SVG};
+use tide::{Body, Response};
 
 #[derive(Template)]
 #[template(path = "hello.html")]
@@ -27,3 +28,20 @@ async fn template_to_body() {
     body.read_to_string(&mut body_string).await.unwrap();
     assert_eq!(body_string, "Hello, world!");
 }
+
+#[derive(Template)]
+#[template(
+    source = "<p>{% block icon type \"image/svg+xml\" %}<svg></svg>{% endblock %}</p>",
+    ext = "html"
+)]
+struct IconTemplate;
+
+#[async_std::test]
+async fn template_block_to_response() {
+    let mut res = askama_tide::into_block_response(&IconTemplate, "icon");
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.content_type(), Some(SVG));
+
+    let res: &mut tide::http::Response = res.as_mut();
+    assert_eq!(res.body_string().await.unwrap(), "<svg></svg>");
+}
@@ -1,6 +1,10 @@
 pub use askama::*;
 pub use warp;
 
+use futures::channel::mpsc;
+use futures::executor::block_on;
+use futures::SinkExt;
+use tokio::task;
 use warp::http::{self, header, StatusCode};
 use warp::hyper::Body;
 use warp::reply::Response;
@@ -20,3 +24,49 @@ pub fn reply<T: askama::Template>(t: &T, ext: &str) -> Response {
     }
     .unwrap()
 }
+
+/// Replies with the block named `block`, using its declared content type
+pub fn reply_block<T: askama::Template>(t: &T, block: &str) -> Response {
+    match t.render_block(block) {
+        Ok(body) => http::Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, mime::block_content_type(t, block))
+            .body(body.into()),
+        Err(_) => http::Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::empty()),
+    }
+    .unwrap()
+}
+
+/// Streams the rendered template to the client in chunks of at least
+/// `chunk_size` bytes, instead of sending it once it is complete
+///
+/// The template is rendered on tokio's blocking thread pool, where it waits for
+/// the client to take each chunk before rendering the next one. Since the
+/// response status is sent with the first chunk, a render error can only abort
+/// the response.
+pub fn stream<T>(t: T, chunk_size: usize) -> Response
+where
+    T: askama::Template + Send + 'static,
+{
+    let ext = t.extension().unwrap_or("txt");
+    let content_type = mime::extension_to_mime_type(ext).to_string();
+
+    let (mut tx, rx) = mpsc::channel(1);
+    task::spawn_blocking(move || {
+        let res = t.render_chunks(chunk_size, &mut |chunk| {
+            // The receiver is gone once the client disconnects
+            block_on(tx.send(Ok(chunk.to_string()))).map_err(|err| Error::Custom(Box::new(err)))
+        });
+        if let Err(err) = res {
+            let _ = block_on(tx.send(Err(err)));
+        }
+    });
+
+    http::Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, content_type)
+        .body(Body::wrap_stream(rx))
+        .unwrap()
+}
@@ -16,3 +16,42 @@ async fn test_warp() {
     assert_eq!(res.status(), 200);
     assert_eq!(res.body(), "Hello, world!");
 }
+
+#[derive(Template)]
+#[template(source = "{% for i in 0..count %}{{ i }},{% endfor %}", ext = "txt")]
+struct CountTemplate {
+    count: usize,
+}
+
+#[tokio::test]
+async fn test_warp_stream() {
+    let filter = warp::get().map(|| askama_warp::stream(CountTemplate { count: 1000 }, 64));
+
+    let res = warp::test::request().reply(&filter).await;
+
+    assert_eq!(res.status(), 200);
+    assert_eq!(
+        res.headers().get("content-type").unwrap(),
+        "text/plain; charset=utf-8"
+    );
+    let expected = (0..1000).map(|i| format!("{},", i)).collect::<String>();
+    assert_eq!(res.body(), expected.as_str());
+}
+
+#[derive(Template)]
+#[template(
+    source = "<p>{% block icon type \"image/svg+xml\" %}<svg></svg>{% endblock %}</p>",
+    ext = "html"
+)]
+struct IconTemplate;
+
+#[tokio::test]
+async fn test_warp_block() {
+    let filter = warp::get().map(|| askama_warp::reply_block(&IconTemplate, "icon"));
+
+    let res = warp::test::request().reply(&filter).await;
+
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.headers().get("content-type").unwrap(), "image/svg+xml");
+    assert_eq!(res.body(), "<svg></svg>");
+}
@@ -0,0 +1,7 @@
+-brand = Askama
+greeting = Hallo, { $name }! Willkommen bei { -brand }.
+unread = { $count ->
+    [0] Keine neuen Nachrichten
+    [one] Eine neue Nachricht
+   *[other] { $count } neue Nachrichten
+}
@@ -0,0 +1,7 @@
+-brand = Askama
+greeting = Hello, { $name }! Welcome to { -brand }.
+unread = { $count ->
+    [0] No new messages
+    [one] One new message
+   *[other] { $count } new messages
+}
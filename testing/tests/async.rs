@@ -0,0 +1,53 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake};
+
+use askama::Template;
+
+// A minimal executor, which is enough for futures that never wait
+fn block_on<F: Future>(fut: F) -> F::Output {
+    struct Noop;
+    impl Wake for Noop {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    let waker = Arc::new(Noop).into();
+    let mut cx = Context::from_waker(&waker);
+    let mut fut = Box::pin(fut);
+    loop {
+        if let Poll::Ready(res) = fut.as_mut().poll(&mut cx) {
+            return res;
+        }
+    }
+}
+
+struct User {
+    name: &'static str,
+}
+
+impl User {
+    async fn load_profile(&self) -> String {
+        format!("<{}>", self.name)
+    }
+}
+
+#[derive(Template)]
+#[template(
+    source = "{{ user.name }}: {{ user.load_profile().await }}",
+    ext = "html",
+    async = true
+)]
+struct AsyncTemplate {
+    user: User,
+}
+
+#[test]
+fn test_async() {
+    fn assert_send<T: Send>(_: &T) {}
+
+    let t = AsyncTemplate {
+        user: User { name: "alice" },
+    };
+    assert_send(&t.render_async());
+    assert_eq!(block_on(t.render_async()).unwrap(), "alice: &lt;alice&gt;");
+}
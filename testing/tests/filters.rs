@@ -26,6 +26,26 @@ fn filter_escape() {
     );
 }
 
+#[derive(Template)]
+#[template(
+    source = "<a href=\"/s?q={{ s|e(\"url\") }}\" onclick=\"f('{{ s|e(\"js\") }}')\" \
+              style=\"color: {{ s|escape(context = \"css\") }}\">{{ s|e(\"html\") }}</a>",
+    ext = "html"
+)]
+struct EscapeContextTemplate<'a> {
+    s: &'a str,
+}
+
+#[test]
+fn test_escape_context() {
+    let t = EscapeContextTemplate { s: "a'\"&" };
+    assert_eq!(
+        t.render().unwrap(),
+        "<a href=\"/s?q=a%27%22%26\" onclick=\"f('a\\x27\\x22\\x26')\" \
+         style=\"color: a\\27 \\22 \\26 \">a&#x27;&quot;&amp;</a>"
+    );
+}
+
 #[derive(Template)]
 #[template(path = "format.html", escape = "none")]
 struct FormatTemplate<'a> {
@@ -79,6 +99,14 @@ mod filters {
         let s = format!("{}", s);
         Ok(s.trim().to_owned())
     }
+    // for test_filter_chain_argument
+    pub fn myor(s: &str, other: &dyn ::std::fmt::Display) -> ::askama::Result<String> {
+        if s.is_empty() {
+            Ok(other.to_string())
+        } else {
+            Ok(s.to_owned())
+        }
+    }
 }
 
 #[test]
@@ -87,6 +115,28 @@ fn test_my_filter() {
     assert_eq!(t.render().unwrap(), "faa");
 }
 
+mod shared_filters {
+    pub fn shout(s: &str) -> ::askama::Result<String> {
+        Ok(format!("{}!", s.to_uppercase()))
+    }
+}
+
+#[derive(Template)]
+#[template(
+    source = "{{ s|myfilter|shout }}",
+    ext = "txt",
+    filters = "self::filters, shared_filters"
+)]
+struct FilterModulesTemplate<'a> {
+    s: &'a str,
+}
+
+#[test]
+fn test_filter_modules() {
+    let t = FilterModulesTemplate { s: "foo" };
+    assert_eq!(t.render().unwrap(), "FAA!");
+}
+
 #[derive(Template)]
 #[template(path = "filters_join.html")]
 struct JoinTemplate<'a> {
@@ -148,6 +198,62 @@ fn test_json() {
     );
 }
 
+#[cfg(feature = "serde_json")]
+#[derive(Template)]
+#[template(
+    source = "<script>var a = {{ a|json(0) }}; var b = {{ a|json(indent = 1) }};</script>",
+    ext = "html"
+)]
+struct JsonScriptTemplate<'a> {
+    a: &'a [&'a str],
+}
+
+#[cfg(feature = "serde_json")]
+#[test]
+fn test_json_script() {
+    let t = JsonScriptTemplate {
+        a: &["</script>", "&"],
+    };
+    assert_eq!(
+        t.render().unwrap(),
+        "<script>var a = [\"\\u003c/script\\u003e\",\"\\u0026\"]; \
+         var b = [\n \"\\u003c/script\\u003e\",\n \"\\u0026\"\n];</script>"
+    );
+}
+
+#[cfg(feature = "serde_json")]
+#[derive(serde::Serialize)]
+struct Config<'a> {
+    title: &'a str,
+    retries: u32,
+}
+
+#[cfg(feature = "serde_json")]
+#[derive(Template)]
+#[template(
+    source = "<div data-config=\"{{ config|to_json_attr }}\"></div>",
+    ext = "html"
+)]
+struct JsonAttrTemplate<'a> {
+    config: Config<'a>,
+}
+
+#[cfg(feature = "serde_json")]
+#[test]
+fn test_to_json_attr() {
+    let t = JsonAttrTemplate {
+        config: Config {
+            title: "\"Hi\" & <bye>",
+            retries: 3,
+        },
+    };
+    assert_eq!(
+        t.render().unwrap(),
+        "<div data-config=\"{&quot;title&quot;:&quot;\\&quot;Hi\\&quot; &amp; &lt;bye&gt;&quot;,\
+         &quot;retries&quot;:3}\"></div>"
+    );
+}
+
 #[derive(Template)]
 #[template(source = "{{ x|mytrim|safe }}", ext = "html")]
 struct NestedFilterTemplate {
@@ -162,6 +268,25 @@ fn test_nested_filter_ref() {
     assert_eq!(t.render().unwrap(), "floo & bar");
 }
 
+#[derive(Template)]
+#[template(
+    source = "{{ a|myor(b|upper) }} {{ a|myor((b|mytrim)|upper)|lower }}",
+    ext = "txt"
+)]
+struct FilterChainArgumentTemplate {
+    a: String,
+    b: String,
+}
+
+#[test]
+fn test_filter_chain_argument() {
+    let t = FilterChainArgumentTemplate {
+        a: "".into(),
+        b: " foo ".into(),
+    };
+    assert_eq!(t.render().unwrap(), " FOO  foo");
+}
+
 #[derive(Template)]
 #[template(
     source = "{% let p = baz.print(foo.as_ref()) %}{{ p|upper }}",
@@ -202,3 +327,622 @@ fn test_filter_truncate() {
     };
     assert_eq!(t.render().unwrap(), "alpha baralpha...");
 }
+
+#[derive(Template)]
+#[template(
+    source = "{% let (pos, rest) = numbers|partition(\">\", 0) %}\
+              {% for n in pos %}{{ n }} {% endfor %}/{% for n in rest %} {{ n }}{% endfor %}",
+    ext = "txt"
+)]
+struct PartitionTemplate {
+    numbers: Vec<i32>,
+}
+
+#[test]
+fn test_partition() {
+    let t = PartitionTemplate {
+        numbers: vec![3, -1, 0, 5],
+    };
+    assert_eq!(t.render().unwrap(), "3 5 / -1 0");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% for n in nums %}{{ n|ordinal }} {% endfor %}",
+    ext = "txt"
+)]
+struct OrdinalTemplate {
+    nums: Vec<u32>,
+}
+
+#[test]
+fn test_ordinal() {
+    let t = OrdinalTemplate {
+        nums: vec![1, 2, 3, 11, 12, 13, 21],
+    };
+    assert_eq!(t.render().unwrap(), "1st 2nd 3rd 11th 12th 13th 21st ");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{{ s|camel_case }} {{ s|pascal_case }} {{ s|snake_case }} {{ s|kebab_case }}",
+    ext = "txt"
+)]
+struct CaseTemplate<'a> {
+    s: &'a str,
+}
+
+#[test]
+fn test_case_filters() {
+    for s in &["hello world", "HelloWorld"] {
+        let t = CaseTemplate { s };
+        assert_eq!(
+            t.render().unwrap(),
+            "helloWorld HelloWorld hello_world hello-world"
+        );
+    }
+}
+
+#[derive(Template)]
+#[template(source = "[{{ s|repeat(3) }}][{{ s|repeat(n) }}]", ext = "txt")]
+struct RepeatTemplate<'a> {
+    s: &'a str,
+    n: usize,
+}
+
+#[test]
+fn test_repeat() {
+    let t = RepeatTemplate { s: "ab", n: 0 };
+    assert_eq!(t.render().unwrap(), "[ababab][]");
+}
+
+#[derive(Template)]
+#[template(source = "<p>{{ text|nl2br }}</p>", ext = "html")]
+struct Nl2brTemplate<'a> {
+    text: &'a str,
+}
+
+#[test]
+fn test_nl2br() {
+    let t = Nl2brTemplate {
+        text: "1 < 2\nbut 3 > 2\n",
+    };
+    assert_eq!(t.render().unwrap(), "<p>1 &lt; 2<br/>but 3 &gt; 2<br/></p>");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{{ name|default(self.fallback()) }}|{{ name ?? self.fallback() }}|\
+              {{ missing|default(\"none\") }}|{{ missing ?? \"none\" }}",
+    ext = "txt"
+)]
+struct DefaultTemplate<'a> {
+    name: Option<&'a str>,
+    missing: Option<&'a str>,
+}
+
+impl<'a> DefaultTemplate<'a> {
+    fn fallback(&self) -> &'static str {
+        panic!("fallback should not be evaluated")
+    }
+}
+
+#[test]
+fn test_default() {
+    let t = DefaultTemplate {
+        name: Some("foo"),
+        missing: None,
+    };
+    assert_eq!(t.render().unwrap(), "foo|foo|none|none");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{{ empty|default(\"-\") }}|{{ zero|default(\"-\") }}|{{ no|default(\"-\") }}|\
+              {{ some_empty|default(\"-\") }}|{{ some_empty ?? \"-\" }}|{{ one|default(\"-\") }}",
+    ext = "txt"
+)]
+struct DefaultFalsyTemplate<'a> {
+    empty: &'a str,
+    zero: u32,
+    no: bool,
+    some_empty: Option<&'a str>,
+    one: u32,
+}
+
+#[test]
+fn test_default_falsy() {
+    // An option holding a falsy value is kept
+    let t = DefaultFalsyTemplate {
+        empty: "",
+        zero: 0,
+        no: false,
+        some_empty: Some(""),
+        one: 1,
+    };
+    assert_eq!(t.render().unwrap(), "-|-|-|||1");
+}
+
+#[derive(Template)]
+#[template(source = "[{{ x|default }}]", ext = "txt")]
+struct DefaultEmptyTemplate<'a> {
+    x: Option<&'a str>,
+}
+
+#[test]
+fn test_default_without_argument() {
+    let t = DefaultEmptyTemplate { x: None };
+    assert_eq!(t.render().unwrap(), "[]");
+    let t = DefaultEmptyTemplate { x: Some("v") };
+    assert_eq!(t.render().unwrap(), "[v]");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{{ s|truncate_words(3) }}|{{ s|truncate_words(2, \" [more]\") }}|{{ s|truncate_words(9) }}",
+    ext = "txt"
+)]
+struct TruncateWordsTemplate<'a> {
+    s: &'a str,
+}
+
+#[test]
+fn test_truncate_words() {
+    let t = TruncateWordsTemplate {
+        s: "the quick brown fox jumps",
+    };
+    assert_eq!(
+        t.render().unwrap(),
+        "the quick brown...|the quick [more]|the quick brown fox jumps"
+    );
+}
+
+#[derive(Template)]
+#[template(
+    source = "{{ nested|flatten|join(\",\") }}|{% for n in nested|flatten %}{{ n }}{% endfor %}",
+    ext = "txt"
+)]
+struct FlattenTemplate {
+    nested: Vec<Vec<u32>>,
+}
+
+#[test]
+fn test_flatten() {
+    let t = FlattenTemplate {
+        nested: vec![vec![1, 2], vec![3]],
+    };
+    assert_eq!(t.render().unwrap(), "1,2,3|123");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{{ s|(upper if loud else lower) }}|{{ s|(safe if loud else e) }}|{{ s|(safe if loud else trim) }}",
+    ext = "html"
+)]
+struct FilterCondTemplate<'a> {
+    s: &'a str,
+    loud: bool,
+}
+
+#[test]
+fn test_filter_cond() {
+    let t = FilterCondTemplate {
+        s: "<Hi>",
+        loud: true,
+    };
+    assert_eq!(t.render().unwrap(), "&lt;HI&gt;|<Hi>|<Hi>");
+    let t = FilterCondTemplate {
+        s: "<Hi>",
+        loud: false,
+    };
+    assert_eq!(t.render().unwrap(), "&lt;hi&gt;|&lt;Hi&gt;|&lt;Hi&gt;");
+}
+
+#[derive(Template)]
+#[template(source = "<p>{{ s|wrap_in(\"em\") }}</p>", ext = "html")]
+struct WrapInTemplate<'a> {
+    s: &'a str,
+}
+
+#[test]
+fn test_wrap_in() {
+    let t = WrapInTemplate { s: "1 < 2" };
+    assert_eq!(t.render().unwrap(), "<p><em>1 &lt; 2</em></p>");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{{ empty|boolean }} {{ full|boolean }} {{ some|boolean }} {{ none|boolean }}",
+    ext = "txt"
+)]
+struct BooleanTemplate<'a> {
+    empty: &'a str,
+    full: String,
+    some: Option<u32>,
+    none: Option<u32>,
+}
+
+#[test]
+fn test_boolean() {
+    let t = BooleanTemplate {
+        empty: "",
+        full: "x".into(),
+        some: Some(0),
+        none: None,
+    };
+    assert_eq!(t.render().unwrap(), "false true true false");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{{ s|strip_prefix(\"v\") }} {{ s|strip_prefix(\"x\") }} \
+              {{ s|strip_suffix(\".0\") }} {{ s|strip_suffix(\".1\") }}",
+    ext = "txt"
+)]
+struct StripTemplate<'a> {
+    s: &'a str,
+}
+
+#[test]
+fn test_strip_prefix_suffix() {
+    let t = StripTemplate { s: "v1.0" };
+    assert_eq!(t.render().unwrap(), "1.0 v1.0 v1 v1.0");
+}
+
+#[derive(Template)]
+#[template(source = "<div{{ cls|default_attr(\"class\") }}>", ext = "html")]
+struct DefaultAttrTemplate<'a> {
+    cls: &'a str,
+}
+
+#[test]
+fn test_default_attr() {
+    let t = DefaultAttrTemplate { cls: "big" };
+    assert_eq!(t.render().unwrap(), "<div class=\"big\">");
+    let t = DefaultAttrTemplate { cls: "" };
+    assert_eq!(t.render().unwrap(), "<div>");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% if (desc|wordcount) > 3 %}long{% else %}short{% endif %}",
+    ext = "txt"
+)]
+struct WordcountCondTemplate<'a> {
+    desc: &'a str,
+}
+
+#[test]
+fn test_wordcount_in_condition() {
+    let t = WordcountCondTemplate {
+        desc: "one two three four",
+    };
+    assert_eq!(t.render().unwrap(), "long");
+    let t = WordcountCondTemplate { desc: "one two" };
+    assert_eq!(t.render().unwrap(), "short");
+}
+
+#[derive(Template)]
+#[template(
+    source = "[{{ s|ljust(5) }}][{{ s|rjust(5) }}][{{ s|ljust(5, '.') }}][{{ s|rjust(5, '-') }}]",
+    ext = "txt"
+)]
+struct JustTemplate<'a> {
+    s: &'a str,
+}
+
+#[test]
+fn test_ljust_rjust() {
+    let t = JustTemplate { s: "ab" };
+    assert_eq!(t.render().unwrap(), "[ab   ][   ab][ab...][---ab]");
+    let t = JustTemplate { s: "abcdefg" };
+    assert_eq!(t.render().unwrap(), "[abcdefg][abcdefg][abcdefg][abcdefg]");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{{ title|title_with_exceptions([\"the\", \"of\"]) }}",
+    ext = "txt"
+)]
+struct TitleWithExceptionsTemplate<'a> {
+    title: &'a str,
+}
+
+#[test]
+fn test_title_with_exceptions() {
+    let t = TitleWithExceptionsTemplate {
+        title: "the lord of the rings",
+    };
+    assert_eq!(t.render().unwrap(), "The Lord of the Rings");
+}
+
+#[derive(Template)]
+#[template(source = "{{ content|escape_if(untrusted) }}", ext = "html")]
+struct EscapeIfTemplate<'a> {
+    content: &'a str,
+    untrusted: bool,
+}
+
+#[test]
+fn test_escape_if() {
+    let t = EscapeIfTemplate {
+        content: "<b>hi</b>",
+        untrusted: true,
+    };
+    assert_eq!(t.render().unwrap(), "&lt;b&gt;hi&lt;&#x2f;b&gt;");
+    let t = EscapeIfTemplate {
+        content: "<b>hi</b>",
+        untrusted: false,
+    };
+    assert_eq!(t.render().unwrap(), "<b>hi</b>");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{{ nums|dedup|join(\",\") }}|{% for n in nums|dedup %}{{ n }}{% endfor %}",
+    ext = "txt"
+)]
+struct DedupTemplate {
+    nums: Vec<u32>,
+}
+
+#[test]
+fn test_dedup() {
+    let t = DedupTemplate {
+        nums: vec![1, 1, 2, 2, 2, 3, 1],
+    };
+    assert_eq!(t.render().unwrap(), "1,2,3,1|1231");
+}
+
+#[derive(Template)]
+#[template(source = "{{ id|pad_number(4) }}", ext = "txt")]
+struct PadNumberTemplate {
+    id: u32,
+}
+
+#[test]
+fn test_pad_number() {
+    assert_eq!(PadNumberTemplate { id: 7 }.render().unwrap(), "0007");
+    assert_eq!(PadNumberTemplate { id: 12345 }.render().unwrap(), "12345");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% for (a, b) in numbers|zip_longest(letters, \"-\") %}{{ a }}{{ b }} {% endfor %}",
+    ext = "txt"
+)]
+struct ZipLongestTemplate<'a> {
+    numbers: Vec<u32>,
+    letters: Vec<&'a str>,
+}
+
+#[test]
+fn test_zip_longest() {
+    let t = ZipLongestTemplate {
+        numbers: vec![1, 2, 3],
+        letters: vec!["x"],
+    };
+    assert_eq!(t.render().unwrap(), "1x 2- 3- ");
+}
+
+#[derive(Template)]
+#[template(source = "{{ text|markdown }}", ext = "html")]
+struct MarkdownTemplate<'a> {
+    text: &'a str,
+}
+
+#[test]
+fn test_markdown() {
+    let t = MarkdownTemplate { text: "**bold**" };
+    assert_eq!(t.render().unwrap(), "<p><strong>bold</strong></p>\n");
+}
+
+#[derive(Template)]
+#[template(source = "{{ s|escape_json_string }}", ext = "txt")]
+struct EscapeJsonStringTemplate<'a> {
+    s: &'a str,
+}
+
+#[test]
+fn test_escape_json_string() {
+    let t = EscapeJsonStringTemplate {
+        s: "say \"hi\"\nto Zoë 👋",
+    };
+    assert_eq!(t.render().unwrap(), r#""say \"hi\"\nto Zoë 👋""#);
+}
+
+#[derive(Template)]
+#[template(source = "[{{ s|center(10)|upper }}]", ext = "txt")]
+struct CenterUpperTemplate<'a> {
+    s: &'a str,
+}
+
+#[test]
+fn test_center_upper() {
+    let t = CenterUpperTemplate { s: "abc" };
+    assert_eq!(t.render().unwrap(), "[   ABC    ]");
+    let t = CenterUpperTemplate { s: "äöü" };
+    assert_eq!(t.render().unwrap(), "[   ÄÖÜ    ]");
+}
+
+#[derive(Template)]
+#[template(source = "[{{ a|coalesce(b, c) }}]", ext = "txt")]
+struct CoalesceTemplate<'a> {
+    a: Option<&'a str>,
+    b: &'a str,
+    c: Option<u32>,
+}
+
+#[test]
+fn test_coalesce() {
+    let t = CoalesceTemplate {
+        a: None,
+        b: "",
+        c: Some(3),
+    };
+    assert_eq!(t.render().unwrap(), "[3]");
+    let t = CoalesceTemplate {
+        a: None,
+        b: "b",
+        c: Some(3),
+    };
+    assert_eq!(t.render().unwrap(), "[b]");
+    let t = CoalesceTemplate {
+        a: Some(""),
+        b: "",
+        c: None,
+    };
+    assert_eq!(t.render().unwrap(), "[]");
+}
+
+#[derive(Template)]
+#[template(source = "{{ \"x\"|coalesce(self.unused()) }}", ext = "txt")]
+struct CoalesceLazyTemplate;
+
+impl CoalesceLazyTemplate {
+    fn unused(&self) -> &'static str {
+        panic!("coalesce should not evaluate this")
+    }
+}
+
+#[test]
+fn test_coalesce_lazy() {
+    assert_eq!(CoalesceLazyTemplate.render().unwrap(), "x");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% filter upper %}hello {{ name }}{% endfilter %}\n\
+              {%- filter trim|truncate(6) -%}\n  {% for c in chars %}{{ c }}{% endfor %}\n{% endfilter %}",
+    ext = "html"
+)]
+struct FilterBlockTemplate<'a> {
+    name: &'a str,
+    chars: Vec<char>,
+}
+
+#[test]
+fn test_filter_block() {
+    let t = FilterBlockTemplate {
+        name: "<world>",
+        chars: "abcdefgh".chars().collect(),
+    };
+    assert_eq!(t.render().unwrap(), "HELLO &LT;WORLD&GT;abcdef...");
+}
+
+#[derive(Template)]
+#[template(
+    source = "[{{ s|ljust(width = 5, fill = '.') }}][{{ s|rjust(5, fill = '-') }}]\
+              [{{ s|truncate(length = 1) }}][{{ s|truncate_words(end = \"!\", n = 1) }}]",
+    ext = "txt"
+)]
+struct NamedArgsTemplate<'a> {
+    s: &'a str,
+}
+
+#[test]
+fn test_filter_named_args() {
+    let t = NamedArgsTemplate { s: "ab cd" };
+    assert_eq!(t.render().unwrap(), "[ab cd][ab cd][a...][ab!]");
+    let t = NamedArgsTemplate { s: "ab" };
+    assert_eq!(t.render().unwrap(), "[ab...][---ab][a...][ab]");
+}
+
+struct City {
+    name: &'static str,
+    country: &'static str,
+}
+
+#[derive(Template)]
+#[template(
+    source = "{{ cities|map(\"name\")|join(\", \") }}\n\
+              {% for (country, group) in cities|groupby(\"country\") -%}\n\
+              {{ country }}: {{ group|map(\"name\")|join(\"/\") }}\n\
+              {% endfor -%}\n\
+              {{ cities|map(\"country\")|unique|join(\" \") }}\n\
+              {% for (k, v) in ranks|dictsort %}{{ k }}={{ v }} {% endfor %}",
+    ext = "txt"
+)]
+struct CollectionFiltersTemplate {
+    cities: Vec<City>,
+    ranks: std::collections::HashMap<&'static str, u32>,
+}
+
+#[test]
+fn test_collection_filters() {
+    let city = |name, country| City { name, country };
+    let t = CollectionFiltersTemplate {
+        cities: vec![city("Lyon", "FR"), city("Bonn", "DE"), city("Paris", "FR")],
+        ranks: vec![("b", 2), ("a", 1), ("c", 3)].into_iter().collect(),
+    };
+    assert_eq!(
+        t.render().unwrap(),
+        "Lyon, Bonn, Paris\nDE: Bonn\nFR: Lyon/Paris\nFR DE\na=1 b=2 c=3 "
+    );
+}
+
+#[derive(Template)]
+#[template(
+    source = "{{ s|truncate(9) }}|{{ s|truncate(9, \"…\") }}|{{ s|truncate(length = 11, words = true) }}\n\
+              {{ s|wordwrap(10) }}\n\
+              {{ \"a\nb\"|indent(2) }}|{{ \"a\nb\"|indent(2, first = true) }}",
+    ext = "txt"
+)]
+struct TextFiltersTemplate<'a> {
+    s: &'a str,
+}
+
+#[test]
+fn test_text_filters() {
+    let t = TextFiltersTemplate {
+        s: "hello big world",
+    };
+    assert_eq!(
+        t.render().unwrap(),
+        "hello big...|hello big…|hello big...\nhello big\nworld\na\n  b|  a\n  b"
+    );
+}
+
+#[derive(Template)]
+#[template(source = "{{ s }}", ext = "tex")]
+struct LatexTemplate<'a> {
+    s: &'a str,
+}
+
+#[derive(Template)]
+#[template(source = "\"{{ s }}\",{{ s|safe }}", ext = "csv")]
+struct CsvTemplate<'a> {
+    s: &'a str,
+}
+
+#[derive(Template)]
+#[template(source = "echo {{ s }}", ext = "sh")]
+struct ShellTemplate<'a> {
+    s: &'a str,
+}
+
+#[derive(Template)]
+#[template(source = "# {{ s }}", ext = "md")]
+struct MarkdownEscapeTemplate<'a> {
+    s: &'a str,
+}
+
+#[test]
+fn test_format_escapers() {
+    assert_eq!(
+        LatexTemplate { s: "5% & $x$" }.render().unwrap(),
+        "5\\% \\& \\$x\\$"
+    );
+    assert_eq!(
+        CsvTemplate { s: "a \"b\"" }.render().unwrap(),
+        "\"a \"\"b\"\"\",a \"b\""
+    );
+    assert_eq!(
+        ShellTemplate { s: "hi; rm *" }.render().unwrap(),
+        "echo hi\\;\\ rm\\ \\*"
+    );
+    assert_eq!(
+        MarkdownEscapeTemplate { s: "**x**" }.render().unwrap(),
+        "# \\*\\*x\\*\\*"
+    );
+}
@@ -0,0 +1,54 @@
+#![cfg(feature = "hot-reload")]
+
+use std::path::Path;
+use std::{env, fs};
+
+use askama::Template;
+use serde::Serialize;
+
+#[derive(Template, Serialize)]
+#[template(path = "hot_reload.html")]
+struct ReloadTemplate<'a> {
+    name: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "hello.html")]
+struct CompiledTemplate<'a> {
+    name: &'a str,
+}
+
+#[derive(Template, Serialize)]
+#[template(path = "hot_reload.html")]
+struct RenamedTemplate<'a> {
+    #[serde(rename = "userName")]
+    name: &'a str,
+}
+
+#[test]
+fn test_hot_reload() {
+    // Edit a copy of the templates rather than the source tree
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("hot_reload");
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("hot_reload.html");
+    let original = concat!(env!("CARGO_MANIFEST_DIR"), "/templates/hot_reload.html");
+    fs::copy(original, &path).unwrap();
+    env::set_var("ASKAMA_RELOAD_DIR", &dir);
+
+    let t = ReloadTemplate { name: "<world>" };
+    assert_eq!(t.render().unwrap(), "Hello, &lt;world&gt;!");
+    fs::write(&path, "Bye, {{ name }}!").unwrap();
+    assert_eq!(t.render().unwrap(), "Bye, &lt;world&gt;!");
+}
+
+#[test]
+fn test_hot_reload_fallback() {
+    let t = CompiledTemplate { name: "world" };
+    assert_eq!(t.render().unwrap(), "Hello, world!");
+}
+
+#[test]
+fn test_hot_reload_serde_attrs() {
+    let t = RenamedTemplate { name: "world" };
+    assert_eq!(t.render().unwrap(), "Hello, world!");
+}
@@ -0,0 +1,82 @@
+use askama::Template;
+
+#[derive(Template)]
+#[template(
+    source = "{% trans \"greeting\" name = name %}\n{% trans \"unread\" count = count %}",
+    ext = "html"
+)]
+struct TransTemplate<'a> {
+    locale: &'a str,
+    name: &'a str,
+    count: usize,
+}
+
+#[test]
+fn test_trans() {
+    let t = TransTemplate {
+        locale: "en-US",
+        name: "<Ada>",
+        count: 1,
+    };
+    assert_eq!(
+        t.render().unwrap(),
+        "Hello, &lt;Ada&gt;! Welcome to Askama.\nOne new message"
+    );
+
+    let t = TransTemplate {
+        locale: "de-AT",
+        name: "Ada",
+        count: 3,
+    };
+    assert_eq!(
+        t.render().unwrap(),
+        "Hallo, Ada! Willkommen bei Askama.\n3 neue Nachrichten"
+    );
+
+    let t = TransTemplate {
+        locale: "fr",
+        name: "Ada",
+        count: 0,
+    };
+    assert_eq!(
+        t.render().unwrap(),
+        "Hello, Ada! Welcome to Askama.\nNo new messages"
+    );
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% for n in counts %}{% let locale = \"de\" %}{% trans \"unread\" count = n %};{% endfor %}",
+    ext = "txt"
+)]
+struct TransLocalTemplate<'a> {
+    counts: &'a [u32],
+}
+
+#[test]
+fn test_trans_local_locale() {
+    let t = TransLocalTemplate { counts: &[0, 1, 2] };
+    assert_eq!(
+        t.render().unwrap(),
+        "Keine neuen Nachrichten;Eine neue Nachricht;2 neue Nachrichten;"
+    );
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% trans \"unread\" count = 1 %} {% trans \"unread\" count = 2 %}",
+    ext = "txt",
+    locale = "lang"
+)]
+struct TransLangTemplate<'a> {
+    lang: &'a str,
+}
+
+#[test]
+fn test_trans_locale_attribute() {
+    let t = TransLangTemplate { lang: "de" };
+    assert_eq!(
+        t.render().unwrap(),
+        "Eine neue Nachricht 2 neue Nachrichten"
+    );
+}
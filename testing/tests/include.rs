@@ -12,3 +12,43 @@ fn test_include() {
     let s = IncludeTemplate { strs: &strs };
     assert_eq!(s.render().unwrap(), "\n  INCLUDED: foo\n  INCLUDED: bar")
 }
+
+#[derive(Template)]
+#[template(
+    source = "{% for s in strs %}{% include \"included.html\" %}|{% include \"included.html\" %}\n{% endfor %}",
+    ext = "txt"
+)]
+struct IncludeTwiceTemplate<'a> {
+    strs: &'a [&'a str],
+}
+
+#[test]
+fn test_include_twice() {
+    let strs = vec!["foo", "bar"];
+    let s = IncludeTwiceTemplate { strs: &strs };
+    assert_eq!(
+        s.render().unwrap(),
+        "INCLUDED: foo|INCLUDED: foo\nINCLUDED: bar|INCLUDED: bar\n"
+    )
+}
+
+#[derive(Template)]
+#[template(source = "a\n{% include \"include-fails.html\" %}", ext = "txt")]
+struct IncludeFailsTemplate<'a> {
+    value: &'a str,
+}
+
+#[test]
+fn test_include_error_location() {
+    let t = IncludeFailsTemplate { value: "x" };
+    match t.render() {
+        Err(askama::Error::Located { template, line, .. }) => {
+            assert_eq!((template, line), ("include-fails.html", 3))
+        }
+        res => panic!("unexpected result {:?}", res),
+    }
+    assert_eq!(
+        t.render().unwrap_err().to_string(),
+        "invalid digit found in string (in include-fails.html at line 3)"
+    );
+}
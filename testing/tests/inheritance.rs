@@ -304,3 +304,225 @@ fn test_let_block() {
     let t = LetChild {};
     assert_eq!(t.render().unwrap(), "1");
 }
+
+#[derive(Template)]
+#[template(
+    source = "{% extends \"base.html\" %}{% block foo %}[{{ super() }}|{{ super() }}]{% endblock %}",
+    ext = "html"
+)]
+struct SuperTwiceChild<'a> {
+    title: &'a str,
+}
+
+#[test]
+fn test_super_twice() {
+    let t = SuperTwiceChild { title: "baz" };
+    assert_eq!(t.render().unwrap(), "baz\n\n[Foo|Foo]\nCopyright 2017");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% extends \"base.html\" %}{% block foo %}<{{ block.super }}|{{ title }}|{{ block.super }}>{% endblock %}",
+    ext = "html"
+)]
+struct BlockSuperChild<'a> {
+    title: &'a str,
+}
+
+#[test]
+fn test_block_super() {
+    let t = BlockSuperChild { title: "baz" };
+    assert_eq!(t.render().unwrap(), "baz\n\n<Foo|baz|Foo>\nCopyright 2017");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% extends \"base.html\" %}{% block foo %}No parent{% endblock %}",
+    ext = "html"
+)]
+struct SuperNeverChild<'a> {
+    title: &'a str,
+}
+
+#[test]
+fn test_super_never() {
+    let t = SuperNeverChild { title: "baz" };
+    assert_eq!(t.render().unwrap(), "baz\n\nNo parent\nCopyright 2017");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% extends \"base.html\" %}{% block foo %}<{% call super() %}>{% endblock %}",
+    ext = "html"
+)]
+struct CallSuperChild<'a> {
+    title: &'a str,
+}
+
+#[test]
+fn test_call_super() {
+    let t = CallSuperChild { title: "baz" };
+    assert_eq!(t.render().unwrap(), "baz\n\n<Foo>\nCopyright 2017");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% block sidebar cache %}[{{ name }} {{ self.expensive() }}]{% endblock %}",
+    ext = "txt"
+)]
+struct CachedBlock<'a> {
+    name: &'a str,
+    calls: std::cell::Cell<usize>,
+    block_cache: askama::helpers::BlockCache,
+}
+
+impl<'a> CachedBlock<'a> {
+    fn new(name: &'a str) -> Self {
+        CachedBlock {
+            name,
+            calls: Default::default(),
+            block_cache: Default::default(),
+        }
+    }
+
+    fn expensive(&self) -> usize {
+        self.calls.set(self.calls.get() + 1);
+        self.calls.get()
+    }
+}
+
+#[test]
+fn test_cached_block() {
+    let a = CachedBlock::new("a");
+    assert_eq!(a.render().unwrap(), "[a 1]");
+    assert_eq!(a.render().unwrap(), "[a 1]");
+    assert_eq!(a.calls.get(), 1);
+
+    // Each instance renders the block with its own fields
+    let b = CachedBlock::new("b");
+    assert_eq!(b.render().unwrap(), "[b 1]");
+    assert_eq!(b.calls.get(), 1);
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% block outer cache %}({% block inner cache %}{{ self.expensive() }}\
+              {% endblock %}){% endblock %}",
+    ext = "txt",
+    block_cache = "rendered"
+)]
+struct NestedCachedBlocks {
+    calls: std::cell::Cell<usize>,
+    rendered: askama::helpers::BlockCache,
+}
+
+impl NestedCachedBlocks {
+    fn expensive(&self) -> usize {
+        self.calls.set(self.calls.get() + 1);
+        self.calls.get()
+    }
+}
+
+#[test]
+fn test_nested_cached_blocks() {
+    let t = NestedCachedBlocks {
+        calls: Default::default(),
+        rendered: Default::default(),
+    };
+    assert_eq!(t.render().unwrap(), "(1)");
+    assert_eq!(t.render().unwrap(), "(1)");
+    assert_eq!(t.calls.get(), 1);
+}
+
+#[derive(Template)]
+#[template(path = "base-with-partial.html")]
+struct BaseWithPartial;
+
+#[derive(Template)]
+#[template(path = "child-overrides-partial.html")]
+struct ChildOverridesPartial;
+
+#[test]
+fn test_block_in_include() {
+    assert_eq!(BaseWithPartial.render().unwrap(), "<p>[default note]</p>");
+    assert_eq!(
+        ChildOverridesPartial.render().unwrap(),
+        "<p>[override, default note]</p>"
+    );
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% block header %}{{ name }}{% endblock %}<table>{% for name in rows %}\
+              {% block row %}<tr>{{ rows.len() }}</tr>{% endblock %}{{ name }}{% endfor %}</table>\
+              {% block footer %}{{ rows.len() }} rows{% endblock %}",
+    ext = "html"
+)]
+struct RenderBlock {
+    name: &'static str,
+    rows: Vec<&'static str>,
+}
+
+#[test]
+fn test_render_block() {
+    let t = ChildTemplate {
+        _parent: BaseTemplate { title: "Bar" },
+    };
+    assert_eq!(
+        t.render_block("content").unwrap(),
+        "(Bar) Content goes here"
+    );
+    assert_eq!(t.render_block("foo").unwrap(), "Foo");
+    assert!(t.render_block("missing").is_err());
+
+    // Only the names bound around a block matter, so `header` uses the field
+    // even though the loop binds the same name
+    let t = RenderBlock {
+        name: "rows",
+        rows: vec!["a", "b"],
+    };
+    assert_eq!(t.render_block("header").unwrap(), "rows");
+    assert_eq!(t.render_block("row").unwrap(), "<tr>2</tr>");
+    assert_eq!(t.render_block("footer").unwrap(), "2 rows");
+    assert_eq!(t.block_content_type("footer"), None);
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% for row in rows %}{% block row %}<{{ row }}>{% endblock %}{% endfor %}",
+    ext = "txt"
+)]
+struct RenderBlockInLoop {
+    rows: Vec<u32>,
+}
+
+#[test]
+fn test_render_block_in_loop() {
+    // The block uses the loop variable, so it only renders as part of the loop
+    let t = RenderBlockInLoop { rows: vec![1, 2] };
+    assert_eq!(t.render().unwrap(), "<1><2>");
+    assert_eq!(
+        t.render_block("row").unwrap_err().to_string(),
+        "block 'row' cannot be rendered on its own, since it uses names bound outside of it: row"
+    );
+}
+
+#[derive(Template)]
+#[template(
+    source = "<p>{% block icon type \"image/svg+xml\" %}<svg></svg>{% endblock %}</p>",
+    ext = "html"
+)]
+struct BlockContentType;
+
+#[test]
+fn test_block_content_type() {
+    assert_eq!(
+        BlockContentType.render_block("icon").unwrap(),
+        "<svg></svg>"
+    );
+    assert_eq!(
+        BlockContentType.block_content_type("icon"),
+        Some("image/svg+xml")
+    );
+    assert_eq!(BlockContentType.block_content_type("missing"), None);
+}
@@ -67,3 +67,333 @@ fn test_for_range() {
         "foo (first)\nfoo (last)\nbar\nbar\nfoo\nbar\nbar\n"
     );
 }
+
+#[derive(Template)]
+#[template(
+    source = "{% for c in word|chars %}{{ loop.index }}:{{ c }} {% endfor %}",
+    ext = "txt"
+)]
+struct ForCharsTemplate<'a> {
+    word: &'a str,
+}
+
+#[test]
+fn test_for_chars() {
+    let t = ForCharsTemplate { word: "añb" };
+    assert_eq!(t.render().unwrap(), "1:a 2:ñ 3:b ");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% for s, i in strings %}{{ i }}={{ s }}{% if i < strings.len() - 1 %},{% endif %}{% endfor %}",
+    ext = "txt"
+)]
+struct ForIndexTemplate<'a> {
+    strings: Vec<&'a str>,
+}
+
+#[test]
+fn test_for_index() {
+    let t = ForIndexTemplate {
+        strings: vec!["a", "b", "c"],
+    };
+    assert_eq!(t.render().unwrap(), "0=a,1=b,2=c");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% for i in range(0, 10, 2) %}{{ i }} {% endfor %}|\
+              {% for i in range(0, 5) %}{{ i }} {% endfor %}|\
+              {% for i in range(start, 10, step) %}{{ i }} {% endfor %}",
+    ext = "txt"
+)]
+struct ForRangeStepTemplate {
+    start: usize,
+    step: usize,
+}
+
+#[test]
+fn test_for_range_step() {
+    let t = ForRangeStepTemplate { start: 1, step: 3 };
+    assert_eq!(t.render().unwrap(), "0 2 4 6 8 |0 1 2 3 4 |1 4 7 ");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% for i in range(10, 0, -3) %}{{ i }} {% endfor %}|\
+              {% for i in range(0, 5, step) %}{{ i }} {% endfor %}",
+    ext = "txt"
+)]
+struct ForRangeNegativeStepTemplate {
+    step: i32,
+}
+
+#[test]
+fn test_for_range_negative_step() {
+    let t = ForRangeNegativeStepTemplate { step: 2 };
+    assert_eq!(t.render().unwrap(), "10 7 4 1 |0 2 4 ");
+    let t = ForRangeNegativeStepTemplate { step: -1 };
+    assert_eq!(t.render().unwrap(), "10 7 4 1 |");
+
+    let t = ForRangeNegativeStepTemplate { step: 0 };
+    assert_eq!(
+        t.render().unwrap_err().to_string(),
+        "range() step must not be zero (in ForRangeNegativeStepTemplate.txt at line 1)"
+    );
+}
+
+#[derive(Template)]
+#[template(source = "{% for i in range(3, 1) %}{{ i }} {% endfor %}", ext = "txt")]
+struct ForRangeFieldTemplate {
+    range: fn(&u32, &u32) -> Vec<u32>,
+}
+
+#[test]
+fn test_for_range_field() {
+    // A field named `range` is called instead of the built-in
+    let t = ForRangeFieldTemplate {
+        range: |a, b| vec![*a, *b],
+    };
+    assert_eq!(t.render().unwrap(), "3 1 ");
+}
+
+struct User<'a> {
+    name: &'a str,
+    age: u32,
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% for User { name, age } in users %}{{ name }} ({{ age }}) {% endfor %}",
+    ext = "txt"
+)]
+struct ForStructTargetTemplate<'a> {
+    users: Vec<User<'a>>,
+}
+
+#[test]
+fn test_for_struct_target() {
+    let t = ForStructTargetTemplate {
+        users: vec![
+            User {
+                name: "alice",
+                age: 30,
+            },
+            User {
+                name: "bob",
+                age: 25,
+            },
+        ],
+    };
+    assert_eq!(t.render().unwrap(), "alice (30) bob (25) ");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% for s in strings %}{{ loop.index + offset }}:{{ loop.index0 * offset }}:\
+              {{ loop.index|into_u64 * big }} {% endfor %}",
+    ext = "txt"
+)]
+struct ForIndexArithmeticTemplate<'a> {
+    strings: Vec<&'a str>,
+    offset: usize,
+    big: u64,
+}
+
+#[test]
+fn test_for_index_arithmetic() {
+    let t = ForIndexArithmeticTemplate {
+        strings: vec!["a", "b"],
+        offset: 10,
+        big: 1 << 40,
+    };
+    assert_eq!(
+        t.render().unwrap(),
+        "11:0:1099511627776 12:10:2199023255552 "
+    );
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% for i in 1..=5 %}{{ i }}{% endfor %}|{% for i in 1..n %}{{ i }}{% endfor %}",
+    ext = "txt"
+)]
+struct ForInclusiveRangeTemplate {
+    n: usize,
+}
+
+#[test]
+fn test_for_inclusive_range() {
+    let t = ForInclusiveRangeTemplate { n: 3 };
+    assert_eq!(t.render().unwrap(), "12345|12");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% for n in nums parallel %}{{ loop.index }}:{{ n }}{% if !loop.last %},{% endif %}{% endfor %}",
+    ext = "txt"
+)]
+struct ForParallelTemplate {
+    nums: Vec<usize>,
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% for n in nums %}{{ loop.index }}:{{ n }}{% if !loop.last %},{% endif %}{% endfor %}",
+    ext = "txt"
+)]
+struct ForSequentialTemplate {
+    nums: Vec<usize>,
+}
+
+#[test]
+fn test_for_parallel() {
+    let nums = (0..10_000).collect::<Vec<_>>();
+    let parallel = ForParallelTemplate { nums: nums.clone() };
+    let sequential = ForSequentialTemplate { nums };
+    assert_eq!(parallel.render().unwrap(), sequential.render().unwrap());
+    assert!(parallel.render().unwrap().starts_with("1:0,2:1,3:2,"));
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% for i in 0..3 as outer %}{% for j in 0..3 %}{% if j > i %}{% continue outer %}{% endif %}{% if i == 2 && j == 1 %}{% break outer %}{% endif %}{{ i }}{{ j }},{% endfor %}{% endfor %}",
+    ext = "txt"
+)]
+struct ForLabeledTemplate;
+
+#[derive(Template)]
+#[template(
+    source = "{% for i in 0..3 labeled outer %}{% for j in 0..3 %}{% if j > i %}{% continue outer %}{% endif %}{% if i == 2 && j == 1 %}{% break outer %}{% endif %}{{ i }}{{ j }},{% endfor %}{% endfor %}",
+    ext = "txt"
+)]
+struct ForLabeledKeywordTemplate;
+
+#[test]
+fn test_for_labeled() {
+    assert_eq!(ForLabeledTemplate.render().unwrap(), "00,10,11,20,");
+    assert_eq!(ForLabeledKeywordTemplate.render().unwrap(), "00,10,11,20,");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% for n in nums %}{{ loop.cycle(row_classes) }}:{{ n }} {% endfor %}|{% for n in nums %}{{ loop.cycle(\"a\", \"b\") }}{% endfor %}",
+    ext = "txt"
+)]
+struct ForCycleTemplate<'a> {
+    nums: Vec<usize>,
+    row_classes: Vec<&'a str>,
+}
+
+#[test]
+fn test_for_cycle() {
+    let t = ForCycleTemplate {
+        nums: vec![1, 2, 3, 4, 5],
+        row_classes: vec!["odd", "even", "third"],
+    };
+    assert_eq!(
+        t.render().unwrap(),
+        "odd:1 even:2 third:3 odd:4 even:5 |ababa"
+    );
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% for s in strings %}{{ s }}{{ loop.remaining }} {% endfor %}",
+    ext = "txt"
+)]
+struct ForRemainingTemplate<'a> {
+    strings: Vec<&'a str>,
+}
+
+#[test]
+fn test_for_remaining() {
+    let t = ForRemainingTemplate {
+        strings: vec!["a", "b", "c", "d", "e"],
+    };
+    assert_eq!(t.render().unwrap(), "a4 b3 c2 d1 e0 ");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% for (i, item) in items|enumerate %}{{ i }}{{ item }}{{ loop.index0 }}{% if i.eq(loop.index0) %}={% endif %} {% endfor %}",
+    ext = "txt"
+)]
+struct ForEnumerateTemplate<'a> {
+    items: Vec<&'a str>,
+}
+
+#[test]
+fn test_for_enumerate() {
+    let t = ForEnumerateTemplate {
+        items: vec!["a", "b", "c"],
+    };
+    assert_eq!(t.render().unwrap(), "0a0= 1b1= 2c2= ");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% for i in range(1, 10, 3) %}{{ i }}>{{ loop.nextitem ?? 0 }} {% endfor %}",
+    ext = "txt"
+)]
+struct ForNextItemTemplate;
+
+#[test]
+fn test_for_nextitem() {
+    assert_eq!(ForNextItemTemplate.render().unwrap(), "1>4 4>7 7>0 ");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% for (i, item) in items|enumerate|take(2) %}{{ i }}:{{ item }} {% endfor %}|\
+              {% for n in items|skip(1)|take(2) %}{{ n }}{% endfor %}",
+    ext = "txt"
+)]
+struct ForChainedFiltersTemplate<'a> {
+    items: Vec<&'a str>,
+}
+
+#[test]
+fn test_for_chained_filters() {
+    let t = ForChainedFiltersTemplate {
+        items: vec!["a", "b", "c", "d"],
+    };
+    assert_eq!(t.render().unwrap(), "0:a 1:b |bc");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% for (group, name) in items %}\
+              {% if loop.changed(group) %}[{{ group }}]{% endif %}{{ name }}\
+              {% if loop.changed(name.len()) %}*{% endif %} \
+              {% endfor %}",
+    ext = "txt"
+)]
+struct ForChangedTemplate<'a> {
+    items: Vec<(&'a str, &'a str)>,
+}
+
+#[test]
+fn test_for_changed() {
+    let t = ForChangedTemplate {
+        items: vec![("a", "x"), ("a", "y"), ("b", "zz"), ("a", "w")],
+    };
+    assert_eq!(t.render().unwrap(), "[a]x* y [b]zz* [a]w* ");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% for (k, (a, ..)) in pairs %}{{ k }}={{ a }} {% endfor %}",
+    ext = "txt"
+)]
+struct ForNestedTargetTemplate<'a> {
+    pairs: Vec<(&'a str, (u32, u32, u32))>,
+}
+
+#[test]
+fn test_for_nested_target() {
+    let t = ForNestedTargetTemplate {
+        pairs: vec![("a", (1, 2, 3)), ("b", (4, 5, 6))],
+    };
+    assert_eq!(t.render().unwrap(), "a=1 b=4 ");
+}
@@ -24,6 +24,18 @@ fn test_import() {
     assert_eq!(t.render().unwrap(), "foo foo foo");
 }
 
+#[derive(Template)]
+#[template(path = "from-import.html")]
+struct FromImportTemplate<'a> {
+    s: &'a str,
+}
+
+#[test]
+fn test_from_import() {
+    let t = FromImportTemplate { s: "foo" };
+    assert_eq!(t.render().unwrap(), "foo foo foo <foo>");
+}
+
 #[derive(Template)]
 #[template(path = "deep-nested-macro.html")]
 struct NestedTemplate;
@@ -43,3 +55,73 @@ fn test_deep_import() {
     let t = DeepImportTemplate;
     assert_eq!(t.render().unwrap(), "foo");
 }
+
+#[derive(Template)]
+#[template(
+    source = "{% macro tag(name, **attrs) %}\
+              <{{ name }}{% for (key, value) in attrs %} {{ key }}=\"{{ value }}\"{% endfor %}>\
+              {% endmacro %}\
+              {% call tag(\"div\", id=\"x\", class=cls) %}\
+              {% call tag(name=\"br\") %}",
+    ext = "html"
+)]
+struct KwargsTemplate<'a> {
+    cls: &'a str,
+}
+
+#[test]
+fn test_kwargs() {
+    let t = KwargsTemplate { cls: "y" };
+    assert_eq!(t.render().unwrap(), "<div class=\"y\" id=\"x\"><br>");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% macro button(label, kind=\"primary\", title=label) %}\
+              <button class=\"{{ kind }}\" title=\"{{ title }}\">{{ label }}</button>\
+              {% endmacro %}\
+              {% call button(\"Save\") %}\
+              {% call button(label=\"Delete\", kind=\"danger\") %}\
+              {% call button(\"Back\", title=hint) %}",
+    ext = "html"
+)]
+struct MacroDefaultsTemplate<'a> {
+    hint: &'a str,
+}
+
+#[test]
+fn test_macro_defaults() {
+    let t = MacroDefaultsTemplate { hint: "Go back" };
+    assert_eq!(
+        t.render().unwrap(),
+        "<button class=\"primary\" title=\"Save\">Save</button>\
+         <button class=\"danger\" title=\"Delete\">Delete</button>\
+         <button class=\"primary\" title=\"Go back\">Back</button>"
+    );
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% macro panel(title) %}<div><h2>{{ title }}</h2>{{ caller() }}</div>{% endmacro %}\
+              {% call panel(\"Outer\") -%}
+                  <p>{{ title }}</p>
+                  {%- call panel(\"Inner\") %}{{ caller_text }}{% endcall -%}
+              {%- endcall %}",
+    ext = "html"
+)]
+struct CallerTemplate<'a> {
+    title: &'a str,
+    caller_text: &'a str,
+}
+
+#[test]
+fn test_caller() {
+    let t = CallerTemplate {
+        title: "<own>",
+        caller_text: "text",
+    };
+    assert_eq!(
+        t.render().unwrap(),
+        "<div><h2>Outer</h2><p>&lt;own&gt;</p><div><h2>Inner</h2>text</div></div>"
+    );
+}
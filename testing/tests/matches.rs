@@ -113,3 +113,53 @@ fn test_match_no_whitespace() {
     let s = MatchNoWhitespace { foo: Some(1) };
     assert_eq!(s.render().unwrap(), "1");
 }
+
+#[allow(dead_code)]
+enum Message {
+    Text { content: String },
+    Ping,
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% match message %}{% when Message::Text { content } %}{{ content }}{% when Message::Ping %}ping{% endmatch %}",
+    ext = "txt"
+)]
+struct MatchStructInline {
+    message: Message,
+}
+
+#[test]
+fn test_match_struct_inline() {
+    let s = MatchStructInline {
+        message: Message::Text {
+            content: "hello".into(),
+        },
+    };
+    assert_eq!(s.render().unwrap(), "hello");
+    let s = MatchStructInline {
+        message: Message::Ping,
+    };
+    assert_eq!(s.render().unwrap(), "ping");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% match item %}{% when Some with (1) | Some with (2) %}small{% when Some with (val) if val.is_positive() %}{{ val }}{% when Some with (_) | None %}none{% endmatch %}",
+    ext = "txt"
+)]
+struct MatchGuardOr {
+    item: Option<i32>,
+}
+
+#[test]
+fn test_match_guard_or() {
+    let s = MatchGuardOr { item: Some(2) };
+    assert_eq!(s.render().unwrap(), "small");
+    let s = MatchGuardOr { item: Some(7) };
+    assert_eq!(s.render().unwrap(), "7");
+    let s = MatchGuardOr { item: Some(-3) };
+    assert_eq!(s.render().unwrap(), "none");
+    let s = MatchGuardOr { item: None };
+    assert_eq!(s.render().unwrap(), "none");
+}
@@ -53,3 +53,114 @@ fn test_ranges() {
     };
     assert_eq!(t.render().unwrap(), "abcd\nbcd\n\na\nab");
 }
+
+#[derive(Template)]
+#[template(
+    source = "{{ a as f64 / 2.0 }} {{ b + a as u64 }} {{ a as f64 / (b + 1) as f64 }}",
+    ext = "txt"
+)]
+struct CastTemplate {
+    a: u8,
+    b: u64,
+}
+
+#[test]
+fn test_cast() {
+    let t = CastTemplate { a: 255, b: 1 };
+    assert_eq!(t.render().unwrap(), "127.5 256 127.5");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{{ \"yes\" if c && a > 1 else \"no\" }} {{ a if a > 2 else b|upper }} {{ 1 if !c else 2 if a == 1 else 3 }}",
+    ext = "html"
+)]
+struct IfElseExprTemplate<'a> {
+    a: i32,
+    b: &'a str,
+    c: bool,
+}
+
+#[test]
+fn test_if_else_expr() {
+    let t = IfElseExprTemplate {
+        a: 1,
+        b: "<b>",
+        c: true,
+    };
+    assert_eq!(t.render().unwrap(), "no &lt;B&gt; 2");
+}
+
+mod tests {
+    pub fn shorter_than(s: &&str, len: &usize) -> askama::Result<bool> {
+        Ok(s.len() < *len)
+    }
+
+    pub fn below(n: &i32, limit: &i32) -> askama::Result<bool> {
+        Ok(n < limit)
+    }
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% if a is some %}some{% endif %} {{ b is empty }} {{ c is not empty }} \
+              {{ n is odd }} {{ (n + 1) is even }} {{ n is divisibleby(3) }} \
+              {{ b is shorter_than(1) }} {{ n * 2 + 1 is odd }} {{ n - 1 is below(9) }}",
+    ext = "txt"
+)]
+struct IsTestTemplate<'a> {
+    a: Option<u8>,
+    b: &'a str,
+    c: Vec<u8>,
+    n: i32,
+}
+
+#[test]
+fn test_is_tests() {
+    let t = IsTestTemplate {
+        a: Some(1),
+        b: "",
+        c: vec![],
+        n: 9,
+    };
+    assert_eq!(
+        t.render().unwrap(),
+        "some true false true true true true true true"
+    );
+}
+
+#[derive(Template)]
+#[template(
+    source = "{{ first ~ \" \" ~ last }}|{{ first|upper ~ (n + 1) }}",
+    ext = "html"
+)]
+struct ConcatTemplate<'a> {
+    first: &'a str,
+    last: &'a str,
+    n: u32,
+}
+
+#[test]
+fn test_concat() {
+    let t = ConcatTemplate {
+        first: "Jane",
+        last: "<Doe>",
+        n: 1,
+    };
+    assert_eq!(t.render().unwrap(), "Jane &lt;Doe&gt;|JANE2");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{{ 0xFF }} {{ 0o17 + 0b1010 }} {{ 1_000_000 }} {{ 1e-3 }} {{ 42u64 }} {{ 2.5f32 * 2.0 }}",
+    ext = "txt"
+)]
+struct NumLitTemplate;
+
+#[test]
+fn test_num_lits() {
+    assert_eq!(
+        NumLitTemplate.render().unwrap(),
+        "255 25 1000000 0.001 42 5"
+    );
+}
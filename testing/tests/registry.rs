@@ -0,0 +1,69 @@
+use askama::{RegisteredTemplate, Registry, Template};
+
+#[derive(Template)]
+#[template(
+    source = "Welcome, {{ name }}!",
+    ext = "txt",
+    register = "email/welcome"
+)]
+struct WelcomeTemplate {
+    name: String,
+}
+
+impl From<&str> for WelcomeTemplate {
+    fn from(name: &str) -> Self {
+        WelcomeTemplate { name: name.into() }
+    }
+}
+
+#[derive(Template)]
+#[template(
+    source = "<p>Bye, {{ name }}</p>",
+    ext = "html",
+    register = "email/goodbye"
+)]
+struct GoodbyeTemplate<'a> {
+    name: &'a str,
+}
+
+impl<'a> From<&'a str> for GoodbyeTemplate<'a> {
+    fn from(name: &'a str) -> Self {
+        GoodbyeTemplate { name }
+    }
+}
+
+#[test]
+fn test_registered_name() {
+    assert_eq!(WelcomeTemplate::NAME, "email/welcome");
+    assert_eq!(GoodbyeTemplate::NAME, "email/goodbye");
+}
+
+#[test]
+fn test_registry() {
+    let mut registry = Registry::new();
+    registry
+        .register::<WelcomeTemplate>()
+        .register::<GoodbyeTemplate<'static>>();
+
+    let mut names = registry.names().collect::<Vec<_>>();
+    names.sort_unstable();
+    assert_eq!(names, vec!["email/goodbye", "email/welcome"]);
+
+    let template = registry.build("email/welcome", "Ann").unwrap();
+    assert_eq!(template.render().unwrap(), "Welcome, Ann!");
+    assert_eq!(template.extension(), Some("txt"));
+    let constructor = registry.get("email/goodbye").unwrap();
+    assert_eq!(
+        constructor("<Bo>").render().unwrap(),
+        "<p>Bye, &lt;Bo&gt;</p>"
+    );
+    assert!(registry.build("email/missing", "Ann").is_none());
+}
+
+#[test]
+#[should_panic(expected = "template 'email/welcome' registered twice")]
+fn test_registry_duplicate() {
+    Registry::<&str>::new()
+        .register::<WelcomeTemplate>()
+        .register::<WelcomeTemplate>();
+}
@@ -0,0 +1,17 @@
+use askama::Template;
+
+#[derive(Template)]
+#[template(path = "./widget.html", relative = true)]
+struct WidgetTemplate<'a> {
+    name: &'a str,
+    s: &'a str,
+}
+
+#[test]
+fn test_relative_path() {
+    let t = WidgetTemplate {
+        name: "<b>",
+        s: "x",
+    };
+    assert_eq!(t.render().unwrap(), "Widget &lt;b&gt;: INCLUDED: x");
+}
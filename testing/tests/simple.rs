@@ -218,6 +218,72 @@ fn test_generics() {
     assert_eq!(t.render().unwrap(), "a42");
 }
 
+#[derive(Template)]
+#[template(
+    source = "{% for x in items %}{{ x }}{% endfor %}/{{ N }}",
+    ext = "txt"
+)]
+struct ConstGenericsTemplate<T: std::fmt::Display, const N: usize> {
+    items: [T; N],
+}
+
+#[test]
+fn test_const_generics() {
+    let t = ConstGenericsTemplate { items: [1, 2, 3] };
+    assert_eq!(t.render().unwrap(), "123/3");
+}
+
+#[derive(Template)]
+#[template(source = "{% for x in items %}{{ x }},{% endfor %}", ext = "txt")]
+struct WhereClauseTemplate<'a, I: ?Sized>
+where
+    for<'b> &'b I: IntoIterator,
+    for<'b> <&'b I as IntoIterator>::Item: std::fmt::Display,
+{
+    items: &'a I,
+}
+
+#[test]
+fn test_where_clause() {
+    let items = vec![1, 2];
+    let t = WhereClauseTemplate::<Vec<u8>> { items: &items };
+    assert_eq!(t.render().unwrap(), "1,2,");
+}
+
+trait Model {
+    type Id: std::fmt::Display;
+    fn id(&self) -> Self::Id;
+}
+
+struct User;
+
+impl Model for User {
+    type Id = u32;
+    fn id(&self) -> u32 {
+        7
+    }
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% block id %}{{ model.id() }}{% endblock %}",
+    ext = "txt",
+    register = "model"
+)]
+struct AssociatedTypeTemplate<M: Model<Id = u32> = User, const W: usize = 3>
+where
+    M::Id: Copy,
+{
+    model: M,
+}
+
+#[test]
+fn test_associated_type_bounds() {
+    let t: AssociatedTypeTemplate = AssociatedTypeTemplate { model: User };
+    assert_eq!(t.render().unwrap(), "7");
+    assert_eq!(t.render_block("id").unwrap(), "7");
+}
+
 #[derive(Template)]
 #[template(path = "composition.html")]
 struct CompositionTemplate {
@@ -381,6 +447,33 @@ fn test_raw_complex() {
     );
 }
 
+#[derive(Template)]
+#[template(source = "{% verbatim %}{{ x }}{% endverbatim %}|{{ x }}", ext = "txt")]
+struct VerbatimTemplate {
+    x: u32,
+}
+
+#[test]
+fn test_verbatim() {
+    let template = VerbatimTemplate { x: 1 };
+    assert_eq!(template.render().unwrap(), "{{ x }}|1");
+}
+
+#[derive(Template)]
+#[template(
+    source = "<p>\n{%- raw %} {{ x }} {# y #} {%- endraw %}\n</p>{% raw -%} \n {{ z }}{% endraw %}",
+    ext = "txt"
+)]
+struct RawWhitespaceTemplate;
+
+#[test]
+fn test_raw_whitespace_control() {
+    assert_eq!(
+        RawWhitespaceTemplate.render().unwrap(),
+        "<p> {{ x }} {# y #}\n</p>{{ z }}"
+    );
+}
+
 mod without_import_on_derive {
     #[derive(askama::Template)]
     #[template(source = "foo", ext = "txt")]
@@ -402,3 +495,173 @@ fn test_define_string_var() {
     let template = DefineStringVar;
     assert_eq!(template.render().unwrap(), "");
 }
+
+#[derive(Template)]
+#[template(
+    source = r###"{{ r"C:\dir" }} {{ r#"say "hi""# }} {{ "tab\there" }}"###,
+    ext = "txt"
+)]
+struct RawStrLitTemplate;
+
+#[test]
+fn test_raw_str_lit() {
+    assert_eq!(
+        RawStrLitTemplate.render().unwrap(),
+        "C:\\dir say \"hi\" tab\there"
+    );
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% for s in [a, b] %}{{ s }}{% endfor %}|{{ [\"x\", \"y\"][i] }}|{{ map[a] }}",
+    ext = "txt"
+)]
+struct ArrayIndexTemplate<'a> {
+    a: &'a str,
+    b: &'a str,
+    i: usize,
+    map: HashMap<&'a str, u8>,
+}
+
+#[test]
+fn test_array_index() {
+    let mut map = HashMap::new();
+    map.insert("a", 7);
+    let t = ArrayIndexTemplate {
+        a: "a",
+        b: "b",
+        i: 1,
+        map,
+    };
+    assert_eq!(t.render().unwrap(), "ab|y|7");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{{ s }} {% autoescape false %}{{ s }} {{ s|e }} \
+              {% autoescape \"html\" %}{{ s }}{% endautoescape %} {{ s }}{% endautoescape %} {{ s }}",
+    ext = "html"
+)]
+struct AutoEscapeTemplate<'a> {
+    s: &'a str,
+}
+
+#[test]
+fn test_autoescape() {
+    let t = AutoEscapeTemplate { s: "<b>" };
+    assert_eq!(
+        t.render().unwrap(),
+        "&lt;b&gt; <b> &lt;b&gt; &lt;b&gt; <b> &lt;b&gt;"
+    );
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% for i in 0..20 %}<li>{{ i }}</li>{% endfor %}",
+    ext = "txt"
+)]
+struct ChunksTemplate;
+
+#[test]
+fn test_render_chunks() {
+    let mut chunks = Vec::new();
+    ChunksTemplate
+        .render_chunks(16, &mut |chunk| {
+            chunks.push(chunk.to_string());
+            Ok(())
+        })
+        .unwrap();
+    assert!(chunks.len() > 1);
+    assert!(chunks[..chunks.len() - 1].iter().all(|c| c.len() >= 16));
+    assert_eq!(chunks.concat(), ChunksTemplate.render().unwrap());
+
+    let mut calls = 0;
+    let res = ChunksTemplate.render_chunks(16, &mut |_| {
+        calls += 1;
+        Err(askama::Error::Fmt(std::fmt::Error))
+    });
+    assert!(res.is_err());
+    assert_eq!(calls, 1);
+}
+
+struct FailingWriter;
+
+impl std::io::Write for FailingWriter {
+    fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::BrokenPipe,
+            "closed",
+        ))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_render_into_buf() {
+    let mut buf = String::from("stale");
+    ChunksTemplate.render_into_buf(&mut buf).unwrap();
+    assert_eq!(buf, ChunksTemplate.render().unwrap());
+    let capacity = buf.capacity();
+    ChunksTemplate.render_into_buf(&mut buf).unwrap();
+    assert_eq!(buf, ChunksTemplate.render().unwrap());
+    assert_eq!(buf.capacity(), capacity);
+}
+
+#[test]
+fn test_write_into_io() {
+    let mut out = Vec::new();
+    ChunksTemplate.write_into_io(&mut out).unwrap();
+    assert_eq!(out, ChunksTemplate.render().unwrap().into_bytes());
+
+    match ChunksTemplate.write_into_io(&mut FailingWriter) {
+        Err(askama::Error::Custom(err)) => assert_eq!(err.to_string(), "closed"),
+        res => panic!("expected an I/O error, got {:?}", res),
+    }
+}
+
+#[derive(Template)]
+#[template(
+    source = "abcd{% let x = 1 %}{% if x == 1 %}efgh{% else %}ij{% endif %}{{ \"klm\" }}\
+              {% block b %}nop{% endblock %}",
+    ext = "txt"
+)]
+struct SizeHintTemplate;
+
+#[test]
+fn test_size_hint() {
+    assert_eq!(SizeHintTemplate.render().unwrap(), "abcdefghklmnop");
+    assert_eq!(SizeHintTemplate.size_hint(), 4 + 3 + 3 + 3);
+    assert_eq!(<SizeHintTemplate as askama::SizedTemplate>::size_hint(), 13);
+}
+
+#[derive(Template)]
+#[template(
+    source = "{{ 42 }} {{ 1.5 }} {{ a as f64 }} {{ b as f32 }} {{ c as f64 }} {{ d as f64 }} \
+              {{ (n as i64) }} {% for _ in 0..2 %}{{ loop.index }}{{ loop.index0 }}{% endfor %}",
+    ext = "html"
+)]
+struct NumbersTemplate {
+    a: f64,
+    b: f32,
+    c: f64,
+    d: f64,
+    n: i8,
+}
+
+#[test]
+fn test_numbers() {
+    let t = NumbersTemplate {
+        a: 2.0,
+        b: -0.0,
+        c: 1e21,
+        d: f64::NAN,
+        n: -128,
+    };
+    assert_eq!(
+        t.render().unwrap(),
+        format!("42 1.5 2 -0 {} NaN -128 1021", 1e21)
+    );
+}
@@ -70,3 +70,120 @@ fn test_if_let() {
     let t = IfLet { a: Some("foo") };
     assert_eq!(t.render().unwrap(), "foo");
 }
+
+#[derive(Template)]
+#[template(
+    source = "{% let parsed = input.parse::<u32>()? %}{{ parsed + 1 }}",
+    ext = "txt"
+)]
+struct LetTry<'a> {
+    input: &'a str,
+}
+
+#[test]
+fn test_let_try() {
+    let t = LetTry { input: "41" };
+    assert_eq!(t.render().unwrap(), "42");
+    let t = LetTry { input: "forty-one" };
+    match t.render() {
+        Err(askama::Error::Located { source, .. }) => match *source {
+            askama::Error::Custom(err) => {
+                assert_eq!(err.to_string(), "invalid digit found in string")
+            }
+            err => panic!("unexpected error {:?}", err),
+        },
+        res => panic!("unexpected result {:?}", res),
+    }
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% let x = 1 %}{% let y = 2 %}{% if true %}{% let z = 3 %}{{ z }}{% endif %}{{ x }}{{ y }}",
+    ext = "txt",
+    deny_shadowing = true
+)]
+struct LetDenyShadowing;
+
+#[test]
+fn test_let_deny_shadowing() {
+    assert_eq!(LetDenyShadowing.render().unwrap(), "312");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% macro wrap(content) %}<div>{{ content|safe }}</div>{% endmacro -%}
+              {% set greeting -%} Hello, <b>{{ name }}</b>! {%- endset -%}
+              {% call wrap(greeting) %} {{ greeting.len() }}",
+    ext = "html"
+)]
+struct LetBlockTemplate<'a> {
+    name: &'a str,
+}
+
+#[test]
+fn test_let_block() {
+    let t = LetBlockTemplate { name: "<you>" };
+    assert_eq!(
+        t.render().unwrap(),
+        "<div>Hello, <b>&lt;you&gt;</b>!</div> 26"
+    );
+}
+
+struct Wrapped(u32);
+
+struct Point {
+    x: u32,
+    y: Wrapped,
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% let Point { x, y: Wrapped(y) } = point %}{{ x }},{{ y }}",
+    ext = "txt"
+)]
+struct LetNestedTemplate {
+    point: Point,
+}
+
+#[test]
+fn test_let_nested() {
+    let t = LetNestedTemplate {
+        point: Point {
+            x: 1,
+            y: Wrapped(2),
+        },
+    };
+    assert_eq!(t.render().unwrap(), "1,2");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{{ log.borrow() }}|{% do log.borrow_mut().push_str(name) %}{{ log.borrow() }}",
+    ext = "txt"
+)]
+struct DoTemplate<'a> {
+    log: std::cell::RefCell<String>,
+    name: &'a str,
+}
+
+#[test]
+fn test_do() {
+    let t = DoTemplate {
+        log: std::cell::RefCell::new("a".into()),
+        name: "b",
+    };
+    assert_eq!(t.render().unwrap(), "a|ab");
+    assert_eq!(*t.log.borrow(), "ab");
+}
+
+#[derive(Template)]
+#[template(
+    source = "{% let a = 1 %}{% with a = 2, b = a + 1 %}{{ a }}{{ b }}{% endwith %}{{ a }}",
+    ext = "txt"
+)]
+struct WithTemplate;
+
+#[test]
+fn test_with() {
+    assert_eq!(WithTemplate.render().unwrap(), "231");
+}
@@ -39,3 +39,25 @@ fn test_extra_whitespace() {
     template.nested_1.nested_2.hash.insert("key", "value");
     assert_eq!(template.render().unwrap(), "\n0\n0\n0\n0\n\n\n\n0\n0\n0\n0\n0\n\na0\na1\nvalue\n\n\n\n\n\n[\n  \"a0\",\n  \"a1\",\n  \"a2\",\n  \"a3\"\n]\n[\n  \"a0\",\n  \"a1\",\n  \"a2\",\n  \"a3\"\n][\n  \"a0\",\n  \"a1\",\n  \"a2\",\n  \"a3\"\n]\n[\n  \"a1\"\n][\n  \"a1\"\n]\n[\n  \"a1\",\n  \"a2\"\n][\n  \"a1\",\n  \"a2\"\n]\n[\n  \"a1\"\n][\n  \"a1\"\n]1-1-1\n3333 3\n2222 2\n0000 0\n3333 3\n\ntruefalse\nfalsefalsefalse\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n");
 }
+
+#[derive(Template)]
+#[template(
+    source = "<ul>\n  {% for item in items %}\n  <li>{{ item }}</li>\n  {% endfor %}\n  {%+ if true %}\n</ul>\n{% endif %}",
+    ext = "txt",
+    trim_blocks = true,
+    lstrip_blocks = true
+)]
+struct TrimBlocks {
+    items: Vec<&'static str>,
+}
+
+#[test]
+fn test_trim_blocks() {
+    let t = TrimBlocks {
+        items: vec!["a", "b"],
+    };
+    assert_eq!(
+        t.render().unwrap(),
+        "<ul>\n  <li>a</li>\n  <li>b</li>\n  </ul>\n"
+    );
+}